@@ -0,0 +1,953 @@
+use bollard::{
+    body_full,
+    container::LogOutput,
+    errors::Error,
+    exec::StartExecResults,
+    models::{
+        self, ContainerCreateBody, ExecConfig, HostConfig, PortMap, RestartPolicyNameEnum,
+        TaskState, VolumeCreateRequest,
+    },
+    query_parameters::{
+        AttachContainerOptions, CreateContainerOptions, CreateImageOptions,
+        DownloadFromContainerOptions, EventsOptions, ImportImageOptions, InspectServiceOptions,
+        ListContainersOptions, ListTasksOptions, ListVolumesOptions, RemoveContainerOptions,
+        UpdateServiceOptions, UploadToContainerOptions,
+    },
+    Docker, API_DEFAULT_VERSION,
+};
+use clap::ValueEnum;
+use futures_util::{future, StreamExt};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{mpsc::Sender, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, runtime::Runtime};
+
+use crate::{
+    exec::{command_failed, stderr_tail, user_socket, Helper, SYSTEM_SOCKET},
+    manifest::{ContainerSpec, MountSpec, VolumeSpec},
+    types::{
+        ContainerConfig, ContainerEvent, ContainerHostConfig, ContainerInfo, ContextEndpoint,
+        ContextMeta, DaemonVersion, DockerConfig, DockerError, EventActor, MountType, Mounts,
+        PortBinding, PsInfo, ReplicatedMode, RestartPolicy, ServiceInfo, ServiceMode, ServiceSpec,
+        VolumeInfo,
+    },
+    CliArguments, HELPER_PID_LABEL, TYPE_BACKUPCONTAINER,
+};
+
+/*
+ * Connection to the Docker Engine API, with a runtime to block on the requests.
+ */
+pub struct DockerApi {
+    runtime: Runtime,
+    docker: Docker,
+}
+
+const TIMEOUT_SECS: u64 = 120;
+
+/*
+ * How long a scaled service may take until its tasks are running or stopped.
+ */
+const SCALE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/*
+ * The connections by the daemon they are to, like the source and the target of a migration.
+ */
+static API: Mutex<Vec<(String, Result<&'static DockerApi, String>)>> = Mutex::new(vec![]);
+
+impl DockerApi {
+    /*
+     * The connection to the docker daemon, shared by all requests to that daemon.
+     */
+    pub fn get(cli_args: &CliArguments) -> Result<&'static DockerApi, DockerError> {
+        let daemon = format!(
+            "{:?} {:?} {:?} {:?} {:?}",
+            cli_args.host, cli_args.context, cli_args.tls_ca, cli_args.tls_cert, cli_args.tls_key
+        );
+        let mut connections = API.lock()?;
+        let connection = match connections.iter().find(|(d, _)| *d == daemon) {
+            Some((_, connection)) => connection.clone(),
+            None => {
+                let connection = DockerApi::connect(cli_args).map(|api| &*Box::leak(Box::new(api)));
+                connections.push((daemon, connection.clone()));
+                connection
+            }
+        };
+        connection.map_err(DockerError::DaemonUnreachable)
+    }
+
+    fn connect(cli_args: &CliArguments) -> Result<DockerApi, String> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let docker = connect(cli_args).map_err(|e| match e {
+            DockerError::DaemonUnreachable(message) => message,
+            e => e.to_string(),
+        })?;
+        Ok(DockerApi { runtime, docker })
+    }
+
+    /*
+     * The running containers.
+     */
+    pub fn list_containers(
+        &self,
+        all: bool,
+        labels: &[String],
+    ) -> Result<Vec<PsInfo>, DockerError> {
+        let options = ListContainersOptions {
+            all,
+            filters: (!labels.is_empty())
+                .then(|| HashMap::from([("label".to_string(), labels.to_vec())])),
+            ..Default::default()
+        };
+        let containers = self
+            .runtime
+            .block_on(self.docker.list_containers(Some(options)))?;
+        Ok(containers
+            .into_iter()
+            .map(|container| PsInfo {
+                names: container
+                    .names
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|name| name.trim_start_matches('/'))
+                    .collect::<Vec<&str>>()
+                    .join(","),
+                state: container.state.map(|state| state.to_string()),
+            })
+            .collect())
+    }
+
+    /*
+     * Inspect a container, None when it does not exist.
+     */
+    pub fn inspect_container(&self, name: &str) -> Result<Option<ContainerInfo>, DockerError> {
+        let inspected = match self
+            .runtime
+            .block_on(self.docker.inspect_container(name, None))
+        {
+            Ok(inspected) => inspected,
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let inspect = serde_json::to_value(&inspected)?;
+        Ok(Some(ContainerInfo {
+            id: inspected.id.unwrap_or_default(),
+            image: inspected.image.unwrap_or_default(),
+            mounts: inspected
+                .mounts
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mount| Mounts {
+                    kind: mount.typ.map(|kind| {
+                        MountType::from_str(&kind.to_string(), true).unwrap_or(MountType::Other)
+                    }),
+                    name: mount.name,
+                    source: mount.source.unwrap_or_default(),
+                    destination: mount.destination.unwrap_or_default(),
+                    rw: mount.rw.unwrap_or(true),
+                })
+                .collect(),
+            config: inspected
+                .config
+                .map(|config| ContainerConfig {
+                    labels: config.labels.unwrap_or_default(),
+                    image: config.image.unwrap_or_default(),
+                    env: config.env.unwrap_or_default(),
+                    cmd: config.cmd.unwrap_or_default(),
+                    entrypoint: config.entrypoint.unwrap_or_default(),
+                })
+                .unwrap_or_default(),
+            host_config: inspected
+                .host_config
+                .map(|host_config| ContainerHostConfig {
+                    links: host_config.links.unwrap_or_default(),
+                    port_bindings: host_config
+                        .port_bindings
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|(port, bindings)| {
+                            let bindings = bindings.map(|bindings| {
+                                bindings
+                                    .into_iter()
+                                    .map(|binding| PortBinding {
+                                        host_ip: binding.host_ip.unwrap_or_default(),
+                                        host_port: binding.host_port.unwrap_or_default(),
+                                    })
+                                    .collect()
+                            });
+                            (port, bindings)
+                        })
+                        .collect(),
+                    restart_policy: host_config.restart_policy.map(|policy| RestartPolicy {
+                        name: policy.name.map(|name| name.to_string()).unwrap_or_default(),
+                        maximum_retry_count: policy.maximum_retry_count.unwrap_or_default(),
+                    }),
+                })
+                .unwrap_or_default(),
+            inspect: Some(inspect),
+        }))
+    }
+
+    pub fn list_volumes(&self) -> Result<Vec<VolumeInfo>, DockerError> {
+        let volumes = self
+            .runtime
+            .block_on(self.docker.list_volumes(None::<ListVolumesOptions>))?;
+        Ok(volumes
+            .volumes
+            .unwrap_or_default()
+            .into_iter()
+            .map(volume_info)
+            .collect())
+    }
+
+    /*
+     * Inspect a named volume, None when it does not exist.
+     */
+    pub fn inspect_volume(&self, name: &str) -> Result<Option<VolumeInfo>, DockerError> {
+        match self.runtime.block_on(self.docker.inspect_volume(name)) {
+            Ok(volume) => Ok(Some(volume_info(volume))),
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /*
+     * Create a named volume with a driver and its options.
+     */
+    pub fn create_volume(&self, name: &str, spec: &VolumeSpec) -> Result<(), DockerError> {
+        let request = VolumeCreateRequest {
+            name: Some(name.to_string()),
+            driver: Some(spec.driver.clone()),
+            driver_opts: Some(spec.options.clone().into_iter().collect()),
+            labels: Some(spec.labels.clone().into_iter().collect()),
+            ..Default::default()
+        };
+        self.runtime.block_on(self.docker.create_volume(request))?;
+        Ok(())
+    }
+
+    /*
+     * The version of the daemon, the first request to it.
+     */
+    pub fn daemon_version(&self) -> Result<DaemonVersion, DockerError> {
+        let version = self.runtime.block_on(self.docker.version())?;
+        Ok(DaemonVersion {
+            version: version.version.unwrap_or_default(),
+            api_version: version.api_version.unwrap_or_default(),
+            os: version.os.unwrap_or_default(),
+        })
+    }
+
+    /*
+     * Inspect a swarm service, None when it does not exist.
+     */
+    pub fn inspect_service(&self, name: &str) -> Result<Option<ServiceInfo>, DockerError> {
+        match self.runtime.block_on(
+            self.docker
+                .inspect_service(name, None::<InspectServiceOptions>),
+        ) {
+            Ok(service) => Ok(Some(service_info(service))),
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /*
+     * Scale a replicated swarm service and wait, like docker service scale does, until as many of
+     * its tasks are running.
+     */
+    pub fn scale_service(&self, name: &str, replicas: u64) -> Result<(), DockerError> {
+        let service = self.runtime.block_on(
+            self.docker
+                .inspect_service(name, None::<InspectServiceOptions>),
+        )?;
+        let version = service
+            .version
+            .and_then(|version| version.index)
+            .unwrap_or_default();
+        let mut spec = service.spec.unwrap_or_default();
+        let replicated = spec
+            .mode
+            .as_mut()
+            .and_then(|mode| mode.replicated.as_mut())
+            .ok_or(DockerError::from(
+                format!("Service {} is global and can not be scaled", name).as_str(),
+            ))?;
+        replicated.replicas = Some(replicas as i64);
+        let options = UpdateServiceOptions {
+            version: version as i32,
+            ..Default::default()
+        };
+        self.runtime
+            .block_on(self.docker.update_service(name, spec, options, None))?;
+
+        let filters = HashMap::from([("service".to_string(), vec![name.to_string()])]);
+        let deadline = Instant::now() + SCALE_TIMEOUT;
+        loop {
+            let tasks = self
+                .runtime
+                .block_on(self.docker.list_tasks(Some(ListTasksOptions {
+                    filters: Some(filters.clone()),
+                })))?;
+            let running = tasks
+                .iter()
+                .filter(|task| {
+                    task.status.as_ref().and_then(|status| status.state) == Some(TaskState::RUNNING)
+                })
+                .count() as u64;
+            if running == replicas {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                return Err(DockerError::from(
+                    format!(
+                        "Service {} has {} running tasks instead of {} after {} seconds",
+                        name,
+                        running,
+                        replicas,
+                        SCALE_TIMEOUT.as_secs()
+                    )
+                    .as_str(),
+                ));
+            }
+            debug!(
+                "Service {} has {} of {} tasks running",
+                name, running, replicas
+            );
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    pub fn stop_container(&self, id: &str) -> Result<(), DockerError> {
+        Ok(self
+            .runtime
+            .block_on(self.docker.stop_container(id, None))?)
+    }
+
+    pub fn start_container(&self, id: &str) -> Result<(), DockerError> {
+        Ok(self
+            .runtime
+            .block_on(self.docker.start_container(id, None))?)
+    }
+
+    pub fn remove_container(&self, id: &str) -> Result<(), DockerError> {
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        Ok(self
+            .runtime
+            .block_on(self.docker.remove_container(id, Some(options)))?)
+    }
+
+    /*
+     * Create and start a container, pulling its image when it is missing.
+     */
+    pub fn create_container(
+        &self,
+        name: &str,
+        spec: &ContainerSpec,
+    ) -> Result<String, DockerError> {
+        let mut port_bindings: PortMap = HashMap::new();
+        for port in spec.ports.iter() {
+            port_bindings
+                .entry(port.container_port.clone())
+                .or_insert_with(|| Some(vec![]))
+                .get_or_insert_default()
+                .push(models::PortBinding {
+                    host_ip: Some(port.host_ip.clone()),
+                    host_port: Some(port.host_port.clone()),
+                });
+        }
+        let restart_policy = spec.restart_policy.as_ref().map(|policy| {
+            let (name, retries) = policy.split_once(':').unwrap_or((policy, "0"));
+            models::RestartPolicy {
+                name: name.parse::<RestartPolicyNameEnum>().ok(),
+                maximum_retry_count: retries.parse().ok(),
+            }
+        });
+        let config = ContainerCreateBody {
+            image: Some(spec.image.clone()),
+            cmd: (!spec.cmd.is_empty()).then(|| spec.cmd.clone()),
+            entrypoint: (!spec.entrypoint.is_empty()).then(|| spec.entrypoint.clone()),
+            env: Some(spec.env.clone()),
+            labels: Some(spec.labels.clone().into_iter().collect()),
+            exposed_ports: Some(port_bindings.keys().cloned().collect()),
+            host_config: Some(HostConfig {
+                binds: Some(spec.mounts.iter().map(MountSpec::bind).collect()),
+                port_bindings: Some(port_bindings),
+                restart_policy,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.runtime.block_on(async {
+            let id = self.create_helper(&spec.image, Some(name), config).await?;
+            self.docker.start_container(&id, None).await?;
+            Ok(id)
+        })
+    }
+
+    /*
+     * Write the tar of an image like docker save does.
+     */
+    pub fn save_image(&self, image: &str, output: &mut dyn Write) -> Result<u64, DockerError> {
+        self.runtime.block_on(async {
+            let mut stream = self.docker.export_image(image);
+            let mut written = 0;
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                output.write_all(&chunk)?;
+                written += chunk.len() as u64;
+            }
+            Ok(written)
+        })
+    }
+
+    /*
+     * Load the images of a tar written by docker save.
+     */
+    pub fn load_image(&self, mut input: File) -> Result<(), DockerError> {
+        let mut failed = false;
+        let chunks = std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let mut buffer = vec![0; 1 << 16];
+            match input.read(&mut buffer) {
+                Ok(0) => None,
+                Ok(read) => {
+                    buffer.truncate(read);
+                    Some(Ok(buffer.into()))
+                }
+                Err(e) => {
+                    failed = true;
+                    Some(Err(e))
+                }
+            }
+        });
+        let options = ImportImageOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        self.runtime.block_on(async {
+            let mut loading =
+                self.docker
+                    .import_image_stream(options, futures_util::stream::iter(chunks), None);
+            while let Some(progress) = loading.next().await {
+                debug!("Load {:?}", progress?);
+            }
+            Ok(())
+        })
+    }
+
+    /*
+     * Send the start and die events of the containers for as long as the receiver is there.
+     */
+    pub fn follow_events(&self, events: &Sender<ContainerEvent>) -> Result<(), DockerError> {
+        let options = EventsOptions {
+            filters: Some(HashMap::from([
+                ("type".to_string(), vec!["container".to_string()]),
+                (
+                    "event".to_string(),
+                    vec!["start".to_string(), "die".to_string()],
+                ),
+            ])),
+            ..Default::default()
+        };
+        self.runtime.block_on(async {
+            let mut stream = self.docker.events(Some(options));
+            while let Some(event) = stream.next().await {
+                let event = event?;
+                let (Some(action), Some(actor)) = (event.action, event.actor) else {
+                    continue;
+                };
+                let event = ContainerEvent {
+                    action,
+                    actor: EventActor {
+                        id: actor.id.unwrap_or_default(),
+                        attributes: actor.attributes.unwrap_or_default(),
+                    },
+                };
+                if events.send(event).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(DockerError::from("the events of the daemon ended"))
+        })
+    }
+
+    pub fn pause_container(&self, id: &str) -> Result<(), DockerError> {
+        Ok(self.runtime.block_on(self.docker.pause_container(id))?)
+    }
+
+    pub fn unpause_container(&self, id: &str) -> Result<(), DockerError> {
+        Ok(self.runtime.block_on(self.docker.unpause_container(id))?)
+    }
+
+    /*
+     * Execute a command in a running container with its output going into the writer, returning
+     * the number of bytes written.
+     */
+    pub fn exec_in_container(
+        &self,
+        id: &str,
+        command: &[&str],
+        env: &[(&str, &str)],
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        self.runtime.block_on(async {
+            let exec = self
+                .docker
+                .create_exec(
+                    id,
+                    ExecConfig {
+                        cmd: Some(command.iter().map(|arg| arg.to_string()).collect()),
+                        env: Some(
+                            env.iter()
+                                .map(|(name, value)| format!("{}={}", name, value))
+                                .collect(),
+                        ),
+                        attach_stdout: Some(true),
+                        attach_stderr: Some(true),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            let mut written = 0;
+            let mut stderr = vec![];
+            if let StartExecResults::Attached {
+                output: mut exec_output,
+                ..
+            } = self.docker.start_exec(&exec.id, None).await?
+            {
+                while let Some(message) = exec_output.next().await {
+                    match message? {
+                        LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                            output.write_all(&message)?;
+                            written += message.len() as u64;
+                        }
+                        LogOutput::StdErr { message } => stderr.extend_from_slice(&message),
+                        LogOutput::StdIn { .. } => {}
+                    }
+                }
+            }
+            let stderr = stderr_tail(
+                command.first().copied().unwrap_or("exec"),
+                stderr.as_slice(),
+            );
+            match self.docker.inspect_exec(&exec.id).await?.exit_code {
+                Some(0) | None => Ok(written),
+                Some(code) => Err(command_failed(
+                    &format!("exec {} in {}", command.join(" "), id),
+                    Some(code),
+                    &stderr,
+                )),
+            }
+        })
+    }
+
+    /*
+     * Run a helper container, streaming the input into it and its output into the writer,
+     * returning the number of bytes written. The container is removed afterwards.
+     */
+    pub fn run_helper(
+        &self,
+        helper: &Helper,
+        image: &str,
+        input: Option<&mut dyn Read>,
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        let interactive = input.is_some();
+        let config = ContainerCreateBody {
+            image: Some(image.to_string()),
+            cmd: Some(helper.command.clone()),
+            labels: Some(HashMap::from([
+                ("type".to_string(), TYPE_BACKUPCONTAINER.to_string()),
+                (HELPER_PID_LABEL.to_string(), process::id().to_string()),
+            ])),
+            attach_stdin: Some(interactive),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            open_stdin: Some(interactive),
+            stdin_once: Some(interactive),
+            host_config: Some(HostConfig {
+                volumes_from: helper.volumes_from.clone().map(|v| vec![v]),
+                binds: Some(helper.volumes.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.runtime.block_on(async {
+            let id = self
+                .create_helper(image, helper.name.as_deref(), config)
+                .await?;
+            let result = async {
+                for (file, dir) in helper.copy_in.iter() {
+                    self.copy_into(&id, file, dir).await?;
+                }
+                let written = self
+                    .attach_helper(&id, &helper.command, input, output)
+                    .await?;
+                for (file, dir) in helper.copy_out.iter() {
+                    self.copy_out_of(&id, file, dir).await?;
+                }
+                Ok::<u64, DockerError>(written)
+            }
+            .await;
+            let removed = self
+                .docker
+                .remove_container(
+                    &id,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await;
+            let written = result?;
+            removed?;
+            Ok(written)
+        })
+    }
+
+    /*
+     * Create the helper container, pulling the image when it is missing like docker run does.
+     */
+    async fn create_helper(
+        &self,
+        image: &str,
+        name: Option<&str>,
+        config: ContainerCreateBody,
+    ) -> Result<String, DockerError> {
+        let options = || CreateContainerOptions {
+            name: name.map(String::from),
+            ..Default::default()
+        };
+        match self
+            .docker
+            .create_container(Some(options()), config.clone())
+            .await
+        {
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {
+                info!("Pulling image {}", image);
+                let (from_image, tag) = split_image_tag(image);
+                let mut pull = self.docker.create_image(
+                    Some(CreateImageOptions {
+                        from_image: Some(from_image.to_string()),
+                        tag: Some(tag.to_string()),
+                        ..Default::default()
+                    }),
+                    None,
+                    None,
+                );
+                while let Some(progress) = pull.next().await {
+                    debug!("Pull {:?}", progress?);
+                }
+                Ok(self
+                    .docker
+                    .create_container(Some(options()), config)
+                    .await?
+                    .id)
+            }
+            created => Ok(created?.id),
+        }
+    }
+
+    /*
+     * Copy a host file into a directory of a container.
+     */
+    async fn copy_into(&self, id: &str, file: &Path, dir: &str) -> Result<(), DockerError> {
+        let mut archive = tar::Builder::new(vec![]);
+        archive.append_path_with_name(file, file.file_name().unwrap_or_default())?;
+        self.docker
+            .upload_to_container(
+                id,
+                Some(UploadToContainerOptions {
+                    path: dir.to_string(),
+                    ..Default::default()
+                }),
+                body_full(archive.into_inner()?.into()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /*
+     * Copy a file of a container into a host directory.
+     */
+    async fn copy_out_of(&self, id: &str, file: &str, dir: &Path) -> Result<(), DockerError> {
+        let mut download = self.docker.download_from_container(
+            id,
+            Some(DownloadFromContainerOptions {
+                path: file.to_string(),
+            }),
+        );
+        let mut archive = vec![];
+        while let Some(chunk) = download.next().await {
+            archive.extend_from_slice(&chunk?);
+        }
+        tar::Archive::new(archive.as_slice()).unpack(dir)?;
+        Ok(())
+    }
+
+    /*
+     * Attach to the helper container and run it until it exits.
+     */
+    async fn attach_helper(
+        &self,
+        id: &str,
+        command: &[String],
+        input: Option<&mut dyn Read>,
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        let attached = self
+            .docker
+            .attach_container(
+                id,
+                Some(AttachContainerOptions {
+                    stream: true,
+                    stdin: input.is_some(),
+                    stdout: true,
+                    stderr: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        self.docker.start_container(id, None).await?;
+
+        let mut container_input = attached.input;
+        let send = async {
+            let Some(input) = input else {
+                return Ok(());
+            };
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = input.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                container_input.write_all(&buffer[..read]).await?;
+            }
+            // Closing the input ends the stdin of the container.
+            container_input.shutdown().await
+        };
+        let mut container_output = attached.output;
+        let mut stderr = vec![];
+        let receive = async {
+            let mut written = 0;
+            while let Some(message) = container_output.next().await {
+                match message? {
+                    LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                        output.write_all(&message)?;
+                        written += message.len() as u64;
+                    }
+                    LogOutput::StdErr { message } => stderr.extend_from_slice(&message),
+                    LogOutput::StdIn { .. } => {}
+                }
+            }
+            Ok::<u64, DockerError>(written)
+        };
+        let (sent, received) = future::join(send, receive).await;
+        let stderr = stderr_tail("helper", stderr.as_slice());
+        let written = received?;
+        sent?;
+
+        match self.docker.wait_container(id, None).next().await {
+            Some(Err(Error::DockerContainerWaitError { code, .. })) => {
+                Err(DockerError::HelperContainerFailed {
+                    command: command.join(" "),
+                    exit: Some(code),
+                    stderr,
+                })
+            }
+            Some(Err(e)) => Err(e.into()),
+            _ => Ok(written),
+        }
+    }
+}
+
+/*
+ * Connect to the daemon like the docker cli does: the --host or the endpoint of the --context option,
+ * DOCKER_HOST (with DOCKER_TLS_VERIFY and DOCKER_CERT_PATH), the endpoint of DOCKER_CONTEXT or the
+ * current context, or else the docker socket. The rootless or system podman socket is used when
+ * there is no docker socket.
+ */
+fn connect(cli_args: &CliArguments) -> Result<Docker, DockerError> {
+    if cli_args.uses_tls() {
+        let host = cli_args
+            .host
+            .clone()
+            .or(env::var("DOCKER_HOST").ok())
+            .ok_or(
+                "--tls-ca, --tls-cert and --tls-key need a tcp:// daemon in --host or DOCKER_HOST",
+            )?;
+        return Ok(Docker::connect_with_ssl(
+            &host,
+            &tls_file(&cli_args.tls_key, "key.pem"),
+            &tls_file(&cli_args.tls_cert, "cert.pem"),
+            &tls_file(&cli_args.tls_ca, "ca.pem"),
+            TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+    if let Some(host) = &cli_args.host {
+        return Ok(Docker::connect_with_host(host)?);
+    }
+    let context = match &cli_args.context {
+        Some(context) => Some(context.clone()),
+        None if env::var_os("DOCKER_HOST").is_some() => None,
+        None => current_context(),
+    };
+    if let Some(context) = context.filter(|context| context != "default") {
+        let (endpoint, tls) = context_endpoint(&context)?;
+        debug!("Using context {} at {}", context, endpoint.host);
+        return Ok(match tls {
+            Some(tls) if !endpoint.skip_tls_verify => Docker::connect_with_ssl(
+                &endpoint.host,
+                &tls.join("key.pem"),
+                &tls.join("cert.pem"),
+                &tls.join("ca.pem"),
+                TIMEOUT_SECS,
+                API_DEFAULT_VERSION,
+            )?,
+            _ => Docker::connect_with_host(&endpoint.host)?,
+        });
+    }
+    if env::var_os("DOCKER_HOST").is_some() {
+        return Ok(Docker::connect_with_defaults()?);
+    }
+    if let Some(socket) = user_socket(cli_args) {
+        return Ok(Docker::connect_with_unix(
+            &socket.to_string_lossy(),
+            TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+    #[cfg(unix)]
+    if !Path::new(SYSTEM_SOCKET).exists() {
+        return Ok(Docker::connect_with_podman_defaults()?);
+    }
+    Ok(Docker::connect_with_local_defaults()?)
+}
+
+/*
+ * A TLS file given on the command line, or the default of the docker cli.
+ */
+fn tls_file(option: &Option<String>, name: &str) -> PathBuf {
+    match option {
+        Some(file) => PathBuf::from(file),
+        None => env::var_os("DOCKER_CERT_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(docker_config_dir())
+            .join(name),
+    }
+}
+
+/*
+ * The configuration directory of the docker cli.
+ */
+fn docker_config_dir() -> PathBuf {
+    env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or(Path::new(&env::var_os("HOME").unwrap_or_default()).join(".docker"))
+}
+
+/*
+ * The context selected with DOCKER_CONTEXT or docker context use.
+ */
+pub fn current_context() -> Option<String> {
+    if let Ok(context) = env::var("DOCKER_CONTEXT") {
+        return Some(context);
+    }
+    let config = File::open(docker_config_dir().join("config.json")).ok()?;
+    serde_json::from_reader::<_, DockerConfig>(config)
+        .ok()?
+        .current_context
+}
+
+/*
+ * The docker endpoint of a context and the directory with its TLS material, if any. The docker cli
+ * stores contexts in directories named after the sha256 of their name.
+ */
+pub fn context_endpoint(context: &str) -> Result<(ContextEndpoint, Option<PathBuf>), DockerError> {
+    let id = Sha256::digest(context.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let contexts = docker_config_dir().join("contexts");
+    let meta = File::open(contexts.join("meta").join(&id).join("meta.json"))
+        .map_err(|e| DockerError::from(format!("Context {} not found: {}", context, e).as_str()))?;
+    let endpoint = serde_json::from_reader::<_, ContextMeta>(meta)?
+        .endpoints
+        .docker;
+    let tls = contexts.join("tls").join(&id).join("docker");
+    Ok((endpoint, tls.is_dir().then_some(tls)))
+}
+
+/*
+ * Split an image into its name and tag, pulling an image without a tag pulls all its tags.
+ */
+fn split_image_tag(image: &str) -> (&str, &str) {
+    if image.contains('@') {
+        return (image, "");
+    }
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image, "latest"),
+    }
+}
+
+/*
+ * A volume of the api as a volume of the docker cli.
+ */
+fn volume_info(volume: models::Volume) -> VolumeInfo {
+    VolumeInfo {
+        name: volume.name,
+        mountpoint: volume.mountpoint,
+        driver: volume.driver,
+        options: volume.options,
+        labels: volume.labels,
+    }
+}
+
+/*
+ * A service of the api as the docker cli inspects it.
+ */
+fn service_info(service: models::Service) -> ServiceInfo {
+    let spec = service.spec.unwrap_or_default();
+    ServiceInfo {
+        id: service.id.unwrap_or_default(),
+        spec: ServiceSpec {
+            name: spec.name.unwrap_or_default(),
+            mode: ServiceMode {
+                replicated: spec
+                    .mode
+                    .and_then(|mode| mode.replicated)
+                    .map(|replicated| ReplicatedMode {
+                        replicas: replicated.replicas.unwrap_or_default() as u64,
+                    }),
+            },
+        },
+    }
+}
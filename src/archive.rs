@@ -0,0 +1,615 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone};
+use clap::ValueEnum;
+use regex::Regex;
+use std::{
+    env,
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    compression::Compression, encryption::Encryption, incremental::SNAPSHOT_DIR, types::DockerError,
+};
+
+/*
+ * The default name template, `<container><mount>-<date>T<time>` like
+ * web_data-2026-10-15T031500, an archive per run so the retention rules have runs to choose from.
+ */
+pub const DEFAULT_NAME_TEMPLATE: &str = "{container}{mount}-{date}T{time}";
+
+/*
+ * The name template of the nested layout, `<container>/<mount>/<date>T<time>` like
+ * web/_data/2026-10-15T031500.
+ */
+const NESTED_NAME_TEMPLATE: &str = "{container}/{mount}/{date}T{time}";
+
+/*
+ * How the archives are laid out in the output directory.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Layout {
+    /// An archive per mount and run in the output directory: web_data-2026-10-15T031500.tar
+    Flat,
+    /// A directory per container and mount with an archive per run:
+    /// web/_data/2026-10-15T031500.tar
+    Nested,
+}
+
+impl Layout {
+    pub fn template(&self) -> NameTemplate {
+        let template = match self {
+            Layout::Flat => DEFAULT_NAME_TEMPLATE,
+            Layout::Nested => NESTED_NAME_TEMPLATE,
+        };
+        NameTemplate::parse(template).expect("the name template of a layout is valid")
+    }
+}
+
+/*
+ * The name of an archive: `<base>[.<level>]<compression><encryption>`, the base is made with the
+ * name template.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveName {
+    pub base: String,
+    pub level: Option<u32>,
+    pub compression: Compression,
+    pub encryption: Option<Encryption>,
+}
+
+impl ArchiveName {
+    pub fn new(base: String, compression: Compression, encryption: Option<Encryption>) -> Self {
+        ArchiveName {
+            base,
+            level: None,
+            compression,
+            encryption,
+        }
+    }
+
+    /*
+     * The filename of the archive.
+     */
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            self.base,
+            self.level.map(|l| format!(".{}", l)).unwrap_or_default(),
+            self.compression.extension(),
+            self.encryption.map(|e| e.extension()).unwrap_or_default()
+        )
+    }
+
+    /*
+     * Parse an archive filename, None if it is not an archive.
+     */
+    pub fn parse(file_name: &str) -> Option<ArchiveName> {
+        let (name, encryption) = Encryption::split_archive_name(file_name);
+        let compression = Compression::from_archive_name(name)?;
+        let name = name.strip_suffix(compression.extension())?;
+        let (base, level) = match name.rsplit_once('.') {
+            Some((base, level))
+                if !level.is_empty() && level.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                (base, level.parse().ok())
+            }
+            _ => (name, None),
+        };
+        Some(ArchiveName {
+            base: base.to_string(),
+            level,
+            compression,
+            encryption,
+        })
+    }
+}
+
+/*
+ * What to do when an archive with the same name already exists in the output directory.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OnConflict {
+    /// Fail the backup of the mount and keep the existing archive
+    Fail,
+    /// Replace the existing archive
+    Overwrite,
+    /// Add .~1, .~2, ... to the name of the new archive
+    Suffix,
+}
+
+/*
+ * The variables of the name template.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    Container,
+    Mount,
+    Date,
+    Time,
+    RunId,
+    Hostname,
+}
+
+impl Variable {
+    const ALL: [Variable; 6] = [
+        Variable::Container,
+        Variable::Mount,
+        Variable::Date,
+        Variable::Time,
+        Variable::RunId,
+        Variable::Hostname,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Variable::Container => "container",
+            Variable::Mount => "mount",
+            Variable::Date => "date",
+            Variable::Time => "time",
+            Variable::RunId => "run-id",
+            Variable::Hostname => "hostname",
+        }
+    }
+
+    /*
+     * Whether the value differs per run, the archives of a mount only differ in these.
+     */
+    fn per_run(&self) -> bool {
+        matches!(self, Variable::Date | Variable::Time | Variable::RunId)
+    }
+
+    /*
+     * The regular expression matching the values of the variable.
+     */
+    fn pattern(&self) -> &'static str {
+        match self {
+            Variable::Date => r"\d{4}-\d{2}-\d{2}",
+            Variable::Time => r"\d{6}",
+            Variable::RunId => "[0-9a-f]{12}",
+            Variable::Mount => "_[^/]*?",
+            _ => "[^/]+?",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+/*
+ * The template the archive names are made with, the variables in braces are replaced per archive
+ * and a / puts the archives in subdirectories of the output directory.
+ */
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+/*
+ * The values of the variables that are the same for all archives of a run.
+ */
+pub struct RunValues {
+    date: String,
+    time: String,
+    run_id: String,
+    hostname: String,
+}
+
+impl RunValues {
+    pub fn new(timestamp: &DateTime<Local>, run_id: &str, template: &NameTemplate) -> Self {
+        RunValues {
+            date: timestamp.format("%Y-%m-%d").to_string(),
+            time: timestamp.format("%H%M%S").to_string(),
+            run_id: run_id.to_string(),
+            hostname: if template.uses(Variable::Hostname) {
+                hostname()
+            } else {
+                String::new()
+            },
+        }
+    }
+}
+
+impl NameTemplate {
+    /*
+     * Parse a name template, a trailing .tar is left out as the extension is added per archive.
+     */
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut rest = template.strip_suffix(".tar").unwrap_or(template);
+        let mut parts = vec![];
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = start + rest[start..].find('}').ok_or("has an unclosed {")?;
+            let name = &rest[start + 1..end];
+            let variable = Variable::ALL
+                .into_iter()
+                .find(|v| v.name() == name)
+                .ok_or(format!(
+                    "has an unknown variable {{{}}}, use {}",
+                    name,
+                    Variable::ALL
+                        .map(|v| format!("{{{}}}", v.name()))
+                        .join(", ")
+                ))?;
+            parts.push(Part::Variable(variable));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        let template = NameTemplate { parts };
+        if template.literals().any(|l| l.contains('}')) {
+            return Err("has an unopened }".to_string());
+        }
+        if !template.uses(Variable::Container) || !template.uses(Variable::Mount) {
+            return Err("needs {container} and {mount} to name every archive apart".to_string());
+        }
+        let relative = template.literals().all(|l| !l.contains('\\'))
+            && !matches!(template.parts.first(), Some(Part::Literal(l)) if l.starts_with('/'))
+            && template.literals().all(|l| l.split('/').all(|s| s != ".."));
+        if !relative {
+            return Err("must be a relative path inside the output directory".to_string());
+        }
+        Ok(template)
+    }
+
+    /*
+     * The base name of the archive of a mount of a container in a run.
+     */
+    pub fn render(
+        &self,
+        container: &str,
+        destination: &str,
+        values: &RunValues,
+    ) -> Result<String, DockerError> {
+        let base: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Variable(Variable::Container) => container.to_string(),
+                Part::Variable(Variable::Mount) => sanitize(destination),
+                Part::Variable(Variable::Date) => values.date.clone(),
+                Part::Variable(Variable::Time) => values.time.clone(),
+                Part::Variable(Variable::RunId) => values.run_id.clone(),
+                Part::Variable(Variable::Hostname) => values.hostname.clone(),
+            })
+            .collect();
+        // A name ending in .<digits> would be read back as an incremental archive.
+        if ArchiveName::parse(&format!("{}.tar", base)).is_none_or(|name| name.base != base) {
+            return Err(DockerError::from(
+                format!("Archive name {} can not be told apart from an incremental archive, change the name template", base).as_str(),
+            ));
+        }
+        Ok(base)
+    }
+
+    /*
+     * Whether a base name was made with this template for the container and mount, any when None.
+     */
+    pub fn matches(&self, base: &str, container: Option<&str>, destination: Option<&str>) -> bool {
+        self.regex(container, destination).is_match(base)
+    }
+
+    /*
+     * The base name with the per run variables left in, which is the same for all runs. The
+     * incremental chains and the retention rules go by it. None if the base name was not made with
+     * this template.
+     */
+    pub fn identity(&self, base: &str) -> Option<String> {
+        let captures = self.regex(None, None).captures(base)?;
+        let mut identity = String::new();
+        let mut end = 0;
+        let per_run = self.variables().filter(|v| v.per_run());
+        for (variable, capture) in per_run.zip(captures.iter().skip(1).flatten()) {
+            identity.push_str(&base[end..capture.start()]);
+            identity.push_str(&format!("{{{}}}", variable.name()));
+            end = capture.end();
+        }
+        let suffix = captures.name("suffix").map_or(base.len(), |s| s.start());
+        identity.push_str(&base[end..suffix]);
+        Some(identity)
+    }
+
+    /*
+     * Whether the names tell the runs apart by their date or time, so a run does not replace the
+     * archive of the run before it.
+     */
+    pub fn has_timestamp(&self) -> bool {
+        self.uses(Variable::Date) || self.uses(Variable::Time)
+    }
+
+    /*
+     * The start of the run a base name was made in, from its {date} and {time}, midnight when
+     * there is no {time}. None without a {date} or if the base name was not made with this
+     * template.
+     */
+    pub fn timestamp(&self, base: &str) -> Option<DateTime<Local>> {
+        let captures = self.regex(None, None).captures(base)?;
+        let values = self
+            .variables()
+            .filter(|v| v.per_run())
+            .zip(captures.iter().skip(1).flatten())
+            .map(|(variable, capture)| (variable, capture.as_str()))
+            .collect::<Vec<_>>();
+        let value = |variable| values.iter().find(|(v, _)| *v == variable).map(|(_, c)| *c);
+        let date = NaiveDate::parse_from_str(value(Variable::Date)?, "%Y-%m-%d").ok()?;
+        let time = match value(Variable::Time) {
+            Some(time) => NaiveTime::parse_from_str(time, "%H%M%S").ok()?,
+            None => NaiveTime::MIN,
+        };
+        Local.from_local_datetime(&date.and_time(time)).earliest()
+    }
+
+    /*
+     * Split the path of an archive into the directory the template names it in and the name
+     * relative to that directory.
+     */
+    pub fn split_path(&self, archive: &Path) -> (PathBuf, String) {
+        let dir = archive
+            .ancestors()
+            .nth(self.depth() + 1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let name = archive
+            .strip_prefix(dir)
+            .unwrap_or(archive)
+            .iter()
+            .map(|c| c.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        (dir.to_path_buf(), name)
+    }
+
+    /*
+     * The archives in the output directory with the name relative to it, in the subdirectories
+     * the template puts them in.
+     */
+    pub fn find_archives(
+        &self,
+        output_dir: &Path,
+    ) -> Result<Vec<(String, ArchiveName, PathBuf, Metadata)>, DockerError> {
+        let mut archives = vec![];
+        let mut dirs = vec![(output_dir.to_path_buf(), String::new(), 0)];
+        while let Some((dir, prefix, depth)) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                let Ok(file_name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let name = format!("{}{}", prefix, file_name);
+                if metadata.is_dir() {
+                    if depth < self.depth() && file_name != SNAPSHOT_DIR {
+                        dirs.push((entry.path(), format!("{}/", name), depth + 1));
+                    }
+                } else if let Some(archive) = metadata
+                    .is_file()
+                    .then(|| ArchiveName::parse(&name))
+                    .flatten()
+                {
+                    archives.push((name, archive, entry.path(), metadata));
+                }
+            }
+        }
+        Ok(archives)
+    }
+
+    /*
+     * The number of subdirectories the archives are put in.
+     */
+    fn depth(&self) -> usize {
+        self.literals().map(|l| l.matches('/').count()).sum()
+    }
+
+    fn uses(&self, variable: Variable) -> bool {
+        self.variables().any(|v| v == variable)
+    }
+
+    fn variables(&self) -> impl Iterator<Item = Variable> + '_ {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Variable(variable) => Some(*variable),
+            Part::Literal(_) => None,
+        })
+    }
+
+    fn literals(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Literal(literal) => Some(literal.as_str()),
+            Part::Variable(_) => None,
+        })
+    }
+
+    /*
+     * The regular expression matching the base names made with this template, capturing the per
+     * run variables and the suffix added on a conflict.
+     */
+    fn regex(&self, container: Option<&str>, destination: Option<&str>) -> Regex {
+        let pattern: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => regex::escape(literal),
+                Part::Variable(Variable::Container) if container.is_some() => {
+                    regex::escape(container.unwrap_or_default())
+                }
+                Part::Variable(Variable::Mount) if destination.is_some() => {
+                    regex::escape(&sanitize(destination.unwrap_or_default()))
+                }
+                Part::Variable(variable) if variable.per_run() => {
+                    format!("({})", variable.pattern())
+                }
+                Part::Variable(variable) => format!("(?:{})", variable.pattern()),
+            })
+            .collect();
+        Regex::new(&format!(r"^{}(?P<suffix>\.~\d+)?$", pattern))
+            .expect("escaped name template is a valid regex")
+    }
+}
+
+/*
+ * The name of this host: from the environment, or else the hostname command.
+ */
+pub fn hostname() -> String {
+    env::var("HOSTNAME")
+        .or(env::var("COMPUTERNAME"))
+        .ok()
+        .filter(|h| !h.is_empty())
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            let hostname = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !hostname.is_empty()).then_some(hostname)
+        })
+        .unwrap_or("localhost".to_string())
+}
+
+/*
+ * Sanitize a path into part of the backup filename.
+ */
+fn sanitize(s: &str) -> String {
+    s.replace('/', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str) -> NameTemplate {
+        NameTemplate::parse(template).unwrap()
+    }
+
+    fn values() -> RunValues {
+        RunValues {
+            date: "2026-10-15".to_string(),
+            time: "031500".to_string(),
+            run_id: "0123456789ab".to_string(),
+            hostname: "nas".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_the_name_templates() {
+        assert_eq!(
+            template("{container}{mount}.tar").parts,
+            [
+                Part::Variable(Variable::Container),
+                Part::Variable(Variable::Mount)
+            ]
+        );
+        assert_eq!(template(NESTED_NAME_TEMPLATE).depth(), 2);
+        for invalid in [
+            "{container}",
+            "{mount}-{date}",
+            "{container}{mount}{week}",
+            "{container}{mount",
+            "{container}}{mount}",
+            "/backups/{container}{mount}",
+            "../{container}{mount}",
+            "{container}/../{mount}",
+            "{container}\\{mount}",
+        ] {
+            assert!(NameTemplate::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn renders_the_names() {
+        let values = values();
+        let render = |t: &str| template(t).render("web", "/data", &values).unwrap();
+        assert_eq!(render(DEFAULT_NAME_TEMPLATE), "web_data-2026-10-15T031500");
+        assert_eq!(render(NESTED_NAME_TEMPLATE), "web/_data/2026-10-15T031500");
+        assert_eq!(
+            render("{hostname}-{container}{mount}-{run-id}"),
+            "nas-web_data-0123456789ab"
+        );
+        // Read back as level 31500 of an incremental archive.
+        let ambiguous = template("{container}{mount}.{time}");
+        assert!(ambiguous.render("web", "/data", &values).is_err());
+    }
+
+    #[test]
+    fn reads_the_identity_back() {
+        let default = template(DEFAULT_NAME_TEMPLATE);
+        let base = "web_data-2026-10-15T031500";
+        assert_eq!(
+            default.identity(base).as_deref(),
+            Some("web_data-{date}T{time}")
+        );
+        assert_eq!(
+            default.identity(&format!("{}.~2", base)).as_deref(),
+            Some("web_data-{date}T{time}")
+        );
+        assert_eq!(default.identity("web_data-latest"), None);
+        let nested = template(NESTED_NAME_TEMPLATE);
+        assert_eq!(
+            nested.identity("web/_data/2026-10-15T031500").as_deref(),
+            Some("web/_data/{date}T{time}")
+        );
+        let run_id = template("{container}{mount}-{run-id}");
+        assert_eq!(
+            run_id.identity("web_data-0123456789ab").as_deref(),
+            Some("web_data-{run-id}")
+        );
+        assert!(default.matches(base, Some("web"), Some("/data")));
+        assert!(default.matches(base, None, None));
+        assert!(!default.matches(base, Some("we"), Some("/data")));
+        assert!(!default.matches(base, Some("web"), Some("/dat")));
+        assert!(default.matches("my-web_data-2026-10-15T031500", Some("my-web"), None));
+    }
+
+    #[test]
+    fn reads_the_time_back() {
+        let taken = Local.with_ymd_and_hms(2026, 10, 15, 12, 34, 56).unwrap();
+        let default = template(DEFAULT_NAME_TEMPLATE);
+        let values = RunValues::new(&taken, "0123456789ab", &default);
+        let base = default.render("web", "/data", &values).unwrap();
+        assert_eq!(default.timestamp(&base), Some(taken));
+        assert_eq!(default.timestamp(&format!("{}.~1", base)), Some(taken));
+
+        let daily = template("{container}{mount}-{date}");
+        let base = daily.render("web", "/data", &values).unwrap();
+        assert_eq!(
+            daily.timestamp(&base),
+            Local.with_ymd_and_hms(2026, 10, 15, 0, 0, 0).earliest()
+        );
+        let run_id = template("{container}{mount}-{run-id}");
+        let base = run_id.render("web", "/data", &values).unwrap();
+        assert!(!run_id.has_timestamp());
+        assert_eq!(run_id.timestamp(&base), None);
+    }
+
+    #[test]
+    fn matches_the_suffix_of_a_conflict() {
+        let default = template(DEFAULT_NAME_TEMPLATE);
+        let base = "web_data-2026-10-15T031500";
+        for suffix in [".~1", ".~12"] {
+            assert!(default.matches(&format!("{}{}", base, suffix), None, None));
+        }
+        for suffix in [".~", ".~1x", ".~1.~2", ".1", "~1"] {
+            assert!(
+                !default.matches(&format!("{}{}", base, suffix), None, None),
+                "{}",
+                suffix
+            );
+        }
+    }
+
+    #[test]
+    fn parses_the_archive_names() {
+        let name = ArchiveName::parse("web_data-2026-10-15T031500.~1.2.tar.gz").unwrap();
+        assert_eq!(name.base, "web_data-2026-10-15T031500.~1");
+        assert_eq!(name.level, Some(2));
+        assert_eq!(name.compression, Compression::Gzip);
+        assert_eq!(name.file_name(), "web_data-2026-10-15T031500.~1.2.tar.gz");
+        assert_eq!(ArchiveName::parse("manifest.json"), None);
+        let (dir, name) = template(NESTED_NAME_TEMPLATE)
+            .split_path(Path::new("/backups/web/_data/2026-10-15T031500.tar"));
+        assert_eq!(dir, Path::new("/backups"));
+        assert_eq!(name, "web/_data/2026-10-15T031500.tar");
+    }
+}
@@ -0,0 +1,3189 @@
+use clap::Args;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archive::{ArchiveName, OnConflict, RunValues},
+    catalog::Catalog,
+    compression::Compression,
+    dump::{environment, Credentials, Database, DumpArchive, DumpMode},
+    email::{self, Email},
+    encryption::Encryption,
+    exec::{
+        check_daemon, exec_in_container, execute_pipeline_into, host_path, inspect_container,
+        inspect_service, inspect_volume, list_containers, list_volumes, pipe, remote_daemon, retry,
+        run_helper_into, save_image, unpause_container, Helper, SuspendedContainer,
+    },
+    hooks::{host_hook, run_hook, Hook, HookFailure},
+    incremental::Incremental,
+    labels::{ContainerLabels, ENABLE_LABEL},
+    logging,
+    manifest::{
+        ContainerManifest, ContainerSpec, Manifest, MountManifest, MountStatus, VolumeSpec,
+    },
+    metrics,
+    notify::{self, NotifyOn},
+    progress,
+    prune::{apply_retention, RetentionArguments},
+    redis::{self, RedisSnapshot},
+    report::{self, human_size},
+    storage::{free_space, ArchiveWriter, Destination},
+    trace,
+    types::{is_anonymous_volume, ContainerInfo, DockerError, MountType, Mounts, PsInfo},
+    CliArguments, COMPOSE_DEPENDS_ON_LABEL, COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL,
+    SWARM_SERVICE_LABEL, TYPE_BACKUPCONTAINER,
+};
+
+/// The options for the archives, shared by the backups of containers and of volumes.
+#[derive(Args, Clone)]
+pub struct ArchiveArguments {
+    /// Compress the archives on the host
+    #[arg(long, value_enum, default_value = "none")]
+    compress: Compression,
+
+    /// The compression level, defaults to the level of the compressor
+    #[arg(long)]
+    compress_level: Option<u32>,
+
+    /// Encrypt the archives with age for this recipient (key or recipients file), can be repeated
+    #[arg(long)]
+    encrypt_age_recipient: Vec<String>,
+
+    /// Encrypt the archives with gpg for this key id, can be repeated
+    #[arg(long, conflicts_with = "encrypt_age_recipient")]
+    encrypt_gpg_recipient: Vec<String>,
+
+    /// Leave the files matching this glob out of the archives (tar --exclude), like node_modules or
+    /// '*.tmp', can be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude_pattern: Vec<String>,
+
+    /// Only archive this path of the mount it lies in, like /data/config out of the /data mount,
+    /// can be repeated. The other mounts are archived completely
+    #[arg(long, value_name = "PATH")]
+    include_path: Vec<String>,
+
+    /// Print the containers and mounts that would be backed up, which containers would be stopped
+    /// and the archives with their estimated sizes, without stopping or writing anything
+    #[arg(long, default_value = "false", conflicts_with = "stdout")]
+    dry_run: bool,
+
+    /// Estimate the size of every mount before archiving it, from its source on the host or else
+    /// with du in a helper container, and record it next to the size of the archive
+    #[arg(long, default_value = "false")]
+    estimate_size: bool,
+
+    /// Only warn when the output directory has less free space than the estimated size of the
+    /// backup, instead of aborting before anything is stopped
+    #[arg(long, default_value = "false")]
+    force: bool,
+
+    /// Remove the helper container archiving a mount when it runs longer than this many minutes,
+    /// like a tar hanging on a dead NFS mount. The mount counts as failed
+    #[arg(long, value_name = "MINUTES")]
+    mount_timeout: Option<u64>,
+
+    /// What to do when an archive with the same name already exists in the output directory
+    #[arg(long, value_enum, default_value = "suffix")]
+    on_conflict: OnConflict,
+
+    /// Make incremental archives using tar snapshots
+    #[arg(long, default_value = "false")]
+    incremental: bool,
+
+    /// Start a new chain with a full archive after this many incremental archives
+    #[arg(long, default_value = "7")]
+    full_every: u32,
+
+    /// Delete the expired archives after the backup according to the retention rules
+    #[arg(long, default_value = "false")]
+    prune_after_backup: bool,
+
+    #[command(flatten)]
+    retention: RetentionArguments,
+
+    /// Stream the archives of all mounts as a single tar to stdout, each container in a directory
+    /// with its name. Extract it with tar --ignore-zeros
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["incremental", "prune_after_backup", "encrypt_age_recipient", "encrypt_gpg_recipient"]
+    )]
+    stdout: bool,
+
+    /// Write the result of the run as json to this file, or to stdout for -: its status and per
+    /// container and mount the status, size, duration, archive and error
+    #[arg(long, value_name = "PATH")]
+    report_json: Option<String>,
+
+    /// Write the metrics of the run to this .prom file for the textfile collector of
+    /// node_exporter: the time of the run, whether it and every container succeeded, the bytes
+    /// written and the duration
+    #[arg(long, value_name = "PATH")]
+    metrics_textfile: Option<String>,
+
+    /// Push the metrics of the run to this Pushgateway, like http://pushgateway:9091, for hosts
+    /// without node_exporter. The instance label is the name of this host
+    #[arg(long, value_name = "URL")]
+    pushgateway: Option<String>,
+
+    /// The job label of the metrics pushed to the Pushgateway
+    #[arg(long, value_name = "NAME", default_value = "volbackup")]
+    pushgateway_job: String,
+
+    /// Ping this healthchecks.io check (or any dead man's switch taking the same urls): URL/start
+    /// when the run begins, URL when it succeeded and URL/fail otherwise, with the last log lines
+    #[arg(long, value_name = "URL")]
+    healthcheck_url: Option<String>,
+
+    /// Post the result of the run as json to this webhook, can be repeated. The body is the
+    /// report of --report-json unless --webhook-template is given
+    #[arg(long, value_name = "URL")]
+    webhook_url: Vec<String>,
+
+    /// A file with the body of the webhooks, in which {status}, {run_id}, {hostname},
+    /// {timestamp}, {duration_secs}, {succeeded}, {failed}, {error} and {summary} are replaced
+    /// by their values escaped for a json string and {report} by the report
+    #[arg(long, value_name = "FILE")]
+    webhook_template: Option<String>,
+
+    /// Post the summary of the run to this Slack incoming webhook, can be repeated
+    #[arg(long, value_name = "URL")]
+    slack_webhook: Vec<String>,
+
+    /// Post the summary of the run to this Discord webhook, can be repeated
+    #[arg(long, value_name = "URL")]
+    discord_webhook: Vec<String>,
+
+    /// Publish a short message with the result of the run to this ntfy topic, like
+    /// https://ntfy.sh/mytopic, can be repeated
+    #[arg(long, value_name = "URL")]
+    ntfy_topic: Vec<String>,
+
+    /// After which runs the webhooks, Slack, Discord and ntfy are notified
+    #[arg(long, value_enum, default_value = "always")]
+    notify_on: NotifyOn,
+
+    /// Mail the summary of the run with the report attached through this SMTP server,
+    /// smtps://host:465 for TLS or smtp://host:587 for STARTTLS
+    #[arg(long, value_name = "URL", requires_all = ["email_from", "email_to"])]
+    smtp_url: Option<String>,
+
+    /// The user to log in to the SMTP server, with the password from SMTP_PASSWORD
+    #[arg(long, value_name = "USER")]
+    smtp_user: Option<String>,
+
+    /// The sender of the mail
+    #[arg(long, value_name = "ADDRESS")]
+    email_from: Option<String>,
+
+    /// The recipient of the mail, can be repeated
+    #[arg(long, value_name = "ADDRESS")]
+    email_to: Vec<String>,
+
+    /// After which runs the mail is sent, like only after failures for quiet nights
+    #[arg(long, value_enum, default_value = "always")]
+    email_on: NotifyOn,
+
+    /// Export the trace of the run, with spans for the containers, mounts and commands, to this
+    /// OpenTelemetry collector with OTLP over HTTP, like http://collector:4318
+    #[arg(long, value_name = "URL", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// The service name of the exported trace
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "OTEL_SERVICE_NAME",
+        default_value = "volbackup"
+    )]
+    otlp_service_name: String,
+}
+
+/// Backup all mounted volumes of the running containers.
+#[derive(Args, Clone)]
+pub struct BackupArguments {
+    /// Stop the container before backup and restart it afterwards
+    #[arg(short, long, default_value = "false")]
+    stop_start: bool,
+
+    /// Stop all containers of a compose project together, back them up and start them again, so
+    /// the archives of its services are consistent with each other. Containers outside a project
+    /// are stopped around their own backup
+    #[arg(long, default_value = "false", conflicts_with = "pause")]
+    stop_stack: bool,
+
+    /// Scale the swarm service of the task containers on this node to 0 replicas for their backup
+    /// and back again, instead of stopping the task containers, which swarm would replace. Needs
+    /// a swarm manager
+    #[arg(long, default_value = "false")]
+    swarm_scale: bool,
+
+    /// Pause the container before backup and unpause it afterwards, which freezes its processes
+    /// for the backup without the downtime of a restart
+    #[arg(long, default_value = "false", conflicts_with = "stop_start")]
+    pause: bool,
+
+    /// Unpause a paused container after this many seconds, also when its backup is not finished
+    #[arg(long, value_name = "SECONDS", default_value = "300")]
+    pause_timeout: u64,
+
+    #[command(flatten)]
+    archive: ArchiveArguments,
+
+    /// Only back up the containers with this name, or with a name matching this regular
+    /// expression as a whole like pg-.*, can be repeated
+    #[arg(long, value_name = "NAME_OR_REGEX")]
+    include: Vec<String>,
+
+    /// Skip the containers with this name, or with a name matching this regular expression as a
+    /// whole, can be repeated
+    #[arg(long, value_name = "NAME_OR_REGEX")]
+    exclude: Vec<String>,
+
+    /// Also back up the stopped containers, they are not started for the backup
+    #[arg(short, long, default_value = "false")]
+    all: bool,
+
+    /// Only back up the containers with this label (key or key=value), can be repeated to require
+    /// several labels
+    #[arg(long, value_name = "LABEL")]
+    filter_label: Vec<String>,
+
+    /// Only back up the containers with the volbackup.enable=true label, without it only the
+    /// containers with volbackup.enable=false are skipped
+    #[arg(long, default_value = "false")]
+    opt_in: bool,
+
+    /// Only back up the containers of this compose project, into a subdirectory named after it.
+    /// With --stop-start the whole project is stopped during the backup
+    #[arg(long)]
+    project: Option<String>,
+
+    /// Only back up the mounts of these types, separated by commas [default: all types]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    mount_types: Vec<MountType>,
+
+    /// Also back up anonymous volumes, they are usually caches recreated with the container
+    #[arg(long, default_value = "false")]
+    include_anonymous: bool,
+
+    /// The number of containers to back up at the same time
+    #[arg(short, long, default_value = "1", conflicts_with = "stdout")]
+    jobs: NonZeroUsize,
+
+    /// The number of mounts of a container to back up at the same time
+    #[arg(long, default_value = "1", conflicts_with = "stdout")]
+    mount_jobs: NonZeroUsize,
+
+    /// Give up on the mounts of a container that are not archived after this many minutes, the
+    /// helper container archiving a mount is removed at that time
+    #[arg(long, value_name = "MINUTES")]
+    container_timeout: Option<u64>,
+
+    /// A command run on the host with the shell before the backup of every container, with
+    /// VOLBACKUP_HOOK_CONTAINER set to the name of the container
+    #[arg(long, value_name = "COMMAND")]
+    pre_hook: Option<String>,
+
+    /// A command run on the host with the shell after the backup of every container, with
+    /// VOLBACKUP_HOOK_STATUS set to success or failed
+    #[arg(long, value_name = "COMMAND")]
+    post_hook: Option<String>,
+
+    /// What to do when a hook fails
+    #[arg(long, value_enum, default_value = "abort")]
+    on_hook_failure: HookFailure,
+
+    /// Dump the databases of PostgreSQL containers with pg_dumpall, as tarring a running database
+    /// is unsafe. The containers are recognized by their image or the volbackup.pg-dump label
+    #[arg(long, value_enum, default_value = "off")]
+    pg_dump: DumpMode,
+
+    /// Dump the databases of MySQL and MariaDB containers with mysqldump --single-transaction.
+    /// The containers are recognized by their image or the volbackup.mysql-dump label
+    #[arg(long, value_enum, default_value = "off")]
+    mysql_dump: DumpMode,
+
+    /// Dump the databases of MongoDB containers with mongodump --archive. The containers are
+    /// recognized by their image or the volbackup.mongo-dump label
+    #[arg(long, value_enum, default_value = "off")]
+    mongo_dump: DumpMode,
+
+    /// Let Redis and Valkey containers save their dump.rdb before archiving their data volume, so
+    /// it is current. The containers are recognized by their image or the volbackup.redis-snapshot
+    /// label
+    #[arg(long, value_enum, default_value = "off")]
+    redis_snapshot: RedisSnapshot,
+
+    /// Also save the image of every container with docker save into images/<id>.tar, once per
+    /// image, so it can be restored without a registry
+    #[arg(long, default_value = "false", conflicts_with = "stdout")]
+    include_image: bool,
+}
+
+/// Backup named volumes straight from the volumes, also those not used by any container.
+#[derive(Args, Clone)]
+pub struct BackupVolumeArguments {
+    /// The volumes to back up, defaults to all volumes listed by docker volume ls
+    volumes: Vec<String>,
+
+    /// Also back up anonymous volumes when backing up all volumes
+    #[arg(long, default_value = "false")]
+    include_anonymous: bool,
+
+    #[command(flatten)]
+    archive: ArchiveArguments,
+
+    /// The number of volumes to back up at the same time
+    #[arg(short, long, default_value = "1", conflicts_with = "stdout")]
+    jobs: NonZeroUsize,
+}
+
+/*
+ * Where a volume is mounted in the helper container for a backup of the volume.
+ */
+const VOLUME_MOUNT: &str = "/volume";
+
+/*
+ * The directory of the images saved with --include-image, in the output directory.
+ */
+const IMAGE_DIR: &str = "images";
+
+impl ArchiveArguments {
+    /*
+     * Check the options that clap can not check.
+     */
+    fn validate(&self, cli_args: &CliArguments) -> Result<(), DockerError> {
+        self.compress.validate_level(self.compress_level)?;
+        if self.prune_after_backup && !self.retention.is_configured() {
+            return Err(DockerError::Config(
+                "--prune-after-backup requires a retention rule".to_string(),
+            ));
+        }
+        if self.prune_after_backup && !cli_args.name_template().has_timestamp() {
+            return Err(DockerError::Config(
+                "--prune-after-backup requires a name template with {date} or {time}".to_string(),
+            ));
+        }
+        if self.stdout && self.report_json.as_deref() == Some("-") {
+            return Err(DockerError::Config(
+                "--report-json - can not be used with --stdout".to_string(),
+            ));
+        }
+        if self.stdout && cli_args.dest.is_some() {
+            return Err(DockerError::Config(
+                "--stdout can not be used with --dest".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /*
+     * The options of the dry run estimating the size of the backup before it starts.
+     */
+    fn sizing(&self) -> Self {
+        ArchiveArguments {
+            dry_run: true,
+            ..self.clone()
+        }
+    }
+
+    /*
+     * The paths to archive of the mount at the destination, only the included paths in it or else
+     * the whole mount.
+     */
+    fn archived_paths(&self, destination: &str) -> Vec<String> {
+        let destination = destination.trim_end_matches('/');
+        let paths: Vec<String> = self
+            .include_path
+            .iter()
+            .map(|path| path.trim_end_matches('/'))
+            .filter(|path| {
+                path.strip_prefix(destination)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map(String::from)
+            .collect();
+        if paths.is_empty() {
+            vec![destination.to_string()]
+        } else {
+            paths
+        }
+    }
+
+    /*
+     * The encryption to apply to the archives and its recipients, if any.
+     */
+    fn encryption(&self) -> Option<(Encryption, &[String])> {
+        if !self.encrypt_age_recipient.is_empty() {
+            Some((Encryption::Age, &self.encrypt_age_recipient))
+        } else if !self.encrypt_gpg_recipient.is_empty() {
+            Some((Encryption::Gpg, &self.encrypt_gpg_recipient))
+        } else {
+            None
+        }
+    }
+}
+
+impl BackupArguments {
+    /*
+     * How the databases are dumped for the containers recognized by their image.
+     */
+    fn dump_mode(&self, database: Database) -> DumpMode {
+        match database {
+            Database::Postgres => self.pg_dump,
+            Database::Mysql => self.mysql_dump,
+            Database::Mongodb => self.mongo_dump,
+        }
+    }
+
+    /*
+     * Whether the containers of a compose project are stopped and started as a whole, instead of
+     * every container around its own backup.
+     */
+    fn stops_stacks(&self) -> bool {
+        self.stop_stack || (self.stop_start && self.project.is_some())
+    }
+
+    /*
+     * The containers selected for the backup by their labels, project and name.
+     */
+    pub fn containers(&self, cli_args: &CliArguments) -> Result<Vec<PsInfo>, DockerError> {
+        let ps_info = self.containers_with_label(None, cli_args)?;
+        select_containers(ps_info, self)
+    }
+
+    /*
+     * The containers with the labels of the backup and, when given, another label.
+     */
+    pub fn containers_with_label(
+        &self,
+        label: Option<&str>,
+        cli_args: &CliArguments,
+    ) -> Result<Vec<PsInfo>, DockerError> {
+        let mut labels = self.filter_label.clone();
+        if let Some(project) = &self.project {
+            labels.push(format!("{}={}", COMPOSE_PROJECT_LABEL, project));
+        }
+        labels.extend(label.map(str::to_string));
+        list_containers(cli_args, self.all, &labels)
+    }
+
+    /*
+     * The arguments for the backup of only this container, also when it is stopped. None when the
+     * container is not selected for the backup.
+     */
+    pub fn only(
+        &self,
+        name: &str,
+        cli_args: &CliArguments,
+    ) -> Result<Option<BackupArguments>, DockerError> {
+        let mut backup_args = self.clone();
+        backup_args.all = true;
+        let selected = logging::quietly(|| backup_args.containers(cli_args))?
+            .iter()
+            .any(|ps_info| ps_info.names == name);
+        backup_args.include = vec![name.to_string()];
+        Ok(selected.then_some(backup_args))
+    }
+
+    /*
+     * The arguments backing up only these containers, or the same containers when there are none.
+     */
+    pub fn including(&self, names: Vec<String>) -> BackupArguments {
+        let mut backup_args = self.clone();
+        if !names.is_empty() {
+            backup_args.include = names;
+        }
+        backup_args
+    }
+
+    /*
+     * The arguments skipping these containers as well.
+     */
+    pub fn excluding(&self, names: impl IntoIterator<Item = String>) -> BackupArguments {
+        let mut backup_args = self.clone();
+        backup_args.exclude.extend(names);
+        backup_args
+    }
+}
+
+/*
+ * How a backup run went: all containers or volumes were backed up, some of them failed or all of
+ * them failed.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Partial,
+    Failed,
+}
+
+impl Outcome {
+    fn of(succeeded: usize, failed: usize) -> Self {
+        match (succeeded, failed) {
+            (_, 0) => Outcome::Success,
+            (0, _) => Outcome::Failed,
+            _ => Outcome::Partial,
+        }
+    }
+}
+
+/*
+ * Backup all running containers.
+ */
+pub fn backup(
+    backup_args: &BackupArguments,
+    cli_args: &CliArguments,
+) -> Result<Outcome, DockerError> {
+    let project_args;
+    let cli_args = match &backup_args.project {
+        Some(project) => {
+            project_args = cli_args.for_project(project);
+            &project_args
+        }
+        None => cli_args,
+    };
+    reported(&backup_args.archive, "Containers", cli_args, |run| {
+        let ps_info = backup_args.containers(cli_args)?;
+        if run.checks_free_space(&backup_args.archive) {
+            let sizing_args = BackupArguments {
+                archive: backup_args.archive.sizing(),
+                ..backup_args.clone()
+            };
+            let _span = trace::span("free space check");
+            let mut sizing = run.sizing(cli_args);
+            logging::quietly(|| backup_containers(&ps_info, &mut sizing, &sizing_args, cli_args))?;
+            sizing.check_free_space(backup_args.archive.force)?;
+        }
+        backup_containers(&ps_info, run, backup_args, cli_args)
+    })
+}
+
+/*
+ * Start a run, make the backup and finish the run, printing the summary of the containers or
+ * volumes, writing the report with --report-json and writing or pushing the metrics also when the
+ * run failed.
+ */
+fn reported(
+    archive_args: &ArchiveArguments,
+    kind: &str,
+    cli_args: &CliArguments,
+    backup: impl FnOnce(&mut BackupRun) -> Result<Outcome, DockerError>,
+) -> Result<Outcome, DockerError> {
+    let started = Instant::now();
+    let healthcheck = archive_args
+        .healthcheck_url
+        .as_ref()
+        .filter(|_| !archive_args.dry_run);
+    if let Some(url) = healthcheck {
+        notify::ping_start(url);
+    }
+    let otlp_endpoint = archive_args
+        .otlp_endpoint
+        .as_ref()
+        .filter(|_| !archive_args.dry_run);
+    if otlp_endpoint.is_some() {
+        trace::enable();
+    }
+    let mut span = trace::span("backup run");
+    span.attribute("volbackup.kind", kind);
+    let mut run = None;
+    let result = BackupRun::start(archive_args, cli_args).and_then(|started| {
+        let run = run.insert(started);
+        let outcome = backup(run)?;
+        run.finish(archive_args, cli_args)?;
+        Ok(outcome)
+    });
+    if let Some(run) = &run {
+        span.attribute("volbackup.run_id", &run.manifest.run_id);
+    }
+    match &result {
+        Ok(Outcome::Success) => {}
+        Ok(Outcome::Partial) => span.fail("Some containers failed"),
+        Ok(Outcome::Failed) => span.fail("All containers failed"),
+        Err(e) => span.fail(e),
+    }
+    drop(span);
+    if let Some(endpoint) = otlp_endpoint {
+        if let Err(e) = trace::export(endpoint, &archive_args.otlp_service_name) {
+            warn!("Could not export the trace to {}: {}", endpoint, e);
+        }
+    }
+    match run.as_ref() {
+        Some(run) if archive_args.dry_run => run.print_planned_total(),
+        Some(run) => report::print_summary(&run.manifest, kind, started),
+        None => {}
+    }
+    let manifest = run.as_ref().map(|run| &run.manifest);
+    let mut result = result;
+    if let Some(path) = &archive_args.report_json {
+        if let Err(e) = report::write(path, manifest, started, &result) {
+            error!("Could not write the report {}: {}", path, e);
+            result = result.and(Err(e));
+        }
+    }
+    // A dry run does not back up anything to report to Prometheus.
+    if archive_args.dry_run {
+        return result;
+    }
+    let metrics = metrics::render(manifest, started, &result);
+    if let Some(path) = &archive_args.metrics_textfile {
+        if let Err(e) = metrics::write_textfile(path, &metrics) {
+            error!("Could not write the metrics {}: {}", path, e);
+            result = result.and(Err(e));
+        }
+    }
+    if let Some(url) = &archive_args.pushgateway {
+        if let Err(e) = metrics::push(url, &archive_args.pushgateway_job, &metrics) {
+            error!("Could not push the metrics to {}: {}", url, e);
+            result = result.and(Err(e));
+        }
+    }
+    if let Some(url) = healthcheck {
+        notify::ping_result(url, &result);
+    }
+    let finished = notify::Finished {
+        manifest,
+        kind,
+        started,
+        result: &result,
+    };
+    if archive_args.notify_on.notifies(&result) {
+        notify::post_webhooks(
+            &archive_args.webhook_url,
+            archive_args.webhook_template.as_deref(),
+            &finished,
+            cli_args,
+        );
+        notify::post_chats(
+            &archive_args.slack_webhook,
+            &archive_args.discord_webhook,
+            &finished,
+            cli_args,
+        );
+        notify::publish_ntfy(&archive_args.ntfy_topic, &finished, cli_args);
+    }
+    if let (Some(smtp_url), Some(from)) = (&archive_args.smtp_url, &archive_args.email_from) {
+        if archive_args.email_on.notifies(&result) {
+            let email = Email {
+                smtp_url,
+                user: archive_args.smtp_user.as_deref(),
+                from,
+                to: &archive_args.email_to,
+            };
+            email::send(&email, &finished, cli_args);
+        }
+    }
+    result
+}
+
+/*
+ * Backup named volumes by mounting them into the helper container.
+ */
+pub fn backup_volumes(
+    volume_args: &BackupVolumeArguments,
+    cli_args: &CliArguments,
+) -> Result<Outcome, DockerError> {
+    reported(&volume_args.archive, "Volumes", cli_args, |run| {
+        if run.checks_free_space(&volume_args.archive) {
+            let sizing_args = BackupVolumeArguments {
+                archive: volume_args.archive.sizing(),
+                ..volume_args.clone()
+            };
+            let _span = trace::span("free space check");
+            let mut sizing = run.sizing(cli_args);
+            logging::quietly(|| backup_volume_list(&sizing_args, &mut sizing, cli_args))?;
+            sizing.check_free_space(volume_args.archive.force)?;
+        }
+        backup_volume_list(volume_args, run, cli_args)
+    })
+}
+
+/*
+ * Backup the given volumes, or all volumes.
+ */
+fn backup_volume_list(
+    volume_args: &BackupVolumeArguments,
+    run: &mut BackupRun,
+    cli_args: &CliArguments,
+) -> Result<Outcome, DockerError> {
+    let volumes = if volume_args.volumes.is_empty() {
+        list_volumes(cli_args)?
+            .into_iter()
+            .filter(|volume| {
+                let skipped = !volume_args.include_anonymous && is_anonymous_volume(&volume.name);
+                if skipped {
+                    info!("[{}] Skipping this anonymous volume", volume.name);
+                }
+                !skipped
+            })
+            .collect::<Vec<_>>()
+    } else {
+        volume_args
+            .volumes
+            .iter()
+            .map(|name| {
+                inspect_volume(cli_args, name)?.ok_or(DockerError::from(
+                    format!("Volume {} not found", name).as_str(),
+                ))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    info!(
+        "Found volumes: {:?}",
+        volumes
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect::<Vec<&str>>()
+    );
+
+    let shared_run: &BackupRun = run;
+    let manifests = run_parallel(&volumes, volume_args.jobs, |volume| {
+        let source = Helper {
+            volumes: vec![format!("{}:{}:ro", volume.name, VOLUME_MOUNT)],
+            ..Default::default()
+        };
+        let mount = Mounts {
+            kind: Some(MountType::Volume),
+            name: Some(volume.name.clone()),
+            source: volume.mountpoint.clone(),
+            destination: VOLUME_MOUNT.to_string(),
+            rw: true,
+        };
+        if volume_args.archive.dry_run {
+            print_plan(
+                &volume.name,
+                None,
+                &source,
+                &[&mount],
+                shared_run,
+                &volume_args.archive,
+                cli_args,
+            );
+            return Ok(ContainerManifest {
+                name: volume.name.clone(),
+                id: volume.name.clone(),
+                mounts: vec![],
+                error: None,
+                container: None,
+                inspect: None,
+                image_archive: None,
+            });
+        }
+        let mount_manifest = backup_mount(
+            &volume.name,
+            &source,
+            &mount,
+            shared_run,
+            &volume_args.archive,
+            cli_args,
+        );
+        Ok(ContainerManifest {
+            name: volume.name.clone(),
+            id: volume.name.clone(),
+            mounts: vec![mount_manifest],
+            error: None,
+            container: None,
+            inspect: None,
+            image_archive: None,
+        })
+    })?;
+    let failed = manifests.iter().filter(|c| !c.succeeded()).count();
+    let outcome = Outcome::of(manifests.len() - failed, failed);
+    run.manifest.containers.extend(manifests);
+    Ok(outcome)
+}
+
+/*
+ * The containers selected by the --include and --exclude filters.
+ */
+fn select_containers(
+    ps_info: Vec<PsInfo>,
+    backup_args: &BackupArguments,
+) -> Result<Vec<PsInfo>, DockerError> {
+    let include = NameFilter::new(&backup_args.include)?;
+    let exclude = NameFilter::new(&backup_args.exclude)?;
+    Ok(ps_info
+        .into_iter()
+        .filter(|ps_info| {
+            let selected = (backup_args.include.is_empty() || include.matches(&ps_info.names))
+                && !exclude.matches(&ps_info.names);
+            if !selected {
+                info!(
+                    "[{}] Skipping this container as it is filtered out",
+                    ps_info.names
+                );
+            }
+            selected
+        })
+        .collect())
+}
+
+/*
+ * Container names or regular expressions. A pattern that is a valid container name only matches
+ * that name, a regular expression has to match the whole name, so pg-* is not taken for anything
+ * with pg in it.
+ */
+struct NameFilter {
+    names: Vec<String>,
+    regexes: Vec<Regex>,
+}
+
+impl NameFilter {
+    fn new(patterns: &[String]) -> Result<Self, DockerError> {
+        let is_name = |pattern: &String| {
+            pattern
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+        };
+        let (names, regexes): (Vec<String>, Vec<String>) =
+            patterns.iter().cloned().partition(is_name);
+        let regexes = regexes
+            .iter()
+            .map(|regex| {
+                Regex::new(&format!("^(?:{})$", regex))
+                    .map_err(|e| DockerError::Config(format!("Invalid filter {}: {}", regex, e)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(NameFilter { names, regexes })
+    }
+
+    /*
+     * Whether any of the names of a container, separated by commas, matches.
+     */
+    fn matches(&self, names: &str) -> bool {
+        names.split(',').any(|name| {
+            self.names.iter().any(|n| n == name) || self.regexes.iter().any(|r| r.is_match(name))
+        })
+    }
+}
+
+/*
+ * State of a backup run, shared by the backups of all containers.
+ */
+struct BackupRun {
+    output_dir: String,
+    destination: Destination,
+    container_destinations: HashMap<String, Destination>,
+    manifest: Manifest,
+    values: RunValues,
+    planned: Mutex<Planned>,
+    /// Only sums the estimated sizes of the archives for the free space check, printing no plan.
+    sizing: bool,
+    /// The locations of the images saved by this run.
+    saved_images: Mutex<HashSet<String>>,
+    /// The swarm services scaled to no replicas while their task containers are backed up.
+    scaled_services: HashSet<String>,
+}
+
+/*
+ * The estimated size of the mounts of a dry run, in total and per local directory they are
+ * written to, and how many mounts have no estimate.
+ */
+#[derive(Default)]
+struct Planned {
+    total: u64,
+    unknown: usize,
+    local: HashMap<PathBuf, u64>,
+}
+
+impl BackupRun {
+    /*
+     * Check the options and prepare the output directory and the destination.
+     */
+    fn start(
+        archive_args: &ArchiveArguments,
+        cli_args: &CliArguments,
+    ) -> Result<Self, DockerError> {
+        archive_args.validate(cli_args)?;
+        check_daemon(cli_args)?;
+        let (output_dir, destination) = if archive_args.stdout {
+            (cli_args.output_dir.clone(), Destination::Stdout)
+        } else if archive_args.dry_run {
+            let output_dir = cli_args.output_dir.clone();
+            let destination = Destination::parse(cli_args.dest.as_deref(), &output_dir)?;
+            info!("Dry run, nothing is stopped or written");
+            let mut run = BackupRun::new(output_dir, destination, cli_args);
+            run.container_destinations = container_destinations(&run.output_dir, cli_args)?;
+            return Ok(run);
+        } else {
+            let output_dir = prepare_output_dir(&cli_args.output_dir)?;
+            let destination = Destination::parse(cli_args.dest.as_deref(), &output_dir)?;
+            (output_dir, destination)
+        };
+        info!("Writing backups to {}", destination.location(""));
+        destination.prepare()?;
+        let mut run = BackupRun::new(output_dir, destination, cli_args);
+        if !archive_args.stdout {
+            run.container_destinations = container_destinations(&run.output_dir, cli_args)?;
+            for (name, destination) in &run.container_destinations {
+                info!(
+                    "Writing backups of {} to {}",
+                    name,
+                    destination.location("")
+                );
+                destination.prepare()?;
+            }
+        }
+        Ok(run)
+    }
+
+    fn new(output_dir: String, destination: Destination, cli_args: &CliArguments) -> Self {
+        let manifest = Manifest::new(&cli_args.image);
+        logging::set_run_id(&manifest.run_id);
+        let values = RunValues::new(
+            &manifest.timestamp,
+            &manifest.run_id,
+            &cli_args.name_template(),
+        );
+        BackupRun {
+            manifest,
+            output_dir,
+            destination,
+            container_destinations: HashMap::new(),
+            values,
+            planned: Mutex::new(Planned::default()),
+            sizing: false,
+            saved_images: Mutex::new(HashSet::new()),
+            scaled_services: HashSet::new(),
+        }
+    }
+
+    /*
+     * A dry run of this run to estimate the size of its archives, writing to the same
+     * destinations.
+     */
+    fn sizing(&self, cli_args: &CliArguments) -> Self {
+        let mut run = BackupRun::new(self.output_dir.clone(), self.destination.clone(), cli_args);
+        run.container_destinations = self.container_destinations.clone();
+        run.sizing = true;
+        run
+    }
+
+    /*
+     * Whether the free space is checked before the backup, when archives are written to a local
+     * directory.
+     */
+    fn checks_free_space(&self, archive_args: &ArchiveArguments) -> bool {
+        let local = |destination: &Destination| matches!(destination, Destination::Local(_));
+        !archive_args.dry_run
+            && (local(&self.destination) || self.container_destinations.values().any(local))
+    }
+
+    /*
+     * Check that every local directory has room for the estimated size of the archives written to
+     * it, failing or with --force warning when it does not. Mounts without an estimate are left
+     * out.
+     */
+    fn check_free_space(&self, force: bool) -> Result<(), DockerError> {
+        let planned = self.planned.lock().unwrap_or_else(|e| e.into_inner());
+        if planned.unknown > 0 {
+            warn!(
+                "The free space check leaves out {} mounts of unknown size",
+                planned.unknown
+            );
+        }
+        for (dir, needed) in &planned.local {
+            let free = match free_space(dir) {
+                Ok(free) => free,
+                Err(e) => {
+                    warn!("Could not check the free space in {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            debug!(
+                "The backup needs about {} bytes in {}, {} bytes are free",
+                needed,
+                dir.display(),
+                free
+            );
+            if free >= *needed {
+                continue;
+            }
+            let message = format!(
+                "Not enough free space in {}: the backup needs about {} but only {} is free",
+                dir.display(),
+                human_size(*needed),
+                human_size(free)
+            );
+            if !force {
+                return Err(DockerError::from(message.as_str()));
+            }
+            warn!("{}, continuing as --force is given", message);
+        }
+        Ok(())
+    }
+
+    /*
+     * Print the total estimated size of the mounts of a dry run and how many of them have no
+     * estimate.
+     */
+    fn print_planned_total(&self) {
+        let planned = self.planned.lock().unwrap_or_else(|e| e.into_inner());
+        let mut line = format!("Total estimated size: {}", human_size(planned.total));
+        if planned.unknown > 0 {
+            line.push_str(&format!(", {} mounts of unknown size", planned.unknown));
+        }
+        println!("{}", line);
+    }
+
+    /*
+     * The destination of the archives of a container, its own destination from the configuration
+     * file or else the destination of the run.
+     */
+    fn destination(&self, name: &str) -> &Destination {
+        self.container_destinations
+            .get(name)
+            .unwrap_or(&self.destination)
+    }
+
+    /*
+     * Save an image of a container with docker save at the destination of the container, once
+     * per image: an image saved by this run, or by an earlier run into the same directory, is not
+     * saved again. Returns the name of the image archive.
+     */
+    fn save_image(
+        &self,
+        name: &str,
+        image: &str,
+        cli_args: &CliArguments,
+    ) -> Result<String, DockerError> {
+        let archive = format!("{}/{}.tar", IMAGE_DIR, image.trim_start_matches("sha256:"));
+        let destination = self.destination(name);
+        let location = destination.location(&archive);
+        let mut saved = self.saved_images.lock().unwrap_or_else(|e| e.into_inner());
+        let stored = match destination {
+            Destination::Local(dir) => dir.join(&archive).exists(),
+            _ => false,
+        };
+        if saved.contains(&location) || stored {
+            info!("[{}] - image {} is saved already", name, image);
+            return Ok(archive);
+        }
+        info!("[{}] - saving image {}", name, image);
+        let mut writer = destination.create(&archive, &[], None)?;
+        if let Err(e) = save_image(cli_args, image, &mut writer) {
+            writer.abort();
+            return Err(e);
+        }
+        writer.finish()?;
+        saved.insert(location);
+        Ok(archive)
+    }
+
+    /*
+     * Write the manifest, record the run in the catalog and prune when asked to.
+     */
+    fn finish(
+        &self,
+        archive_args: &ArchiveArguments,
+        cli_args: &CliArguments,
+    ) -> Result<(), DockerError> {
+        if archive_args.stdout || archive_args.dry_run {
+            // Nothing is written to the output directory when streaming or for a dry run.
+            return Ok(());
+        }
+        for (name, path) in self.manifest.write_inspects(Path::new(&self.output_dir))? {
+            self.destination(name).upload_file(&path)?;
+        }
+        let manifest_path = self.manifest.write(Path::new(&self.output_dir))?;
+        self.destination.upload_file(&manifest_path)?;
+        Catalog::open(cli_args)?.record_run(&self.manifest)?;
+        if archive_args.prune_after_backup {
+            info!("Pruning expired archives");
+            apply_retention(&archive_args.retention, false, cli_args)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * The destinations of the containers with a destination in the configuration file.
+ */
+fn container_destinations(
+    output_dir: &str,
+    cli_args: &CliArguments,
+) -> Result<HashMap<String, Destination>, DockerError> {
+    cli_args
+        .containers
+        .iter()
+        .filter_map(|(name, settings)| {
+            settings
+                .dest
+                .as_deref()
+                .map(|dest| Ok((name.clone(), Destination::parse(Some(dest), output_dir)?)))
+        })
+        .collect()
+}
+
+/*
+ * Create the output directory if needed and return its absolute path.
+ */
+fn prepare_output_dir(output_dir: &str) -> Result<String, DockerError> {
+    let path = Path::new(output_dir);
+    if !path.exists() {
+        info!("Creating output directory {}", output_dir);
+        fs::create_dir_all(path)?;
+    }
+    if !path.is_dir() {
+        return Err(DockerError::from(
+            format!("Output directory {} is not a directory", output_dir).as_str(),
+        ));
+    }
+    if fs::metadata(path)?.permissions().readonly() {
+        return Err(DockerError::from(
+            format!("Output directory {} is not writable", output_dir).as_str(),
+        ));
+    }
+    host_path(path)?
+        .to_str()
+        .map(String::from)
+        .ok_or(DockerError::from(
+            format!("Output directory {} is not a valid path", output_dir).as_str(),
+        ))
+}
+
+/*
+ * Run the job for every item on up to the given number of threads, returning the results in the
+ * order of the items. The first error stops the threads from taking more items.
+ */
+fn run_parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: NonZeroUsize,
+    job: impl Fn(&T) -> Result<R, DockerError> + Sync,
+) -> Result<Vec<R>, DockerError> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new(vec![]);
+    let parent = trace::current();
+    let context = logging::context();
+    let worker = || -> Result<(), DockerError> {
+        trace::continue_in_thread(parent);
+        logging::continue_in_thread(context.clone());
+        while !failed.load(Ordering::SeqCst) {
+            let index = next.fetch_add(1, Ordering::SeqCst);
+            let Some(item) = items.get(index) else {
+                break;
+            };
+            match job(item) {
+                Ok(result) => results.lock()?.push((index, result)),
+                Err(e) => {
+                    failed.store(true, Ordering::SeqCst);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    };
+    thread::scope(|scope| {
+        let threads = jobs.get().min(items.len()).max(1);
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(worker)).collect();
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or(Err(DockerError::from("Backup job panicked")))
+        })
+    })?;
+    let mut results = results.into_inner()?;
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/*
+ * Backup the containers, up to --jobs of them at the same time, and add them to the manifest in
+ * the order docker listed them. With --swarm-scale the task containers of the swarm services come
+ * first, a service at a time.
+ */
+fn backup_containers(
+    ps_info: &[PsInfo],
+    run: &mut BackupRun,
+    backup_args: &BackupArguments,
+    cli_args: &CliArguments,
+) -> Result<Outcome, DockerError> {
+    info!(
+        "Found containers: {:?}",
+        ps_info
+            .iter()
+            .map(|f| { f.names.as_str() })
+            .collect::<Vec<&str>>()
+    );
+
+    let mut counts = (0, 0);
+    let ps_info = if backup_args.swarm_scale {
+        let (services, others) = swarm_services(ps_info, cli_args)?;
+        info!(
+            "Found swarm services: {:?}",
+            services
+                .iter()
+                .map(|(service, _)| service.as_str())
+                .collect::<Vec<&str>>()
+        );
+        for (service, containers) in services {
+            let (succeeded, failed) =
+                backup_service(&service, &containers, run, backup_args, cli_args)?;
+            counts = (counts.0 + succeeded, counts.1 + failed);
+        }
+        others
+    } else {
+        ps_info.iter().collect()
+    };
+
+    let stacks = if backup_args.stops_stacks() {
+        stacks(&ps_info, cli_args)?
+    } else {
+        vec![(None, ps_info)]
+    };
+    for (project, containers) in stacks {
+        let stop_project = project.is_some() && !backup_args.archive.dry_run;
+        let start_order = if stop_project {
+            info!("Stopping compose project {}", project.unwrap_or_default());
+            start_order(&containers, cli_args)?
+        } else {
+            vec![]
+        };
+        // The containers depending on others are stopped first.
+        let mut stopped = vec![];
+        for ps_info in start_order.iter().rev() {
+            stopped.push(SuspendedContainer::stop(
+                cli_args,
+                &ps_info.names,
+                &ps_info.names,
+            )?);
+        }
+        let shared_run: &BackupRun = run;
+        let results = run_parallel(&containers, backup_args.jobs, |ps_info| {
+            backup_container(ps_info, shared_run, backup_args, cli_args)
+        });
+        while let Some(container) = stopped.pop() {
+            container.resume()?;
+        }
+        let (succeeded, failed) = add_manifests(run, results?);
+        counts = (counts.0 + succeeded, counts.1 + failed);
+    }
+    Ok(Outcome::of(counts.0, counts.1))
+}
+
+/*
+ * Add the manifests of backed up containers to the run, returning how many containers succeeded
+ * and failed.
+ */
+fn add_manifests(run: &mut BackupRun, manifests: Vec<Option<ContainerManifest>>) -> (usize, usize) {
+    let mut succeeded = 0;
+    let mut failed = 0;
+    for container_manifest in manifests.into_iter().flatten() {
+        match container_manifest.succeeded() {
+            true => succeeded += 1,
+            false => failed += 1,
+        }
+        run.manifest.containers.push(container_manifest);
+    }
+    (succeeded, failed)
+}
+
+/*
+ * A swarm service and its task containers on this node.
+ */
+type Service<'a> = (String, Vec<&'a PsInfo>);
+
+/*
+ * The task containers on this node grouped by their swarm service, and the other containers.
+ */
+fn swarm_services<'a>(
+    ps_info: &'a [PsInfo],
+    cli_args: &CliArguments,
+) -> Result<(Vec<Service<'a>>, Vec<&'a PsInfo>), DockerError> {
+    let mut services: Vec<Service> = vec![];
+    let mut others = vec![];
+    for ps_info in ps_info {
+        let service = inspect_container(cli_args, &ps_info.names)?
+            .and_then(|info| info.config.labels.get(SWARM_SERVICE_LABEL).cloned());
+        match service {
+            Some(service) => match services.iter_mut().find(|(name, _)| *name == service) {
+                Some((_, containers)) => containers.push(ps_info),
+                None => services.push((service, vec![ps_info])),
+            },
+            None => others.push(ps_info),
+        }
+    }
+    Ok((services, others))
+}
+
+/*
+ * Back up the task containers of a swarm service on this node with the service scaled to no
+ * replicas, so swarm does not replace them. A global service can not be scaled, its tasks are
+ * backed up running.
+ */
+fn backup_service(
+    service: &str,
+    containers: &[&PsInfo],
+    run: &mut BackupRun,
+    backup_args: &BackupArguments,
+    cli_args: &CliArguments,
+) -> Result<(usize, usize), DockerError> {
+    let replicas = inspect_service(cli_args, service)
+        .map_err(|e| {
+            DockerError::from(
+                format!(
+                    "Could not inspect swarm service {}, --swarm-scale needs a swarm manager: {}",
+                    service, e
+                )
+                .as_str(),
+            )
+        })?
+        .ok_or(DockerError::from(
+            format!("Swarm service {} not found", service).as_str(),
+        ))?
+        .replicas();
+    let scaled = match replicas {
+        None => {
+            warn!(
+                "[{}] The service is global and can not be scaled, its tasks are backed up running",
+                service
+            );
+            None
+        }
+        Some(replicas) if backup_args.archive.dry_run => {
+            info!(
+                "[{}] Would scale the service to 0 replicas and back to {}",
+                service, replicas
+            );
+            None
+        }
+        Some(replicas) => {
+            run.scaled_services.insert(service.to_string());
+            Some(SuspendedContainer::scale_down(cli_args, service, replicas)?)
+        }
+    };
+    let shared_run: &BackupRun = run;
+    let results = run_parallel(containers, backup_args.jobs, |ps_info| {
+        backup_container(ps_info, shared_run, backup_args, cli_args)
+    });
+    if let Some(scaled) = scaled {
+        scaled.resume()?;
+    }
+    Ok(add_manifests(run, results?))
+}
+
+/*
+ * A compose project and its containers, without a project for the containers outside a project.
+ */
+type Stack<'a> = (Option<String>, Vec<&'a PsInfo>);
+
+/*
+ * The containers grouped by their compose project, in the order docker listed them.
+ */
+fn stacks<'a>(
+    ps_info: &[&'a PsInfo],
+    cli_args: &CliArguments,
+) -> Result<Vec<Stack<'a>>, DockerError> {
+    let mut stacks: Vec<Stack> = vec![];
+    for &ps_info in ps_info {
+        let project = inspect_container(cli_args, &ps_info.names)?
+            .and_then(|info| info.config.labels.get(COMPOSE_PROJECT_LABEL).cloned());
+        match stacks.iter_mut().find(|(stack, _)| *stack == project) {
+            Some((_, containers)) => containers.push(ps_info),
+            None => stacks.push((project, vec![ps_info])),
+        }
+    }
+    Ok(stacks)
+}
+
+/*
+ * The running containers of a project in the order to start them, the containers others depend on
+ * first: the services in the depends_on of compose and the linked containers. Docker lists the
+ * newest containers first, without dependencies the oldest container is started first.
+ */
+fn start_order<'a>(
+    ps_info: &[&'a PsInfo],
+    cli_args: &CliArguments,
+) -> Result<Vec<&'a PsInfo>, DockerError> {
+    let running: Vec<&PsInfo> = ps_info
+        .iter()
+        .rev()
+        .copied()
+        .filter(|p| p.is_running())
+        .collect();
+    let mut services = vec![];
+    let mut depends_on = vec![];
+    for ps_info in &running {
+        let container_info = inspect_container(cli_args, &ps_info.names)?;
+        let labels = container_info.as_ref().map(|info| &info.config.labels);
+        services.push(labels.and_then(|labels| labels.get(COMPOSE_SERVICE_LABEL).cloned()));
+        // Like db:service_healthy:true,cache:service_started:false
+        let mut names: Vec<String> = labels
+            .and_then(|labels| labels.get(COMPOSE_DEPENDS_ON_LABEL))
+            .map(|dependencies| {
+                dependencies
+                    .split(',')
+                    .filter_map(|dependency| dependency.split(':').next())
+                    .map(|service| service.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if let Some(container_info) = &container_info {
+            names.extend(
+                container_info
+                    .linked_containers()
+                    .into_iter()
+                    .map(String::from),
+            );
+        }
+        depends_on.push(names);
+    }
+    let dependencies: Vec<Vec<usize>> = depends_on
+        .iter()
+        .map(|names| {
+            (0..running.len())
+                .filter(|&index| {
+                    names.iter().any(|name| {
+                        services[index].as_ref() == Some(name)
+                            || running[index].names.split(',').any(|n| n == name)
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    // Depth first, None when not visited yet and Some(false) while visiting its dependencies.
+    fn visit(
+        index: usize,
+        running: &[&PsInfo],
+        dependencies: &[Vec<usize>],
+        visited: &mut [Option<bool>],
+        order: &mut Vec<usize>,
+    ) {
+        match visited[index] {
+            Some(true) => return,
+            Some(false) => {
+                warn!(
+                    "[{}] Ignoring the dependency cycle of the container",
+                    running[index].names
+                );
+                return;
+            }
+            None => {}
+        }
+        visited[index] = Some(false);
+        for &dependency in &dependencies[index] {
+            visit(dependency, running, dependencies, visited, order);
+        }
+        visited[index] = Some(true);
+        order.push(index);
+    }
+    let mut visited = vec![None; running.len()];
+    let mut order = vec![];
+    for index in 0..running.len() {
+        visit(index, &running, &dependencies, &mut visited, &mut order);
+    }
+    debug!(
+        "Start order: {:?}",
+        order
+            .iter()
+            .map(|&index| running[index].names.as_str())
+            .collect::<Vec<&str>>()
+    );
+    Ok(order.into_iter().map(|index| running[index]).collect())
+}
+
+/*
+ * Inspect a container to find out the mounts and back them up, returning the manifest of the
+ * container.
+ */
+fn backup_container(
+    ps_info: &PsInfo,
+    run: &BackupRun,
+    backup_args: &BackupArguments,
+    cli_args: &CliArguments,
+) -> Result<Option<ContainerManifest>, DockerError> {
+    let container_name = &ps_info.names;
+    let _context = logging::container(container_name);
+    info!(
+        "[{container_name}] Getting container information for {}",
+        container_name
+    );
+
+    let mut span = trace::span("container");
+    span.attribute("container.name", container_name);
+    let Some(container_info) = inspect_container(cli_args, container_name)? else {
+        error!("[{container_name}] Response from inspect is wrong (no data returned)");
+        return Ok(None);
+    };
+    let result = backup_all_mounts(&container_info, ps_info, run, backup_args, cli_args);
+    span.result(&result);
+    let result = result?;
+    if let Some(manifest) = result.as_ref().filter(|manifest| !manifest.succeeded()) {
+        span.fail(manifest.error.as_deref().unwrap_or("A mount failed"));
+    }
+    match &result {
+        Some(manifest) if !manifest.succeeded() => error!(
+            "[{container_name}] Error backing up container {}",
+            container_name
+        ),
+        Some(_) => info!(
+            "[{container_name}] Backup of container {} done. 🫡",
+            container_name
+        ),
+        None => {}
+    }
+    Ok(result)
+}
+
+/*
+ * Backup the mounts listed in the container as tar files, returning the manifest of the container.
+ * Backup containers are skipped.
+ */
+fn backup_all_mounts(
+    container_info: &ContainerInfo,
+    container: &PsInfo,
+    run: &BackupRun,
+    backup_args: &BackupArguments,
+    cli_args: &CliArguments,
+) -> Result<Option<ContainerManifest>, DockerError> {
+    debug!("Inspect: {:?}", container_info);
+    info!("[{}] Start backup of volumes", container.names);
+
+    if *container_info
+        .config
+        .labels
+        .get("type")
+        .unwrap_or(&"-".to_string())
+        == TYPE_BACKUPCONTAINER
+    {
+        info!(
+            "[{}] Skipping this container as it it a backup container 🫡",
+            container.names
+        );
+        return Ok(None);
+    }
+    let settings = cli_args.containers.get(&container.names);
+    let labels =
+        ContainerLabels::parse(&container_info.config.labels).and_then(|labels| match settings {
+            Some(settings) => Ok(labels.or(settings.labels()?)),
+            None => Ok(labels),
+        });
+    let labels = match labels {
+        Ok(labels) => labels,
+        Err(e) => return Ok(Some(failed_container(container_info, container, e))),
+    };
+    let container_cli_args = settings
+        .and_then(|settings| settings.image.as_ref())
+        .map(|image| CliArguments {
+            image: image.clone(),
+            ..cli_args.clone()
+        });
+    let cli_args = container_cli_args.as_ref().unwrap_or(cli_args);
+    match labels.enable {
+        Some(true) => {}
+        Some(false) => {
+            info!(
+                "[{}] Skipping this container as it has the {}=false label",
+                container.names, ENABLE_LABEL
+            );
+            return Ok(None);
+        }
+        _ if backup_args.opt_in => {
+            info!(
+                "[{}] Skipping this container as it does not have the {}=true label",
+                container.names, ENABLE_LABEL
+            );
+            return Ok(None);
+        }
+        _ => {}
+    }
+
+    let mut archive_args = backup_args.archive.clone();
+    if let Some(compress) = labels.compress {
+        if let Err(e) = compress.validate_level(archive_args.compress_level) {
+            return Ok(Some(failed_container(container_info, container, e)));
+        }
+        archive_args.compress = compress;
+    }
+    archive_args
+        .exclude_pattern
+        .extend(labels.exclude_pattern.iter().cloned());
+    archive_args
+        .include_path
+        .extend(labels.include_path.iter().cloned());
+    // A project stopped as a whole, or a swarm service scaled down, is already stopped now.
+    let service = container_info.config.labels.get(SWARM_SERVICE_LABEL);
+    let stop_project = (backup_args.stops_stacks()
+        && container_info
+            .config
+            .labels
+            .contains_key(COMPOSE_PROJECT_LABEL))
+        || service.is_some_and(|service| run.scaled_services.contains(service));
+    let running = container.is_running() && !stop_project;
+    let mut dump = database_dump(&labels, &container_info.config.image, backup_args);
+    if dump.is_some() && !running {
+        info!(
+            "[{}] - not dumping the databases as the container is not running",
+            container.names
+        );
+        dump = None;
+    }
+    let mounts: Vec<&Mounts> = container_info
+        .mounts
+        .iter()
+        .filter(|mount| {
+            let replaced = dump.is_some_and(|(database, mode)| {
+                mode == DumpMode::Instead && database.holds_data(mount, &container_info.config.env)
+            });
+            if replaced {
+                info!(
+                    "[{}] - skipping {} as the databases are dumped instead",
+                    container.names, mount.destination
+                );
+                return false;
+            }
+            if labels.excludes(&mount.destination) {
+                info!(
+                    "[{}] - skipping {} as it is excluded by a label or the config file",
+                    container.names, mount.destination
+                );
+                return false;
+            }
+            if let Some(reason) = mount.unarchivable() {
+                info!(
+                    "[{}] - skipping {} as it is {}",
+                    container.names, mount.destination, reason
+                );
+                return false;
+            }
+            if mount.is_anonymous() && !backup_args.include_anonymous {
+                info!(
+                    "[{}] - skipping {} as it is an anonymous volume",
+                    container.names, mount.destination
+                );
+                return false;
+            }
+            let types = &backup_args.mount_types;
+            if !types.is_empty() && !mount.kind.is_some_and(|kind| types.contains(&kind)) {
+                info!(
+                    "[{}] - skipping {} as it is a {} mount",
+                    container.names,
+                    mount.destination,
+                    mount.kind.map(|kind| kind.to_string()).unwrap_or_default()
+                );
+                return false;
+            }
+            true
+        })
+        .collect();
+
+    let mut stop_start = !stop_project
+        && labels
+            .stop
+            .unwrap_or(backup_args.stop_start || backup_args.stop_stack)
+        && container.is_running();
+    // Swarm would replace a stopped task container with a new task.
+    if stop_start && service.is_some() {
+        if !backup_args.swarm_scale && !run.sizing {
+            warn!(
+                "[{}] Not stopping the task container of a swarm service, use --swarm-scale to \
+                 scale the service down instead",
+                container.names
+            );
+        }
+        stop_start = false;
+    }
+    // Stopping wins over pausing, a project stopped as a whole is not paused.
+    let pause = labels.pause.unwrap_or(backup_args.pause) && running && !stop_start;
+    if archive_args.dry_run {
+        let downtime = if stop_start || (stop_project && container.is_running()) {
+            Some("stopped")
+        } else if pause {
+            Some("paused")
+        } else {
+            None
+        };
+        let dump_mount = dump.map(|(database, _)| database.mount());
+        let mut planned = mounts.clone();
+        planned.extend(&dump_mount);
+        let source = Helper {
+            volumes_from: Some(container_info.id.clone()),
+            ..Default::default()
+        };
+        print_plan(
+            &container.names,
+            downtime,
+            &source,
+            &planned,
+            run,
+            &archive_args,
+            cli_args,
+        );
+        return Ok(None);
+    }
+    let pre_hook = settings
+        .and_then(|settings| settings.pre_hook.as_ref())
+        .or(backup_args.pre_hook.as_ref());
+    let post_hook = settings
+        .and_then(|settings| settings.post_hook.as_ref())
+        .or(backup_args.post_hook.as_ref());
+    let name = &container.names;
+    let on_failure = backup_args.on_hook_failure;
+    if let Some(command) = pre_hook {
+        if !run_hook(Hook::Pre, name, on_failure, || {
+            host_hook(Hook::Pre, command, name, None)
+        }) {
+            let e = DockerError::from("Not backed up as the pre hook failed");
+            return Ok(Some(failed_container(container_info, container, e)));
+        }
+    }
+    // Commands can only be executed in a container that keeps running during the backup.
+    let exec = |hook: Hook, command: &Option<String>| match command {
+        Some(command) if running && !stop_start => run_hook(hook, name, on_failure, || {
+            exec_in_container(cli_args, &container_info.id, command)
+        }),
+        Some(_) => {
+            info!(
+                "[{}] - not running the {} command as the container does not run during the backup",
+                name,
+                hook.name()
+            );
+            true
+        }
+        None => true,
+    };
+    let quiesced = exec(Hook::PreExec, &labels.pre_exec);
+
+    let mut mounts_manifest = vec![];
+    let mut errors = vec![];
+    if quiesced {
+        if let Some((database, _)) = dump {
+            mounts_manifest.push(backup_dump(
+                name,
+                database,
+                container_info,
+                &labels,
+                run,
+                &archive_args,
+                cli_args,
+            ));
+        }
+        // Redis saves its data itself when it is stopped.
+        let redis =
+            labels
+                .redis_snapshot
+                .unwrap_or(if redis::is_redis(&container_info.config.image) {
+                    backup_args.redis_snapshot
+                } else {
+                    RedisSnapshot::Off
+                });
+        if redis != RedisSnapshot::Off && running && !stop_start {
+            let env = &container_info.config.env;
+            if let Err(e) = redis::snapshot(cli_args, name, &container_info.id, env, redis) {
+                let e = format!("Redis did not save its data: {}", e);
+                container_error(name, e, &mut errors);
+            }
+        }
+        let stopped = stop_start
+            .then(|| SuspendedContainer::stop(cli_args, name, &container_info.id))
+            .transpose()?;
+        let deadline = backup_args
+            .container_timeout
+            .map(|minutes| Instant::now() + Duration::from_secs(minutes * 60));
+        let archive_mounts = || {
+            run_parallel(&mounts, backup_args.mount_jobs, |mount| {
+                let source = Helper {
+                    volumes_from: Some(container_info.id.clone()),
+                    timeout: deadline
+                        .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+                    ..Default::default()
+                };
+                Ok(backup_mount(
+                    &container.names,
+                    &source,
+                    mount,
+                    run,
+                    &archive_args,
+                    cli_args,
+                ))
+            })
+        };
+        if pause {
+            let timeout = Duration::from_secs(backup_args.pause_timeout);
+            let (archived, timed_out) =
+                while_paused(name, &container_info.id, timeout, cli_args, archive_mounts)?;
+            mounts_manifest.extend(archived?);
+            if timed_out {
+                let e = "Unpaused before the backup finished, the archives may be inconsistent";
+                container_error(name, e.to_string(), &mut errors);
+            }
+        } else {
+            mounts_manifest.extend(archive_mounts()?);
+        }
+        if let Some(stopped) = stopped {
+            stopped.resume()?;
+        }
+    } else {
+        let e = "Not backed up as the pre-exec hook failed";
+        container_error(name, e.to_string(), &mut errors);
+    }
+    // The post-exec command also runs after a failed pre-exec command, to undo what it did.
+    if !exec(Hook::PostExec, &labels.post_exec) {
+        errors.push("The post-exec hook failed".to_string());
+    }
+    let image_archive = match backup_args.include_image && !container_info.image.is_empty() {
+        true => match run.save_image(name, &container_info.image, cli_args) {
+            Ok(archive) => Some(archive),
+            Err(e) => {
+                container_error(name, format!("Saving the image failed: {}", e), &mut errors);
+                None
+            }
+        },
+        false => None,
+    };
+    let mut container_manifest = ContainerManifest {
+        name: container.names.clone(),
+        id: container_info.id.clone(),
+        mounts: mounts_manifest,
+        error: None,
+        container: Some(ContainerSpec::of(container_info)),
+        inspect: container_info.inspect.clone(),
+        image_archive,
+    };
+    if let Some(command) = post_hook {
+        let success = errors.is_empty() && container_manifest.succeeded();
+        if !run_hook(Hook::Post, name, on_failure, || {
+            host_hook(Hook::Post, command, name, Some(success))
+        }) {
+            errors.push("The post hook failed".to_string());
+        }
+    }
+    if !errors.is_empty() {
+        container_manifest.error = Some(errors.join("; "));
+    }
+    Ok(Some(container_manifest))
+}
+
+/*
+ * Log an error of a container and remember it for its manifest.
+ */
+fn container_error(name: &str, error: String, errors: &mut Vec<String>) {
+    error!("[{}] {}", name, error);
+    errors.push(error);
+}
+
+/*
+ * Run the backup of a container while it is paused, returning its result and whether the
+ * container had to be unpaused before the backup finished. The container is unpaused after the
+ * timeout so a hanging backup can not freeze it for good.
+ */
+fn while_paused<R>(
+    name: &str,
+    id: &str,
+    timeout: Duration,
+    cli_args: &CliArguments,
+    backup: impl FnOnce() -> R,
+) -> Result<(R, bool), DockerError> {
+    let paused = SuspendedContainer::pause(cli_args, name, id)?;
+    let (finished, watched) = mpsc::channel::<()>();
+    let (result, unpaused) = thread::scope(|scope| {
+        let watchdog = scope.spawn(move || match watched.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "[{}] Unpausing container as its backup takes more than {}s",
+                    name,
+                    timeout.as_secs()
+                );
+                Some(unpause_container(cli_args, id))
+            }
+            _ => None,
+        });
+        let result = backup();
+        drop(finished);
+        (result, watchdog.join().unwrap_or(None))
+    });
+    let timed_out = unpaused.is_some();
+    match unpaused {
+        Some(unpaused) => {
+            paused.forget();
+            unpaused?
+        }
+        None => paused.resume()?,
+    }
+    Ok((result, timed_out))
+}
+
+/*
+ * The manifest of a container that could not be backed up at all, logging the error.
+ */
+fn failed_container(
+    container_info: &ContainerInfo,
+    container: &PsInfo,
+    e: DockerError,
+) -> ContainerManifest {
+    error!("[{}] {}", container.names, e);
+    ContainerManifest {
+        name: container.names.clone(),
+        id: container_info.id.clone(),
+        mounts: vec![],
+        error: Some(e.to_string()),
+        container: Some(ContainerSpec::of(container_info)),
+        inspect: container_info.inspect.clone(),
+        image_archive: None,
+    }
+}
+
+/*
+ * Backup a single mount of a container or a volume, returning its manifest. The source helper has
+ * the volumes mounted.
+ */
+fn backup_mount(
+    name: &str,
+    source: &Helper,
+    mount: &Mounts,
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> MountManifest {
+    let _context = logging::mount(name, &mount.destination);
+    info!("[{}] - backing up {}", name, mount.destination);
+    let mut span = trace::span("mount");
+    span.attribute("container.name", name);
+    span.attribute("mount.destination", &mount.destination);
+    let start = Instant::now();
+    let operation = format!("[{}] - backup of {}", name, mount.destination);
+    let estimate = archive_args
+        .estimate_size
+        .then(|| estimated_size(name, source, mount, archive_args, cli_args))
+        .flatten();
+    if let Some(estimate) = estimate {
+        debug!(
+            "[{}] - estimated size of {} is {} bytes",
+            name, mount.destination, estimate
+        );
+    }
+    let result = retry(cli_args, &operation, || {
+        archive_mount(name, source, mount, estimate, run, archive_args, cli_args)
+    });
+    span.result(&result);
+    let mut manifest = mount_manifest(name, mount, result, start);
+    manifest.estimated_size = estimate;
+    manifest.volume = volume_spec(name, mount, cli_args);
+    span.attribute("archive.size", manifest.size);
+    manifest
+}
+
+/*
+ * The database of a container to dump and how, from its labels or else from its image. None when
+ * it is not dumped.
+ */
+fn database_dump(
+    labels: &ContainerLabels,
+    image: &str,
+    backup_args: &BackupArguments,
+) -> Option<(Database, DumpMode)> {
+    let labeled = [
+        (Database::Postgres, labels.pg_dump),
+        (Database::Mysql, labels.mysql_dump),
+        (Database::Mongodb, labels.mongo_dump),
+    ]
+    .into_iter()
+    .find_map(|(database, mode)| mode.map(|mode| (database, mode)));
+    let (database, mode) = labeled.or_else(|| {
+        Database::detect(image).map(|database| (database, backup_args.dump_mode(database)))
+    })?;
+    (mode != DumpMode::Off).then_some((database, mode))
+}
+
+/*
+ * Dump the databases of a database container into an archive, returning its manifest with the
+ * dump in place of a mount.
+ */
+fn backup_dump(
+    name: &str,
+    database: Database,
+    container_info: &ContainerInfo,
+    labels: &ContainerLabels,
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> MountManifest {
+    info!(
+        "[{}] - dumping the databases with {}",
+        name,
+        database.tool()
+    );
+    let mut span = trace::span("dump");
+    span.attribute("container.name", name);
+    span.attribute("db.system", database.tool());
+    let start = Instant::now();
+    let result = archive_dump(
+        name,
+        database,
+        container_info,
+        labels,
+        run,
+        archive_args,
+        cli_args,
+    );
+    span.result(&result);
+    mount_manifest(name, &database.mount(), result, start)
+}
+
+/*
+ * The driver, options and labels of the named volume of a mount, so a restore can create it again
+ * like it was. None for other mounts and when the volume cannot be inspected.
+ */
+fn volume_spec(name: &str, mount: &Mounts, cli_args: &CliArguments) -> Option<VolumeSpec> {
+    let volume = mount
+        .name
+        .as_ref()
+        .filter(|_| mount.kind == Some(MountType::Volume))?;
+    match inspect_volume(cli_args, volume) {
+        Ok(volume_info) => volume_info.as_ref().map(VolumeSpec::of),
+        Err(e) => {
+            warn!(
+                "[{}] Could not inspect volume {}, its driver is not recorded: {}",
+                name, volume, e
+            );
+            None
+        }
+    }
+}
+
+/*
+ * The manifest of a mount from the result of archiving it.
+ */
+fn mount_manifest(
+    name: &str,
+    mount: &Mounts,
+    result: Result<(String, u64, String), DockerError>,
+    start: Instant,
+) -> MountManifest {
+    let duration_secs = start.elapsed().as_secs_f64();
+    match result {
+        Ok((archive, size, sha256)) => MountManifest {
+            source: mount.source.clone(),
+            destination: mount.destination.clone(),
+            archive: Some(archive),
+            size,
+            sha256: Some(sha256),
+            estimated_size: None,
+            duration_secs,
+            status: MountStatus::Success,
+            error: None,
+            volume: None,
+        },
+        Err(e) => {
+            error!(
+                "[{}] Error in backup of volume {}: {}",
+                name, mount.destination, e
+            );
+            MountManifest {
+                source: mount.source.clone(),
+                destination: mount.destination.clone(),
+                archive: None,
+                size: 0,
+                sha256: None,
+                estimated_size: None,
+                duration_secs,
+                status: MountStatus::Failed,
+                error: Some(e.to_string()),
+                volume: None,
+            }
+        }
+    }
+}
+
+/*
+ * Stream the tar of a single mount from a helper container into an archive at the destination,
+ * returning the filename, size and sha256 of the archive. The estimated size of the mount is used
+ * for the progress.
+ */
+fn archive_mount(
+    name: &str,
+    source: &Helper,
+    mount: &Mounts,
+    estimate: Option<u64>,
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Result<(String, u64, String), DockerError> {
+    let encryption = archive_args.encryption();
+    let (archive, incremental) = archive_name(name, mount, run, archive_args, cli_args)?;
+
+    let mut helper = Helper {
+        command: ["tar", "cf", "-"].map(String::from).to_vec(),
+        ..source.clone()
+    };
+    // The source has the time left for all mounts of the container.
+    let mount_timeout = archive_args
+        .mount_timeout
+        .map(|minutes| Duration::from_secs(minutes * 60));
+    helper.timeout = helper.timeout.into_iter().chain(mount_timeout).min();
+    if let Some(incremental) = &incremental {
+        incremental.configure_helper(&mut helper, cli_args)?;
+    }
+    if *run.destination(name) == Destination::Stdout {
+        // The tars of all mounts are concatenated, keep the containers apart.
+        helper.command.push(format!("--transform=s,^,{}/,", name));
+    }
+    for pattern in &archive_args.exclude_pattern {
+        helper.command.push(format!("--exclude={}", pattern));
+    }
+    helper
+        .command
+        .extend(archive_args.archived_paths(&mount.destination));
+
+    let mut commands = vec![];
+    commands.extend(
+        archive_args
+            .compress
+            .compress_command(archive_args.compress_level),
+    );
+    commands.extend(encryption.map(|(e, recipients)| e.encrypt_command(recipients)));
+    // Only an uncompressed archive has about the size of the files in the mount.
+    let total =
+        (progress::enabled() && archive_args.compress == Compression::None && encryption.is_none())
+            .then(|| estimate.or_else(|| host_size(mount, archive_args, cli_args)))
+            .flatten();
+    // A compressed archive is rarely larger than the files, leave room for the tar headers and
+    // files growing while they are archived.
+    let expected = matches!(run.destination(name), Destination::S3(_))
+        .then(|| estimate.or_else(|| host_size(mount, archive_args, cli_args)))
+        .flatten()
+        .map(|size| size + size / 10);
+    let (size, digest) = write_archive(name, &archive, mount, expected, run, |writer| {
+        let label = format!("{} {}", name, mount.destination);
+        let mut writer = progress::Counting::new(writer, label, total);
+        run_helper_into(&helper, commands, &mut writer, cli_args)
+    })?;
+
+    if let (Some(incremental), Some(level)) = (&incremental, archive.level) {
+        incremental.commit(level, &archive.file_name())?;
+    }
+    Ok((archive.file_name(), size, digest))
+}
+
+/*
+ * Dump the databases of a container as a tar streamed into an archive at the destination,
+ * returning the filename, size and sha256 of the archive. The plaintext dump never lands on disk.
+ */
+fn archive_dump(
+    name: &str,
+    database: Database,
+    container_info: &ContainerInfo,
+    labels: &ContainerLabels,
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Result<(String, u64, String), DockerError> {
+    // A dump is always complete, there are no incremental dumps.
+    let dump_args = ArchiveArguments {
+        incremental: false,
+        ..archive_args.clone()
+    };
+    let mount = database.mount();
+    let (archive, _) = archive_name(name, &mount, run, &dump_args, cli_args)?;
+    let env = environment(&container_info.config.env, labels.dump_env_file.as_deref())?;
+    let credentials = Credentials {
+        user: labels.dump_user.clone(),
+        password: labels.dump_password.clone(),
+        uri: labels.mongo_uri.clone(),
+        uri_file: labels.mongo_uri_file.clone(),
+    };
+    let dump = |output: &mut dyn Write| -> Result<u64, DockerError> {
+        let mut tar = DumpArchive::new(output, database);
+        let dumped = database.dump(cli_args, &container_info.id, &env, &credentials, &mut tar)?;
+        debug!("[{}] - dumped {} bytes", name, dumped);
+        Ok(tar.finish()?)
+    };
+
+    let mut commands = vec![];
+    commands.extend(
+        dump_args
+            .compress
+            .compress_command(dump_args.compress_level),
+    );
+    commands.extend(
+        dump_args
+            .encryption()
+            .map(|(e, recipients)| e.encrypt_command(recipients)),
+    );
+    let (size, digest) = write_archive(name, &archive, &mount, None, run, |writer| {
+        if commands.is_empty() {
+            return dump(writer);
+        }
+        let (input, mut pipe) = pipe()?;
+        thread::scope(|scope| {
+            let dumping = scope.spawn(move || dump(&mut pipe));
+            let written = execute_pipeline_into(commands, Stdio::from(input), writer);
+            let dumped = dumping
+                .join()
+                .unwrap_or_else(|_| Err(DockerError::from("The dump panicked")));
+            // A failing command breaks the pipe of the dump, a failing dump only ends its input.
+            written.and_then(|size| dumped.map(|_| size))
+        })
+    })?;
+    Ok((archive.file_name(), size, digest))
+}
+
+/*
+ * Write an archive of a mount at the destination, discarding it when writing fails. Returns the
+ * size and sha256 of the archive. The expected size is passed on to uploads that need it.
+ */
+fn write_archive(
+    name: &str,
+    archive: &ArchiveName,
+    mount: &Mounts,
+    expected: Option<u64>,
+    run: &BackupRun,
+    write: impl FnOnce(&mut ArchiveWriter) -> Result<u64, DockerError>,
+) -> Result<(u64, String), DockerError> {
+    let timestamp = run.manifest.timestamp.to_rfc3339();
+    let destination = run.destination(name);
+    let mut writer = destination.create(
+        &archive.file_name(),
+        &[
+            ("container", name),
+            ("mount", mount.destination.as_str()),
+            ("timestamp", timestamp.as_str()),
+        ],
+        expected,
+    )?;
+    let size = match write(&mut writer) {
+        Ok(size) => size,
+        Err(e) => {
+            writer.abort();
+            return Err(e);
+        }
+    };
+    let digest = writer.finish()?;
+    destination.write_checksum(&archive.file_name(), &digest)?;
+    debug!(
+        "[{}] - wrote {} with sha256 {}",
+        name,
+        destination.location(&archive.file_name()),
+        digest
+    );
+    Ok((size, digest))
+}
+
+/*
+ * The name of the archive of a mount and the incremental state of the mount when making incremental
+ * archives. For a dry run the snapshot files are left alone.
+ */
+fn archive_name(
+    name: &str,
+    mount: &Mounts,
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Result<(ArchiveName, Option<Incremental>), DockerError> {
+    let template = &cli_args.name_template();
+    let mut archive = ArchiveName::new(
+        template.render(name, &mount.destination, &run.values)?,
+        archive_args.compress,
+        archive_args.encryption().map(|(e, _)| e),
+    );
+    let incremental = archive_args.incremental.then(|| {
+        let identity = template.identity(&archive.base);
+        Incremental::new(
+            Path::new(&run.output_dir),
+            identity.as_deref().unwrap_or(&archive.base),
+        )
+    });
+    if let Some(incremental) = &incremental {
+        let level = if archive_args.dry_run {
+            incremental.next_level(archive_args.full_every)?
+        } else {
+            incremental.prepare(archive_args.full_every)?
+        };
+        info!(
+            "[{}] - incremental level {} of {}",
+            name, level, mount.destination
+        );
+        archive.level = Some(level);
+    }
+    let overwrite = archive_args.on_conflict == OnConflict::Overwrite;
+    if let Destination::Local(dir) = run.destination(name) {
+        if !(archive_args.dry_run && overwrite) {
+            resolve_conflict(&mut archive, dir, archive_args.on_conflict, name)?;
+        }
+    }
+    Ok((archive, incremental))
+}
+
+/*
+ * Print what the backup of a container or volume would do: whether it is stopped and per mount
+ * its estimated size and the archive it would be written to. The source helper has the volumes
+ * mounted.
+ */
+fn print_plan(
+    name: &str,
+    downtime: Option<&str>,
+    source: &Helper,
+    mounts: &[&Mounts],
+    run: &BackupRun,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) {
+    let mut plan = format!(
+        "{}{}\n",
+        name,
+        downtime
+            .map(|downtime| format!(" ({} during the backup)", downtime))
+            .unwrap_or_default()
+    );
+    for mount in mounts {
+        let (target, level) = match archive_name(name, mount, run, archive_args, cli_args) {
+            Ok((archive, _)) => (
+                run.destination(name).location(&archive.file_name()),
+                archive.level,
+            ),
+            Err(e) => (format!("fails: {}", e), None),
+        };
+        let estimate = match previous_size(name, mount, level, cli_args) {
+            Some(size) => Some(size),
+            // Without an earlier incremental archive there is no telling how much changed.
+            None if level.is_some_and(|level| level > 0) => None,
+            None => estimated_size(name, source, mount, archive_args, cli_args),
+        };
+        {
+            let mut planned = run.planned.lock().unwrap_or_else(|e| e.into_inner());
+            match estimate {
+                Some(size) => planned.total += size,
+                None => planned.unknown += 1,
+            }
+            if let (Some(size), Destination::Local(dir)) = (estimate, run.destination(name)) {
+                *planned.local.entry(dir.clone()).or_default() += size;
+            }
+        }
+        let size = estimate
+            .map(|size| size.to_string())
+            .unwrap_or("?".to_string());
+        plan.push_str(&format!(
+            "  {:<24} {:>12}  {}\n",
+            mount.destination, size, target
+        ));
+    }
+    if !run.sizing {
+        print!("{}", plan);
+    }
+}
+
+/*
+ * The size of the last archive of the mount in the catalog that is like the next one, compressed
+ * like it was: an incremental archive for a level above 0, else a full archive. A mount that is
+ * backed up every night does not have to be measured again, and an incremental archive is not
+ * estimated with the size of the whole mount.
+ */
+fn previous_size(
+    name: &str,
+    mount: &Mounts,
+    level: Option<u32>,
+    cli_args: &CliArguments,
+) -> Option<u64> {
+    if !Catalog::exists(cli_args) {
+        return None;
+    }
+    let sizes = Catalog::open(cli_args)
+        .and_then(|catalog| catalog.archive_sizes(name, &mount.destination))
+        .ok()?;
+    let incremental = level.is_some_and(|level| level > 0);
+    sizes
+        .into_iter()
+        .find(|(archive, _)| {
+            ArchiveName::parse(archive)
+                .is_some_and(|archive| archive.level.is_some_and(|level| level > 0) == incremental)
+        })
+        .map(|(_, size)| size)
+}
+
+/*
+ * The size of the files a mount would archive, from its source on the host or else with du in a
+ * helper container that has the volumes mounted. None when neither can tell.
+ */
+fn estimated_size(
+    name: &str,
+    source: &Helper,
+    mount: &Mounts,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Option<u64> {
+    host_size(mount, archive_args, cli_args)
+        .or_else(|| helper_size(name, source, mount, archive_args, cli_args))
+}
+
+/*
+ * The size of the files a mount would archive, from its source on the host. None when the source
+ * is not on this host or can not be read.
+ */
+fn host_size(
+    mount: &Mounts,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Option<u64> {
+    if remote_daemon(cli_args) || mount.source.is_empty() {
+        return None;
+    }
+    let destination = mount.destination.trim_end_matches('/');
+    let excludes = exclude_regex(&archive_args.exclude_pattern);
+    archive_args
+        .archived_paths(&mount.destination)
+        .iter()
+        .map(|path| {
+            let relative = path[destination.len()..].trim_start_matches('/');
+            directory_size(
+                &Path::new(&mount.source).join(relative),
+                path,
+                excludes.as_ref(),
+            )
+        })
+        .sum()
+}
+
+/*
+ * The size of the files a mount would archive, counted by du in a helper container with the same
+ * excludes as tar. None when du fails, like for a file it can not read.
+ */
+fn helper_size(
+    name: &str,
+    source: &Helper,
+    mount: &Mounts,
+    archive_args: &ArchiveArguments,
+    cli_args: &CliArguments,
+) -> Option<u64> {
+    // A database dump has no mount in the container to measure.
+    mount.kind?;
+    let mut helper = Helper {
+        command: ["du", "-s", "-c", "-b"].map(String::from).to_vec(),
+        ..source.clone()
+    };
+    for pattern in &archive_args.exclude_pattern {
+        helper.command.push(format!("--exclude={}", pattern));
+    }
+    helper
+        .command
+        .extend(archive_args.archived_paths(&mount.destination));
+    let mut output = vec![];
+    if let Err(e) = run_helper_into(&helper, vec![], &mut output, cli_args) {
+        debug!(
+            "[{}] - could not estimate the size of {}: {}",
+            name, mount.destination, e
+        );
+        return None;
+    }
+    // The last line is the total of all paths.
+    String::from_utf8_lossy(&output)
+        .lines()
+        .last()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/*
+ * The total size of the files below a path, without following symbolic links, leaving out the
+ * files tar excludes. The path is matched against the excludes as tar sees it, by the archived
+ * path in the container. Unreadable subdirectories are left out.
+ */
+fn directory_size(path: &Path, archived: &str, excludes: Option<&Regex>) -> Option<u64> {
+    let excluded = |archived: &str| excludes.is_some_and(|excludes| excludes.is_match(archived));
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if excluded(archived) {
+        return Some(0);
+    }
+    if !metadata.is_dir() {
+        return Some(metadata.len());
+    }
+    let mut size = 0;
+    let mut dirs = vec![(path.to_path_buf(), archived.to_string())];
+    while let Some((dir, archived_dir)) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            if dir == path {
+                return None;
+            }
+            continue;
+        };
+        for entry in entries.flatten() {
+            let archived = format!(
+                "{}/{}",
+                archived_dir.trim_end_matches('/'),
+                entry.file_name().to_string_lossy()
+            );
+            if excluded(&archived) {
+                continue;
+            }
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => dirs.push((entry.path(), archived)),
+                Ok(metadata) => size += metadata.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    Some(size)
+}
+
+/*
+ * The regular expression matching the paths tar leaves out for the --exclude globs. Like tar a glob
+ * matches the end of the path from the start of any of its components, and its wildcards match
+ * slashes too: node_modules leaves out every node_modules directory. None without globs, or when
+ * a glob can not be translated.
+ */
+fn exclude_regex(globs: &[String]) -> Option<Regex> {
+    if globs.is_empty() {
+        return None;
+    }
+    let alternatives: Vec<String> = globs.iter().map(|glob| glob_regex(glob)).collect();
+    let regex = format!("(?:^|/)(?:{})$", alternatives.join("|"));
+    Regex::new(&regex)
+        .map_err(|e| debug!("Could not match the exclude patterns: {}", e))
+        .ok()
+}
+
+/*
+ * A shell glob as a regular expression: * and ? match any characters, [...] a set of them and a
+ * backslash quotes the next character.
+ */
+fn glob_regex(glob: &str) -> String {
+    let mut translated = String::new();
+    let mut chars = glob.trim_end_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => translated.push_str(".*"),
+            '?' => translated.push('.'),
+            '\\' => {
+                if let Some(quoted) = chars.next() {
+                    translated.push_str(&regex::escape(&quoted.to_string()));
+                }
+            }
+            '[' => {
+                let mut set = chars.clone();
+                let negated = set.next_if(|&c| c == '!' || c == '^').is_some();
+                let mut members = String::new();
+                let mut closed = false;
+                for c in set.by_ref() {
+                    // A ] first in the set is one of its members.
+                    if c == ']' && !members.is_empty() {
+                        closed = true;
+                        break;
+                    }
+                    match c {
+                        '-' => members.push('-'),
+                        c => members.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                if closed {
+                    translated.push('[');
+                    if negated {
+                        translated.push('^');
+                    }
+                    translated.push_str(&members);
+                    translated.push(']');
+                    chars = set;
+                } else {
+                    translated.push_str("\\[");
+                }
+            }
+            c => translated.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    translated
+}
+
+/*
+ * Handle an archive with the same name in the output directory: fail, overwrite it or give the new
+ * archive the first free suffix.
+ */
+fn resolve_conflict(
+    archive: &mut ArchiveName,
+    dir: &Path,
+    on_conflict: OnConflict,
+    name: &str,
+) -> Result<(), DockerError> {
+    let existing = dir.join(archive.file_name());
+    if !existing.exists() {
+        return Ok(());
+    }
+    match on_conflict {
+        OnConflict::Fail => Err(DockerError::from(
+            format!("Archive {} already exists", existing.display()).as_str(),
+        )),
+        OnConflict::Overwrite => {
+            warn!("[{}] - overwriting {}", name, existing.display());
+            Ok(())
+        }
+        OnConflict::Suffix => {
+            let base = archive.base.clone();
+            let mut suffix = 1;
+            loop {
+                archive.base = format!("{}.~{}", base, suffix);
+                if !dir.join(archive.file_name()).exists() {
+                    break;
+                }
+                suffix += 1;
+            }
+            info!(
+                "[{}] - {} already exists, writing {}",
+                name,
+                existing.display(),
+                archive.file_name()
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+    use std::{fs::File, io::Read, process::Command, sync::Arc};
+
+    use super::*;
+    use crate::{mock::MockExecutor, testing::TestDir, types::ServiceInfo};
+
+    trait Running {
+        fn running(self, name: &str, mounts: &[&str], labels: serde_json::Value) -> Self;
+    }
+
+    impl Running for MockExecutor {
+        /*
+         * A running container with the id <name>-id, a named volume per mount and the labels.
+         */
+        fn running(self, name: &str, mounts: &[&str], labels: serde_json::Value) -> Self {
+            let mounts: Vec<_> = mounts
+                .iter()
+                .map(|destination| {
+                    let volume = format!("{}{}", name, destination.replace('/', "_"));
+                    json!({
+                        "Type": "volume",
+                        "Name": volume,
+                        "Source": format!("/var/lib/docker/volumes/{}/_data", volume),
+                        "Destination": destination,
+                    })
+                })
+                .collect();
+            let info = json!({
+                "Id": format!("{}-id", name),
+                "Mounts": mounts,
+                "Config": {"Labels": labels, "Image": "busybox"},
+            });
+            let ps_info = json!({"Names": name, "State": "running"});
+            self.container(
+                serde_json::from_value(ps_info).unwrap(),
+                serde_json::from_value(info).unwrap(),
+            )
+        }
+    }
+
+    fn service(name: &str, replicas: Option<u64>) -> ServiceInfo {
+        let mode = match replicas {
+            Some(replicas) => json!({"Replicated": {"Replicas": replicas}}),
+            None => json!({"Global": {}}),
+        };
+        serde_json::from_value(json!({"ID": name, "Spec": {"Name": name, "Mode": mode}})).unwrap()
+    }
+
+    /*
+     * Back up with the arguments of the command line into the directory of the test.
+     */
+    fn run_backup(mock: &Arc<MockExecutor>, output_dir: &TestDir, args: &[&str]) -> Outcome {
+        let output = output_dir.path().to_string_lossy();
+        let command_line = ["volbackup", "--output-dir", &output, "--no-progress"];
+        let mut cli_args = CliArguments::parse_from(command_line.iter().chain(args));
+        cli_args.executor = Some(mock.clone());
+        backup(&cli_args.backup, &cli_args).unwrap()
+    }
+
+    /*
+     * The calls changing the state of the containers and services and archiving their mounts, as the operation
+     * and the container without the arguments of the helper.
+     */
+    fn steps(mock: &MockExecutor) -> Vec<String> {
+        mock.calls()
+            .into_iter()
+            .filter_map(|call| {
+                let words: Vec<_> = call.split(' ').collect();
+                match words.as_slice() {
+                    ["run", target, "tar", ..] => Some(format!("tar {}", target)),
+                    ["stop" | "start" | "pause" | "unpause", ..] => Some(call),
+                    ["service", "scale", ..] => Some(call),
+                    ["exec", id, program, ..] => Some(format!("exec {} {}", id, program)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /*
+     * The container, mount and status of every mount in the manifest of the run.
+     */
+    fn mount_statuses(output_dir: &TestDir) -> Vec<(String, String, MountStatus)> {
+        let manifest = fs::read(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: Manifest = serde_json::from_slice(&manifest).unwrap();
+        let mut statuses: Vec<_> = manifest
+            .containers
+            .iter()
+            .flat_map(|container| {
+                container.mounts.iter().map(|mount| {
+                    let name = container.name.clone();
+                    (name, mount.destination.clone(), mount.status)
+                })
+            })
+            .collect();
+        statuses.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        statuses
+    }
+
+    fn status(container: &str, mount: &str, status: MountStatus) -> (String, String, MountStatus) {
+        (container.to_string(), mount.to_string(), status)
+    }
+
+    fn web() -> MockExecutor {
+        MockExecutor::new().running("web", &["/data"], json!({}))
+    }
+
+    #[test]
+    fn stops_the_container_around_its_archives() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        assert_eq!(
+            run_backup(&mock, &output_dir, &["--stop-start"]),
+            Outcome::Success
+        );
+        assert_eq!(steps(&mock), ["stop web-id", "tar web-id", "start web-id"]);
+    }
+
+    #[test]
+    fn starts_the_container_after_a_failed_archive() {
+        let mock = Arc::new(web().fail("run web-id tar", "tar: /data: Cannot open"));
+        let output_dir = TestDir::new();
+        assert_eq!(
+            run_backup(&mock, &output_dir, &["--stop-start"]),
+            Outcome::Failed
+        );
+        assert_eq!(steps(&mock), ["stop web-id", "tar web-id", "start web-id"]);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [status("web", "/data", MountStatus::Failed)]
+        );
+    }
+
+    #[test]
+    fn leaves_the_container_running_without_stop_start() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        assert_eq!(run_backup(&mock, &output_dir, &[]), Outcome::Success);
+        assert_eq!(steps(&mock), ["tar web-id"]);
+    }
+
+    #[test]
+    fn matches_the_whole_name_with_a_regular_expression() {
+        let patterns = |patterns: &[&str]| {
+            NameFilter::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+        };
+        let pg = patterns(&["pg-.*"]);
+        assert!(pg.matches("pg-main"));
+        assert!(pg.matches("web,pg-replica"));
+        assert!(!pg.matches("app-pg-main"));
+        assert!(!pg.matches("pgadmin"));
+        // A name is not taken for a regular expression, the dot matches only itself.
+        let name = patterns(&["app.1"]);
+        assert!(name.matches("app.1"));
+        assert!(!name.matches("app-1"));
+        assert!(!name.matches("app.10"));
+        let either = patterns(&["db|cache"]);
+        assert!(either.matches("cache"));
+        assert!(!either.matches("db-cache"));
+        assert!(NameFilter::new(&["pg-(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn backs_up_the_included_containers_only() {
+        let mock = Arc::new(
+            MockExecutor::new()
+                .running("pg-main", &["/data"], json!({}))
+                .running("app-pg", &["/data"], json!({}))
+                .running("pgadmin", &["/data"], json!({}))
+                .running("pg-test", &["/data"], json!({})),
+        );
+        let output_dir = TestDir::new();
+        let args = ["--include", "pg-.*", "--exclude", ".*-test"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(steps(&mock), ["tar pg-main-id"]);
+    }
+
+    #[test]
+    fn pauses_the_container_around_its_archives() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        assert_eq!(
+            run_backup(&mock, &output_dir, &["--pause"]),
+            Outcome::Success
+        );
+        assert_eq!(
+            steps(&mock),
+            ["pause web-id", "tar web-id", "unpause web-id"]
+        );
+    }
+
+    #[test]
+    fn stopping_by_label_wins_over_pausing() {
+        let labels = json!({"volbackup.stop": "true"});
+        let mock = Arc::new(MockExecutor::new().running("web", &["/data"], labels));
+        let output_dir = TestDir::new();
+        assert_eq!(
+            run_backup(&mock, &output_dir, &["--pause"]),
+            Outcome::Success
+        );
+        assert_eq!(steps(&mock), ["stop web-id", "tar web-id", "start web-id"]);
+    }
+
+    #[test]
+    fn does_not_stop_task_containers_of_a_swarm_service() {
+        let labels = json!({SWARM_SERVICE_LABEL: "shop_web"});
+        let mock = Arc::new(MockExecutor::new().running("web", &["/data"], labels));
+        let output_dir = TestDir::new();
+        assert_eq!(
+            run_backup(&mock, &output_dir, &["--stop-start"]),
+            Outcome::Success
+        );
+        assert_eq!(steps(&mock), ["tar web-id"]);
+    }
+
+    #[test]
+    fn scales_a_replicated_service_down_and_back() {
+        let labels = json!({SWARM_SERVICE_LABEL: "shop_web"});
+        let mock = MockExecutor::new()
+            .running("web", &["/data"], labels)
+            .service(service("shop_web", Some(3)));
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--stop-start", "--swarm-scale"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(
+            steps(&mock),
+            [
+                "service scale shop_web=0",
+                "tar web-id",
+                "service scale shop_web=3"
+            ]
+        );
+    }
+
+    #[test]
+    fn scales_a_service_back_when_scaling_it_down_fails() {
+        let labels = json!({SWARM_SERVICE_LABEL: "shop_web"});
+        let mock = MockExecutor::new()
+            .running("web", &["/data"], labels)
+            .service(service("shop_web", Some(3)))
+            .fail("service scale shop_web=0", "timeout waiting for the tasks");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let output = output_dir.path().to_string_lossy();
+        let command_line = ["volbackup", "--output-dir", &output, "--swarm-scale"];
+        let mut cli_args = CliArguments::parse_from(command_line);
+        cli_args.executor = Some(mock.clone());
+        assert!(backup(&cli_args.backup, &cli_args).is_err());
+        assert_eq!(
+            steps(&mock),
+            ["service scale shop_web=0", "service scale shop_web=3"]
+        );
+    }
+
+    #[test]
+    fn scales_a_service_back_when_its_backup_fails() {
+        let labels = json!({SWARM_SERVICE_LABEL: "shop_web"});
+        let mock = MockExecutor::new()
+            .running("web", &["/data"], labels)
+            .service(service("shop_web", Some(3)))
+            .fail("run web-id tar", "tar: /data: Cannot open");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--swarm-scale"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Failed);
+        assert_eq!(
+            steps(&mock),
+            [
+                "service scale shop_web=0",
+                "tar web-id",
+                "service scale shop_web=3"
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_scale_a_global_service() {
+        let labels = json!({SWARM_SERVICE_LABEL: "monitor"});
+        let mock = MockExecutor::new()
+            .running("agent", &["/data"], labels)
+            .service(service("monitor", None));
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--stop-start", "--swarm-scale"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(steps(&mock), ["tar agent-id"]);
+    }
+
+    #[test]
+    fn counts_the_failed_mounts_of_a_container() {
+        let mock = MockExecutor::new()
+            .running("web", &["/data", "/uploads"], json!({}))
+            .running("db", &["/var/lib/db"], json!({}))
+            .fail("run web-id tar cf - /uploads", "tar: /uploads: Cannot open");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        assert_eq!(run_backup(&mock, &output_dir, &[]), Outcome::Partial);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [
+                status("db", "/var/lib/db", MountStatus::Success),
+                status("web", "/data", MountStatus::Success),
+                status("web", "/uploads", MountStatus::Failed),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_a_conflicting_archive_name() {
+        let dir = TestDir::new();
+        let archive = ArchiveName {
+            base: "web_data".to_string(),
+            level: None,
+            compression: Compression::None,
+            encryption: None,
+        };
+        let resolved = |on_conflict| {
+            let mut archive = archive.clone();
+            resolve_conflict(&mut archive, dir.path(), on_conflict, "web")
+                .map(|_| archive.file_name())
+        };
+        assert_eq!(resolved(OnConflict::Fail).unwrap(), "web_data.tar");
+        File::create(dir.path().join("web_data.tar")).unwrap();
+        assert!(resolved(OnConflict::Fail).is_err());
+        assert_eq!(resolved(OnConflict::Overwrite).unwrap(), "web_data.tar");
+        assert_eq!(resolved(OnConflict::Suffix).unwrap(), "web_data.~1.tar");
+        File::create(dir.path().join("web_data.~1.tar")).unwrap();
+        assert_eq!(resolved(OnConflict::Suffix).unwrap(), "web_data.~2.tar");
+    }
+
+    #[test]
+    fn does_not_back_up_when_the_pre_hook_aborts() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        let args = ["--stop-start", "--pre-hook", "false"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Failed);
+        assert!(steps(&mock).is_empty());
+    }
+
+    #[test]
+    fn backs_up_when_a_failed_pre_hook_continues() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        let args = ["--pre-hook", "false", "--on-hook-failure", "continue"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(steps(&mock), ["tar web-id"]);
+    }
+
+    #[test]
+    fn counts_a_failed_post_hook_as_failed() {
+        let mock = Arc::new(web());
+        let output_dir = TestDir::new();
+        let args = ["--post-hook", "false"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Failed);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [status("web", "/data", MountStatus::Success)]
+        );
+    }
+
+    #[test]
+    fn runs_the_post_exec_command_after_a_failed_pre_exec_command() {
+        let labels = json!({
+            "volbackup.pre-exec": "sync-start",
+            "volbackup.post-exec": "sync-end",
+        });
+        let mock = MockExecutor::new()
+            .running("web", &["/data"], labels)
+            .fail("exec web-id sh -c sync-start", "sync-start: not found");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        assert_eq!(run_backup(&mock, &output_dir, &[]), Outcome::Failed);
+        assert_eq!(steps(&mock), ["exec web-id sh", "exec web-id sh"]);
+    }
+
+    #[test]
+    fn backs_up_when_a_failed_pre_exec_command_continues() {
+        let labels = json!({"volbackup.pre-exec": "sync-start"});
+        let mock = MockExecutor::new()
+            .running("web", &["/data"], labels)
+            .fail("exec web-id sh -c sync-start", "sync-start: not found");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--on-hook-failure", "continue"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(steps(&mock), ["exec web-id sh", "tar web-id"]);
+    }
+
+    /*
+     * The files in the archive of a dump with their contents, decompressing a gzipped archive.
+     */
+    fn dump_files(output_dir: &TestDir, archive: &str) -> Vec<(String, String)> {
+        let archive = fs::read_dir(output_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with(archive) && !name.ends_with(".sha256")
+            })
+            .unwrap();
+        let tar = match archive.extension().and_then(|e| e.to_str()) {
+            Some("gz") => {
+                let gunzip = Command::new("gzip").arg("-dc").arg(&archive).output();
+                gunzip.unwrap().stdout
+            }
+            _ => fs::read(&archive).unwrap(),
+        };
+        tar::Archive::new(tar.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().to_string();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (path, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streams_a_dump_through_the_compression() {
+        let labels = json!({"volbackup.pg-dump": "instead"});
+        let mock = MockExecutor::new()
+            .running("db", &["/var/lib/postgresql/data"], labels)
+            .exec_output("pg_dumpall", b"CREATE TABLE t;\n");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--compress", "gzip"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Success);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [status("db", "/pg_dumpall", MountStatus::Success)]
+        );
+        assert_eq!(
+            dump_files(&output_dir, "db_pg_dumpall-"),
+            [(
+                "pg_dumpall.sql".to_string(),
+                "CREATE TABLE t;\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn streams_an_uncompressed_dump_alongside_the_mounts() {
+        let labels = json!({"volbackup.mysql-dump": "alongside"});
+        let mock = MockExecutor::new()
+            .running("db", &["/var/lib/mysql"], labels)
+            .exec_output("sh", b"CREATE DATABASE app;\n");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        assert_eq!(run_backup(&mock, &output_dir, &[]), Outcome::Success);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [
+                status("db", "/mysqldump", MountStatus::Success),
+                status("db", "/var/lib/mysql", MountStatus::Success),
+            ]
+        );
+        assert_eq!(
+            dump_files(&output_dir, "db_mysqldump-"),
+            [(
+                "mysqldump.sql".to_string(),
+                "CREATE DATABASE app;\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn discards_the_archive_of_a_failed_dump() {
+        let labels = json!({"volbackup.mongo-dump": "instead"});
+        let mock = MockExecutor::new()
+            .running("db", &["/data/db"], labels)
+            .fail("exec db-id sh", "Failed: can't create session: auth error");
+        let mock = Arc::new(mock);
+        let output_dir = TestDir::new();
+        let args = ["--compress", "gzip"];
+        assert_eq!(run_backup(&mock, &output_dir, &args), Outcome::Failed);
+        assert_eq!(
+            mount_statuses(&output_dir),
+            [status("db", "/mongodump", MountStatus::Failed)]
+        );
+        let archives: Vec<_> = fs::read_dir(output_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with("db_"))
+            .collect();
+        assert!(archives.is_empty(), "{:?}", archives);
+    }
+
+    #[test]
+    fn matches_the_exclude_patterns_like_tar() {
+        let globs = ["node_modules", "*.tmp", "cache/[!k]*", "a?c"].map(String::from);
+        let excludes = exclude_regex(&globs).unwrap();
+        for path in [
+            "/app/node_modules",
+            "/app/src/node_modules",
+            "/app/x.tmp",
+            "/app/sub/dir/x.tmp",
+            "/app/cache/data",
+            "/app/abc",
+        ] {
+            assert!(excludes.is_match(path), "{}", path);
+        }
+        for path in [
+            "/app/node_modules_old",
+            "/app/x.tmp.gz",
+            "/app/cache/keep",
+            "/app/abbc",
+            "/app/src",
+        ] {
+            assert!(!excludes.is_match(path), "{}", path);
+        }
+        assert!(exclude_regex(&[]).is_none());
+        assert_eq!(glob_regex("[]a]"), "[\\]a]");
+        assert_eq!(glob_regex("x[y"), "x\\[y");
+    }
+
+    #[test]
+    fn leaves_the_excluded_files_out_of_the_host_size() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.path().join("node_modules/lib")).unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("node_modules/lib/index.js"), [0; 1000]).unwrap();
+        fs::write(dir.path().join("src/main.js"), [0; 100]).unwrap();
+        fs::write(dir.path().join("src/build.tmp"), [0; 10]).unwrap();
+        let size = |globs: &[&str]| {
+            let globs: Vec<_> = globs.iter().map(|glob| glob.to_string()).collect();
+            directory_size(dir.path(), "/app", exclude_regex(&globs).as_ref())
+        };
+        assert_eq!(size(&[]), Some(1110));
+        assert_eq!(size(&["node_modules", "*.tmp"]), Some(100));
+        assert_eq!(size(&["app"]), Some(0));
+    }
+}
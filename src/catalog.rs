@@ -0,0 +1,465 @@
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{manifest::Manifest, types::DockerError, CliArguments};
+
+/*
+ * Name of the catalog database in the output directory, unless configured otherwise.
+ */
+const CATALOG_FILE: &str = "catalog.db";
+
+/*
+ * How long to wait for another run writing to the catalog, like a scheduled backup and a prune at
+ * the same time.
+ */
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*
+ * The tables with the timestamps of the runs.
+ */
+const TIMESTAMP_TABLES: [&str; 3] = ["backups", "inspects", "manifests"];
+
+/*
+ * A timestamp as the catalog records it: in UTC with the same number of digits, so they sort
+ * like the times, like 2026-10-15T01:15:00.000000Z.
+ */
+fn stored(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/*
+ * A backup of a single mount as recorded in the catalog.
+ */
+#[derive(Debug, Serialize)]
+pub struct CatalogEntry {
+    pub timestamp: String,
+    pub container: String,
+    pub destination: String,
+    pub archive: Option<String>,
+    pub size: u64,
+    pub status: String,
+}
+
+/*
+ * A backup run as recorded in the catalog, with its mounts counted.
+ */
+#[derive(Debug, Serialize)]
+pub struct CatalogRun {
+    pub timestamp: String,
+    pub containers: u64,
+    pub mounts: u64,
+    pub failed: u64,
+    pub size: u64,
+}
+
+/*
+ * SQLite database recording every backup, so its history can be queried.
+ */
+pub struct Catalog {
+    connection: Connection,
+}
+
+impl Catalog {
+    /*
+     * The location of the catalog database.
+     */
+    pub fn path(cli_args: &CliArguments) -> PathBuf {
+        cli_args
+            .catalog
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or(Path::new(&cli_args.output_dir).join(CATALOG_FILE))
+    }
+
+    /*
+     * Whether a catalog has been created already.
+     */
+    pub fn exists(cli_args: &CliArguments) -> bool {
+        Catalog::path(cli_args).exists()
+    }
+
+    /*
+     * Open the catalog, creating it when missing.
+     */
+    pub fn open(cli_args: &CliArguments) -> Result<Catalog, DockerError> {
+        let mut connection = Connection::open(Catalog::path(cli_args))?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS backups (
+                id INTEGER PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                container TEXT NOT NULL,
+                container_id TEXT NOT NULL,
+                source TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                archive TEXT,
+                size INTEGER NOT NULL,
+                checksum TEXT,
+                status TEXT NOT NULL,
+                error TEXT
+            );
+            CREATE INDEX IF NOT EXISTS backups_mount ON backups (container, destination);
+            CREATE TABLE IF NOT EXISTS inspects (
+                id INTEGER PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                container TEXT NOT NULL,
+                container_id TEXT NOT NULL,
+                inspect TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS inspects_container ON inspects (container);
+            CREATE TABLE IF NOT EXISTS manifests (
+                id INTEGER PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                manifest TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS manifests_timestamp ON manifests (timestamp);",
+        )?;
+        // The timestamps were recorded in the offset of the run, which does not sort across a
+        // change of the clock.
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < 1 {
+            let transaction = connection.transaction()?;
+            for table in TIMESTAMP_TABLES {
+                let timestamps = transaction
+                    .prepare(&format!("SELECT DISTINCT timestamp FROM {}", table))?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                for timestamp in timestamps {
+                    if let Ok(time) = DateTime::parse_from_rfc3339(&timestamp) {
+                        transaction.execute(
+                            &format!("UPDATE {} SET timestamp = ?1 WHERE timestamp = ?2", table),
+                            params![stored(&time.with_timezone(&Utc)), timestamp],
+                        )?;
+                    }
+                }
+            }
+            transaction.pragma_update(None, "user_version", 1)?;
+            transaction.commit()?;
+        }
+        Ok(Catalog { connection })
+    }
+
+    /*
+     * Record all mounts of a backup run, and its manifest as manifest.json is overwritten by the
+     * next run.
+     */
+    pub fn record_run(&mut self, manifest: &Manifest) -> Result<(), DockerError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO manifests (timestamp, manifest) VALUES (?1, ?2)",
+            params![
+                stored(&manifest.timestamp.with_timezone(&Utc)),
+                serde_json::to_string(manifest)?
+            ],
+        )?;
+        for container in manifest.containers.iter() {
+            for mount in container.mounts.iter() {
+                transaction.execute(
+                    "INSERT INTO backups (timestamp, container, container_id, source, destination,
+                        archive, size, checksum, status, error)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        stored(&manifest.timestamp.with_timezone(&Utc)),
+                        container.name,
+                        container.id,
+                        mount.source,
+                        mount.destination,
+                        mount.archive,
+                        mount.size as i64,
+                        mount.sha256,
+                        serde_json::to_value(mount.status)?.as_str(),
+                        mount.error,
+                    ],
+                )?;
+            }
+            if let Some(inspect) = &container.inspect {
+                transaction.execute(
+                    "INSERT INTO inspects (timestamp, container, container_id, inspect)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        stored(&manifest.timestamp.with_timezone(&Utc)),
+                        container.name,
+                        container.id,
+                        serde_json::to_string(inspect)?,
+                    ],
+                )?;
+            }
+        }
+        Ok(transaction.commit()?)
+    }
+
+    /*
+     * All recorded backups, optionally of a single container, oldest first.
+     */
+    pub fn entries(&self, container: Option<&str>) -> Result<Vec<CatalogEntry>, DockerError> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, container, destination, archive, size, status
+             FROM backups WHERE ?1 IS NULL OR container = ?1 ORDER BY timestamp, id",
+        )?;
+        let entries = statement.query_map(params![container], |row| {
+            Ok(CatalogEntry {
+                timestamp: row.get(0)?,
+                container: row.get(1)?,
+                destination: row.get(2)?,
+                archive: row.get(3)?,
+                size: row.get::<_, i64>(4)? as u64,
+                status: row.get(5)?,
+            })
+        })?;
+        Ok(entries.collect::<Result<Vec<CatalogEntry>, _>>()?)
+    }
+
+    /*
+     * The last backup of every mount of every container, whether it succeeded or not.
+     */
+    pub fn latest(&self) -> Result<Vec<CatalogEntry>, DockerError> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, container, destination, archive, size, status
+             FROM backups WHERE id IN (SELECT MAX(id) FROM backups GROUP BY container, destination)
+             ORDER BY container, destination",
+        )?;
+        let entries = statement.query_map([], |row| {
+            Ok(CatalogEntry {
+                timestamp: row.get(0)?,
+                container: row.get(1)?,
+                destination: row.get(2)?,
+                archive: row.get(3)?,
+                size: row.get::<_, i64>(4)? as u64,
+                status: row.get(5)?,
+            })
+        })?;
+        Ok(entries.collect::<Result<Vec<CatalogEntry>, _>>()?)
+    }
+
+    /*
+     * The last runs, newest first.
+     */
+    pub fn runs(&self, limit: usize) -> Result<Vec<CatalogRun>, DockerError> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, COUNT(DISTINCT container), COUNT(*), SUM(status = 'failed'),
+                SUM(size)
+             FROM backups GROUP BY timestamp ORDER BY timestamp DESC LIMIT ?1",
+        )?;
+        let runs = statement.query_map(params![limit as i64], |row| {
+            Ok(CatalogRun {
+                timestamp: row.get(0)?,
+                containers: row.get::<_, i64>(1)? as u64,
+                mounts: row.get::<_, i64>(2)? as u64,
+                failed: row.get::<_, i64>(3)? as u64,
+                size: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        Ok(runs.collect::<Result<Vec<CatalogRun>, _>>()?)
+    }
+
+    /*
+     * The archive of the latest successful backup of a mount of a container.
+     */
+    pub fn latest_archive(
+        &self,
+        container: &str,
+        destination: &str,
+    ) -> Result<Option<String>, DockerError> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT archive FROM backups
+                 WHERE container = ?1 AND destination = ?2 AND status = 'success'
+                 ORDER BY timestamp DESC, id DESC LIMIT 1",
+                params![container, destination],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /*
+     * The last archives written for a mount of a container with their sizes, newest first, also
+     * those pruned since.
+     */
+    pub fn archive_sizes(
+        &self,
+        container: &str,
+        destination: &str,
+    ) -> Result<Vec<(String, u64)>, DockerError> {
+        let mut statement = self.connection.prepare(
+            "SELECT archive, size FROM backups
+             WHERE container = ?1 AND destination = ?2 AND archive IS NOT NULL
+                AND status IN ('success', 'pruned')
+             ORDER BY timestamp DESC, id DESC LIMIT 50",
+        )?;
+        let sizes = statement.query_map(params![container, destination], |row| {
+            Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+        })?;
+        Ok(sizes.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /*
+     * The start of the run that wrote an archive, if it is recorded.
+     */
+    pub fn archive_timestamp(&self, archive: &str) -> Result<Option<String>, DockerError> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT timestamp FROM backups WHERE archive = ?1 ORDER BY id DESC LIMIT 1",
+                params![archive],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /*
+     * The archive of the latest successful backup of a mount of a container that was made at or
+     * before a time.
+     */
+    pub fn archive_at(
+        &self,
+        container: &str,
+        destination: &str,
+        at: &DateTime<Local>,
+    ) -> Result<Option<String>, DockerError> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, archive FROM backups
+             WHERE container = ?1 AND destination = ?2 AND status = 'success'
+             ORDER BY timestamp DESC, id DESC",
+        )?;
+        let archives = statement.query_map(params![container, destination], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for archive in archives {
+            let (timestamp, archive) = archive?;
+            // Compared to the second as in the archive names.
+            if DateTime::parse_from_rfc3339(&timestamp)
+                .is_ok_and(|timestamp| timestamp.timestamp() <= at.timestamp())
+            {
+                return Ok(Some(archive));
+            }
+        }
+        Ok(None)
+    }
+
+    /*
+     * The manifest of the last run that backed up all mounts of a container, with none of their
+     * archives pruned since.
+     */
+    pub fn last_manifest(&self, container: &str) -> Result<Option<Manifest>, DockerError> {
+        self.manifest_where(
+            "SELECT timestamp FROM backups WHERE container = ?1 GROUP BY timestamp
+             HAVING SUM(status != 'success') = 0 ORDER BY MAX(id) DESC LIMIT 1",
+            container,
+        )
+    }
+
+    /*
+     * The manifest of the run that wrote an archive.
+     */
+    pub fn archive_manifest(&self, archive: &str) -> Result<Option<Manifest>, DockerError> {
+        self.manifest_where(
+            "SELECT timestamp FROM backups WHERE archive = ?1 ORDER BY id DESC LIMIT 1",
+            archive,
+        )
+    }
+
+    /*
+     * The manifest of the run of the first query, selecting its timestamp by the parameter.
+     */
+    fn manifest_where(
+        &self,
+        query: &str,
+        parameter: &str,
+    ) -> Result<Option<Manifest>, DockerError> {
+        let manifest: Option<String> = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT manifest FROM manifests WHERE timestamp = ({})
+                     ORDER BY id DESC LIMIT 1",
+                    query
+                ),
+                params![parameter],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(manifest
+            .map(|manifest| serde_json::from_str(&manifest))
+            .transpose()?)
+    }
+
+    /*
+     * The recorded sha256 of an archive, if any.
+     */
+    pub fn checksum(&self, archive: &str) -> Result<Option<String>, DockerError> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT checksum FROM backups
+                 WHERE archive = ?1 AND checksum IS NOT NULL
+                 ORDER BY timestamp DESC, id DESC LIMIT 1",
+                params![archive],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /*
+     * Mark the backups stored in an archive as pruned.
+     */
+    pub fn prune_archive(&self, archive: &str) -> Result<(), DockerError> {
+        self.connection.execute(
+            "UPDATE backups SET status = 'pruned' WHERE archive = ?1",
+            params![archive],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn orders_the_runs_of_older_versions_by_their_time() {
+        let output_dir = TestDir::new();
+        let output = output_dir.path().to_string_lossy();
+        let cli_args = CliArguments::parse_from(["volbackup", "--output-dir", &output]);
+        // Recorded in the offset of the run, the first one after the clock was set back.
+        let catalog = Catalog::open(&cli_args).unwrap();
+        catalog
+            .connection
+            .execute_batch(
+                "PRAGMA user_version = 0;
+                 INSERT INTO backups (timestamp, container, container_id, source, destination,
+                    archive, size, status)
+                 VALUES ('2026-10-25T02:10:00+01:00', 'web', 'id', 'web_data', '/data',
+                    'web_data-later.tar', 1, 'success'),
+                    ('2026-10-25T02:30:00.5+02:00', 'web', 'id', 'web_data', '/data',
+                    'web_data-earlier.tar', 1, 'success');",
+            )
+            .unwrap();
+        drop(catalog);
+
+        let catalog = Catalog::open(&cli_args).unwrap();
+        assert_eq!(
+            catalog.latest_archive("web", "/data").unwrap().as_deref(),
+            Some("web_data-later.tar")
+        );
+        let runs = catalog.runs(10).unwrap();
+        let timestamps: Vec<_> = runs.iter().map(|run| run.timestamp.as_str()).collect();
+        assert_eq!(
+            timestamps,
+            ["2026-10-25T01:10:00.000000Z", "2026-10-25T00:30:00.500000Z"]
+        );
+        let version: i64 = catalog
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 1);
+    }
+}
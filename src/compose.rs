@@ -0,0 +1,345 @@
+use clap::Args;
+use log::info;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::{ContainerSpec, Manifest, INSPECT_SUFFIX},
+    types::{ContainerInfo, DockerError},
+    CliArguments, COMPOSE_DEPENDS_ON_LABEL, COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL,
+};
+
+/// Generate a docker-compose.yml from the docker inspect output archived with the backups.
+#[derive(Args, Clone)]
+pub struct ExportComposeArguments {
+    /// The containers to export, as services of the compose file
+    #[arg(
+        long = "container",
+        value_name = "NAME",
+        required_unless_present = "project"
+    )]
+    containers: Vec<String>,
+
+    /// Export all backed up containers of this compose project
+    #[arg(long, conflicts_with = "containers")]
+    project: Option<String>,
+
+    /// Write the compose file into this file instead of to stdout
+    #[arg(long, value_name = "FILE")]
+    file: Option<PathBuf>,
+}
+
+/*
+ * The networks every container is in without saying so.
+ */
+const DEFAULT_NETWORKS: [&str; 3] = ["bridge", "host", "none"];
+
+/*
+ * Write a compose file with a service per container, from the <container>.inspect.json files the
+ * backups wrote into the output directory. The named volumes get the driver and options the last
+ * manifest recorded for them.
+ */
+pub fn export_compose(
+    compose_args: &ExportComposeArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let output_dir = Path::new(&cli_args.output_dir);
+    let containers = match &compose_args.project {
+        Some(project) => project_inspects(output_dir, project)?,
+        None => compose_args
+            .containers
+            .iter()
+            .map(|name| Ok((name.clone(), read_inspect(output_dir, name)?)))
+            .collect::<Result<Vec<_>, DockerError>>()?,
+    };
+    let manifest = Manifest::read(output_dir).ok();
+
+    let services = containers
+        .iter()
+        .map(|(name, inspect)| Ok((name, inspect, ContainerInfo::from_inspect(inspect.clone())?)))
+        .collect::<Result<Vec<_>, DockerError>>()?;
+    // Scaled services have several containers, the others are named after their container.
+    let mut service_names = vec![];
+    for (name, _, info) in services.iter() {
+        let service = info
+            .config
+            .labels
+            .get(COMPOSE_SERVICE_LABEL)
+            .filter(|service| !service_names.contains(*service))
+            .unwrap_or(name);
+        service_names.push(service.clone());
+    }
+
+    let mut document = Map::new();
+    if let Some(project) = &compose_args.project {
+        document.insert("name".to_string(), json!(project));
+    }
+    let mut compose_services = Map::new();
+    let mut volumes = Map::new();
+    let mut networks = Map::new();
+    for ((name, inspect, info), service_name) in services.iter().zip(service_names.iter()) {
+        let spec = ContainerSpec::of(info);
+        let mut service = Map::new();
+        service.insert("container_name".to_string(), json!(name));
+        service.insert("image".to_string(), json!(spec.image));
+        if !spec.cmd.is_empty() {
+            service.insert("command".to_string(), json!(spec.cmd));
+        }
+        insert_nonempty(
+            &mut service,
+            "entrypoint",
+            inspect.pointer("/Config/Entrypoint"),
+        );
+        insert_nonempty(
+            &mut service,
+            "working_dir",
+            inspect.pointer("/Config/WorkingDir"),
+        );
+        insert_nonempty(&mut service, "user", inspect.pointer("/Config/User"));
+        if !spec.env.is_empty() {
+            service.insert("environment".to_string(), json!(spec.env));
+        }
+        if !spec.ports.is_empty() {
+            let ports = spec
+                .ports
+                .iter()
+                .map(|port| port.publish())
+                .collect::<Vec<_>>();
+            service.insert("ports".to_string(), json!(ports));
+        }
+        if !spec.mounts.is_empty() {
+            let mounts = spec
+                .mounts
+                .iter()
+                .map(|mount| mount.bind())
+                .collect::<Vec<_>>();
+            service.insert("volumes".to_string(), json!(mounts));
+        }
+        for mount in spec.mounts.iter().filter(|mount| mount.kind == "volume") {
+            let recorded = manifest.as_ref().and_then(|m| m.volume(&mount.source));
+            let mut volume = Map::new();
+            volume.insert("name".to_string(), json!(mount.source));
+            if let Some(recorded) = recorded {
+                volume.insert("driver".to_string(), json!(recorded.driver));
+                if !recorded.options.is_empty() {
+                    volume.insert("driver_opts".to_string(), json!(recorded.options));
+                }
+                let labels = user_labels(recorded.labels.iter());
+                if !labels.is_empty() {
+                    volume.insert("labels".to_string(), Value::Object(labels));
+                }
+            }
+            volumes.insert(mount.source.clone(), Value::Object(volume));
+        }
+        if let Some(policy) = &spec.restart_policy {
+            service.insert("restart".to_string(), json!(policy));
+        }
+        let labels = user_labels(spec.labels.iter());
+        if !labels.is_empty() {
+            service.insert("labels".to_string(), Value::Object(labels));
+        }
+
+        let network_mode = inspect
+            .pointer("/HostConfig/NetworkMode")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let service_networks = inspect
+            .pointer("/NetworkSettings/Networks")
+            .and_then(Value::as_object)
+            .map(|networks| {
+                networks
+                    .keys()
+                    .filter(|network| !DEFAULT_NETWORKS.contains(&network.as_str()))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if service_networks.is_empty() {
+            // Without a network compose would put the service into the default network of the
+            // project.
+            if !network_mode.is_empty() && network_mode != "default" {
+                service.insert("network_mode".to_string(), json!(network_mode));
+            }
+        } else {
+            for network in service_networks.iter() {
+                networks.insert(network.clone(), json!({ "name": network }));
+            }
+            service.insert("networks".to_string(), json!(service_networks));
+        }
+
+        // Like db:service_healthy:true, only the services in the compose file can be waited for.
+        let depends_on = info
+            .config
+            .labels
+            .get(COMPOSE_DEPENDS_ON_LABEL)
+            .map(|dependencies| {
+                dependencies
+                    .split(',')
+                    .filter_map(|dependency| {
+                        let mut parts = dependency.trim().split(':');
+                        let service = parts.next()?;
+                        let condition = parts.next().unwrap_or("service_started");
+                        service_names
+                            .iter()
+                            .any(|name| name == service)
+                            .then(|| (service.to_string(), json!({ "condition": condition })))
+                    })
+                    .collect::<Map<_, _>>()
+            })
+            .unwrap_or_default();
+        if !depends_on.is_empty() {
+            service.insert("depends_on".to_string(), Value::Object(depends_on));
+        }
+        compose_services.insert(service_name.clone(), Value::Object(service));
+    }
+    document.insert("services".to_string(), Value::Object(compose_services));
+    if !volumes.is_empty() {
+        document.insert("volumes".to_string(), Value::Object(volumes));
+    }
+    if !networks.is_empty() {
+        document.insert("networks".to_string(), Value::Object(networks));
+    }
+
+    let mut yaml = String::new();
+    write_yaml(&Value::Object(document), 0, &mut yaml);
+    match &compose_args.file {
+        Some(path) => {
+            File::create(path)?.write_all(yaml.as_bytes())?;
+            info!(
+                "Wrote the compose file of {} containers to {}",
+                containers.len(),
+                path.display()
+            );
+        }
+        None => io::stdout().write_all(yaml.as_bytes())?,
+    }
+    Ok(())
+}
+
+/*
+ * The docker inspect output the last backup of a container archived.
+ */
+fn read_inspect(output_dir: &Path, name: &str) -> Result<Value, DockerError> {
+    let path = output_dir.join(format!("{}{}", name, INSPECT_SUFFIX));
+    let file = File::open(&path).map_err(|e| {
+        DockerError::from(
+            format!(
+                "No docker inspect output of container {} in {}: {}",
+                name,
+                path.display(),
+                e
+            )
+            .as_str(),
+        )
+    })?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+/*
+ * The docker inspect output of the backed up containers of a compose project, by name.
+ */
+fn project_inspects(output_dir: &Path, project: &str) -> Result<Vec<(String, Value)>, DockerError> {
+    let names = fs::read_dir(output_dir)?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|file_name| Some(file_name.strip_suffix(INSPECT_SUFFIX)?.to_string()))
+        .collect::<BTreeSet<_>>();
+    let mut inspects = vec![];
+    for name in names {
+        let inspect = read_inspect(output_dir, &name)?;
+        let labels = inspect.pointer("/Config/Labels");
+        if labels
+            .and_then(|labels| labels.get(COMPOSE_PROJECT_LABEL))
+            .and_then(Value::as_str)
+            == Some(project)
+        {
+            inspects.push((name, inspect));
+        }
+    }
+    if inspects.is_empty() {
+        return Err(DockerError::from(
+            format!(
+                "No backed up container of compose project {} in {}",
+                project,
+                output_dir.display()
+            )
+            .as_str(),
+        ));
+    }
+    Ok(inspects)
+}
+
+/*
+ * The labels without those compose sets itself on the containers and volumes it creates.
+ */
+fn user_labels<'a>(labels: impl Iterator<Item = (&'a String, &'a String)>) -> Map<String, Value> {
+    labels
+        .filter(|(label, _)| !label.starts_with("com.docker.compose."))
+        .map(|(label, value)| (label.clone(), json!(value)))
+        .collect()
+}
+
+/*
+ * Insert a string or list of the inspect output unless it is missing or empty.
+ */
+fn insert_nonempty(service: &mut Map<String, Value>, key: &str, value: Option<&Value>) {
+    let empty = match value {
+        Some(Value::String(string)) => string.is_empty(),
+        Some(Value::Array(items)) => items.is_empty(),
+        _ => true,
+    };
+    if let (false, Some(value)) = (empty, value) {
+        service.insert(key.to_string(), value.clone());
+    }
+}
+
+/*
+ * Write a value as yaml in block style. Strings are written as json strings, which yaml reads as
+ * double quoted strings, so no value is mistaken for a number or a boolean.
+ */
+fn write_yaml(value: &Value, indent: usize, yaml: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let plain = !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+                let key = match plain {
+                    true => key.clone(),
+                    false => Value::String(key.clone()).to_string(),
+                };
+                yaml.push_str(&format!("{}{}:", pad, key));
+                write_nested(value, indent, yaml);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                yaml.push_str(&format!("{}-", pad));
+                write_nested(item, indent, yaml);
+            }
+        }
+        scalar => yaml.push_str(&format!("{}{}\n", pad, scalar)),
+    }
+}
+
+/*
+ * Write the value of a key or a list item, a collection on the lines below it and a scalar on the
+ * same line.
+ */
+fn write_nested(value: &Value, indent: usize, yaml: &mut String) {
+    match value {
+        Value::Object(map) if map.is_empty() => yaml.push_str(" {}\n"),
+        Value::Array(items) if items.is_empty() => yaml.push_str(" []\n"),
+        Value::Object(_) | Value::Array(_) => {
+            yaml.push('\n');
+            write_yaml(value, indent + 2, yaml);
+        }
+        scalar => yaml.push_str(&format!(" {}\n", scalar)),
+    }
+}
@@ -0,0 +1,100 @@
+use clap::ValueEnum;
+use std::process::Command;
+
+use crate::types::DockerError;
+
+/*
+ * Compression of the tar stream, done host-side by piping through the compressor.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /*
+     * All compressions, used to recognize archives by their extension.
+     */
+    pub const ALL: [Compression; 4] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Xz,
+    ];
+
+    /*
+     * The extension of an archive with this compression.
+     */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => ".tar",
+            Compression::Gzip => ".tar.gz",
+            Compression::Zstd => ".tar.zst",
+            Compression::Xz => ".tar.xz",
+        }
+    }
+
+    /*
+     * Determine the compression of an archive from its filename.
+     */
+    pub fn from_archive_name(name: &str) -> Option<Compression> {
+        Compression::ALL
+            .into_iter()
+            .find(|c| name.ends_with(c.extension()))
+    }
+
+    /*
+     * Check that a compression level is valid for this compression.
+     */
+    pub fn validate_level(&self, level: Option<u32>) -> Result<(), DockerError> {
+        let range = match self {
+            Compression::None => return Ok(()),
+            Compression::Gzip => 1..=9,
+            Compression::Zstd => 1..=19,
+            Compression::Xz => 0..=9,
+        };
+        match level {
+            Some(level) if !range.contains(&level) => Err(DockerError::Config(format!(
+                "Compression level {} is not valid for {:?}, use {} to {}",
+                level,
+                self,
+                range.start(),
+                range.end()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+     * The host command compressing stdin to stdout, if any.
+     */
+    pub fn compress_command(&self, level: Option<u32>) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        command.arg("-c");
+        if let Some(level) = level {
+            command.arg(format!("-{}", level));
+        }
+        Some(command)
+    }
+
+    /*
+     * The host command decompressing stdin to stdout, if any.
+     */
+    pub fn decompress_command(&self) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        command.args(["-d", "-c"]);
+        Some(command)
+    }
+
+    fn program(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+            Compression::Xz => Some("xz"),
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use clap::{Arg, ArgAction, Command, Id, ValueEnum};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compression::Compression, dump::DumpMode, labels::ContainerLabels, redis::RedisSnapshot,
+    schedule::Cron, types::DockerError,
+};
+
+/*
+ * The configuration file used when --config is not given and there is no user configuration file.
+ */
+const SYSTEM_CONFIG: &str = "/etc/volbackup.toml";
+
+/*
+ * The prefix of the environment variables setting the options.
+ */
+const ENV_PREFIX: &str = "VOLBACKUP_";
+
+/*
+ * A configuration file: the options as defaults for the command line, per command in a section
+ * named after the command, and the settings per container in the [containers.<name>] sections.
+ */
+#[derive(Debug, Default)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    options: toml::Table,
+    pub containers: HashMap<String, ContainerConfig>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    containers: HashMap<String, ContainerConfig>,
+    #[serde(flatten)]
+    options: toml::Table,
+}
+
+/*
+ * The backup settings of a container from the configuration file, like its labels.
+ */
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ContainerConfig {
+    pub enable: Option<bool>,
+    pub stop: Option<bool>,
+    pub pause: Option<bool>,
+    pub compress: Option<String>,
+    pub exclude: Vec<String>,
+    pub exclude_pattern: Vec<String>,
+    pub include_path: Vec<String>,
+    pub image: Option<String>,
+    pub dest: Option<String>,
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    pub pre_exec: Option<String>,
+    pub post_exec: Option<String>,
+    pub pg_dump: Option<String>,
+    pub mysql_dump: Option<String>,
+    pub mongo_dump: Option<String>,
+    pub mongo_uri: Option<String>,
+    pub mongo_uri_file: Option<String>,
+    pub dump_user: Option<String>,
+    pub dump_password: Option<String>,
+    pub dump_env_file: Option<String>,
+    pub redis_snapshot: Option<String>,
+    pub schedule: Option<String>,
+}
+
+impl Config {
+    /*
+     * Read the configuration file given with --config or VOLBACKUP_CONFIG, or else the user
+     * configuration file or the system configuration file when they exist.
+     */
+    pub fn load(args: &[OsString]) -> Result<Self, DockerError> {
+        let Some(path) = config_argument(args)
+            .or_else(|| {
+                env::var_os(format!("{}CONFIG", ENV_PREFIX)).filter(|path| !path.is_empty())
+            })
+            .map(PathBuf::from)
+            .or_else(default_path)
+        else {
+            return Ok(Config::default());
+        };
+        let text = fs::read_to_string(&path).map_err(|e| {
+            DockerError::Config(format!(
+                "Can not read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let file: ConfigFile = toml::from_str(&text).map_err(|e| {
+            DockerError::Config(format!("Invalid config file {}: {}", path.display(), e))
+        })?;
+        for (name, container) in &file.containers {
+            container.labels().map_err(|e| {
+                DockerError::Config(format!(
+                    "Invalid settings for container {} in {}: {}",
+                    name,
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(Config {
+            path: Some(path),
+            options: file.options,
+            containers: file.containers,
+        })
+    }
+
+    /*
+     * Use the options of the configuration file as the defaults of the arguments of the command, so
+     * the command line overrides them. Options outside a section apply to all commands having them,
+     * the options of the backup section also to the backup without a command, to the schedule
+     * and to the server.
+     */
+    pub fn apply(&self, mut command: Command) -> Result<Command, DockerError> {
+        let path = self.path.as_deref().unwrap_or(Path::new(""));
+        let source = format!("config file {}", path.display());
+        for (key, value) in &self.options {
+            let toml::Value::Table(section) = value else {
+                let mut known = set_default(&mut command, key, value, &source)?;
+                for subcommand in command.get_subcommands_mut() {
+                    known |= set_default(subcommand, key, value, &source)?;
+                }
+                if !known {
+                    return Err(unknown_setting(key, path));
+                }
+                continue;
+            };
+            let Some(subcommand) = command.find_subcommand_mut(key) else {
+                return Err(DockerError::Config(format!(
+                    "Unknown section [{}] in config file {}",
+                    key,
+                    path.display()
+                )));
+            };
+            for (option, value) in section {
+                let name = format!("{}.{}", key, option);
+                if !set_default(subcommand, option, value, &source)? {
+                    return Err(unknown_setting(&name, path));
+                }
+            }
+            if key == "backup" {
+                for (option, value) in section {
+                    set_default(&mut command, option, value, &source)?;
+                    for name in ["schedule", "serve"] {
+                        if let Some(subcommand) = command.find_subcommand_mut(name) {
+                            set_default(subcommand, option, value, &source)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(command)
+    }
+}
+
+impl ContainerConfig {
+    /*
+     * The settings as if they were given by labels.
+     */
+    pub fn labels(&self) -> Result<ContainerLabels, DockerError> {
+        let paths = |paths: &[String]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.trim_end_matches('/').to_string())
+                .filter(|path| !path.is_empty())
+                .collect()
+        };
+        let mode = |value: &Option<String>, key: &str| {
+            value
+                .as_ref()
+                .map(|value| {
+                    DumpMode::from_str(value, true).map_err(|_| {
+                        DockerError::Config(format!("Invalid value {} for {}", value, key))
+                    })
+                })
+                .transpose()
+        };
+        Ok(ContainerLabels {
+            enable: self.enable,
+            exclude: paths(&self.exclude),
+            exclude_pattern: self.exclude_pattern.clone(),
+            include_path: paths(&self.include_path),
+            stop: self.stop,
+            pause: self.pause,
+            compress: self
+                .compress
+                .as_ref()
+                .map(|value| {
+                    Compression::from_str(value, true).map_err(|_| {
+                        DockerError::Config(format!("Invalid value {} for compress", value))
+                    })
+                })
+                .transpose()?,
+            pre_exec: self.pre_exec.clone(),
+            post_exec: self.post_exec.clone(),
+            pg_dump: mode(&self.pg_dump, "pg-dump")?,
+            mysql_dump: mode(&self.mysql_dump, "mysql-dump")?,
+            mongo_dump: mode(&self.mongo_dump, "mongo-dump")?,
+            mongo_uri: self.mongo_uri.clone(),
+            mongo_uri_file: self.mongo_uri_file.clone(),
+            dump_user: self.dump_user.clone(),
+            dump_password: self.dump_password.clone(),
+            dump_env_file: self.dump_env_file.clone(),
+            redis_snapshot: self
+                .redis_snapshot
+                .as_ref()
+                .map(|value| {
+                    RedisSnapshot::from_str(value, true).map_err(|_| {
+                        DockerError::Config(format!("Invalid value {} for redis-snapshot", value))
+                    })
+                })
+                .transpose()?,
+            schedule: self
+                .schedule
+                .as_ref()
+                .map(|value| {
+                    Cron::parse(value).map(|_| value.clone()).map_err(|e| {
+                        DockerError::Config(format!("Invalid value {} for schedule: {}", value, e))
+                    })
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/*
+ * Read the options from VOLBACKUP_<OPTION> environment variables, like VOLBACKUP_OUTPUT_DIR, which
+ * override the configuration file. The values of lists are separated by commas.
+ */
+pub fn apply_env(mut command: Command) -> Result<Command, DockerError> {
+    set_env(&mut command)?;
+    for subcommand in command.get_subcommands_mut() {
+        set_env(subcommand)?;
+    }
+    Ok(command)
+}
+
+/*
+ * Let clap read the options of a command from their environment variables, except for the lists
+ * which become the defaults instead as clap would take the variable as a single value.
+ */
+fn set_env(command: &mut Command) -> Result<(), DockerError> {
+    let options: Vec<(Id, String, bool)> = command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+            Some((arg.get_id().clone(), long.to_string(), takes_list(arg)))
+        })
+        .collect();
+    for (id, long, list) in options {
+        let name = format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase());
+        if !list {
+            *command = std::mem::take(command).mut_arg(id, |arg| arg.env(name));
+            continue;
+        }
+        let Some(value) = env::var_os(&name) else {
+            continue;
+        };
+        let value = value
+            .into_string()
+            .map_err(|_| DockerError::Config(format!("Invalid value for {}: not UTF-8", name)))?;
+        let values = value
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| toml::Value::String(value.to_string()))
+            .collect();
+        set_default(command, &long, &toml::Value::Array(values), &name)?;
+    }
+    Ok(())
+}
+
+/*
+ * Whether an argument takes several values.
+ */
+fn takes_list(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+        || arg
+            .get_num_args()
+            .is_some_and(|range| range.max_values() > 1)
+}
+
+/*
+ * Make a value of the configuration file or of an environment variable the default of the
+ * argument with the same long name, returning whether the command has such an argument.
+ */
+fn set_default(
+    command: &mut Command,
+    key: &str,
+    value: &toml::Value,
+    source: &str,
+) -> Result<bool, DockerError> {
+    let Some(arg) = command.get_arguments().find(|arg| {
+        arg.get_long() == Some(key)
+            && arg.get_id() != "config"
+            && !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version)
+    }) else {
+        return Ok(false);
+    };
+    let invalid = |reason: &str| {
+        DockerError::Config(format!(
+            "Invalid value for {} in {}: {}",
+            key, source, reason
+        ))
+    };
+    let values = match value {
+        toml::Value::Array(items) => items.iter().map(scalar).collect::<Option<Vec<_>>>(),
+        value => scalar(value).map(|value| vec![value]),
+    }
+    .ok_or_else(|| invalid("expected a value or a list of values"))?;
+    if values.len() != 1 && !takes_list(arg) {
+        return Err(invalid("expected a single value"));
+    }
+    // Check the values with the parser of the argument, a bad default only fails when it is used.
+    let check = Command::new("config").no_binary_name(true).arg(
+        Arg::new("value")
+            .long(key.to_string())
+            .value_name(key.replace('-', "_").to_uppercase())
+            .value_parser(arg.get_value_parser().clone())
+            .action(ArgAction::Append),
+    );
+    check
+        .try_get_matches_from(values.iter().map(|value| format!("--{}={}", key, value)))
+        .map_err(|e| {
+            let message = e.to_string();
+            let reason = message.lines().next().unwrap_or_default();
+            invalid(reason.trim_start_matches("error: "))
+        })?;
+    let id = arg.get_id().clone();
+    *command = std::mem::take(command).mut_arg(id, |arg| arg.default_values(values));
+    Ok(true)
+}
+
+/*
+ * A single value of the configuration file as a command line value.
+ */
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+fn unknown_setting(key: &str, path: &Path) -> DockerError {
+    DockerError::Config(format!(
+        "Unknown setting {} in config file {}",
+        key,
+        path.display()
+    ))
+}
+
+/*
+ * The value of --config on the command line, which is needed before parsing it.
+ */
+fn config_argument(args: &[OsString]) -> Option<OsString> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(OsString::from(value));
+        }
+    }
+    None
+}
+
+/*
+ * The configuration file in $XDG_CONFIG_HOME (or ~/.config) or else the system configuration file,
+ * if it exists.
+ */
+fn default_path() -> Option<PathBuf> {
+    let user_config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("volbackup").join("config.toml"));
+    user_config
+        .into_iter()
+        .chain([PathBuf::from(SYSTEM_CONFIG)])
+        .find(|path| path.is_file())
+}
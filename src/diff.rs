@@ -0,0 +1,198 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Read,
+    path::PathBuf,
+};
+
+use crate::{
+    exec::{run_helper_into, Helper},
+    list::read_archive,
+    storage::Destination,
+    types::DockerError,
+    CliArguments,
+};
+
+/*
+ * The modification time and the size of a file that is not a directory, the size is None for
+ * symlinks and other special files.
+ */
+type FileState = (u64, Option<u64>);
+
+/*
+ * What a restore would change in the live files of a mount.
+ */
+#[derive(Debug, Default)]
+pub struct RestoreDiff {
+    pub overwritten: Vec<String>,
+    pub added: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl RestoreDiff {
+    /*
+     * Print a line per changed file, like ~ data/etc/config.yml for an overwritten file.
+     */
+    pub fn print(&self) {
+        let mut lines = self
+            .overwritten
+            .iter()
+            .map(|path| (path, '~'))
+            .chain(self.added.iter().map(|path| (path, '+')))
+            .chain(self.deleted.iter().map(|path| (path, '-')))
+            .collect::<Vec<_>>();
+        lines.sort();
+        for (path, change) in lines {
+            println!("{} {}", change, path);
+        }
+    }
+}
+
+/*
+ * Compare the files of the archives, replayed in order like a restore extracts them, with the
+ * live files of the mount. The live files are listed in a helper container with the target of the
+ * restore, without a target the mount is empty like a new volume.
+ */
+pub fn restore_diff(
+    archives: &[PathBuf],
+    target: Option<&Helper>,
+    mount: &str,
+    paths: &[String],
+    destination: &Destination,
+    age_identity: Option<&str>,
+    cli_args: &CliArguments,
+) -> Result<RestoreDiff, DockerError> {
+    // Paths are compared the way tar stores them, like data/etc/config.yml.
+    let mount = mount.trim_matches('/');
+    let members = paths
+        .iter()
+        .map(|path| format!("{}/{}", mount, path.trim_matches('/')))
+        .collect::<Vec<_>>();
+    let selected = |path: &str| {
+        members.is_empty()
+            || members
+                .iter()
+                .any(|member| path == member || path.starts_with(format!("{}/", member).as_str()))
+    };
+
+    let live = match target {
+        Some(target) => live_files(target, mount, cli_args)?,
+        None => BTreeMap::new(),
+    };
+    let mut restored = live.clone();
+    let mut extracted = BTreeSet::new();
+    for archive in archives {
+        read_archive(archive, destination, age_identity, cli_args, |mut entry| {
+            let path = entry
+                .path()?
+                .to_string_lossy()
+                .trim_matches('/')
+                .to_string();
+            if !selected(&path) {
+                return Ok(());
+            }
+            let header = entry.header();
+            let mtime = header.mtime()?;
+            match header.entry_type().as_byte() {
+                // The dumpdir of an incremental archive lists what is left in the directory, the
+                // other files are deleted when it is extracted.
+                b'D' => {
+                    let mut contents = vec![];
+                    entry.read_to_end(&mut contents)?;
+                    let kept = dumpdir_names(&contents);
+                    let prefix = format!("{}/", path);
+                    restored.retain(|file, _| match file.strip_prefix(prefix.as_str()) {
+                        Some(rest) => kept.contains(rest.split('/').next().unwrap_or(rest)),
+                        None => true,
+                    });
+                }
+                b'5' => {}
+                _ => {
+                    let size = header.entry_type().is_file().then(|| entry.size());
+                    restored.insert(path.clone(), (mtime, size));
+                    extracted.insert(path);
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let mut diff = RestoreDiff::default();
+    for (path, state) in restored.iter() {
+        match live.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(live_state) if extracted.contains(path) && !same(live_state, state) => {
+                diff.overwritten.push(path.clone())
+            }
+            Some(_) if extracted.contains(path) => diff.unchanged += 1,
+            Some(_) => {}
+        }
+    }
+    diff.deleted = live
+        .keys()
+        .filter(|path| selected(path) && !restored.contains_key(*path))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+/*
+ * Whether a live file has the modification time and size of the file in the archive.
+ */
+fn same(live: &FileState, archived: &FileState) -> bool {
+    live.0 == archived.0 && archived.1.is_none_or(|size| live.1 == Some(size))
+}
+
+/*
+ * The names of the files in a GNU dumpdir, which are null terminated and start with a letter
+ * telling whether they are in the archive (Y), unchanged (N) or a directory (D).
+ */
+fn dumpdir_names(contents: &[u8]) -> BTreeSet<String> {
+    contents
+        .split(|b| *b == 0)
+        .filter(|name| matches!(name.first(), Some(b'Y' | b'N' | b'D')))
+        .map(|name| String::from_utf8_lossy(&name[1..]).to_string())
+        .collect()
+}
+
+/*
+ * The files below the mount that are not directories, listed with stat in a helper container.
+ */
+fn live_files(
+    target: &Helper,
+    mount: &str,
+    cli_args: &CliArguments,
+) -> Result<BTreeMap<String, FileState>, DockerError> {
+    let mut helper = target.clone();
+    helper.command = [
+        "find",
+        format!("/{}", mount).as_str(),
+        "!",
+        "-type",
+        "d",
+        "-exec",
+        "stat",
+        "-c",
+        "%Y %s %F %n",
+        "{}",
+        "+",
+    ]
+    .map(String::from)
+    .to_vec();
+    let mut output = vec![];
+    run_helper_into(&helper, vec![], &mut output, cli_args)?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .filter_map(|line| {
+            let (mtime, rest) = line.split_once(' ')?;
+            let (size, rest) = rest.split_once(' ')?;
+            // The file type has spaces, like regular file, the path starts at the mount.
+            let (kind, path) = rest.split_once(format!(" /{}", mount).as_str())?;
+            let size = match kind {
+                "regular file" | "regular empty file" => Some(size.parse().ok()?),
+                _ => None,
+            };
+            Some((format!("{}{}", mount, path), (mtime.parse().ok()?, size)))
+        })
+        .collect())
+}
@@ -0,0 +1,426 @@
+use clap::ValueEnum;
+use std::{
+    fs,
+    io::{self, Write},
+    time::SystemTime,
+};
+
+use crate::{exec::exec_in_container_into, types::DockerError, types::Mounts, CliArguments};
+
+/*
+ * Whether the databases of a database container are dumped, as tarring the files of a running
+ * database does not give a consistent backup.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DumpMode {
+    /// Only archive the mounts
+    Off,
+    /// Archive a dump next to the mounts
+    Alongside,
+    /// Archive a dump instead of the mount holding the data directory
+    Instead,
+}
+
+/*
+ * The databases that can be dumped.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Database {
+    Postgres,
+    Mysql,
+    Mongodb,
+}
+
+impl Database {
+    /*
+     * The database an image looks like: postgres, bitnami/postgresql, postgis/postgis and
+     * timescale/timescaledb, mysql, mariadb, percona and bitnami/mysql, or mongo, bitnami/mongodb
+     * and mongodb/mongodb-community-server.
+     */
+    pub fn detect(image: &str) -> Option<Database> {
+        let repository = image.split('@').next().unwrap_or_default();
+        let name = repository.rsplit('/').next().unwrap_or_default();
+        let name = name.split(':').next().unwrap_or_default();
+        let is = |prefixes: &[&str]| prefixes.iter().any(|prefix| name.starts_with(prefix));
+        if is(&["postgres", "postgis", "timescaledb"]) {
+            Some(Database::Postgres)
+        } else if is(&["mysql", "mariadb", "percona"]) {
+            Some(Database::Mysql)
+        } else if is(&["mongo"]) {
+            Some(Database::Mongodb)
+        } else {
+            None
+        }
+    }
+
+    /*
+     * The dump tool, which also names the archive of the dump.
+     */
+    pub fn tool(&self) -> &'static str {
+        match self {
+            Database::Postgres => "pg_dumpall",
+            Database::Mysql => "mysqldump",
+            Database::Mongodb => "mongodump",
+        }
+    }
+
+    /*
+     * The name of the dump in its archive.
+     */
+    pub fn file(&self) -> String {
+        match self {
+            Database::Mongodb => format!("{}.archive", self.tool()),
+            _ => format!("{}.sql", self.tool()),
+        }
+    }
+
+    /*
+     * The pseudo mount standing for the dump in the plan and the manifest.
+     */
+    pub fn mount(&self) -> Mounts {
+        Mounts {
+            kind: None,
+            name: None,
+            source: self.file(),
+            destination: format!("/{}", self.tool()),
+            rw: true,
+        }
+    }
+
+    /*
+     * Whether a mount holds the data directory of the database, by default that of the official
+     * image.
+     */
+    pub fn holds_data(&self, mount: &Mounts, env: &[String]) -> bool {
+        let data = match self {
+            Database::Postgres => variable(env, "PGDATA").unwrap_or("/var/lib/postgresql/data"),
+            Database::Mysql => "/var/lib/mysql",
+            Database::Mongodb => "/data/db",
+        };
+        let destination = mount.destination.trim_end_matches('/');
+        data == destination || data.starts_with(&format!("{}/", destination))
+    }
+
+    /*
+     * Dump all databases of the container into the writer, returning the number of bytes written.
+     * The user and password default to those in the environment of the container, the password
+     * is passed in the environment of the dump tool.
+     */
+    pub fn dump(
+        &self,
+        cli_args: &CliArguments,
+        id: &str,
+        env: &[String],
+        credentials: &Credentials,
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        match self {
+            Database::Postgres => {
+                let user = credentials
+                    .user
+                    .as_deref()
+                    .or(variable(env, "POSTGRES_USER"))
+                    .unwrap_or("postgres");
+                let password = credentials
+                    .password
+                    .as_deref()
+                    .or(variable(env, "POSTGRES_PASSWORD"));
+                let command = ["pg_dumpall", "--username", user];
+                let env: Vec<(&str, &str)> =
+                    password.map(|p| ("PGPASSWORD", p)).into_iter().collect();
+                exec_in_container_into(cli_args, id, &command, &env, output)
+            }
+            Database::Mysql => {
+                let (user, password) = mysql_credentials(env, credentials);
+                let user = format!("--user={}", user);
+                // MariaDB 11 only has mariadb-dump.
+                let command = [
+                    "sh",
+                    "-c",
+                    "exec \"$(command -v mariadb-dump || echo mysqldump)\" \"$@\"",
+                    "sh",
+                    "--all-databases",
+                    "--single-transaction",
+                    &user,
+                ];
+                let env: Vec<(&str, &str)> =
+                    password.map(|p| ("MYSQL_PWD", p)).into_iter().collect();
+                exec_in_container_into(cli_args, id, &command, &env, output)
+            }
+            Database::Mongodb => {
+                // The shell in the container puts the secrets on the command line of mongodump,
+                // the uri file is read in the container like the docker secrets.
+                let command = [
+                    "sh",
+                    "-c",
+                    concat!(
+                        "exec mongodump --archive",
+                        " ${MONGODUMP_URI:+--uri=\"$MONGODUMP_URI\"}",
+                        " ${MONGODUMP_URI_FILE:+--uri=\"$(cat \"$MONGODUMP_URI_FILE\")\"}",
+                        " ${MONGODUMP_USER:+--username=\"$MONGODUMP_USER\"",
+                        " --password=\"$MONGODUMP_PASSWORD\" --authenticationDatabase=admin}"
+                    ),
+                ];
+                // The uri has the credentials in it.
+                let uri = credentials.uri.is_some() || credentials.uri_file.is_some();
+                let user = credentials
+                    .user
+                    .as_deref()
+                    .or(variable(env, "MONGO_INITDB_ROOT_USERNAME"));
+                let password = credentials
+                    .password
+                    .as_deref()
+                    .or(variable(env, "MONGO_INITDB_ROOT_PASSWORD"));
+                let env: Vec<(&str, &str)> = [
+                    ("MONGODUMP_URI", credentials.uri.as_deref()),
+                    ("MONGODUMP_URI_FILE", credentials.uri_file.as_deref()),
+                    ("MONGODUMP_USER", user.filter(|_| !uri)),
+                    ("MONGODUMP_PASSWORD", password),
+                ]
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|value| (name, value)))
+                .collect();
+                exec_in_container_into(cli_args, id, &command, &env, output)
+            }
+        }
+    }
+}
+
+/*
+ * The largest part of a dump in its archive. A tar has the size of a file before its content, so
+ * a dump is streamed in parts held in memory instead of being staged in a file.
+ */
+const PART_SIZE: usize = 64 << 20;
+
+/*
+ * Writes a dump as a tar into the output as it comes. A dump that fits in a part is the file
+ * itself, like pg_dumpall.sql, a larger one is split into pg_dumpall.sql.000, pg_dumpall.sql.001
+ * and so on, which tar xO writes out in order.
+ */
+pub struct DumpArchive<W: Write> {
+    builder: tar::Builder<Counted<W>>,
+    file: String,
+    part: Vec<u8>,
+    part_size: usize,
+    parts: usize,
+}
+
+impl<W: Write> DumpArchive<W> {
+    pub fn new(output: W, database: Database) -> Self {
+        DumpArchive {
+            builder: tar::Builder::new(Counted {
+                inner: output,
+                count: 0,
+            }),
+            file: database.file(),
+            part: vec![],
+            part_size: PART_SIZE,
+            parts: 0,
+        }
+    }
+
+    fn append(&mut self, name: &str) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(self.part.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        self.builder
+            .append_data(&mut header, name, self.part.as_slice())?;
+        self.part.clear();
+        Ok(())
+    }
+
+    /*
+     * Write the rest of the dump and the end of the tar, returning the size of the tar.
+     */
+    pub fn finish(mut self) -> io::Result<u64> {
+        let name = match self.parts {
+            0 => self.file.clone(),
+            parts => format!("{}.{:03}", self.file, parts),
+        };
+        self.append(&name)?;
+        Ok(self.builder.into_inner()?.count)
+    }
+}
+
+impl<W: Write> Write for DumpArchive<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full part is only written when more follows, so a small dump keeps its name.
+        if self.part.len() == self.part_size && !buf.is_empty() {
+            let name = format!("{}.{:03}", self.file, self.parts);
+            self.append(&name)?;
+            self.parts += 1;
+        }
+        let taken = buf.len().min(self.part_size - self.part.len());
+        self.part.extend_from_slice(&buf[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/*
+ * A writer counting the bytes written into it.
+ */
+struct Counted<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/*
+ * The credentials for the dump from the labels, taken from the environment of the container when
+ * missing. The connection uri, or the file in the container holding it, is only used by mongodump.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct Credentials {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub uri: Option<String>,
+    pub uri_file: Option<String>,
+}
+
+/*
+ * The user and password for mysqldump: root with the root password when the container has one,
+ * or else the user created by the image.
+ */
+fn mysql_credentials<'a>(
+    env: &'a [String],
+    credentials: &'a Credentials,
+) -> (&'a str, Option<&'a str>) {
+    if let Some(user) = credentials.user.as_deref() {
+        return (user, credentials.password.as_deref());
+    }
+    let image = |name: &str| {
+        variable(env, &format!("MYSQL_{}", name)).or(variable(env, &format!("MARIADB_{}", name)))
+    };
+    if let Some(password) = image("ROOT_PASSWORD") {
+        return ("root", credentials.password.as_deref().or(Some(password)));
+    }
+    match image("USER") {
+        Some(user) => (user, credentials.password.as_deref().or(image("PASSWORD"))),
+        None => ("root", credentials.password.as_deref()),
+    }
+}
+
+/*
+ * The environment of the container with the variables of an env file added, like the env_file of
+ * a compose service.
+ */
+pub fn environment(env: &[String], env_file: Option<&str>) -> Result<Vec<String>, DockerError> {
+    let mut env = env.to_vec();
+    if let Some(env_file) = env_file {
+        let content = fs::read_to_string(env_file).map_err(|e| {
+            DockerError::from(format!("Can not read env file {}: {}", env_file, e).as_str())
+        })?;
+        env.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| {
+                    let value = value.trim();
+                    let unquoted = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .or(value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                        .unwrap_or(value);
+                    format!("{}={}", key.trim(), unquoted)
+                }),
+        );
+    }
+    Ok(env)
+}
+
+/*
+ * The value of an environment variable of the container, the last one when it is set more than
+ * once. None when it is not set or empty.
+ */
+fn variable<'a>(env: &'a [String], name: &str) -> Option<&'a str> {
+    env.iter()
+        .filter_map(|variable| variable.split_once('='))
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .next_back()
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    /*
+     * The files of a dump archive with their contents, with parts of the size.
+     */
+    fn archived(dump: &[u8], part_size: usize) -> Vec<(String, Vec<u8>)> {
+        let mut output = vec![];
+        let mut archive = DumpArchive::new(&mut output, Database::Postgres);
+        archive.part_size = part_size;
+        archive.write_all(dump).unwrap();
+        let size = archive.finish().unwrap();
+        assert_eq!(size, output.len() as u64);
+        tar::Archive::new(output.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().to_string();
+                let mut content = vec![];
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn archives_a_small_dump_as_one_file() {
+        let files = archived(b"CREATE TABLE t;", 16);
+        assert_eq!(
+            files,
+            [("pg_dumpall.sql".into(), b"CREATE TABLE t;".to_vec())]
+        );
+        assert_eq!(archived(b"", 16), [("pg_dumpall.sql".into(), vec![])]);
+    }
+
+    #[test]
+    fn splits_a_large_dump_into_parts() {
+        let files = archived(b"0123456789abcdefXYZ", 8);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "pg_dumpall.sql.000",
+                "pg_dumpall.sql.001",
+                "pg_dumpall.sql.002"
+            ]
+        );
+        let content: Vec<u8> = files.into_iter().flat_map(|(_, content)| content).collect();
+        assert_eq!(content, b"0123456789abcdefXYZ");
+    }
+
+    #[test]
+    fn does_not_split_a_dump_of_exactly_one_part() {
+        let files = archived(b"01234567", 8);
+        assert_eq!(files, [("pg_dumpall.sql".into(), b"01234567".to_vec())]);
+    }
+}
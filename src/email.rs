@@ -0,0 +1,134 @@
+use chrono::Local;
+use log::{error, info};
+use std::{env, process::Command};
+
+use crate::{
+    exec::{curl_config, retry, run_command_with_input, CurlConfig},
+    notify::Finished,
+    types::DockerError,
+    CliArguments,
+};
+
+/*
+ * Where and how the report of a run is mailed.
+ */
+pub struct Email<'a> {
+    pub smtp_url: &'a str,
+    pub user: Option<&'a str>,
+    pub from: &'a str,
+    pub to: &'a [String],
+}
+
+/*
+ * Mail the summary of the run with the report attached, sent by curl. An smtps:// url is TLS from
+ * the start, an smtp:// url has to upgrade with STARTTLS. The password is taken from
+ * SMTP_PASSWORD, or from ~/.netrc. A failing mail is retried and then logged, it does not fail
+ * the backup.
+ */
+pub fn send(email: &Email, finished: &Finished, cli_args: &CliArguments) {
+    let message = match message(email, finished) {
+        Ok(message) => message,
+        Err(e) => {
+            error!("Could not make the mail: {}", e);
+            return;
+        }
+    };
+    info!("Mailing the report to {}", email.to.join(", "));
+    let operation = format!("Mail to {}", email.to.join(", "));
+    if let Err(e) = retry(cli_args, &operation, || {
+        let (command, _credentials) = curl(email)?;
+        run_command_with_input(command, Some(message.as_bytes())).map(|_| ())
+    }) {
+        error!("Could not mail the report: {}", e);
+    }
+}
+
+/*
+ * The curl command sending the mail read from its stdin, with the config holding the password
+ * when there is a user, so the password is not on its command line.
+ */
+fn curl(email: &Email) -> Result<(Command, Option<CurlConfig>), DockerError> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--netrc-optional", "--ssl-reqd"]);
+    command.args(["--url", email.smtp_url, "--mail-from", email.from]);
+    for to in email.to {
+        command.args(["--mail-rcpt", to.as_str()]);
+    }
+    command.args(["--upload-file", "-"]);
+    let credentials = email
+        .user
+        .map(|user| {
+            let password = env::var("SMTP_PASSWORD").unwrap_or_default();
+            let config = curl_config(&[("user", &format!("{}:{}", user, password))]);
+            CurlConfig::create(&mut command, &config)
+        })
+        .transpose()?;
+    Ok((command, credentials))
+}
+
+/*
+ * The mail with the summary as the text and the report as a json attachment.
+ */
+fn message(email: &Email, finished: &Finished) -> Result<String, DockerError> {
+    let boundary = format!("volbackup-{}", Local::now().timestamp_micros());
+    let headers = [
+        format!("From: {}", email.from),
+        format!("To: {}", email.to.join(", ")),
+        format!("Subject: {}", finished.title()),
+        format!("Date: {}", Local::now().to_rfc2822()),
+        "MIME-Version: 1.0".to_string(),
+        format!("Content-Type: multipart/mixed; boundary=\"{}\"", boundary),
+    ];
+    let parts = [
+        vec![
+            "Content-Type: text/plain; charset=utf-8".to_string(),
+            "Content-Transfer-Encoding: base64".to_string(),
+            String::new(),
+            base64_lines(finished.text().as_bytes()),
+        ],
+        vec![
+            "Content-Type: application/json; name=\"report.json\"".to_string(),
+            "Content-Disposition: attachment; filename=\"report.json\"".to_string(),
+            "Content-Transfer-Encoding: base64".to_string(),
+            String::new(),
+            base64_lines(finished.report()?.as_bytes()),
+        ],
+    ];
+    let mut lines = headers.to_vec();
+    lines.push(String::new());
+    for part in parts {
+        lines.push(format!("--{}", boundary));
+        lines.extend(part);
+    }
+    lines.push(format!("--{}--", boundary));
+    // Mail has CRLF line endings.
+    Ok(lines.join("\r\n") + "\r\n")
+}
+
+/*
+ * Base64 in lines of 76 characters, as mail wants it.
+ */
+fn base64_lines(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    lines.join("\r\n")
+}
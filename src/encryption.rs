@@ -0,0 +1,87 @@
+use std::{path::Path, process::Command};
+
+use crate::types::DockerError;
+
+/*
+ * Encryption of the archive stream, done host-side by piping through the encryption tool.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encryption {
+    Age,
+    Gpg,
+}
+
+impl Encryption {
+    pub const ALL: [Encryption; 2] = [Encryption::Age, Encryption::Gpg];
+
+    /*
+     * The extension added to the archive name by this encryption.
+     */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encryption::Age => ".age",
+            Encryption::Gpg => ".gpg",
+        }
+    }
+
+    /*
+     * Split the encryption extension off an archive filename.
+     */
+    pub fn split_archive_name(name: &str) -> (&str, Option<Encryption>) {
+        Encryption::ALL
+            .into_iter()
+            .find_map(|e| name.strip_suffix(e.extension()).map(|rest| (rest, Some(e))))
+            .unwrap_or((name, None))
+    }
+
+    /*
+     * The host command encrypting stdin to stdout for the given recipients.
+     */
+    pub fn encrypt_command(&self, recipients: &[String]) -> Command {
+        let mut command = Command::new(self.program());
+        match self {
+            Encryption::Age => {
+                for recipient in recipients {
+                    // A recipient can also be a file with (multiple) recipients.
+                    if Path::new(recipient).is_file() {
+                        command.args(["-R", recipient.as_str()]);
+                    } else {
+                        command.args(["-r", recipient.as_str()]);
+                    }
+                }
+            }
+            Encryption::Gpg => {
+                command.args(["--batch", "--encrypt", "--output", "-"]);
+                for recipient in recipients {
+                    command.args(["--recipient", recipient.as_str()]);
+                }
+            }
+        }
+        command
+    }
+
+    /*
+     * The host command decrypting stdin to stdout, gpg uses the keys of the local gpg agent.
+     */
+    pub fn decrypt_command(&self, identity: Option<&str>) -> Result<Command, DockerError> {
+        let mut command = Command::new(self.program());
+        match (self, identity) {
+            (Encryption::Age, Some(identity)) => command.args(["-d", "-i", identity]),
+            (Encryption::Age, None) => {
+                return Err(DockerError::Config(
+                    "Decrypting an age archive requires an identity file (--age-identity)"
+                        .to_string(),
+                ))
+            }
+            (Encryption::Gpg, _) => command.args(["--quiet", "--decrypt", "--output", "-"]),
+        };
+        Ok(command)
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Encryption::Age => "age",
+            Encryption::Gpg => "gpg",
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use std::process::{Command, Stdio};
+
+use crate::types::DockerError;
+
+/*
+ * What to do when a pre or post hook fails.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum HookFailure {
+    /// Do not back up the container when its pre hook fails, count it as failed when its post hook
+    /// fails
+    Abort,
+    /// Log a warning and carry on
+    Continue,
+}
+
+/*
+ * The hooks: the pre and post hooks run on the host, the pre-exec and post-exec commands in the
+ * container.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    Pre,
+    Post,
+    PreExec,
+    PostExec,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::Pre => "pre",
+            Hook::Post => "post",
+            Hook::PreExec => "pre-exec",
+            Hook::PostExec => "post-exec",
+        }
+    }
+}
+
+/*
+ * Run a hook for a container, logging the output of the command. Returns whether the backup can
+ * go on.
+ */
+pub fn run_hook(
+    hook: Hook,
+    name: &str,
+    on_failure: HookFailure,
+    execute: impl FnOnce() -> Result<String, DockerError>,
+) -> bool {
+    info!("[{}] - running {} hook", name, hook.name());
+    let result = execute().map(|output| {
+        for line in output.lines() {
+            info!("[{}] - {} hook: {}", name, hook.name(), line);
+        }
+    });
+    match result {
+        Ok(()) => true,
+        Err(e) if on_failure == HookFailure::Continue => {
+            warn!(
+                "[{}] - {} hook failed, continuing: {}",
+                name,
+                hook.name(),
+                e
+            );
+            true
+        }
+        Err(e) => {
+            warn!("[{}] - {} hook failed: {}", name, hook.name(), e);
+            false
+        }
+    }
+}
+
+/*
+ * Run a hook command on the host with the shell and return its output. A post hook is told whether
+ * the backup succeeded.
+ */
+pub fn host_hook(
+    hook: Hook,
+    command: &str,
+    name: &str,
+    success: Option<bool>,
+) -> Result<String, DockerError> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell
+        .arg(command)
+        .env("VOLBACKUP_HOOK", hook.name())
+        .env("VOLBACKUP_HOOK_CONTAINER", name)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+    if let Some(success) = success {
+        let status = if success { "success" } else { "failed" };
+        shell.env("VOLBACKUP_HOOK_STATUS", status);
+    }
+    debug!("Execute {:?}", shell);
+    // The output is logged as the archives may go to stdout.
+    let output = shell
+        .output()
+        .map_err(|e| DockerError::from(format!("Could not start {}: {}", command, e).as_str()))?;
+    if !output.status.success() {
+        return Err(DockerError::from(output.status.to_string().as_str()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
@@ -0,0 +1,151 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    exec::{host_path, remote_daemon, visible_to_daemon, Helper},
+    types::DockerError,
+    CliArguments,
+};
+
+/*
+ * Directory in the output directory holding the tar snapshot files and chains.
+ */
+pub const SNAPSHOT_DIR: &str = ".snapshots";
+
+/*
+ * The chain of incremental archives of a mount, the level 0 archive first.
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Chain {
+    pub archives: Vec<String>,
+}
+
+/*
+ * Incremental backup state of a single mount, using tar --listed-incremental snapshots.
+ */
+pub struct Incremental {
+    snapshot_dir: PathBuf,
+    base: String,
+}
+
+impl Incremental {
+    pub fn new(output_dir: &Path, base: &str) -> Self {
+        Incremental {
+            snapshot_dir: output_dir.join(SNAPSHOT_DIR),
+            // The archives of a name template with subdirectories share the snapshot directory.
+            base: base.replace('/', "_"),
+        }
+    }
+
+    /*
+     * Load the current chain, empty if there is none yet.
+     */
+    pub fn load_chain(&self) -> Result<Chain, DockerError> {
+        let path = self.chain_path();
+        if !path.exists() {
+            return Ok(Chain::default());
+        }
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /*
+     * Determine the level of the next archive and prepare the snapshot file tar will update.
+     * A new chain is started after `full_every` incremental archives.
+     */
+    pub fn prepare(&self, full_every: u32) -> Result<u32, DockerError> {
+        fs::create_dir_all(&self.snapshot_dir)?;
+        let pending = self.pending_snapshot_path();
+        if pending.exists() {
+            fs::remove_file(&pending)?;
+        }
+        let level = self.next_level(full_every)?;
+        if level == 0 {
+            info!("Starting a new incremental chain for {}", self.base);
+            return Ok(0);
+        }
+        fs::copy(self.snapshot_path(), pending)?;
+        Ok(level)
+    }
+
+    /*
+     * The level of the next archive, without touching the snapshot files.
+     */
+    pub fn next_level(&self, full_every: u32) -> Result<u32, DockerError> {
+        let level = self.load_chain()?.archives.len() as u32;
+        if level == 0 || level > full_every || !self.snapshot_path().exists() {
+            return Ok(0);
+        }
+        Ok(level)
+    }
+
+    /*
+     * Make tar in the helper container use the pending snapshot file. The snapshot directory is
+     * bind mounted, for a remote daemon or a daemon of the user that does not see the directory
+     * the snapshot file is copied into the helper and back.
+     */
+    pub fn configure_helper(
+        &self,
+        helper: &mut Helper,
+        cli_args: &CliArguments,
+    ) -> Result<(), DockerError> {
+        let copied = remote_daemon(cli_args) || !visible_to_daemon(&self.snapshot_dir, cli_args)?;
+        if !copied {
+            let snapshot_dir = host_path(&self.snapshot_dir)?;
+            helper
+                .volumes
+                .push(format!("{}:/backupsnap", snapshot_dir.display()));
+            helper.command.push(format!(
+                "--listed-incremental=/backupsnap/{}",
+                self.pending_snapshot_file()
+            ));
+            return Ok(());
+        }
+        let pending = self.pending_snapshot_path();
+        if pending.exists() {
+            helper.copy_in.push((pending, "/tmp".to_string()));
+        }
+        let snapshot = format!("/tmp/{}", self.pending_snapshot_file());
+        helper
+            .command
+            .push(format!("--listed-incremental={}", snapshot));
+        helper
+            .copy_out
+            .push((snapshot, host_path(&self.snapshot_dir)?));
+        Ok(())
+    }
+
+    /*
+     * Record a successfully written archive of the given level in the chain.
+     */
+    pub fn commit(&self, level: u32, archive: &str) -> Result<(), DockerError> {
+        fs::rename(self.pending_snapshot_path(), self.snapshot_path())?;
+        let mut chain = if level == 0 {
+            Chain::default()
+        } else {
+            self.load_chain()?
+        };
+        chain.archives.push(archive.to_string());
+        serde_json::to_writer_pretty(File::create(self.chain_path())?, &chain)?;
+        Ok(())
+    }
+
+    fn chain_path(&self) -> PathBuf {
+        self.snapshot_dir.join(format!("{}.chain.json", self.base))
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.snapshot_dir.join(format!("{}.snar", self.base))
+    }
+
+    fn pending_snapshot_file(&self) -> String {
+        format!("{}.snar.new", self.base)
+    }
+
+    fn pending_snapshot_path(&self) -> PathBuf {
+        self.snapshot_dir.join(self.pending_snapshot_file())
+    }
+}
@@ -0,0 +1,182 @@
+use futures_util::future::{self, Either};
+use log::{error, info, warn};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use crate::{
+    exec::{list_containers, remove_container, resume_container, Suspension},
+    CliArguments, HELPER_PID_LABEL,
+};
+
+/*
+ * What an interrupted run has to clean up besides its helper containers, which are found by their
+ * label: the containers it stopped or paused, the services it scaled down, the partial archives
+ * it is writing and the files with credentials.
+ */
+struct Cleanup {
+    suspended: Vec<Suspended>,
+    partial: Vec<PathBuf>,
+}
+
+struct Suspended {
+    key: u64,
+    cli_args: CliArguments,
+    name: String,
+    id: String,
+    suspension: Suspension,
+}
+
+static CLEANUP: Mutex<Cleanup> = Mutex::new(Cleanup {
+    suspended: vec![],
+    partial: vec![],
+});
+
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+/*
+ * The containers this process stopped, until their stop is seen in the events of the daemon.
+ */
+static STOPPED: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+fn cleanup() -> MutexGuard<'static, Cleanup> {
+    // A panic while holding the lock does not make the list wrong.
+    CLEANUP.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/*
+ * Remember a container that was stopped or paused, or a service scaled down, returning the key to
+ * forget it again when it is resumed.
+ */
+pub fn suspended(cli_args: &CliArguments, name: &str, id: &str, suspension: Suspension) -> u64 {
+    let key = NEXT_KEY.fetch_add(1, Ordering::SeqCst);
+    if suspension == Suspension::Stopped {
+        let mut stopped = STOPPED.lock().unwrap_or_else(|e| e.into_inner());
+        stopped.push(id.to_string());
+    }
+    cleanup().suspended.push(Suspended {
+        key,
+        cli_args: cli_args.clone(),
+        name: name.to_string(),
+        id: id.to_string(),
+        suspension,
+    });
+    key
+}
+
+pub fn resumed(key: u64) {
+    cleanup().suspended.retain(|suspended| suspended.key != key);
+}
+
+/*
+ * Whether the container was stopped by this process, forgetting that it was.
+ */
+pub fn stopped_here(id: &str) -> bool {
+    let mut stopped = STOPPED.lock().unwrap_or_else(|e| e.into_inner());
+    let index = stopped.iter().position(|stopped| stopped == id);
+    index.map(|index| stopped.remove(index)).is_some()
+}
+
+/*
+ * Remember a partial archive, or a file with credentials, until it is renamed or removed.
+ */
+pub fn writing(path: &Path) {
+    cleanup().partial.push(path.to_path_buf());
+}
+
+pub fn written(path: &Path) {
+    cleanup().partial.retain(|partial| partial != path);
+}
+
+/*
+ * Handle Ctrl-C and SIGTERM: remove the helper containers of this run, resume the containers it
+ * stopped or paused and remove the partial archives and the files with credentials, then exit
+ * with 128 plus the signal number like a shell reports it.
+ */
+pub fn install(cli_args: &CliArguments) {
+    let cli_args = cli_args.clone();
+    let spawned = thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            let signal = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .and_then(|runtime| runtime.block_on(signalled()));
+            match signal {
+                Ok((signal, code)) => {
+                    warn!("Interrupted by {}, cleaning up", signal);
+                    clean_up(&cli_args);
+                    process::exit(code);
+                }
+                Err(e) => warn!("Can not handle the signals: {}", e),
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Can not handle the signals: {}", e);
+    }
+}
+
+#[cfg(unix)]
+async fn signalled() -> io::Result<(&'static str, i32)> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let interrupted = Box::pin(interrupt.recv());
+    let terminated = Box::pin(terminate.recv());
+    let signal = match future::select(interrupted, terminated).await {
+        Either::Left(_) => ("SIGINT", 130),
+        Either::Right(_) => ("SIGTERM", 143),
+    };
+    Ok(signal)
+}
+
+#[cfg(not(unix))]
+async fn signalled() -> io::Result<(&'static str, i32)> {
+    tokio::signal::ctrl_c().await?;
+    Ok(("Ctrl-C", 130))
+}
+
+/*
+ * Clean up after an interrupted run. The lock is kept until the process exits, so the backup can
+ * not stop another container meanwhile.
+ */
+fn clean_up(cli_args: &CliArguments) {
+    let mut cleanup = cleanup();
+    let label = format!("{}={}", HELPER_PID_LABEL, process::id());
+    match list_containers(cli_args, true, &[label]) {
+        Ok(helpers) => {
+            for helper in helpers {
+                info!("Removing helper container {}", helper.names);
+                if let Err(e) = remove_container(cli_args, &helper.names) {
+                    error!("Could not remove helper container {}: {}", helper.names, e);
+                }
+            }
+        }
+        Err(e) => error!("Could not list the helper containers: {}", e),
+    }
+    // The containers stopped last are started first, like a project is started in order.
+    for suspended in cleanup.suspended.drain(..).rev() {
+        let resumed = resume_container(
+            &suspended.cli_args,
+            &suspended.name,
+            &suspended.id,
+            suspended.suspension,
+        );
+        if let Err(e) = resumed {
+            error!("[{}] Could not resume the container: {}", suspended.name, e);
+        }
+    }
+    for partial in cleanup.partial.drain(..) {
+        info!("Removing {}", partial.display());
+        if let Err(e) = fs::remove_file(&partial) {
+            error!("Could not remove {}: {}", partial.display(), e);
+        }
+    }
+}
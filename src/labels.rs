@@ -0,0 +1,171 @@
+use clap::ValueEnum;
+use std::collections::HashMap;
+
+use crate::{compression::Compression, dump::DumpMode, redis::RedisSnapshot, types::DockerError};
+
+/*
+ * The labels enabling or disabling the backup of a container and overriding the options for it.
+ */
+pub const ENABLE_LABEL: &str = "volbackup.enable";
+const EXCLUDE_LABEL: &str = "volbackup.exclude";
+const EXCLUDE_PATTERN_LABEL: &str = "volbackup.exclude-pattern";
+const INCLUDE_PATH_LABEL: &str = "volbackup.include-path";
+const STOP_LABEL: &str = "volbackup.stop";
+const PAUSE_LABEL: &str = "volbackup.pause";
+const COMPRESS_LABEL: &str = "volbackup.compress";
+const PRE_EXEC_LABEL: &str = "volbackup.pre-exec";
+const POST_EXEC_LABEL: &str = "volbackup.post-exec";
+const PG_DUMP_LABEL: &str = "volbackup.pg-dump";
+const MYSQL_DUMP_LABEL: &str = "volbackup.mysql-dump";
+const MONGO_DUMP_LABEL: &str = "volbackup.mongo-dump";
+const MONGO_URI_LABEL: &str = "volbackup.mongo-uri";
+const MONGO_URI_FILE_LABEL: &str = "volbackup.mongo-uri-file";
+const DUMP_USER_LABEL: &str = "volbackup.dump-user";
+const DUMP_PASSWORD_LABEL: &str = "volbackup.dump-password";
+const DUMP_ENV_FILE_LABEL: &str = "volbackup.dump-env-file";
+const REDIS_SNAPSHOT_LABEL: &str = "volbackup.redis-snapshot";
+pub const SCHEDULE_LABEL: &str = "volbackup.schedule";
+
+/*
+ * The backup settings of a container from its labels, None when the label is not set.
+ */
+#[derive(Debug, Default)]
+pub struct ContainerLabels {
+    pub enable: Option<bool>,
+    pub exclude: Vec<String>,
+    pub exclude_pattern: Vec<String>,
+    pub include_path: Vec<String>,
+    pub stop: Option<bool>,
+    pub pause: Option<bool>,
+    pub compress: Option<Compression>,
+    pub pre_exec: Option<String>,
+    pub post_exec: Option<String>,
+    pub pg_dump: Option<DumpMode>,
+    pub mysql_dump: Option<DumpMode>,
+    pub mongo_dump: Option<DumpMode>,
+    pub mongo_uri: Option<String>,
+    pub mongo_uri_file: Option<String>,
+    pub dump_user: Option<String>,
+    pub dump_password: Option<String>,
+    pub dump_env_file: Option<String>,
+    pub redis_snapshot: Option<RedisSnapshot>,
+    pub schedule: Option<String>,
+}
+
+impl ContainerLabels {
+    pub fn parse(labels: &HashMap<String, String>) -> Result<Self, DockerError> {
+        let invalid = |label: &str, value: &str| {
+            DockerError::from(format!("Invalid value {} for label {}", value, label).as_str())
+        };
+        let flag = |label: &str| match labels.get(label).map(String::as_str) {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => Err(invalid(label, value)),
+        };
+        let list = |label: &str| -> Vec<String> {
+            labels
+                .get(label)
+                .map(|values| {
+                    values
+                        .split(',')
+                        .map(|value| value.trim().to_string())
+                        .filter(|value| !value.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let paths = |label: &str| -> Vec<String> {
+            list(label)
+                .iter()
+                .map(|path| path.trim_end_matches('/').to_string())
+                .filter(|path| !path.is_empty())
+                .collect()
+        };
+        let command = |label: &str| {
+            labels
+                .get(label)
+                .map(|command| command.trim().to_string())
+                .filter(|command| !command.is_empty())
+        };
+        let mode = |label: &str| {
+            labels
+                .get(label)
+                .map(|value| DumpMode::from_str(value, true).map_err(|_| invalid(label, value)))
+                .transpose()
+        };
+        Ok(ContainerLabels {
+            enable: flag(ENABLE_LABEL)?,
+            exclude: paths(EXCLUDE_LABEL),
+            exclude_pattern: list(EXCLUDE_PATTERN_LABEL),
+            include_path: paths(INCLUDE_PATH_LABEL),
+            stop: flag(STOP_LABEL)?,
+            pause: flag(PAUSE_LABEL)?,
+            compress: labels
+                .get(COMPRESS_LABEL)
+                .map(|value| {
+                    Compression::from_str(value, true).map_err(|_| invalid(COMPRESS_LABEL, value))
+                })
+                .transpose()?,
+            pre_exec: command(PRE_EXEC_LABEL),
+            post_exec: command(POST_EXEC_LABEL),
+            pg_dump: mode(PG_DUMP_LABEL)?,
+            mysql_dump: mode(MYSQL_DUMP_LABEL)?,
+            mongo_dump: mode(MONGO_DUMP_LABEL)?,
+            mongo_uri: command(MONGO_URI_LABEL),
+            mongo_uri_file: command(MONGO_URI_FILE_LABEL),
+            dump_user: command(DUMP_USER_LABEL),
+            dump_password: labels.get(DUMP_PASSWORD_LABEL).cloned(),
+            dump_env_file: command(DUMP_ENV_FILE_LABEL),
+            redis_snapshot: labels
+                .get(REDIS_SNAPSHOT_LABEL)
+                .map(|value| {
+                    RedisSnapshot::from_str(value, true)
+                        .map_err(|_| invalid(REDIS_SNAPSHOT_LABEL, value))
+                })
+                .transpose()?,
+            schedule: command(SCHEDULE_LABEL),
+        })
+    }
+
+    /*
+     * The settings of the labels, falling back to the other settings for the labels that are not
+     * set. The lists are combined.
+     */
+    pub fn or(self, other: ContainerLabels) -> ContainerLabels {
+        let combine = |mut values: Vec<String>, other: Vec<String>| {
+            values.extend(other);
+            values
+        };
+        ContainerLabels {
+            enable: self.enable.or(other.enable),
+            exclude: combine(self.exclude, other.exclude),
+            exclude_pattern: combine(self.exclude_pattern, other.exclude_pattern),
+            include_path: combine(self.include_path, other.include_path),
+            stop: self.stop.or(other.stop),
+            pause: self.pause.or(other.pause),
+            compress: self.compress.or(other.compress),
+            pre_exec: self.pre_exec.or(other.pre_exec),
+            post_exec: self.post_exec.or(other.post_exec),
+            pg_dump: self.pg_dump.or(other.pg_dump),
+            mysql_dump: self.mysql_dump.or(other.mysql_dump),
+            mongo_dump: self.mongo_dump.or(other.mongo_dump),
+            mongo_uri: self.mongo_uri.or(other.mongo_uri),
+            mongo_uri_file: self.mongo_uri_file.or(other.mongo_uri_file),
+            dump_user: self.dump_user.or(other.dump_user),
+            dump_password: self.dump_password.or(other.dump_password),
+            dump_env_file: self.dump_env_file.or(other.dump_env_file),
+            redis_snapshot: self.redis_snapshot.or(other.redis_snapshot),
+            schedule: self.schedule.or(other.schedule),
+        }
+    }
+
+    /*
+     * Whether a mount is excluded by its destination.
+     */
+    pub fn excludes(&self, destination: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|mount| mount == destination.trim_end_matches('/'))
+    }
+}
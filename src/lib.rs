@@ -0,0 +1,295 @@
+//! Back up the volumes of docker containers into tar archives, stopping the containers around
+//! the backup, and restore them again. The `volbackup` binary is a command line over this crate,
+//! other tools can plan, run and restore backups with [`BackupPlanner`], [`BackupRunner`] and
+//! [`RestoreRunner`]:
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use volbackup::{BackupRunner, CliArguments, Outcome};
+//!
+//! let cli_args = CliArguments::parse_from(["volbackup", "--output-dir", "/backups"]);
+//! match BackupRunner::new(&cli_args).containers(["web"]).run() {
+//!     Ok(Outcome::Success) => println!("All volumes backed up"),
+//!     Ok(outcome) => println!("Backup finished: {:?}", outcome),
+//!     Err(e) => eprintln!("Backup failed: {}", e),
+//! }
+//! ```
+//!
+//! The arguments are those of the command line, so an embedding tool takes the same options and
+//! defaults. The crate logs through the `log` crate and leaves setting up a logger to the tool.
+//!
+//! Docker is talked to through a [`DockerExecutor`], the one of `--backend` unless another one is
+//! given in [`CliArguments::executor`]. A [`MockExecutor`] simulates the daemon, so the backup logic
+//! runs without docker:
+//!
+//! ```
+//! use clap::Parser;
+//! use std::sync::Arc;
+//! use volbackup::{BackupPlanner, CliArguments, MockExecutor};
+//!
+//! let ps_info = serde_json::from_str(r#"{"Names": "web", "State": "running"}"#).unwrap();
+//! let info = serde_json::from_str(
+//!     r#"{"Id": "1234", "Mounts": [{"Destination": "/data"}], "Config": {}}"#,
+//! )
+//! .unwrap();
+//! let mock = Arc::new(MockExecutor::new().container(ps_info, info));
+//! let mut cli_args = CliArguments::parse_from(["volbackup"]);
+//! cli_args.executor = Some(mock.clone());
+//!
+//! let planned = BackupPlanner::new(&cli_args).plan().unwrap();
+//! assert_eq!(planned[0].mounts, ["/data"]);
+//! assert_eq!(mock.calls(), ["ps", "inspect web"]);
+//! ```
+
+use clap::{Parser, Subcommand};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+mod api;
+mod archive;
+pub mod backup;
+pub mod catalog;
+pub mod compose;
+mod compression;
+pub mod config;
+mod diff;
+mod dump;
+mod email;
+mod encryption;
+pub mod exec;
+mod hooks;
+mod incremental;
+pub mod interrupt;
+mod labels;
+pub mod list;
+pub mod lock;
+pub mod logging;
+mod manifest;
+mod metrics;
+pub mod migrate;
+mod mock;
+mod notify;
+pub mod progress;
+pub mod prune;
+mod redis;
+mod report;
+pub mod restore;
+mod runner;
+pub mod schedule;
+pub mod serve;
+mod storage;
+mod trace;
+pub mod types;
+pub mod verify;
+
+#[cfg(test)]
+mod testing;
+
+pub use backup::Outcome;
+pub use exec::{ApiExecutor, CliExecutor, DockerExecutor};
+pub use manifest::{ContainerSpec, MountSpec, PortSpec};
+pub use mock::MockExecutor;
+pub use runner::{BackupPlanner, BackupRunner, PlannedContainer, RestoreRunner};
+pub use types::DockerError;
+
+const TYPE_BACKUPCONTAINER: &str = "docker-volbackup";
+const HELPER_PID_LABEL: &str = "volbackup.pid";
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const COMPOSE_DEPENDS_ON_LABEL: &str = "com.docker.compose.depends_on";
+const SWARM_SERVICE_LABEL: &str = "com.docker.swarm.service.name";
+
+/// Backup all mounted volumes connected to a running container.
+#[derive(Parser, Clone)]
+pub struct CliArguments {
+    /// The configuration file with the defaults of the options and the settings per container
+    /// [default: $XDG_CONFIG_HOME/volbackup/config.toml or /etc/volbackup.toml, when they exist]
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// The image to use for running a volume backup
+    #[arg(short, long, default_value = "ubuntu", global = true)]
+    image: String,
+
+    /// Arguments for the levels, the target and the file of the log
+    #[command(flatten)]
+    pub log: logging::LogArguments,
+
+    /// Where to find the docker executable [default: docker, or podman when there is no docker, on
+    /// the PATH or in the usual install locations]
+    #[arg(short, long, global = true)]
+    docker: Option<String>,
+
+    /// The docker daemon to connect to, like ssh://user@server or tcp://server:2376
+    #[arg(short = 'H', long, global = true)]
+    host: Option<String>,
+
+    /// The CA certificate to verify the daemon with [default: ca.pem in DOCKER_CERT_PATH or ~/.docker]
+    #[arg(long, global = true, conflicts_with = "context")]
+    tls_ca: Option<String>,
+
+    /// The client certificate for daemons that require mutual TLS [default: cert.pem in DOCKER_CERT_PATH or ~/.docker]
+    #[arg(long, global = true, conflicts_with = "context")]
+    tls_cert: Option<String>,
+
+    /// The key of the client certificate [default: key.pem in DOCKER_CERT_PATH or ~/.docker]
+    #[arg(long, global = true, conflicts_with = "context")]
+    tls_key: Option<String>,
+
+    /// The docker context to use, overriding DOCKER_HOST and the current context
+    #[arg(long, global = true, conflicts_with = "host")]
+    context: Option<String>,
+
+    /// Talk to docker by running the docker executable or through the Docker Engine API socket, or
+    /// answer from the --fixtures without docker
+    #[arg(long, value_enum, default_value = "cli", global = true)]
+    backend: exec::Backend,
+
+    /// The directory with the ps.json, inspect/<NAME>.json, volumes.json and events.json that
+    /// --backend mock answers with instead of a docker daemon, for testing
+    #[arg(long, value_name = "DIR", global = true)]
+    fixtures: Option<PathBuf>,
+
+    /// Retry a failed inspect, stop, start, pause or unpause of a container, a failed scale of a
+    /// swarm service and a failed archive of a mount this many times, for the hiccups of the
+    /// daemon
+    #[arg(long, default_value = "0", global = true)]
+    retries: u32,
+
+    /// The wait before the first retry, doubled for every next retry
+    #[arg(long, value_name = "SECONDS", default_value = "1", global = true)]
+    retry_delay: u64,
+
+    /// The directory containing the backup archives, created if missing
+    #[arg(short, long, default_value = ".", global = true)]
+    output_dir: String,
+
+    /// The names of the archives, with the variables {container}, {mount} (like _data), {date},
+    /// {time}, {run-id} and {hostname}. A / puts the archives in subdirectories
+    #[arg(
+        long,
+        global = true,
+        default_value = archive::DEFAULT_NAME_TEMPLATE,
+        value_parser = archive::NameTemplate::parse
+    )]
+    name_template: archive::NameTemplate,
+
+    /// Lay the archives out flat in the output directory, like web_data-2026-10-15T031500.tar, or
+    /// nested in a directory per container and mount, like web/_data/2026-10-15T031500.tar. A
+    /// shorthand for a --name-template
+    #[arg(long, value_enum, global = true, conflicts_with = "name_template")]
+    layout: Option<archive::Layout>,
+
+    /// Store the archives in a remote storage instead of the output directory: s3://bucket/prefix,
+    /// gs://bucket/prefix, azblob://container/prefix, rclone:remote:path, webdav[s]://host/path,
+    /// ssh://user@host[:port]/path
+    #[arg(long, global = true)]
+    dest: Option<String>,
+
+    /// The catalog database recording all backups [default: <OUTPUT_DIR>/catalog.db]
+    #[arg(long, global = true)]
+    catalog: Option<String>,
+
+    /// Do not show the progress of the archives, which is only shown when stderr is a terminal
+    #[arg(long, default_value = "false", global = true)]
+    pub no_progress: bool,
+
+    /// The file locking out other runs while changing containers or archives [default:
+    /// .volbackup.lock in the output directory]
+    #[arg(long, value_name = "FILE", global = true)]
+    lock_file: Option<PathBuf>,
+
+    /// Wait this many seconds for another run to finish, instead of exiting right away
+    #[arg(long, value_name = "SECONDS", default_value = "0", global = true)]
+    lock_timeout: u64,
+
+    /// Arguments for the backup when no command is given
+    #[command(flatten)]
+    pub backup: backup::BackupArguments,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// The settings per container from the configuration file
+    #[arg(skip)]
+    pub containers: HashMap<String, config::ContainerConfig>,
+
+    /// Talk to docker through this executor instead of the one of --backend
+    #[arg(skip)]
+    pub executor: Option<Arc<dyn DockerExecutor>>,
+}
+
+impl CliArguments {
+    /*
+     * The template of the archive names, the one of the --layout when it is given.
+     */
+    fn name_template(&self) -> archive::NameTemplate {
+        match self.layout {
+            Some(layout) => layout.template(),
+            None => self.name_template.clone(),
+        }
+    }
+
+    /*
+     * Whether the daemon is talked to over mutual TLS with the given certificates.
+     */
+    fn uses_tls(&self) -> bool {
+        self.tls_ca.is_some() || self.tls_cert.is_some() || self.tls_key.is_some()
+    }
+
+    /*
+     * The arguments with a subdirectory named after the project in the output directory and in
+     * the destination.
+     */
+    fn for_project(&self, project: &str) -> CliArguments {
+        let mut cli_args = self.clone();
+        cli_args.output_dir = Path::new(&self.output_dir)
+            .join(project)
+            .to_string_lossy()
+            .to_string();
+        cli_args.dest = self.dest.as_ref().map(|dest| match dest.ends_with(':') {
+            true => format!("{}{}", dest, project),
+            false => format!("{}/{}", dest.trim_end_matches('/'), project),
+        });
+        cli_args
+    }
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Commands {
+    /// Backup all mounted volumes of the running containers (default)
+    Backup(backup::BackupArguments),
+
+    /// Backup named volumes, also those not used by any container
+    BackupVolume(backup::BackupVolumeArguments),
+
+    /// Restore a volume from a backup archive
+    Restore(restore::RestoreArguments),
+
+    /// List the available backup archives
+    List(list::ListArguments),
+
+    /// List the files inside a backup archive with their sizes and modification times
+    ListContents(list::ListContentsArguments),
+
+    /// Delete expired archives according to the retention rules
+    Prune(prune::PruneArguments),
+
+    /// Verify the checksums and the tar structure of the backup archives
+    Verify(verify::VerifyArguments),
+
+    /// Run as a daemon backing up the containers on a cron schedule
+    Schedule(schedule::ScheduleArguments),
+
+    /// Serve an HTTP API to trigger the backups, query the runs and follow the log
+    Serve(serve::ServeArguments),
+
+    /// Copy named volumes to another docker daemon, like when moving a stack to a new server
+    Migrate(migrate::MigrateArguments),
+
+    /// Generate a docker-compose.yml from the configuration of backed up containers
+    ExportCompose(compose::ExportComposeArguments),
+}
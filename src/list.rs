@@ -0,0 +1,254 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::Args;
+use log::info;
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::{
+    catalog::Catalog,
+    exec::{execute_pipeline_into, pipe},
+    restore::{archive_file_name, find_archive},
+    storage::Destination,
+    types::DockerError,
+    CliArguments,
+};
+
+/// List the backup archives.
+#[derive(Args, Clone)]
+pub struct ListArguments {
+    /// Only list the archives of this container
+    #[arg(short, long)]
+    container: Option<String>,
+}
+
+/*
+ * Print the backup history from the catalog, or the archives found in the output directory when
+ * there is no catalog yet.
+ */
+pub fn list(list_args: &ListArguments, cli_args: &CliArguments) -> Result<(), DockerError> {
+    if Catalog::exists(cli_args) {
+        let catalog = Catalog::open(cli_args)?;
+        for entry in catalog.entries(list_args.container.as_deref())? {
+            // Recorded in UTC, shown in the local time.
+            let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp)
+                .map(|timestamp| {
+                    timestamp
+                        .with_timezone(&Local)
+                        .to_rfc3339_opts(SecondsFormat::Secs, false)
+                })
+                .unwrap_or(entry.timestamp);
+            println!(
+                "{}  {:<8} {:>12}  {} {}  {}",
+                timestamp,
+                entry.status,
+                entry.size,
+                entry.container,
+                entry.destination,
+                entry.archive.unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let template = &cli_args.name_template();
+    let mut archives = template
+        .find_archives(Path::new(&cli_args.output_dir))?
+        .into_iter()
+        .filter(|(_, archive, _, _)| {
+            list_args
+                .container
+                .as_ref()
+                .is_none_or(|container| template.matches(&archive.base, Some(container), None))
+        })
+        .map(|(name, _, _, metadata)| (name, metadata.len()))
+        .collect::<Vec<(String, u64)>>();
+    archives.sort();
+    for (name, size) in archives {
+        println!("{:>12}  {}", size, name);
+    }
+    Ok(())
+}
+
+/// List the files inside a backup archive.
+#[derive(Args, Clone)]
+pub struct ListContentsArguments {
+    /// The archive to list, defaults to the latest archive of the given container and mount
+    archive: Option<String>,
+
+    /// The container whose archive is listed
+    #[arg(short, long, requires = "mount")]
+    container: Option<String>,
+
+    /// The mount destination inside the container, e.g. /data
+    #[arg(short, long)]
+    mount: Option<String>,
+
+    /// The age identity file to decrypt age encrypted archives with, gpg uses the gpg agent
+    #[arg(long)]
+    age_identity: Option<String>,
+}
+
+/*
+ * Print the files inside an archive with their type and permissions, size and modification time,
+ * like tar -tv does. Remote archives are downloaded while listing.
+ */
+pub fn list_contents(
+    contents_args: &ListContentsArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let destination = Destination::parse(cli_args.dest.as_deref(), &cli_args.output_dir)?;
+    let archive = match (
+        &contents_args.archive,
+        &contents_args.container,
+        &contents_args.mount,
+    ) {
+        (Some(archive), _, _) => PathBuf::from(archive),
+        (None, Some(container), Some(mount)) => {
+            find_archive(container, mount, None, &destination, cli_args)?
+        }
+        _ => {
+            return Err(DockerError::from(
+                "Either an archive or a container and a mount is required",
+            ))
+        }
+    };
+    let (mut files, mut size) = (0, 0);
+    read_archive(
+        &archive,
+        &destination,
+        contents_args.age_identity.as_deref(),
+        cli_args,
+        |entry| {
+            if entry.header().entry_type().is_file() {
+                files += 1;
+                size += entry.size();
+            }
+            print_entry(&entry)
+        },
+    )?;
+    info!("{} files, {} bytes in {}", files, size, archive.display());
+    Ok(())
+}
+
+/*
+ * Read the entries of an archive, decrypting and decompressing it on the host. Remote archives are
+ * downloaded while reading.
+ */
+pub fn read_archive(
+    archive: &Path,
+    destination: &Destination,
+    age_identity: Option<&str>,
+    cli_args: &CliArguments,
+    mut visit: impl FnMut(tar::Entry<File>) -> Result<(), DockerError>,
+) -> Result<(), DockerError> {
+    let name = archive_file_name(archive, cli_args)?;
+    let (input, download) = match destination.download_command(&name.file_name())? {
+        Some((download, credentials)) => (credentials, Some(download)),
+        None => {
+            let file = File::open(archive).map_err(|e| {
+                DockerError::from(
+                    format!("Archive {} not found: {}", archive.display(), e).as_str(),
+                )
+            })?;
+            (Some(file), None)
+        }
+    };
+    let mut commands: Vec<Command> = download.into_iter().collect();
+    commands.extend(
+        name.encryption
+            .map(|e| e.decrypt_command(age_identity))
+            .transpose()?,
+    );
+    commands.extend(name.compression.decompress_command());
+
+    match (commands.is_empty(), input) {
+        (true, Some(file)) => read_entries(file, &mut visit),
+        (_, input) => {
+            let (reader, mut writer) = pipe()?;
+            let input = input.map(Stdio::from).unwrap_or(Stdio::null());
+            thread::scope(|scope| {
+                let copied =
+                    scope.spawn(move || execute_pipeline_into(commands, input, &mut writer));
+                let read = read_entries(reader, &mut visit);
+                let copied = copied
+                    .join()
+                    .unwrap_or(Err(DockerError::from("The decompression thread failed")));
+                match (read, copied) {
+                    // A failing download or decompression leaves a broken tar, its error tells why.
+                    (_, Err(e)) => Err(e),
+                    (read, Ok(_)) => read,
+                }
+            })
+        }
+    }
+}
+
+/*
+ * Visit every entry of a tar and read it to the end, so the pipeline writing it does not block.
+ */
+fn read_entries(
+    input: File,
+    visit: &mut dyn FnMut(tar::Entry<File>) -> Result<(), DockerError>,
+) -> Result<(), DockerError> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+        visit(entry?)?;
+    }
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(())
+}
+
+/*
+ * Print an entry of a tar with its type and permissions, size, modification time and path.
+ */
+fn print_entry(entry: &tar::Entry<File>) -> Result<(), DockerError> {
+    let header = entry.header();
+    let kind = match header.entry_type().as_byte() {
+        // GNU incremental archives store their directories as dumpdir (D) entries.
+        b'5' | b'D' => 'd',
+        b'2' => 'l',
+        b'1' => 'h',
+        b'3' => 'c',
+        b'4' => 'b',
+        b'6' => 'p',
+        _ => '-',
+    };
+    let modified = DateTime::from_timestamp(header.mtime()? as i64, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let target = entry
+        .link_name()?
+        .map(|target| format!(" -> {}", target.display()))
+        .unwrap_or_default();
+    println!(
+        "{}{}  {:>12}  {}  {}{}",
+        kind,
+        permissions(header.mode()?),
+        if kind == '-' { entry.size() } else { 0 },
+        modified,
+        entry.path()?.display(),
+        target
+    );
+    Ok(())
+}
+
+/*
+ * The permission bits of a mode like rwxr-xr-x.
+ */
+fn permissions(mode: u32) -> String {
+    (0..9)
+        .map(|bit| match mode & (0o400 >> bit) {
+            0 => '-',
+            _ => ['r', 'w', 'x'][bit % 3],
+        })
+        .collect()
+}
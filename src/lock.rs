@@ -0,0 +1,86 @@
+use chrono::Local;
+use log::info;
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Seek, Write},
+    path::Path,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{types::DockerError, CliArguments};
+
+const LOCK_FILE: &str = ".volbackup.lock";
+
+/*
+ * The lock of a run, held until it is dropped. Two runs on the same output directory would stop
+ * and start the containers in between each other, so only one of them runs at a time.
+ */
+pub struct RunLock {
+    _file: File,
+}
+
+/*
+ * Take the lock of the run, waiting up to --lock-timeout seconds for the run holding it. The lock
+ * file tells which process holds the lock, the lock itself is released by the operating system
+ * when the process exits, so a crashed run never leaves a stale lock behind.
+ */
+pub fn acquire(cli_args: &CliArguments) -> Result<RunLock, DockerError> {
+    let path = cli_args
+        .lock_file
+        .clone()
+        .unwrap_or_else(|| Path::new(&cli_args.output_dir).join(LOCK_FILE));
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    let deadline = Instant::now() + Duration::from_secs(cli_args.lock_timeout);
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                if !waiting {
+                    info!(
+                        "Waiting up to {} seconds for {} to finish",
+                        cli_args.lock_timeout,
+                        holder(&path)
+                    );
+                    waiting = true;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(DockerError::Locked(format!(
+                    "The lock {} is held by {}",
+                    path.display(),
+                    holder(&path)
+                )))
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{} {}", process::id(), Local::now().to_rfc3339())?;
+    Ok(RunLock { _file: file })
+}
+
+/*
+ * The run holding the lock, from the process id and start time in the lock file.
+ */
+fn holder(path: &Path) -> String {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    match content.split_whitespace().collect::<Vec<_>>()[..] {
+        [pid, started] => format!("another run (process {} started at {})", pid, started),
+        _ => "another run".to_string(),
+    }
+}
@@ -0,0 +1,621 @@
+use chrono::{DateTime, Datelike, Local, SecondsFormat};
+use clap::{ArgAction, Args, ValueEnum};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+};
+
+/*
+ * How many of the last log lines are kept for the notifications.
+ */
+const TAIL_LINES: usize = 100;
+
+static TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/*
+ * The receivers of the lines logged from now on, like the clients following the log of the server.
+ */
+static FOLLOWERS: Mutex<Vec<Sender<String>>> = Mutex::new(vec![]);
+
+#[derive(Args, Clone)]
+pub struct LogArguments {
+    /// Logging level of the console: off, error, warn, info, debug or trace [default: info, or
+    /// the level in RUST_LOG]
+    #[arg(short, long, value_name = "LEVEL", global = true)]
+    loglevel: Option<LevelFilter>,
+
+    /// Log more, -v for debug and -vv for trace
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less, -q for only the warnings and errors and -qq for only the errors
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    quiet: u8,
+
+    /// The level of a module, like exec=debug for the docker commands, can be repeated. The
+    /// modules of other crates are given with their path, like bollard::docker=debug
+    #[arg(long, value_name = "MODULE=LEVEL", value_parser = module_filter, global = true)]
+    log_filter: Vec<(String, LevelFilter)>,
+
+    /// Where the log goes: the console, the systemd journal or syslog, with the priority of every
+    /// line
+    #[arg(long, value_enum, default_value = "console", global = true)]
+    log_target: LogTarget,
+
+    /// The format of the lines on the console and in the log file: text, or json with an object
+    /// per line
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    /// Also write the log to this file, at its own level and rotated by size or time
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// The level of the log file, independent of --loglevel
+    #[arg(long, value_name = "LEVEL", default_value = "info", global = true)]
+    log_file_level: LevelFilter,
+
+    /// Rotate the log file when it grows over this many MiB, 0 to not rotate it by size
+    #[arg(long, value_name = "MIB", default_value = "10", global = true)]
+    log_max_size: u64,
+
+    /// Also rotate the log file every day or every week
+    #[arg(long, value_enum, default_value = "never", global = true)]
+    log_rotate: LogRotate,
+
+    /// How many rotated log files are kept, as PATH.1 (the newest) up to PATH.<COUNT>
+    #[arg(long, value_name = "COUNT", default_value = "5", global = true)]
+    log_keep: usize,
+}
+
+/*
+ * A module with its level, the modules of volbackup are given without the crate.
+ */
+fn module_filter(value: &str) -> Result<(String, LevelFilter), String> {
+    let (module, level) = value
+        .split_once('=')
+        .ok_or_else(|| "expected MODULE=LEVEL".to_string())?;
+    let level = level.parse().map_err(|e| format!("{}", e))?;
+    let module = match module.contains("::") || module == "volbackup" {
+        true => module.trim_end_matches("::").to_string(),
+        false => format!("volbackup::{}", module),
+    };
+    Ok((module, level))
+}
+
+/*
+ * The logger of the console with the level of the options. RUST_LOG is taken as it is, the level
+ * of the options only replaces its level when one is given. The module filters come last.
+ */
+pub fn console(args: &LogArguments) -> env_logger::Builder {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let mut builder = env_logger::Builder::from_default_env();
+    let level = args.loglevel.unwrap_or(LevelFilter::Info) as usize;
+    let level = (level + usize::from(args.verbose)).saturating_sub(usize::from(args.quiet));
+    let given = args.loglevel.is_some() || args.verbose > 0 || args.quiet > 0;
+    if given || env::var_os("RUST_LOG").is_none() {
+        builder.filter_level(LEVELS[level.min(LEVELS.len() - 1)]);
+    }
+    for (module, level) in &args.log_filter {
+        builder.filter_module(module, *level);
+    }
+    builder
+}
+
+/*
+ * Where the lines at the console level go.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogTarget {
+    Console,
+    Journald,
+    Syslog,
+}
+
+/*
+ * How the lines on the console and in the log file are written.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/*
+ * The container and the mount a thread is backing up, which the json lines and the journal carry,
+ * and whether the thread only logs its warnings and errors.
+ */
+#[derive(Clone, Default)]
+pub struct Context {
+    container: Option<String>,
+    mount: Option<String>,
+    quiet: bool,
+}
+
+static RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/*
+ * The lines logged from now on belong to the run.
+ */
+pub fn set_run_id(run_id: &str) {
+    *RUN_ID.lock().unwrap_or_else(|e| e.into_inner()) = Some(run_id.to_string());
+}
+
+/*
+ * The context of this thread until the guard is dropped.
+ */
+pub struct ContextGuard(Option<Context>);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            CONTEXT.with(|context| *context.borrow_mut() = previous);
+        }
+    }
+}
+
+fn enter(change: impl FnOnce(&mut Context)) -> ContextGuard {
+    CONTEXT.with(|context| {
+        let previous = context.borrow().clone();
+        change(&mut context.borrow_mut());
+        ContextGuard(Some(previous))
+    })
+}
+
+/*
+ * The lines of this thread are about the container.
+ */
+pub fn container(name: &str) -> ContextGuard {
+    enter(|context| context.container = Some(name.to_string()))
+}
+
+/*
+ * The lines of this thread are about the mount of the container or volume.
+ */
+pub fn mount(name: &str, destination: &str) -> ContextGuard {
+    enter(|context| {
+        context.container = Some(name.to_string());
+        context.mount = Some(destination.to_string());
+    })
+}
+
+/*
+ * Run without the info log on this thread and the threads continuing its context, for a pass over
+ * the containers or volumes that is logged again when they are backed up. The other threads, like
+ * a backup running next to the server, keep logging.
+ */
+pub fn quietly<R>(pass: impl FnOnce() -> R) -> R {
+    let _quiet = enter(|context| context.quiet = true);
+    pass()
+}
+
+/*
+ * Whether a line is logged on this thread, which only logs its warnings and errors quietly.
+ */
+fn audible(level: Level) -> bool {
+    level <= Level::Warn || !CONTEXT.with(|context| context.borrow().quiet)
+}
+
+/*
+ * The context of this thread, to continue it on another thread.
+ */
+pub fn context() -> Context {
+    CONTEXT.with(|context| context.borrow().clone())
+}
+
+/*
+ * Continue the context of another thread on this thread.
+ */
+pub fn continue_in_thread(parent: Context) {
+    CONTEXT.with(|context| *context.borrow_mut() = parent);
+}
+
+/*
+ * The fields of a line besides its level and message: the module, the run, the container and the
+ * mount.
+ */
+fn fields(record: &Record) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("target", record.target().to_string())];
+    if let Some(run_id) = RUN_ID.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        fields.push(("run_id", run_id));
+    }
+    let context = context();
+    if let Some(container) = context.container {
+        fields.push(("container", container));
+    }
+    if let Some(mount) = context.mount {
+        fields.push(("mount", mount));
+    }
+    fields
+}
+
+/*
+ * A line as a json object.
+ */
+fn json_line(record: &Record) -> String {
+    let mut line = serde_json::Map::new();
+    line.insert(
+        "timestamp".to_string(),
+        Local::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, false)
+            .into(),
+    );
+    line.insert("level".to_string(), record.level().as_str().into());
+    for (name, value) in fields(record) {
+        line.insert(name.to_string(), value.into());
+    }
+    line.insert("message".to_string(), record.args().to_string().into());
+    serde_json::Value::Object(line).to_string()
+}
+
+/*
+ * The name the lines carry in the journal and syslog.
+ */
+const IDENTIFIER: &str = "volbackup";
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/*
+ * The syslog priority of a level, as journald takes it too.
+ */
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/*
+ * The socket of journald or syslog the lines are sent to.
+ */
+struct Socket {
+    target: LogTarget,
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+impl Socket {
+    #[cfg(unix)]
+    fn connect(target: LogTarget, path: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        Ok(Socket { target, socket })
+    }
+
+    #[cfg(not(unix))]
+    fn connect(_target: LogTarget, _path: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "journald and syslog are only available on unix",
+        ))
+    }
+
+    fn send(&self, record: &Record) -> io::Result<()> {
+        let message = match self.target {
+            LogTarget::Syslog => syslog_message(record).into_bytes(),
+            _ => journal_entry(record),
+        };
+        #[cfg(unix)]
+        self.socket.send(&message)?;
+        #[cfg(not(unix))]
+        let _ = message;
+        Ok(())
+    }
+}
+
+/*
+ * A line for syslog in the format of RFC 3164 with the user facility.
+ */
+fn syslog_message(record: &Record) -> String {
+    format!(
+        "<{}>{} {}[{}]: {}",
+        8 + priority(record.level()),
+        Local::now().format("%b %e %H:%M:%S"),
+        IDENTIFIER,
+        std::process::id(),
+        record.args()
+    )
+}
+
+/*
+ * An entry in the native protocol of journald. A value with newlines is written with its length.
+ */
+fn journal_entry(record: &Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        if value.contains('\n') {
+            entry.extend_from_slice(name.as_bytes());
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        } else {
+            entry.extend_from_slice(format!("{}={}\n", name, value).as_bytes());
+        }
+    };
+    field("PRIORITY", &priority(record.level()).to_string());
+    field("SYSLOG_IDENTIFIER", IDENTIFIER);
+    field("SYSLOG_PID", &std::process::id().to_string());
+    field("MESSAGE", &record.args().to_string());
+    for (name, value) in fields(record) {
+        match name {
+            "target" => field("CODE_MODULE", &value),
+            _ => field(&format!("VOLBACKUP_{}", name.to_uppercase()), &value),
+        }
+    }
+    if let Some(file) = record.file() {
+        field("CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field("CODE_LINE", &line.to_string());
+    }
+    entry
+}
+
+/*
+ * When the log file is rotated besides its size.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogRotate {
+    Never,
+    Daily,
+    Weekly,
+}
+
+impl LogRotate {
+    /*
+     * The period of a time, a log file is rotated when the period changes.
+     */
+    fn period(self, time: DateTime<Local>) -> Option<(i32, u32)> {
+        match self {
+            LogRotate::Never => None,
+            LogRotate::Daily => Some((time.year(), time.ordinal())),
+            LogRotate::Weekly => Some((time.iso_week().year(), time.iso_week().week())),
+        }
+    }
+}
+
+/*
+ * The log file being written, with its size and the period it was started in.
+ */
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: Option<(i32, u32)>,
+    max_size: u64,
+    rotate: LogRotate,
+    keep: usize,
+    format: LogFormat,
+}
+
+impl LogFile {
+    /*
+     * Open the log file to append to it, rotating it first when it is too large or from an
+     * earlier period.
+     */
+    fn open(args: &LogArguments, path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let mut log_file = LogFile {
+            path: path.to_path_buf(),
+            file: open_append(path)?,
+            size: 0,
+            period: None,
+            max_size: args.log_max_size * 1024 * 1024,
+            rotate: args.log_rotate,
+            keep: args.log_keep,
+            format: args.log_format,
+        };
+        let metadata = log_file.file.metadata()?;
+        log_file.size = metadata.len();
+        let started = metadata.modified().map(DateTime::<Local>::from);
+        log_file.period = args
+            .log_rotate
+            .period(started.unwrap_or_else(|_| Local::now()));
+        if log_file.needs_rotation(0) {
+            log_file.rotate()?;
+        }
+        Ok(log_file)
+    }
+
+    /*
+     * Whether the file is rotated before writing a line of the length.
+     */
+    fn needs_rotation(&self, length: u64) -> bool {
+        let too_large = self.max_size > 0 && self.size > 0 && self.size + length > self.max_size;
+        too_large || self.rotate.period(Local::now()) != self.period
+    }
+
+    /*
+     * Move the file to PATH.1 and the older ones up, dropping the oldest, and start a new file.
+     */
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |number: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", number));
+            PathBuf::from(path)
+        };
+        for number in (1..self.keep).rev() {
+            let _ = fs::rename(numbered(number), numbered(number + 1));
+        }
+        match self.keep {
+            0 => fs::remove_file(&self.path)?,
+            _ => fs::rename(&self.path, numbered(1))?,
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.period = self.rotate.period(Local::now());
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.needs_rotation(line.len() as u64) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/*
+ * The logger of env_logger for the console, or journald or syslog at its level instead, also
+ * writing the log file and keeping the last lines for the notifications.
+ */
+struct Logger {
+    console: env_logger::Logger,
+    socket: Option<Socket>,
+    file: Option<(LevelFilter, Mutex<LogFile>)>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+            || self
+                .file
+                .as_ref()
+                .is_some_and(|(level, _)| metadata.level() <= *level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !audible(record.level()) {
+            return;
+        }
+        if self.console.matches(record) {
+            // A line journald or syslog did not take is not lost.
+            let sent = self.socket.as_ref().map(|socket| socket.send(record));
+            if !matches!(sent, Some(Ok(()))) {
+                self.console.log(record);
+            }
+            let line = format!(
+                "{} {:<5} {}",
+                Local::now().format("%Y-%m-%dT%H:%M:%S"),
+                record.level(),
+                record.args()
+            );
+            let mut followers = FOLLOWERS.lock().unwrap_or_else(|e| e.into_inner());
+            followers.retain(|follower| follower.send(line.clone()).is_ok());
+            let mut tail = TAIL.lock().unwrap_or_else(|e| e.into_inner());
+            if tail.len() == TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        if let Some((level, file)) = &self.file {
+            if record.level() <= *level {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                let line = match file.format {
+                    LogFormat::Text => format!(
+                        "[{} {:<5} {}] {}\n",
+                        Local::now().format("%Y-%m-%dT%H:%M:%S%:z"),
+                        record.level(),
+                        record.target(),
+                        record.args()
+                    ),
+                    LogFormat::Json => format!("{}\n", json_line(record)),
+                };
+                // There is no log to report a failing log file in.
+                let _ = file.write_line(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some((_, file)) = &self.file {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+/*
+ * Install the logger for the console, journald or syslog and, with --log-file, the log file.
+ */
+pub fn init(mut console: env_logger::Builder, args: &LogArguments) -> io::Result<()> {
+    if args.log_format == LogFormat::Json {
+        console.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+    let console = console.build();
+    let socket = match args.log_target {
+        LogTarget::Console => None,
+        LogTarget::Journald => Some(Socket::connect(LogTarget::Journald, JOURNALD_SOCKET)?),
+        LogTarget::Syslog => Some(Socket::connect(LogTarget::Syslog, SYSLOG_SOCKET)?),
+    };
+    let file = args
+        .log_file
+        .as_ref()
+        .map(|path| LogFile::open(args, path))
+        .transpose()
+        .map_err(|e| {
+            let path = args.log_file.as_deref().unwrap_or(Path::new(""));
+            io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+        })?;
+    let file_level = file
+        .as_ref()
+        .map(|_| args.log_file_level)
+        .unwrap_or(LevelFilter::Off);
+    let level = console.filter().max(file_level);
+    let logger = Logger {
+        console,
+        socket,
+        file: file.map(|file| (file_level, Mutex::new(file))),
+    };
+    log::set_boxed_logger(Box::new(logger)).expect("the logger is installed once");
+    log::set_max_level(level);
+    Ok(())
+}
+
+/*
+ * Receive the lines logged to the console from now on, until the receiver is dropped.
+ */
+pub fn follow() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    FOLLOWERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(sender);
+    receiver
+}
+
+/*
+ * The last lines that were logged to the console.
+ */
+pub fn tail() -> String {
+    let tail = TAIL.lock().unwrap_or_else(|e| e.into_inner());
+    tail.iter().map(|line| format!("{}\n", line)).collect()
+}
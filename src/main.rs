@@ -1,181 +1,192 @@
-use crate::{
-    exec::{docker_json_command, docker_jsonline_command, docker_outputless_command},
-    types::{ContainerInfo, PsInfo},
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches};
+use log::{error, info};
+use std::{env, ffi::OsString, process::ExitCode};
+use volbackup::{
+    backup, compose, config, interrupt, list, lock, logging, migrate, progress, prune, restore,
+    schedule, serve, types, verify, CliArguments, Commands,
 };
-use clap::Parser;
-use log::{debug, error, info};
-use std::{env, process::ExitCode};
-use types::DockerError;
-
-mod exec;
-mod types;
-
-const TYPE_BACKUPCONTAINER: &str = "docker-volbackup";
-
-/// Backup all mounted volumes connected to a running container.
-#[derive(Parser)]
-pub struct CliArguments {
-    /// Stop the container before backup and restart it afterwards
-    #[arg(short, long, default_value = "false")]
-    stop_start: bool,
-
-    /// The image to use for running a volume backup
-    #[arg(short, long, default_value = "ubuntu")]
-    image: String,
-
-    /// Logging level
-    #[arg(short, long, default_value = "info")]
-    loglevel: String,
-
-    /// Where to find the docker executable
-    #[arg(short, long, default_value = "/usr/bin/docker")]
-    docker: String,
-}
 
 /*
  * Entrypoint.
  */
 fn main() -> ExitCode {
-    let cli_args = CliArguments::parse();
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", cli_args.loglevel.as_str())
+    let args: Vec<OsString> = env::args_os().collect();
+    let loaded = config::Config::load(&args).and_then(|config| {
+        let command = config::apply_env(config.apply(CliArguments::command())?)?;
+        Ok((config, command))
+    });
+    let (config, command) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => return usage_error(CliArguments::command().error(ErrorKind::InvalidValue, e)),
+    };
+    let parsed = command
+        .try_get_matches_from(args)
+        .and_then(|matches| CliArguments::from_arg_matches(&matches));
+    let mut cli_args = match parsed {
+        Ok(cli_args) => cli_args,
+        Err(e) => return usage_error(e),
+    };
+    cli_args.containers = config.containers;
+    let mut logger = logging::console(&cli_args.log);
+    if progress::enable(cli_args.no_progress) {
+        // The log lines are written above the progress line, env_logger does not color them then.
+        logger.target(env_logger::Target::Pipe(Box::new(progress::LogWriter)));
+    }
+    if let Err(e) = logging::init(logger, &cli_args.log) {
+        eprintln!("Could not set up the log: {}", e);
+        return ExitCode::from(EXIT_CONFIG);
     }
-    env_logger::init();
 
     info!("Docker volume backup v1.0");
+    if let Some(path) = &config.path {
+        info!("Using config file {}", path.display());
+    }
+    interrupt::install(&cli_args);
+
+    // The commands stopping containers or changing archives do not run at the same time, the
+    // schedule and the server lock every backup they run.
+    let _lock = match &cli_args.command {
+        None
+        | Some(Commands::Backup(_))
+        | Some(Commands::BackupVolume(_))
+        | Some(Commands::Restore(_))
+        | Some(Commands::Prune(_)) => match lock::acquire(&cli_args) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                error!("{}", e);
+                return error_exit_code(&e);
+            }
+        },
+        _ => None,
+    };
 
-    match docker_jsonline_command::<PsInfo, _, _>(vec!["ps", "--format=json"], &cli_args) {
-        Ok(ps_info) => match backup_container(ps_info, cli_args).expect("Backup failed") {
-            true => ExitCode::FAILURE,
-            false => ExitCode::SUCCESS,
+    match &cli_args.command {
+        None => backup_exit_code(backup::backup(&cli_args.backup, &cli_args)),
+        Some(Commands::Backup(backup_args)) => {
+            backup_exit_code(backup::backup(backup_args, &cli_args))
+        }
+        Some(Commands::BackupVolume(volume_args)) => {
+            backup_exit_code(backup::backup_volumes(volume_args, &cli_args))
+        }
+        Some(Commands::Restore(restore_args)) => match restore::restore(restore_args, &cli_args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Restore failed: {}", e);
+                error_exit_code(&e)
+            }
         },
-        Err(e) => {
-            error!("Error {:?}", e);
-            ExitCode::SUCCESS
+        Some(Commands::List(list_args)) => match list::list(list_args, &cli_args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("List failed: {}", e);
+                error_exit_code(&e)
+            }
+        },
+        Some(Commands::ListContents(contents_args)) => {
+            match list::list_contents(contents_args, &cli_args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("List contents failed: {}", e);
+                    error_exit_code(&e)
+                }
+            }
+        }
+        Some(Commands::Prune(prune_args)) => match prune::prune(prune_args, &cli_args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Prune failed: {}", e);
+                error_exit_code(&e)
+            }
+        },
+        Some(Commands::Verify(verify_args)) => match verify::verify(verify_args, &cli_args) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::from(EXIT_PARTIAL),
+            Err(e) => {
+                error!("Verify failed: {}", e);
+                error_exit_code(&e)
+            }
+        },
+        Some(Commands::Schedule(schedule_args)) => {
+            match schedule::schedule(schedule_args, &cli_args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("Schedule failed: {}", e);
+                    error_exit_code(&e)
+                }
+            }
+        }
+        Some(Commands::Serve(serve_args)) => match serve::serve(serve_args, &cli_args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Serve failed: {}", e);
+                error_exit_code(&e)
+            }
+        },
+        Some(Commands::Migrate(migrate_args)) => match migrate::migrate(migrate_args, &cli_args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Migrate failed: {}", e);
+                error_exit_code(&e)
+            }
+        },
+        Some(Commands::ExportCompose(compose_args)) => {
+            match compose::export_compose(compose_args, &cli_args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("Export compose failed: {}", e);
+                    error_exit_code(&e)
+                }
+            }
         }
     }
 }
 
 /*
- * Inspect a container to find out the mounts.
+ * The exit codes for monitoring: some containers, volumes or archives failed, everything failed
+ * or the command could not run at all, the command line or the configuration is invalid, the
+ * docker daemon can not be reached, another run is active.
  */
-fn backup_container(ps_info: Vec<PsInfo>, cli_args: CliArguments) -> Result<bool, DockerError> {
-    info!(
-        "Found containers: {:?}",
-        ps_info
-            .iter()
-            .map(|f| { f.names.as_str() })
-            .collect::<Vec<&str>>()
-    );
-
-    let mut has_errors = false;
-    for ps_info in ps_info {
-        let container_name = &ps_info.names;
-        info!(
-            "[{container_name}] Getting container information for {}",
-            container_name
-        );
+const EXIT_PARTIAL: u8 = 1;
+const EXIT_FAILED: u8 = 2;
+const EXIT_CONFIG: u8 = 3;
+const EXIT_UNREACHABLE: u8 = 4;
+const EXIT_LOCKED: u8 = 5;
 
-        let inspected = docker_json_command::<ContainerInfo, _, _>(
-            vec!["inspect", container_name.as_str(), "--format=json"],
-            &cli_args,
-        )?;
-        if let Some(container_info) = inspected.get(0) {
-            if !backup_all_mounts(container_info, &ps_info, &cli_args)? {
-                has_errors = true;
-                error!(
-                    "[{container_name}] Error backing up container {}",
-                    container_name
-                )
-            } else {
-                info!(
-                    "[{container_name}] Backup of container {} done. 🫡",
-                    container_name
-                )
-            }
-        } else {
-            error!("[{container_name}] Response from inspect is wrong (no data returned)")
+/*
+ * The exit code of a backup.
+ */
+fn backup_exit_code(result: Result<backup::Outcome, types::DockerError>) -> ExitCode {
+    match result {
+        Ok(backup::Outcome::Success) => ExitCode::SUCCESS,
+        Ok(backup::Outcome::Partial) => ExitCode::from(EXIT_PARTIAL),
+        Ok(backup::Outcome::Failed) => ExitCode::from(EXIT_FAILED),
+        Err(e) => {
+            error!("Backup failed: {}", e);
+            error_exit_code(&e)
         }
     }
-    Ok(!has_errors)
 }
 
 /*
- * Backup the mounts listed in the container as tar files.
+ * The exit code of a command that failed with an error.
  */
-fn backup_all_mounts(
-    container_info: &ContainerInfo,
-    container: &PsInfo,
-    cli_args: &CliArguments,
-) -> Result<bool, DockerError> {
-    debug!("Inspect: {:?}", container_info);
-    info!("[{}] Start backup of volumes", container.names);
-
-    if *container_info
-        .config
-        .labels
-        .get("type")
-        .unwrap_or(&"-".to_string())
-        == TYPE_BACKUPCONTAINER
-    {
-        info!(
-            "[{}] Skipping this container as it it a backup container 🫡",
-            container.names
-        );
-        return Ok(true);
-    }
-
-    if cli_args.stop_start {
-        info!("[{}] Stopping container", container.names);
-        docker_outputless_command(cli_args, vec!["stop", container_info.id.as_str()])?;
+fn error_exit_code(error: &types::DockerError) -> ExitCode {
+    match error {
+        types::DockerError::DaemonUnreachable(_) => ExitCode::from(EXIT_UNREACHABLE),
+        types::DockerError::Config(_) => ExitCode::from(EXIT_CONFIG),
+        types::DockerError::Locked(_) => ExitCode::from(EXIT_LOCKED),
+        _ => ExitCode::from(EXIT_FAILED),
     }
-
-    let mut errors = 0;
-    for mount in container_info.mounts.iter() {
-        info!("[{}] - backing up {}", container.names, mount.destination);
-        if docker_outputless_command(
-            cli_args,
-            vec![
-                "run",
-                "--rm",
-                "--label",
-                format!("type={}", TYPE_BACKUPCONTAINER).as_str(),
-                "-v",
-                ".:/backupdest",
-                "--volumes-from",
-                container_info.id.as_str(),
-                cli_args.image.as_str(),
-                "tar",
-                "cvf",
-                format!(
-                    "/backupdest/{}{}.tar",
-                    container.names,
-                    sanitize(&mount.destination).as_str()
-                )
-                .as_str(),
-                mount.destination.as_str(),
-            ],
-        )
-        .is_err()
-        {
-            error!(
-                "[{}] Error in backup of volume {}",
-                container.names, mount.destination
-            );
-            errors += 1;
-        };
-    }
-    if cli_args.stop_start {
-        info!("[{}] Restarting container", container.names);
-        docker_outputless_command(cli_args, vec!["start", container_info.id.as_str()])?;
-    }
-
-    Ok(errors == 0)
 }
 
 /*
- * Sanitize a path into part of the backup filename.
+ * Print an invalid command line or configuration like clap does, but exit with the exit code of a
+ * configuration error. The help and the version are printed with exit code 0.
  */
-fn sanitize(s: &str) -> String {
-    s.replace('/', "_")
+fn usage_error(error: clap::Error) -> ExitCode {
+    let _ = error.print();
+    match error.use_stderr() {
+        true => ExitCode::from(EXIT_CONFIG),
+        false => ExitCode::SUCCESS,
+    }
 }
@@ -0,0 +1,317 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::types::{ContainerInfo, DockerError, MountType, VolumeInfo};
+
+/*
+ * Name of the manifest file written next to the archives.
+ */
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/*
+ * Suffix of the file with the docker inspect output of a container, next to the manifest.
+ */
+pub const INSPECT_SUFFIX: &str = ".inspect.json";
+
+/*
+ * Description of a backup run, written as json next to the archives.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub timestamp: DateTime<Local>,
+    #[serde(default)]
+    pub run_id: String,
+    pub image: String,
+    pub containers: Vec<ContainerManifest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContainerManifest {
+    pub name: String,
+    pub id: String,
+    pub mounts: Vec<MountManifest>,
+    /// Why the container failed besides its failed mounts, like a failed hook.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// The configuration to recreate the container with, not for volumes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<ContainerSpec>,
+    /// The full docker inspect output, written to a file of its own and the catalog.
+    #[serde(skip)]
+    pub inspect: Option<serde_json::Value>,
+    /// The docker save tar of the image of the container, with --include-image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_archive: Option<String>,
+}
+
+impl ContainerManifest {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+            && self
+                .mounts
+                .iter()
+                .all(|mount| mount.status == MountStatus::Success)
+    }
+}
+
+/*
+ * What docker run needs to create an equivalent container, taken from the inspect output.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerSpec {
+    pub image: String,
+    /// Like sha256:<hex>, the image the container was created from.
+    #[serde(default)]
+    pub image_id: String,
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    /// The entrypoint the container was created with, the one of the image unless overridden.
+    #[serde(default)]
+    pub entrypoint: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub mounts: Vec<MountSpec>,
+    /// Like unless-stopped or on-failure:3.
+    #[serde(default)]
+    pub restart_policy: Option<String>,
+}
+
+/*
+ * A published port, like 127.0.0.1:8080:80/tcp for docker run -p.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortSpec {
+    pub container_port: String,
+    #[serde(default)]
+    pub host_ip: String,
+    #[serde(default)]
+    pub host_port: String,
+}
+
+/*
+ * A volume or bind mount, the source is the name of the volume or the path on the host.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountSpec {
+    pub kind: String,
+    pub source: String,
+    pub destination: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/*
+ * What docker volume create needs to create an equivalent named volume.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeSpec {
+    pub name: String,
+    pub driver: String,
+    /// The options of the driver, like type, device and o of the local driver.
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ContainerSpec {
+    /*
+     * The configuration of an inspected container.
+     */
+    pub fn of(container_info: &ContainerInfo) -> Self {
+        let host_config = &container_info.host_config;
+        let mut ports = host_config
+            .port_bindings
+            .iter()
+            .flat_map(|(port, bindings)| {
+                bindings.iter().flatten().map(|binding| PortSpec {
+                    container_port: port.clone(),
+                    host_ip: binding.host_ip.clone(),
+                    host_port: binding.host_port.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        ports.sort_by(|a, b| {
+            (&a.container_port, &a.host_port).cmp(&(&b.container_port, &b.host_port))
+        });
+        ContainerSpec {
+            image: container_info.config.image.clone(),
+            image_id: container_info.image.clone(),
+            cmd: container_info.config.cmd.clone(),
+            entrypoint: container_info.config.entrypoint.clone(),
+            env: container_info.config.env.clone(),
+            labels: container_info.config.labels.clone().into_iter().collect(),
+            ports,
+            // Tmpfs and the other kinds of mounts have nothing to restore.
+            mounts: container_info
+                .mounts
+                .iter()
+                .filter_map(|mount| {
+                    match mount.kind {
+                        Some(MountType::Volume) => Some(("volume", mount.name.clone()?)),
+                        Some(MountType::Bind) => Some(("bind", mount.source.clone())),
+                        _ => None,
+                    }
+                    .map(|(kind, source)| MountSpec {
+                        kind: kind.to_string(),
+                        source,
+                        destination: mount.destination.clone(),
+                        read_only: !mount.rw,
+                    })
+                })
+                .collect(),
+            restart_policy: host_config
+                .restart_policy
+                .as_ref()
+                .filter(|policy| !matches!(policy.name.as_str(), "" | "no"))
+                .map(|policy| match policy.maximum_retry_count {
+                    0 => policy.name.clone(),
+                    retries => format!("{}:{}", policy.name, retries),
+                }),
+        }
+    }
+}
+
+impl VolumeSpec {
+    /*
+     * The configuration of an inspected volume.
+     */
+    pub fn of(volume_info: &VolumeInfo) -> Self {
+        VolumeSpec {
+            name: volume_info.name.clone(),
+            driver: volume_info.driver.clone(),
+            options: volume_info.options.clone().into_iter().collect(),
+            labels: volume_info.labels.clone().into_iter().collect(),
+        }
+    }
+}
+
+impl PortSpec {
+    /*
+     * The port as docker run --publish takes it.
+     */
+    pub fn publish(&self) -> String {
+        match self.host_ip.as_str() {
+            "" => format!("{}:{}", self.host_port, self.container_port),
+            ip if ip.contains(':') => {
+                format!("[{}]:{}:{}", ip, self.host_port, self.container_port)
+            }
+            ip => format!("{}:{}:{}", ip, self.host_port, self.container_port),
+        }
+    }
+}
+
+impl MountSpec {
+    /*
+     * The mount as docker run --volume takes it.
+     */
+    pub fn bind(&self) -> String {
+        match self.read_only {
+            true => format!("{}:{}:ro", self.source, self.destination),
+            false => format!("{}:{}", self.source, self.destination),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MountManifest {
+    pub source: String,
+    pub destination: String,
+    pub archive: Option<String>,
+    pub size: u64,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The size of the files of the mount estimated before archiving it, with --estimate-size.
+    #[serde(default)]
+    pub estimated_size: Option<u64>,
+    pub duration_secs: f64,
+    pub status: MountStatus,
+    pub error: Option<String>,
+    /// The driver and options of a named volume, to create it again like it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<VolumeSpec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountStatus {
+    Success,
+    Failed,
+}
+
+impl Manifest {
+    pub fn new(image: &str) -> Self {
+        let timestamp = Local::now();
+        // The process id tells apart the runs started at the same moment.
+        let run_id = Sha256::digest(format!("{:?} {}", timestamp, process::id()).as_bytes())
+            .iter()
+            .take(6)
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        Manifest {
+            timestamp,
+            run_id,
+            image: image.to_string(),
+            containers: vec![],
+        }
+    }
+
+    /*
+     * Read the manifest of the last run in the output directory.
+     */
+    pub fn read(output_dir: &Path) -> Result<Manifest, DockerError> {
+        let path = output_dir.join(MANIFEST_FILE);
+        let file = File::open(&path).map_err(|e| {
+            DockerError::from(format!("Manifest {} not found: {}", path.display(), e).as_str())
+        })?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /*
+     * The recorded driver and options of a named volume.
+     */
+    pub fn volume(&self, name: &str) -> Option<&VolumeSpec> {
+        self.containers
+            .iter()
+            .flat_map(|container| container.mounts.iter())
+            .filter_map(|mount| mount.volume.as_ref())
+            .find(|volume| volume.name == name)
+    }
+
+    /*
+     * Write the manifest into the output directory, returning its path.
+     */
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf, DockerError> {
+        let path = output_dir.join(MANIFEST_FILE);
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+        Ok(path)
+    }
+
+    /*
+     * Write the docker inspect output of every container into <container>.inspect.json in the
+     * output directory, returning the containers and the paths of their files.
+     */
+    pub fn write_inspects(&self, output_dir: &Path) -> Result<Vec<(&str, PathBuf)>, DockerError> {
+        let mut written = vec![];
+        for container in self.containers.iter() {
+            if let Some(inspect) = &container.inspect {
+                let path = output_dir.join(format!("{}{}", container.name, INSPECT_SUFFIX));
+                serde_json::to_writer_pretty(File::create(&path)?, inspect)?;
+                written.push((container.name.as_str(), path));
+            }
+        }
+        Ok(written)
+    }
+}
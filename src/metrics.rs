@@ -0,0 +1,163 @@
+use chrono::Local;
+use std::{fmt::Write as _, fs, process::Command, time::Instant};
+
+use crate::{
+    archive::hostname,
+    backup::Outcome,
+    exec::run_command_with_input,
+    manifest::{ContainerManifest, Manifest, MountStatus},
+    types::DockerError,
+};
+
+/*
+ * The metrics of a backup run in the text format of Prometheus: when the run started, whether it
+ * succeeded, how long it took and the bytes written, in total and per container. Without a
+ * manifest the run failed before any container was backed up.
+ */
+pub fn render(
+    manifest: Option<&Manifest>,
+    started: Instant,
+    result: &Result<Outcome, DockerError>,
+) -> String {
+    let containers = manifest
+        .map(|manifest| manifest.containers.as_slice())
+        .unwrap_or_default();
+    let timestamp = manifest
+        .map(|manifest| manifest.timestamp)
+        .unwrap_or_else(Local::now);
+    let failures = containers
+        .iter()
+        .filter(|container| !container.succeeded())
+        .count();
+    let written = |container: &ContainerManifest| -> u64 {
+        container
+            .mounts
+            .iter()
+            .filter(|mount| mount.status == MountStatus::Success)
+            .map(|mount| mount.size)
+            .sum()
+    };
+    let mut metrics = String::new();
+    let mut gauge = |name: &str, help: &str, values: Vec<(Option<&str>, f64)>| {
+        let _ = writeln!(metrics, "# HELP volbackup_{} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE volbackup_{} gauge", name);
+        for (container, value) in values {
+            let labels = container
+                .map(|container| format!("{{container=\"{}\"}}", label_value(container)))
+                .unwrap_or_default();
+            let _ = writeln!(metrics, "volbackup_{}{} {}", name, labels, value);
+        }
+    };
+    gauge(
+        "last_run_timestamp_seconds",
+        "When the last backup run started.",
+        vec![(None, timestamp.timestamp() as f64)],
+    );
+    gauge(
+        "last_run_success",
+        "Whether the last backup run backed up all containers.",
+        vec![(None, f64::from(matches!(result, Ok(Outcome::Success))))],
+    );
+    gauge(
+        "last_run_duration_seconds",
+        "How long the last backup run took.",
+        vec![(None, started.elapsed().as_secs_f64())],
+    );
+    gauge(
+        "last_run_bytes_written",
+        "The bytes of the archives written by the last backup run.",
+        vec![(None, containers.iter().map(written).sum::<u64>() as f64)],
+    );
+    gauge(
+        "last_run_failures",
+        "The containers or volumes the last backup run failed to back up.",
+        vec![(None, failures as f64)],
+    );
+    gauge(
+        "container_success",
+        "Whether all mounts of the container were backed up by the last run.",
+        containers
+            .iter()
+            .map(|container| {
+                (
+                    Some(container.name.as_str()),
+                    f64::from(container.succeeded()),
+                )
+            })
+            .collect(),
+    );
+    gauge(
+        "container_bytes_written",
+        "The bytes of the archives of the container written by the last run.",
+        containers
+            .iter()
+            .map(|container| (Some(container.name.as_str()), written(container) as f64))
+            .collect(),
+    );
+    gauge(
+        "container_duration_seconds",
+        "How long archiving the mounts of the container took in the last run.",
+        containers
+            .iter()
+            .map(|container| {
+                let duration = container.mounts.iter().map(|mount| mount.duration_secs);
+                (Some(container.name.as_str()), duration.sum())
+            })
+            .collect(),
+    );
+    metrics
+}
+
+/*
+ * Write the metrics for the textfile collector of node_exporter. The file is written next to it
+ * first and renamed, so the collector never reads half a file.
+ */
+pub fn write_textfile(path: &str, metrics: &str) -> Result<(), DockerError> {
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, metrics)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/*
+ * Push the metrics to a Pushgateway with curl, in the group of the job and this host as the
+ * instance. The group is replaced, so containers that are gone do not linger.
+ */
+pub fn push(url: &str, job: &str, metrics: &str) -> Result<(), DockerError> {
+    let url = format!(
+        "{}/metrics/job/{}/instance/{}",
+        url.trim_end_matches('/'),
+        path_segment(job),
+        path_segment(&hostname())
+    );
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--netrc-optional"]);
+    command.args(["--request", "PUT", "--data-binary", "@-", url.as_str()]);
+    run_command_with_input(command, Some(metrics.as_bytes()))?;
+    Ok(())
+}
+
+/*
+ * A label value in the url of the Pushgateway, percent encoded.
+ */
+fn path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/*
+ * A label value with the backslashes, quotes and newlines escaped.
+ */
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
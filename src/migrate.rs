@@ -0,0 +1,138 @@
+use clap::Args;
+use log::{info, warn};
+use std::{io::Write, thread};
+
+use crate::{
+    exec::{inspect_volume, pipe, run_helper_from, run_helper_into, Helper},
+    progress,
+    report::human_size,
+    types::DockerError,
+    CliArguments,
+};
+
+/// Copy named volumes to another docker daemon.
+#[derive(Args, Clone)]
+pub struct MigrateArguments {
+    /// The named volumes to copy, they are created on the other daemon
+    #[arg(required = true)]
+    volumes: Vec<String>,
+
+    /// The daemon to copy the volumes to, like ssh://user@newserver
+    #[arg(long, required_unless_present = "to_context")]
+    to_host: Option<String>,
+
+    /// The docker context of the daemon to copy the volumes to
+    #[arg(long, conflicts_with = "to_host")]
+    to_context: Option<String>,
+}
+
+/*
+ * Where the volumes are mounted in the helper containers.
+ */
+const VOLUME_MOUNT: &str = "/volume";
+
+/*
+ * Copy the volumes from the daemon of the command line to the other daemon, streaming the tar of a
+ * volume from a helper container on the one daemon into a helper container on the other without
+ * writing it to a file. The migration stops at the first volume that fails.
+ */
+pub fn migrate(
+    migrate_args: &MigrateArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let target_args = migrate_args.target(cli_args);
+    for volume in migrate_args.volumes.iter() {
+        if inspect_volume(cli_args, volume)?.is_none() {
+            return Err(DockerError::from(
+                format!("Volume {} not found", volume).as_str(),
+            ));
+        }
+        if inspect_volume(&target_args, volume)?.is_some() {
+            return Err(DockerError::from(
+                format!("Volume {} already exists on the other daemon", volume).as_str(),
+            ));
+        }
+    }
+    for volume in migrate_args.volumes.iter() {
+        info!(
+            "[{}] Copying volume to {}",
+            volume,
+            migrate_args.target_name()
+        );
+        let size = migrate_volume(volume, cli_args, &target_args).inspect_err(|_| {
+            warn!(
+                "[{}] The volume on {} is incomplete, remove it before migrating it again",
+                volume,
+                migrate_args.target_name()
+            );
+        })?;
+        info!("[{}] Copied {} 🫡", volume, human_size(size));
+    }
+    Ok(())
+}
+
+impl MigrateArguments {
+    /*
+     * The arguments talking to the other daemon. The TLS files are those of the daemon of the
+     * command line, the other daemon uses the ones of its context.
+     */
+    fn target(&self, cli_args: &CliArguments) -> CliArguments {
+        let mut target_args = cli_args.clone();
+        target_args.host = self.to_host.clone();
+        target_args.context = self.to_context.clone();
+        target_args.tls_ca = None;
+        target_args.tls_cert = None;
+        target_args.tls_key = None;
+        target_args
+    }
+
+    fn target_name(&self) -> &str {
+        self.to_host
+            .as_deref()
+            .or(self.to_context.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/*
+ * Stream the tar of a volume into a new volume on the other daemon, returning its size.
+ */
+fn migrate_volume(
+    volume: &str,
+    cli_args: &CliArguments,
+    target_args: &CliArguments,
+) -> Result<u64, DockerError> {
+    let source = Helper {
+        volumes: vec![format!("{}:{}:ro", volume, VOLUME_MOUNT)],
+        command: ["tar", "cf", "-", VOLUME_MOUNT].map(String::from).to_vec(),
+        ..Default::default()
+    };
+    let target = Helper {
+        volumes: vec![format!("{}:{}", volume, VOLUME_MOUNT)],
+        command: ["tar", "xf", "-", "-C", "/"].map(String::from).to_vec(),
+        ..Default::default()
+    };
+    let (input, mut output) = pipe()?;
+    thread::scope(|scope| {
+        let extracted =
+            scope.spawn(move || run_helper_from(&target, vec![], Some(input), target_args));
+        let copied = {
+            let mut writer = progress::Counting::new(&mut output, volume.to_string(), None);
+            run_helper_into(&source, vec![], &mut writer, cli_args).and_then(|copied| {
+                writer.flush()?;
+                Ok(copied)
+            })
+        };
+        // The target reads to the end of the tar once the pipe is closed.
+        drop(output);
+        let extracted = extracted
+            .join()
+            .unwrap_or(Err(DockerError::from("The target helper thread failed")));
+        match (copied, extracted) {
+            // A target that failed breaks the pipe, its error tells why.
+            (Err(DockerError::IoError(_)), Err(e)) => Err(e),
+            (Err(e), _) | (Ok(_), Err(e)) => Err(e),
+            (Ok(size), Ok(())) => Ok(size),
+        }
+    })
+}
@@ -0,0 +1,653 @@
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::{mpsc::Sender, Mutex, OnceLock},
+};
+
+use crate::{
+    exec::{
+        command_failed, execute_pipeline_into, execute_pipeline_with_input, DockerExecutor, Helper,
+    },
+    manifest::{ContainerSpec, VolumeSpec},
+    types::{
+        ContainerConfig, ContainerEvent, ContainerInfo, DaemonVersion, DockerError, MountType,
+        Mounts, PsInfo, ServiceInfo, VolumeInfo,
+    },
+    CliArguments, SWARM_SERVICE_LABEL,
+};
+
+/*
+ * An executor simulating the docker daemon, to run the backup logic without docker. It answers
+ * with the containers, volumes and events it is given and the helper containers and the commands
+ * executed in the containers write the output given for their program. Without one a tar helper
+ * writes a predictable archive of a file per archived path and du its size. Every operation is
+ * recorded like a docker command, `stop web` or `run web tar cf -`, so the order of the stops,
+ * starts and archives can be checked, and an operation can be made to fail.
+ */
+#[derive(Default)]
+pub struct MockExecutor {
+    containers: Mutex<Vec<(PsInfo, ContainerInfo)>>,
+    volumes: Mutex<Vec<VolumeInfo>>,
+    services: Mutex<Vec<ServiceInfo>>,
+    events: Vec<ContainerEvent>,
+    helper_outputs: HashMap<String, Vec<u8>>,
+    exec_outputs: HashMap<String, Vec<u8>>,
+    failures: Vec<(String, String)>,
+    calls: Mutex<Vec<String>>,
+    received: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+static FIXTURES: OnceLock<Result<MockExecutor, String>> = OnceLock::new();
+
+impl MockExecutor {
+    pub fn new() -> Self {
+        MockExecutor::default()
+    }
+
+    /*
+     * The executor of --backend mock, answering from the --fixtures directory for all operations.
+     */
+    pub fn fixtures(cli_args: &CliArguments) -> Result<&'static MockExecutor, DockerError> {
+        FIXTURES
+            .get_or_init(|| match &cli_args.fixtures {
+                Some(dir) => MockExecutor::from_fixtures(dir).map_err(|e| e.to_string()),
+                None => Err("--backend mock needs the --fixtures DIR to answer with".to_string()),
+            })
+            .as_ref()
+            .map_err(|e| DockerError::Config(e.clone()))
+    }
+
+    /*
+     * Answer with the output docker prints, saved in the directory: ps.json from `docker ps --all
+     * --format=json`, inspect/<NAME>.json from `docker inspect NAME` for each of its containers,
+     * and optionally volumes.json from `docker volume ls --format=json`, services.json from `docker
+     * service inspect --format=json` and events.json from `docker events --format '{{json .}}'`.
+     */
+    pub fn from_fixtures(dir: &Path) -> Result<Self, DockerError> {
+        let mut mock = MockExecutor::new();
+        for ps_info in read_fixture::<PsInfo>(&dir.join("ps.json"))? {
+            let path = dir.join("inspect").join(format!("{}.json", ps_info.names));
+            let Some(inspect) = read_fixture::<serde_json::Value>(&path)?.into_iter().next() else {
+                return Err(DockerError::Config(format!(
+                    "The fixture {} has no container",
+                    path.display()
+                )));
+            };
+            let info = ContainerInfo::from_inspect(inspect).map_err(|e| {
+                DockerError::Config(format!("Invalid fixture {}: {}", path.display(), e))
+            })?;
+            mock = mock.container(ps_info, info);
+        }
+        let optional = |name: &str| Some(dir.join(name)).filter(|path| path.exists());
+        if let Some(path) = optional("volumes.json") {
+            mock.volumes = Mutex::new(read_fixture(&path)?);
+        }
+        if let Some(path) = optional("services.json") {
+            mock.services = Mutex::new(read_fixture(&path)?);
+        }
+        if let Some(path) = optional("events.json") {
+            mock.events = read_fixture(&path)?;
+        }
+        Ok(mock)
+    }
+
+    /*
+     * A container as docker ps lists it and docker inspect shows it.
+     */
+    pub fn container(self, ps_info: PsInfo, info: ContainerInfo) -> Self {
+        if let Ok(mut containers) = self.containers.lock() {
+            containers.push((ps_info, info));
+        }
+        self
+    }
+
+    pub fn volume(self, info: VolumeInfo) -> Self {
+        if let Ok(mut volumes) = self.volumes.lock() {
+            volumes.push(info);
+        }
+        self
+    }
+
+    /*
+     * A swarm service as docker service inspect shows it, its task containers have its name in
+     * their service label.
+     */
+    pub fn service(self, info: ServiceInfo) -> Self {
+        if let Ok(mut services) = self.services.lock() {
+            services.push(info);
+        }
+        self
+    }
+
+    /*
+     * An event sent to the ones following the events of the daemon.
+     */
+    pub fn event(mut self, event: ContainerEvent) -> Self {
+        self.events.push(event);
+        self
+    }
+
+    /*
+     * What the helper containers running the program write, like a tar stream for tar.
+     */
+    pub fn helper_output(mut self, program: &str, output: &[u8]) -> Self {
+        self.helper_outputs
+            .insert(program.to_string(), output.to_vec());
+        self
+    }
+
+    /*
+     * What the program writes when it is executed in a container.
+     */
+    pub fn exec_output(mut self, program: &str, output: &[u8]) -> Self {
+        self.exec_outputs
+            .insert(program.to_string(), output.to_vec());
+        self
+    }
+
+    /*
+     * Fail the operations starting with the words of the operation, like `stop web` or `run web
+     * tar`, with stderr as the output of the failed command. The stderr of docker failing to reach
+     * the daemon fails as an unreachable daemon.
+     */
+    pub fn fail(mut self, operation: &str, stderr: &str) -> Self {
+        self.failures
+            .push((operation.to_string(), stderr.to_string()));
+        self
+    }
+
+    /*
+     * The operations in the order they were executed.
+     */
+    pub fn calls(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .map(|calls| calls.clone())
+            .unwrap_or_default()
+    }
+
+    /*
+     * The input the last helper container with the volumes of the container or of the volumes
+     * (joined with commas) was given, like the tar stream of a restore.
+     */
+    pub fn received(&self, target: &str) -> Option<Vec<u8>> {
+        self.received
+            .lock()
+            .ok()
+            .and_then(|received| received.get(target).cloned())
+    }
+
+    /*
+     * Record the operation and fail it when it is to fail.
+     */
+    fn call(&self, operation: String) -> Result<(), DockerError> {
+        self.calls.lock()?.push(operation.clone());
+        let failure = self.failures.iter().find(|(failing, _)| {
+            operation == *failing || operation.starts_with(&format!("{} ", failing))
+        });
+        match failure {
+            Some((_, stderr)) => Err(command_failed(&operation, Some(1), stderr)),
+            None => Ok(()),
+        }
+    }
+
+    /*
+     * Set the state of a container by its id or name.
+     */
+    fn set_state(&self, id: &str, state: &str) -> Result<(), DockerError> {
+        for (ps_info, info) in self.containers.lock()?.iter_mut() {
+            if info.id == id || ps_info.names == id {
+                ps_info.state = Some(state.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn run_helper(&self, helper: &Helper) -> Result<(), DockerError> {
+        let target = helper_target(helper);
+        self.call(format!("run {} {}", target, helper.command.join(" ")))
+            .map_err(|e| match e {
+                DockerError::CommandFailed {
+                    command,
+                    exit,
+                    stderr,
+                } => DockerError::HelperContainerFailed {
+                    command,
+                    exit,
+                    stderr,
+                },
+                e => e,
+            })
+    }
+}
+
+/*
+ * The container whose volumes a helper has, or the names of its volumes without where they are
+ * mounted.
+ */
+fn helper_target(helper: &Helper) -> String {
+    match &helper.volumes_from {
+        Some(container) => container.clone(),
+        None => helper
+            .volumes
+            .iter()
+            .map(|volume| volume.split(':').next().unwrap_or(volume))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/*
+ * Read the output of a docker command saved in a file, as jsonline or as a json array.
+ */
+fn read_fixture<R: DeserializeOwned>(path: &Path) -> Result<Vec<R>, DockerError> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        DockerError::Config(format!("Invalid fixture {}: {}", path.display(), e))
+    };
+    let content = fs::read(path).map_err(|e| invalid(&e))?;
+    if content.trim_ascii_start().starts_with(b"[") {
+        return serde_json::from_slice(&content).map_err(|e| invalid(&e));
+    }
+    serde_jsonlines::JsonLinesReader::new(content.as_slice())
+        .read_all::<R>()
+        .collect::<io::Result<Vec<R>>>()
+        .map_err(|e| invalid(&e))
+}
+
+/*
+ * The paths a tar or du helper is given, after its options.
+ */
+fn helper_paths(helper: &Helper) -> impl Iterator<Item = &str> {
+    helper
+        .command
+        .iter()
+        .skip(1)
+        .map(String::as_str)
+        .filter(|argument| !argument.starts_with('-') && *argument != "cf")
+}
+
+/*
+ * The file a mock helper archives for a path, telling the container and the path.
+ */
+fn predictable_file(helper: &Helper, path: &str) -> (String, Vec<u8>) {
+    let name = format!("{}/volbackup-mock.txt", path.trim_matches('/'));
+    let content = format!("{} {}\n", helper_target(helper), path);
+    (name, content.into_bytes())
+}
+
+/*
+ * A tar archive of a file per archived path, the same for every run.
+ */
+fn predictable_archive(helper: &Helper) -> Result<Vec<u8>, DockerError> {
+    let mut archive = tar::Builder::new(vec![]);
+    for path in helper_paths(helper) {
+        let (name, content) = predictable_file(helper, path);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        archive.append_data(&mut header, name, content.as_slice())?;
+    }
+    Ok(archive.into_inner()?)
+}
+
+/*
+ * What du prints for the predictable archive: the size of every path and their total.
+ */
+fn predictable_size(helper: &Helper) -> Vec<u8> {
+    let mut lines = String::new();
+    let mut total = 0;
+    for path in helper_paths(helper) {
+        let size = predictable_file(helper, path).1.len();
+        lines.push_str(&format!("{}\t{}\n", size, path));
+        total += size;
+    }
+    lines.push_str(&format!("{}\ttotal\n", total));
+    lines.into_bytes()
+}
+
+/*
+ * Write the snapshot file of an incremental tar, empty, where the helper would leave it on the
+ * host: in a mounted directory or in a directory it is copied out to.
+ */
+fn write_snapshots(helper: &Helper) -> Result<(), DockerError> {
+    let snapshots = helper
+        .command
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("--listed-incremental="));
+    for snapshot in snapshots {
+        let mounted = helper.volumes.iter().find_map(|volume| {
+            let (host, dir) = volume.rsplit_once(':')?;
+            let file = snapshot.strip_prefix(dir)?.strip_prefix('/')?;
+            Some(Path::new(host).join(file))
+        });
+        let copied = helper
+            .copy_out
+            .iter()
+            .filter(|(file, _)| file == snapshot)
+            .filter_map(|(file, dir)| Some(dir.join(Path::new(file).file_name()?)));
+        for path in mounted.into_iter().chain(copied) {
+            File::create(path)?;
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Whether a container has all of the labels, given as key=value or only the key.
+ */
+fn has_labels(info: &ContainerInfo, labels: &[String]) -> bool {
+    labels.iter().all(|label| match label.split_once('=') {
+        Some((key, value)) => info.config.labels.get(key).is_some_and(|v| v == value),
+        None => info.config.labels.contains_key(label),
+    })
+}
+
+impl DockerExecutor for MockExecutor {
+    fn daemon_version(&self, _cli_args: &CliArguments) -> Result<DaemonVersion, DockerError> {
+        self.call("version".to_string())?;
+        Ok(DaemonVersion {
+            version: "mock".to_string(),
+            api_version: "mock".to_string(),
+            os: env::consts::OS.to_string(),
+        })
+    }
+
+    fn list_containers(
+        &self,
+        _cli_args: &CliArguments,
+        all: bool,
+        labels: &[String],
+    ) -> Result<Vec<PsInfo>, DockerError> {
+        self.call("ps".to_string())?;
+        Ok(self
+            .containers
+            .lock()?
+            .iter()
+            .filter(|(ps_info, info)| (all || ps_info.is_running()) && has_labels(info, labels))
+            .map(|(ps_info, _)| ps_info.clone())
+            .collect())
+    }
+
+    fn inspect_container(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+    ) -> Result<Option<ContainerInfo>, DockerError> {
+        self.call(format!("inspect {}", name))?;
+        Ok(self
+            .containers
+            .lock()?
+            .iter()
+            .find(|(ps_info, info)| ps_info.names == name || info.id == name)
+            .map(|(_, info)| info.clone()))
+    }
+
+    fn list_volumes(&self, _cli_args: &CliArguments) -> Result<Vec<VolumeInfo>, DockerError> {
+        self.call("volume ls".to_string())?;
+        Ok(self.volumes.lock()?.clone())
+    }
+
+    fn inspect_volume(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+    ) -> Result<Option<VolumeInfo>, DockerError> {
+        self.call(format!("volume inspect {}", name))?;
+        Ok(self
+            .volumes
+            .lock()?
+            .iter()
+            .find(|volume| volume.name == name)
+            .cloned())
+    }
+
+    fn create_volume(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+        spec: &VolumeSpec,
+    ) -> Result<(), DockerError> {
+        self.call(format!("volume create {} {}", name, spec.driver))?;
+        self.volumes.lock()?.push(VolumeInfo {
+            name: name.to_string(),
+            mountpoint: format!("/var/lib/docker/volumes/{}/_data", name),
+            driver: spec.driver.clone(),
+            options: spec.options.clone().into_iter().collect(),
+            labels: spec.labels.clone().into_iter().collect(),
+        });
+        Ok(())
+    }
+
+    fn inspect_service(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+    ) -> Result<Option<ServiceInfo>, DockerError> {
+        self.call(format!("service inspect {}", name))?;
+        Ok(self
+            .services
+            .lock()?
+            .iter()
+            .find(|service| service.spec.name == name || service.id == name)
+            .cloned())
+    }
+
+    /*
+     * Scaling a service stops or starts its task containers, by their service label.
+     */
+    fn scale_service(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+        replicas: u64,
+    ) -> Result<(), DockerError> {
+        self.call(format!("service scale {}={}", name, replicas))?;
+        let mut services = self.services.lock()?;
+        let replicated = services
+            .iter_mut()
+            .find(|service| service.spec.name == name)
+            .ok_or(DockerError::from(
+                format!("Service {} not found", name).as_str(),
+            ))?
+            .spec
+            .mode
+            .replicated
+            .as_mut()
+            .ok_or(DockerError::from(
+                format!("Service {} is global and can not be scaled", name).as_str(),
+            ))?;
+        replicated.replicas = replicas;
+        let state = if replicas == 0 { "exited" } else { "running" };
+        for (ps_info, info) in self.containers.lock()?.iter_mut() {
+            if info
+                .config
+                .labels
+                .get(SWARM_SERVICE_LABEL)
+                .map(String::as_str)
+                == Some(name)
+            {
+                ps_info.state = Some(state.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_container(&self, _cli_args: &CliArguments, id: &str) -> Result<(), DockerError> {
+        self.call(format!("stop {}", id))?;
+        self.set_state(id, "exited")
+    }
+
+    fn start_container(&self, _cli_args: &CliArguments, id: &str) -> Result<(), DockerError> {
+        self.call(format!("start {}", id))?;
+        self.set_state(id, "running")
+    }
+
+    fn pause_container(&self, _cli_args: &CliArguments, id: &str) -> Result<(), DockerError> {
+        self.call(format!("pause {}", id))?;
+        self.set_state(id, "paused")
+    }
+
+    fn unpause_container(&self, _cli_args: &CliArguments, id: &str) -> Result<(), DockerError> {
+        self.call(format!("unpause {}", id))?;
+        self.set_state(id, "running")
+    }
+
+    fn remove_container(&self, _cli_args: &CliArguments, id: &str) -> Result<(), DockerError> {
+        self.call(format!("rm {}", id))?;
+        self.containers
+            .lock()?
+            .retain(|(ps_info, info)| info.id != id && ps_info.names != id);
+        Ok(())
+    }
+
+    /*
+     * Add a running container named after the image, with the id mock-<name>.
+     */
+    fn create_container(
+        &self,
+        _cli_args: &CliArguments,
+        name: &str,
+        spec: &ContainerSpec,
+    ) -> Result<String, DockerError> {
+        self.call(format!("create {} {}", name, spec.image))?;
+        let ps_info = PsInfo {
+            names: name.to_string(),
+            state: Some("running".to_string()),
+        };
+        let info = ContainerInfo {
+            id: format!("mock-{}", name),
+            image: spec.image_id.clone(),
+            mounts: spec
+                .mounts
+                .iter()
+                .map(|mount| Mounts {
+                    kind: MountType::from_str(&mount.kind, true).ok(),
+                    name: (mount.kind == "volume").then(|| mount.source.clone()),
+                    source: mount.source.clone(),
+                    destination: mount.destination.clone(),
+                    rw: !mount.read_only,
+                })
+                .collect(),
+            config: ContainerConfig {
+                labels: spec.labels.clone().into_iter().collect(),
+                image: spec.image.clone(),
+                env: spec.env.clone(),
+                cmd: spec.cmd.clone(),
+                entrypoint: spec.entrypoint.clone(),
+            },
+            host_config: Default::default(),
+            inspect: None,
+        };
+        let id = info.id.clone();
+        self.containers.lock()?.push((ps_info, info));
+        Ok(id)
+    }
+
+    /*
+     * Write the image id as the tar of the image.
+     */
+    fn save_image(
+        &self,
+        _cli_args: &CliArguments,
+        image: &str,
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        self.call(format!("save {}", image))?;
+        let saved = format!("{}\n", image);
+        output.write_all(saved.as_bytes())?;
+        Ok(saved.len() as u64)
+    }
+
+    fn load_image(&self, _cli_args: &CliArguments, mut input: File) -> Result<(), DockerError> {
+        self.call("load".to_string())?;
+        io::copy(&mut input, &mut io::sink())?;
+        Ok(())
+    }
+
+    /*
+     * Send the events it was given, the events then end without being lost.
+     */
+    fn follow_events(
+        &self,
+        _cli_args: &CliArguments,
+        events: &Sender<ContainerEvent>,
+    ) -> Result<(), DockerError> {
+        self.call("events".to_string())?;
+        for event in self.events.iter() {
+            if events.send(event.clone()).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_in_container_into(
+        &self,
+        _cli_args: &CliArguments,
+        id: &str,
+        command: &[&str],
+        _env: &[(&str, &str)],
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        self.call(format!("exec {} {}", id, command.join(" ")))?;
+        let written = command
+            .first()
+            .and_then(|program| self.exec_outputs.get(*program))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        output.write_all(written)?;
+        Ok(written.len() as u64)
+    }
+
+    fn run_helper_into(
+        &self,
+        _cli_args: &CliArguments,
+        helper: &Helper,
+        commands: Vec<Command>,
+        output: &mut dyn Write,
+    ) -> Result<u64, DockerError> {
+        self.run_helper(helper)?;
+        let program = helper.command.first().map(String::as_str);
+        let written = match (program, program.and_then(|p| self.helper_outputs.get(p))) {
+            (_, Some(written)) => written.clone(),
+            (Some("tar"), None) => {
+                write_snapshots(helper)?;
+                predictable_archive(helper)?
+            }
+            (Some("du"), None) => predictable_size(helper),
+            _ => vec![],
+        };
+        execute_pipeline_with_input(commands, &written, output)
+    }
+
+    fn run_helper_from(
+        &self,
+        _cli_args: &CliArguments,
+        helper: &Helper,
+        commands: Vec<Command>,
+        input: Option<File>,
+    ) -> Result<(), DockerError> {
+        self.run_helper(helper)?;
+        let mut received = vec![];
+        match (input, commands.is_empty()) {
+            (Some(mut file), true) => {
+                file.read_to_end(&mut received)?;
+            }
+            (input, _) => {
+                let input = input.map(Stdio::from).unwrap_or(Stdio::null());
+                execute_pipeline_into(commands, input, &mut received)?;
+            }
+        }
+        self.received
+            .lock()?
+            .insert(helper_target(helper), received);
+        Ok(())
+    }
+}
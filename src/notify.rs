@@ -0,0 +1,365 @@
+use chrono::Local;
+use clap::ValueEnum;
+use log::{error, info, warn};
+use std::{env, fs, process::Command, time::Instant};
+
+use crate::{
+    archive::hostname,
+    backup::Outcome,
+    exec::{curl_config, retry, run_command_with_input},
+    logging,
+    manifest::Manifest,
+    report,
+    types::DockerError,
+    CliArguments,
+};
+
+/*
+ * Ping the start endpoint of a healthchecks.io style check, so a run that never finishes is
+ * noticed too. A failed ping does not stop the backup.
+ */
+pub fn ping_start(url: &str) {
+    let url = format!("{}/start", url.trim_end_matches('/'));
+    if let Err(e) = ping(&url, None) {
+        warn!("Could not ping {}: {}", url, e);
+    }
+}
+
+/*
+ * Ping the check with the result of the run, the url itself for a successful run and the fail
+ * endpoint otherwise, with the last log lines and the error that stopped the run as the body.
+ */
+pub fn ping_result(url: &str, result: &Result<Outcome, DockerError>) {
+    let url = url.trim_end_matches('/');
+    let url = match result {
+        Ok(Outcome::Success) => url.to_string(),
+        _ => format!("{}/fail", url),
+    };
+    let mut body = logging::tail();
+    if let Err(e) = result {
+        // Main only logs the error after the ping.
+        body.push_str(&format!("Backup failed: {}\n", e));
+    }
+    if let Err(e) = ping(&url, Some(body.as_bytes())) {
+        warn!("Could not ping {}: {}", url, e);
+    }
+}
+
+/*
+ * Ping a url with curl, posting the body when there is one. Retried a few times, as a missed
+ * ping raises an alarm.
+ */
+fn ping(url: &str, body: Option<&[u8]>) -> Result<(), DockerError> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail"]);
+    command.args(["--max-time", "10", "--retry", "3"]);
+    if body.is_some() {
+        command.args(["--data-binary", "@-"]);
+    }
+    command.arg(url);
+    run_command_with_input(command, body)?;
+    Ok(())
+}
+
+/*
+ * After which runs the notifications are sent.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum NotifyOn {
+    Always,
+    Failure,
+    Success,
+}
+
+impl NotifyOn {
+    pub fn notifies(self, result: &Result<Outcome, DockerError>) -> bool {
+        let success = matches!(result, Ok(Outcome::Success));
+        match self {
+            NotifyOn::Always => true,
+            NotifyOn::Failure => !success,
+            NotifyOn::Success => success,
+        }
+    }
+}
+
+/*
+ * A finished run to notify about. Without a manifest the run failed before any container was
+ * backed up.
+ */
+pub struct Finished<'a> {
+    pub manifest: Option<&'a Manifest>,
+    pub kind: &'a str,
+    pub started: Instant,
+    pub result: &'a Result<Outcome, DockerError>,
+}
+
+impl Finished<'_> {
+    fn status(&self) -> &'static str {
+        match self.result {
+            Ok(Outcome::Success) => "success",
+            Ok(Outcome::Partial) => "partial",
+            _ => "failed",
+        }
+    }
+
+    fn summary(&self) -> String {
+        self.manifest
+            .map(|manifest| report::summary(manifest, self.kind, self.started))
+            .unwrap_or_default()
+    }
+
+    /*
+     * How the run on this host went, like Backup on server1 failed.
+     */
+    pub fn title(&self) -> String {
+        let outcome = match self.result {
+            Ok(Outcome::Success) => "succeeded",
+            Ok(Outcome::Partial) => "partly failed",
+            _ => "failed",
+        };
+        format!("Backup on {} {}", hostname(), outcome)
+    }
+
+    /*
+     * The summary of the run with the error that stopped it.
+     */
+    pub fn text(&self) -> String {
+        let mut text = self.summary();
+        if let Err(e) = self.result {
+            text.push_str(&format!("Backup failed: {}\n", e));
+        }
+        text
+    }
+
+    /*
+     * A message of a few lines for a phone: how many containers or volumes were backed up and
+     * which failed, or the error that stopped the run.
+     */
+    fn short_message(&self) -> String {
+        if let Err(e) = self.result {
+            return format!("Backup failed: {}", e);
+        }
+        let containers = self
+            .manifest
+            .map(|manifest| manifest.containers.as_slice())
+            .unwrap_or_default();
+        let failed: Vec<&str> = containers
+            .iter()
+            .filter(|container| !container.succeeded())
+            .map(|container| container.name.as_str())
+            .collect();
+        let mut message = format!(
+            "{} backed up, {} failed in {:.0}s",
+            containers.len() - failed.len(),
+            failed.len(),
+            self.started.elapsed().as_secs_f64()
+        );
+        if !failed.is_empty() {
+            message.push_str(&format!("\nFailed: {}", failed.join(", ")));
+        }
+        message
+    }
+
+    /*
+     * The message for a chat: the title with the summary in a code block.
+     */
+    fn chat_message(&self) -> String {
+        let emoji = match self.result {
+            Ok(Outcome::Success) => ":white_check_mark:",
+            Ok(Outcome::Partial) => ":warning:",
+            _ => ":x:",
+        };
+        format!("{} {}\n```\n{}```", emoji, self.title(), self.text())
+    }
+
+    /*
+     * The report of the run as json.
+     */
+    pub fn report(&self) -> Result<String, DockerError> {
+        report::to_json(self.manifest, self.started, self.result)
+    }
+
+    /*
+     * The body of a webhook: the template with the values of the run filled in, or else the
+     * report of the run.
+     */
+    fn webhook_body(&self, template: Option<&str>) -> Result<String, DockerError> {
+        let report = self.report()?;
+        let Some(template) = template else {
+            return Ok(report);
+        };
+        let containers = self
+            .manifest
+            .map(|manifest| manifest.containers.as_slice())
+            .unwrap_or_default();
+        let failed = containers.iter().filter(|c| !c.succeeded()).count();
+        let values = [
+            ("status", self.status().to_string()),
+            (
+                "run_id",
+                self.manifest
+                    .map(|manifest| manifest.run_id.clone())
+                    .unwrap_or_default(),
+            ),
+            ("hostname", hostname()),
+            (
+                "timestamp",
+                self.manifest
+                    .map(|manifest| manifest.timestamp)
+                    .unwrap_or_else(Local::now)
+                    .to_rfc3339(),
+            ),
+            (
+                "duration_secs",
+                format!("{:.1}", self.started.elapsed().as_secs_f64()),
+            ),
+            ("succeeded", (containers.len() - failed).to_string()),
+            ("failed", failed.to_string()),
+            (
+                "error",
+                self.result
+                    .as_ref()
+                    .err()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("summary", self.summary()),
+        ];
+        let mut body = template.replace("{report}", &report);
+        for (name, value) in values {
+            body = body.replace(&format!("{{{}}}", name), &json_string(&value));
+        }
+        Ok(body)
+    }
+}
+
+/*
+ * A value escaped to go into a json string.
+ */
+fn json_string(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/*
+ * Post the result of the run as json to the webhooks, with the body from the template file when
+ * there is one. A failing webhook is retried and then logged, it does not fail the backup.
+ */
+pub fn post_webhooks(
+    urls: &[String],
+    template: Option<&str>,
+    finished: &Finished,
+    cli_args: &CliArguments,
+) {
+    if urls.is_empty() {
+        return;
+    }
+    let template = match template.map(fs::read_to_string).transpose() {
+        Ok(template) => template,
+        Err(e) => {
+            error!("Could not read the webhook template: {}", e);
+            return;
+        }
+    };
+    let body = match finished.webhook_body(template.as_deref()) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Could not make the body of the webhooks: {}", e);
+            return;
+        }
+    };
+    notify(urls, &[JSON], &body, cli_args);
+}
+
+/*
+ * Post the summary of the run, with the failed mounts, to the Slack and Discord webhooks of the
+ * ops channels.
+ */
+pub fn post_chats(
+    slack: &[String],
+    discord: &[String],
+    finished: &Finished,
+    cli_args: &CliArguments,
+) {
+    let message = finished.chat_message();
+    let slack_body = serde_json::json!({ "text": message }).to_string();
+    notify(slack, &[JSON], &slack_body, cli_args);
+    let discord_body =
+        serde_json::json!({ "content": truncate(&message, DISCORD_LIMIT) }).to_string();
+    notify(discord, &[JSON], &discord_body, cli_args);
+}
+
+/*
+ * The most characters Discord takes in a message.
+ */
+const DISCORD_LIMIT: usize = 2000;
+
+/*
+ * A message cut to the limit, closing its code block.
+ */
+fn truncate(message: &str, limit: usize) -> String {
+    if message.chars().count() <= limit {
+        return message.to_string();
+    }
+    let end = "\n…\n```";
+    let cut: String = message.chars().take(limit - end.chars().count()).collect();
+    format!("{}{}", cut, end)
+}
+
+/*
+ * Publish a short message with the result of the run to the ntfy topics, with a high priority
+ * when a container failed so it stands out on the phone. The token for a protected topic is taken
+ * from NTFY_TOKEN.
+ */
+pub fn publish_ntfy(topics: &[String], finished: &Finished, cli_args: &CliArguments) {
+    if topics.is_empty() {
+        return;
+    }
+    let (priority, tag) = match finished.result {
+        Ok(Outcome::Success) => ("default", "white_check_mark"),
+        Ok(Outcome::Partial) => ("high", "warning"),
+        _ => ("high", "x"),
+    };
+    let mut headers = vec![
+        format!("Title: {}", finished.title()),
+        format!("Priority: {}", priority),
+        format!("Tags: {}", tag),
+    ];
+    if let Ok(token) = env::var("NTFY_TOKEN") {
+        headers.push(format!("Authorization: Bearer {}", token));
+    }
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+    notify(topics, &headers, &finished.short_message(), cli_args);
+}
+
+/*
+ * The header of a json body.
+ */
+const JSON: &str = "Content-Type: application/json";
+
+/*
+ * Post the body to the urls, retrying a failing one and then logging it.
+ */
+fn notify(urls: &[String], headers: &[&str], body: &str, cli_args: &CliArguments) {
+    for url in urls {
+        info!("Notifying {}", url);
+        let operation = format!("Webhook {}", url);
+        if let Err(e) = retry(cli_args, &operation, || post(url, headers, body)) {
+            error!("Could not notify {}: {}", url, e);
+        }
+    }
+}
+
+/*
+ * Post a body with the headers to a url with curl. They are all given to curl in a config on its
+ * input, so a token in a header or in the url of a webhook is not on its command line.
+ */
+fn post(url: &str, headers: &[&str], body: &str) -> Result<(), DockerError> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--max-time", "30"]);
+    command.args(["--config", "-"]);
+    let mut options: Vec<(&str, &str)> = headers.iter().map(|header| ("header", *header)).collect();
+    options.extend([("data-raw", body), ("url", url)]);
+    run_command_with_input(command, Some(curl_config(&options).as_bytes()))?;
+    Ok(())
+}
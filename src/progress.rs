@@ -0,0 +1,215 @@
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::report::human_size;
+
+/*
+ * The progress of the archives being written, shown as a single line at the bottom of the
+ * terminal that the log lines are written above.
+ */
+struct Bars {
+    next: u64,
+    bars: Vec<Bar>,
+    drawn: bool,
+    drawn_at: Option<Instant>,
+}
+
+struct Bar {
+    key: u64,
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    started: Instant,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static BARS: Mutex<Bars> = Mutex::new(Bars {
+    next: 0,
+    bars: vec![],
+    drawn: false,
+    drawn_at: None,
+});
+
+/*
+ * How often the progress line is redrawn at most.
+ */
+const REDRAW: Duration = Duration::from_millis(200);
+
+fn bars() -> MutexGuard<'static, Bars> {
+    // A panic while holding the lock does not make the progress wrong.
+    BARS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/*
+ * Show the progress when stderr is a terminal, unless it is switched off for cron. Returns whether
+ * the progress is shown.
+ */
+pub fn enable(no_progress: bool) -> bool {
+    let enabled = !no_progress && io::stderr().is_terminal();
+    ENABLED.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+impl Bars {
+    fn clear(&mut self, stderr: &mut dyn Write) {
+        if self.drawn {
+            let _ = write!(stderr, "\r\x1b[K");
+            self.drawn = false;
+        }
+    }
+
+    /*
+     * Draw the progress of all archives on one line, cut to the width of the terminal so it does
+     * not wrap.
+     */
+    fn draw(&mut self, stderr: &mut dyn Write) {
+        self.clear(stderr);
+        if self.bars.is_empty() {
+            return;
+        }
+        self.drawn_at = Some(Instant::now());
+        let width = env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse::<usize>().ok())
+            .unwrap_or(80);
+        let line = self
+            .bars
+            .iter()
+            .map(Bar::render)
+            .collect::<Vec<_>>()
+            .join("  ");
+        let line: String = line.chars().take(width.saturating_sub(1)).collect();
+        let _ = write!(stderr, "{}", line);
+        let _ = stderr.flush();
+        self.drawn = true;
+    }
+}
+
+impl Bar {
+    /*
+     * The progress of an archive: with the size of the source a bar with the percentage, without
+     * it the bytes written and the rate.
+     */
+    fn render(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => {
+                // Tar adds headers, an archive can be a little larger than its files.
+                let fraction = (self.done as f64 / total as f64).min(0.99);
+                let filled = (fraction * 10.0) as usize;
+                format!(
+                    "{} [{}{}] {:>2}% {}",
+                    self.label,
+                    "#".repeat(filled),
+                    "-".repeat(10 - filled),
+                    (fraction * 100.0) as u32,
+                    human_size(self.done)
+                )
+            }
+            _ => {
+                let seconds = self.started.elapsed().as_secs_f64().max(0.001);
+                format!(
+                    "{} {} {}/s",
+                    self.label,
+                    human_size(self.done),
+                    human_size((self.done as f64 / seconds) as u64)
+                )
+            }
+        }
+    }
+}
+
+/*
+ * A writer counting the bytes of an archive into its progress, the progress is removed when the
+ * writer is dropped. Counts nothing when the progress is not shown.
+ */
+pub struct Counting<'a> {
+    inner: &'a mut dyn Write,
+    key: Option<u64>,
+}
+
+impl<'a> Counting<'a> {
+    pub fn new(inner: &'a mut dyn Write, label: String, total: Option<u64>) -> Self {
+        let key = enabled().then(|| {
+            let mut bars = bars();
+            let key = bars.next;
+            bars.next += 1;
+            bars.bars.push(Bar {
+                key,
+                label,
+                total,
+                done: 0,
+                started: Instant::now(),
+            });
+            key
+        });
+        Counting { inner, key }
+    }
+}
+
+impl Write for Counting<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(key) = self.key {
+            let mut bars = bars();
+            if let Some(bar) = bars.bars.iter_mut().find(|bar| bar.key == key) {
+                bar.done += written as u64;
+            }
+            if bars
+                .drawn_at
+                .is_none_or(|drawn_at| drawn_at.elapsed() >= REDRAW)
+            {
+                bars.draw(&mut io::stderr().lock());
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for Counting<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            let mut bars = bars();
+            bars.bars.retain(|bar| bar.key != key);
+            bars.draw(&mut io::stderr().lock());
+        }
+    }
+}
+
+/*
+ * The target of the log, which clears the progress line before a log line and draws it again
+ * below it.
+ */
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bars = bars();
+        let mut stderr = io::stderr().lock();
+        bars.clear(&mut stderr);
+        stderr.write_all(buf)?;
+        if buf.ends_with(b"\n") {
+            bars.draw(&mut stderr);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,391 @@
+use chrono::{DateTime, Datelike, Local};
+use clap::Args;
+use log::{debug, info};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    catalog::Catalog,
+    incremental::Incremental,
+    storage::{checksum_file_name, Destination},
+    types::DockerError,
+    CliArguments,
+};
+
+/// The retention rules, evaluated per container and mount.
+#[derive(Args, Clone)]
+pub struct RetentionArguments {
+    /// Keep the last N archives
+    #[arg(long)]
+    keep_last: Option<usize>,
+
+    /// Keep the last archive of each of the last N days
+    #[arg(long)]
+    keep_daily: Option<usize>,
+
+    /// Keep the last archive of each of the last N weeks
+    #[arg(long)]
+    keep_weekly: Option<usize>,
+
+    /// Keep the last archive of each of the last N months
+    #[arg(long)]
+    keep_monthly: Option<usize>,
+}
+
+impl RetentionArguments {
+    /*
+     * Whether any retention rule is configured.
+     */
+    pub fn is_configured(&self) -> bool {
+        self.keep_last.is_some()
+            || self.keep_daily.is_some()
+            || self.keep_weekly.is_some()
+            || self.keep_monthly.is_some()
+    }
+}
+
+/// Delete the archives which are expired according to the retention rules.
+#[derive(Args, Clone)]
+pub struct PruneArguments {
+    #[command(flatten)]
+    retention: RetentionArguments,
+
+    /// Only show which archives would be deleted
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+}
+
+/*
+ * The period an archive falls in, for the daily, weekly and monthly rules.
+ */
+type Period = fn(&DateTime<Local>) -> (i32, u32);
+
+struct Archive {
+    name: String,
+    path: PathBuf,
+    level: Option<u32>,
+    taken: DateTime<Local>,
+}
+
+/*
+ * Prune command.
+ */
+pub fn prune(prune_args: &PruneArguments, cli_args: &CliArguments) -> Result<(), DockerError> {
+    apply_retention(&prune_args.retention, prune_args.dry_run, cli_args)
+}
+
+/*
+ * Delete the expired archives in the output directory. An incremental chain is kept or deleted as
+ * a whole, and the archives of the current chain of a mount are always kept as the next archives
+ * build on it.
+ */
+pub fn apply_retention(
+    retention: &RetentionArguments,
+    dry_run: bool,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    if !retention.is_configured() {
+        return Err(DockerError::from(
+            "No retention rule given, use --keep-last, --keep-daily, --keep-weekly or --keep-monthly",
+        ));
+    }
+    if Destination::parse(cli_args.dest.as_deref(), &cli_args.output_dir)?.is_remote() {
+        return Err(DockerError::from(
+            "Pruning is only supported for archives in the output directory",
+        ));
+    }
+    let output_dir = Path::new(&cli_args.output_dir);
+    let catalog = Catalog::exists(cli_args)
+        .then(|| Catalog::open(cli_args))
+        .transpose()?;
+    let template = &cli_args.name_template();
+    if !template.has_timestamp() {
+        return Err(DockerError::Config(
+            "The name template has no {date} or {time}, every run replaces the archive of a mount \
+             so there is nothing to prune"
+                .to_string(),
+        ));
+    }
+    let mut archives: HashMap<String, Vec<Archive>> = HashMap::new();
+    for (name, archive, path, metadata) in template.find_archives(output_dir)? {
+        let Some(identity) = template.identity(&archive.base) else {
+            continue;
+        };
+        // The modification time is only a last resort, copying the archives resets it.
+        let recorded = match &catalog {
+            Some(catalog) => catalog
+                .archive_timestamp(&name)?
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+                .map(|timestamp| timestamp.with_timezone(&Local)),
+            None => None,
+        };
+        let taken = match template.timestamp(&archive.base).or(recorded) {
+            Some(taken) => taken,
+            None => metadata.modified()?.into(),
+        };
+        archives.entry(identity).or_default().push(Archive {
+            name,
+            path,
+            level: archive.level,
+            taken,
+        });
+    }
+
+    for (identity, mut archives) in archives {
+        archives.sort_by_key(|a| (Reverse(a.taken), Reverse(a.level)));
+        let chain: HashSet<String> = Incremental::new(output_dir, &identity)
+            .load_chain()?
+            .archives
+            .into_iter()
+            .collect();
+        let keep = archives_kept_with_chains(&archives, retention);
+        for (index, archive) in archives.iter().enumerate() {
+            let in_chain = chain.contains(&archive.name);
+            if keep.contains(&index) || in_chain {
+                debug!("Keeping {}", archive.path.display());
+            } else if dry_run {
+                info!("Would delete {}", archive.path.display());
+            } else {
+                info!("Deleting {}", archive.path.display());
+                fs::remove_file(&archive.path)?;
+                let checksum = archive.path.with_file_name(checksum_file_name(
+                    &archive
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                ));
+                if checksum.exists() {
+                    fs::remove_file(checksum)?;
+                }
+                if let Some(catalog) = &catalog {
+                    catalog.prune_archive(&archive.name)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/*
+ * The indexes of the archives (sorted newest first) that are kept by at least one rule, with the
+ * whole chain of each kept incremental archive: it can only be restored with the archives of its
+ * chain before it.
+ */
+fn archives_kept_with_chains(
+    archives: &[Archive],
+    retention: &RetentionArguments,
+) -> HashSet<usize> {
+    let kept = archives_to_keep(archives, retention);
+    let units = chain_units(archives);
+    (0..archives.len())
+        .filter(|index| kept.iter().any(|kept| units[*kept] == units[*index]))
+        .collect()
+}
+
+/*
+ * The indexes of the archives (sorted newest first) that are kept by at least one rule.
+ */
+fn archives_to_keep(archives: &[Archive], retention: &RetentionArguments) -> HashSet<usize> {
+    let mut keep: HashSet<usize> =
+        (0..retention.keep_last.unwrap_or(0).min(archives.len())).collect();
+    let periods: [(Option<usize>, Period); 3] = [
+        (retention.keep_daily, |d| (d.year(), d.ordinal())),
+        (retention.keep_weekly, |d| {
+            (d.iso_week().year(), d.iso_week().week())
+        }),
+        (retention.keep_monthly, |d| (d.year(), d.month())),
+    ];
+    for (count, period) in periods {
+        let Some(count) = count else { continue };
+        let mut seen = HashSet::new();
+        for (index, archive) in archives.iter().enumerate() {
+            if seen.len() >= count {
+                break;
+            }
+            if seen.insert(period(&archive.taken)) {
+                keep.insert(index);
+            }
+        }
+    }
+    keep
+}
+
+/*
+ * The chain every archive (sorted newest first) belongs to, as the index of its level 0 archive.
+ * An archive without a level, or an incremental archive whose level 0 archive is gone, is a chain
+ * of its own.
+ */
+fn chain_units(archives: &[Archive]) -> Vec<usize> {
+    let mut units = (0..archives.len()).collect::<Vec<_>>();
+    let mut base = None;
+    for (index, archive) in archives.iter().enumerate().rev() {
+        match archive.level {
+            Some(0) => base = Some(index),
+            Some(_) => units[index] = base.unwrap_or(index),
+            None => base = None,
+        }
+    }
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDateTime, TimeZone};
+
+    /*
+     * An archive taken at noon of the day, away from the clock changes, or at a time of that day.
+     */
+    fn archive(taken: &str, level: Option<u32>) -> Archive {
+        let taken = match taken.len() {
+            10 => format!("{} 12:00", taken),
+            _ => taken.to_string(),
+        };
+        let naive = NaiveDateTime::parse_from_str(&taken, "%Y-%m-%d %H:%M").unwrap();
+        Archive {
+            name: format!("web_data-{}.tar", taken),
+            path: PathBuf::from(format!("web_data-{}.tar", taken)),
+            level,
+            taken: Local.from_local_datetime(&naive).unwrap(),
+        }
+    }
+
+    fn retention(
+        last: Option<usize>,
+        daily: Option<usize>,
+        weekly: Option<usize>,
+        monthly: Option<usize>,
+    ) -> RetentionArguments {
+        RetentionArguments {
+            keep_last: last,
+            keep_daily: daily,
+            keep_weekly: weekly,
+            keep_monthly: monthly,
+        }
+    }
+
+    fn kept(archives: &[Archive], retention: &RetentionArguments) -> Vec<usize> {
+        let mut kept = archives_kept_with_chains(archives, retention)
+            .into_iter()
+            .collect::<Vec<_>>();
+        kept.sort();
+        kept
+    }
+
+    #[test]
+    fn keeps_the_last_archives() {
+        let archives = [
+            archive("2026-10-15", None),
+            archive("2026-10-14", None),
+            archive("2026-10-13", None),
+        ];
+        assert_eq!(
+            kept(&archives, &retention(Some(2), None, None, None)),
+            [0, 1]
+        );
+        assert_eq!(
+            kept(&archives, &retention(Some(5), None, None, None)),
+            [0, 1, 2]
+        );
+        assert!(kept(&archives, &retention(Some(0), None, None, None)).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_last_archive_of_each_day() {
+        let archives = [
+            archive("2026-10-15 22:00", None),
+            archive("2026-10-15 03:00", None),
+            archive("2026-10-14", None),
+            archive("2026-10-12", None),
+        ];
+        assert_eq!(
+            kept(&archives, &retention(None, Some(2), None, None)),
+            [0, 2]
+        );
+        // The days without an archive do not count.
+        assert_eq!(
+            kept(&archives, &retention(None, Some(3), None, None)),
+            [0, 2, 3]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_archive_of_each_week() {
+        let archives = [
+            archive("2027-01-01", None),
+            // Monday of the same ISO week 53 of 2026.
+            archive("2026-12-28", None),
+            archive("2026-12-27", None),
+            archive("2026-10-15", None),
+            archive("2026-10-12", None),
+            archive("2026-10-11", None),
+        ];
+        assert_eq!(
+            kept(&archives, &retention(None, None, Some(2), None)),
+            [0, 2]
+        );
+        assert_eq!(
+            kept(&archives, &retention(None, None, Some(4), None)),
+            [0, 2, 3, 5]
+        );
+    }
+
+    #[test]
+    fn keeps_what_any_rule_keeps() {
+        let archives = [
+            archive("2026-10-15", None),
+            archive("2026-10-14", None),
+            archive("2026-09-30", None),
+            archive("2026-08-31", None),
+        ];
+        assert_eq!(
+            kept(&archives, &retention(Some(1), None, None, Some(3))),
+            [0, 2, 3]
+        );
+        assert_eq!(
+            kept(&archives, &retention(None, Some(2), None, Some(1))),
+            [0, 1]
+        );
+    }
+
+    #[test]
+    fn keeps_incremental_chains_as_a_whole() {
+        let archives = [
+            archive("2026-10-15", Some(2)),
+            archive("2026-10-14", Some(1)),
+            archive("2026-10-13", Some(0)),
+            archive("2026-10-12", Some(1)),
+            archive("2026-10-11", Some(0)),
+            archive("2026-10-10", None),
+            // Its full archive is gone, it is a chain of its own.
+            archive("2026-10-09", Some(1)),
+        ];
+        assert_eq!(chain_units(&archives), [2, 2, 2, 4, 4, 5, 6]);
+        assert_eq!(
+            kept(&archives, &retention(Some(1), None, None, None)),
+            [0, 1, 2]
+        );
+        // The increment of the older chain keeps its full archive.
+        assert_eq!(
+            kept(&archives, &retention(Some(4), None, None, None)),
+            [0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            kept(&archives, &retention(None, None, None, None)),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            kept(&archives, &retention(None, None, Some(2), None)),
+            [0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            kept(&archives, &retention(None, Some(6), None, None)),
+            [0, 1, 2, 3, 4, 5]
+        );
+    }
+}
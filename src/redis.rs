@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use log::{debug, info};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{exec::exec_in_container_into, types::DockerError, CliArguments};
+
+/*
+ * How long a background save may take.
+ */
+const SAVE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/*
+ * Whether Redis writes its dump.rdb before the data volume is archived, so the archive has the
+ * current data.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum RedisSnapshot {
+    /// Archive the data volume as it is
+    Off,
+    /// Save in the background with BGSAVE and wait for it to finish
+    Bgsave,
+    /// Save with SAVE, which blocks the clients while saving
+    Save,
+}
+
+/*
+ * Whether an image looks like a Redis image: redis, bitnami/redis or valkey/valkey.
+ */
+pub fn is_redis(image: &str) -> bool {
+    let repository = image.split('@').next().unwrap_or_default();
+    let name = repository.rsplit('/').next().unwrap_or_default();
+    name.starts_with("redis") || name.starts_with("valkey")
+}
+
+/*
+ * Let Redis in the container write its dump.rdb and wait until it is written. The password is
+ * taken from REDIS_PASSWORD in the environment of the container, as the bitnami image has it.
+ */
+pub fn snapshot(
+    cli_args: &CliArguments,
+    name: &str,
+    id: &str,
+    env: &[String],
+    mode: RedisSnapshot,
+) -> Result<(), DockerError> {
+    let password = env
+        .iter()
+        .filter_map(|variable| variable.strip_prefix("REDIS_PASSWORD="))
+        .next_back()
+        .filter(|password| !password.is_empty());
+    let cli = |command: &str| -> Result<String, DockerError> {
+        let mut output = vec![];
+        let env: Vec<(&str, &str)> = password.map(|p| ("REDISCLI_AUTH", p)).into_iter().collect();
+        // Valkey images may only have valkey-cli.
+        let script = "exec \"$(command -v redis-cli || echo valkey-cli)\" \"$@\"";
+        exec_in_container_into(
+            cli_args,
+            id,
+            &["sh", "-c", script, "sh", command],
+            &env,
+            &mut output,
+        )?;
+        let reply = String::from_utf8_lossy(&output).trim().to_string();
+        debug!("[{}] - redis {}: {}", name, command, reply);
+        if reply.starts_with("ERR") || reply.starts_with("NOAUTH") || reply.starts_with("WRONGPASS")
+        {
+            return Err(DockerError::from(
+                format!("{} failed: {}", command, reply).as_str(),
+            ));
+        }
+        Ok(reply)
+    };
+    match mode {
+        RedisSnapshot::Off => Ok(()),
+        RedisSnapshot::Save => {
+            info!("[{}] - saving the Redis data", name);
+            cli("SAVE").map(|_| ())
+        }
+        RedisSnapshot::Bgsave => {
+            info!("[{}] - saving the Redis data in the background", name);
+            let last_save = cli("LASTSAVE")?;
+            // Also when a save is already in progress, its end is waited for.
+            cli("BGSAVE")?;
+            let start = Instant::now();
+            while cli("LASTSAVE")? == last_save {
+                if start.elapsed() > SAVE_TIMEOUT {
+                    return Err(DockerError::from(
+                        format!("BGSAVE did not finish in {}s", SAVE_TIMEOUT.as_secs()).as_str(),
+                    ));
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+            let persistence = cli("INFO persistence")?;
+            if !persistence.contains("rdb_last_bgsave_status:ok") {
+                return Err(DockerError::from("BGSAVE failed, see the log of Redis"));
+            }
+            Ok(())
+        }
+    }
+}
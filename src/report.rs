@@ -0,0 +1,225 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backup::Outcome,
+    manifest::{Manifest, MountManifest, MountStatus},
+    types::DockerError,
+};
+
+/*
+ * The result of a backup run for scripts and dashboards: how the run went and per container the
+ * result of its mounts, with the errors.
+ */
+#[derive(Serialize)]
+struct Report<'a> {
+    run_id: Option<&'a str>,
+    timestamp: Option<DateTime<Local>>,
+    duration_secs: f64,
+    status: Outcome,
+    error: Option<String>,
+    containers: Vec<ContainerReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct ContainerReport<'a> {
+    name: &'a str,
+    id: &'a str,
+    status: MountStatus,
+    error: Option<&'a str>,
+    mounts: &'a [MountManifest],
+}
+
+/*
+ * Write the report of a run as json to the file, or to stdout for -. Without a manifest the run
+ * failed before any container was backed up.
+ */
+pub fn write(
+    path: &str,
+    manifest: Option<&Manifest>,
+    started: Instant,
+    result: &Result<Outcome, DockerError>,
+) -> Result<(), DockerError> {
+    let report = report(manifest, started, result);
+    if path == "-" {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, &report)?;
+        writeln!(stdout)?;
+    } else {
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    }
+    Ok(())
+}
+
+/*
+ * The report of a run as json, for the notifications.
+ */
+pub fn to_json(
+    manifest: Option<&Manifest>,
+    started: Instant,
+    result: &Result<Outcome, DockerError>,
+) -> Result<String, DockerError> {
+    Ok(serde_json::to_string_pretty(&report(
+        manifest, started, result,
+    ))?)
+}
+
+fn report<'a>(
+    manifest: Option<&'a Manifest>,
+    started: Instant,
+    result: &Result<Outcome, DockerError>,
+) -> Report<'a> {
+    let containers = manifest
+        .map(|manifest| {
+            manifest
+                .containers
+                .iter()
+                .map(|container| ContainerReport {
+                    name: &container.name,
+                    id: &container.id,
+                    status: match container.succeeded() {
+                        true => MountStatus::Success,
+                        false => MountStatus::Failed,
+                    },
+                    error: container.error.as_deref(),
+                    mounts: &container.mounts,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Report {
+        run_id: manifest.map(|manifest| manifest.run_id.as_str()),
+        timestamp: manifest.map(|manifest| manifest.timestamp),
+        duration_secs: started.elapsed().as_secs_f64(),
+        status: *result.as_ref().unwrap_or(&Outcome::Failed),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        containers,
+    }
+}
+
+/*
+ * Print a summary of the run to stderr.
+ */
+pub fn print_summary(manifest: &Manifest, kind: &str, started: Instant) {
+    eprint!("{}", summary(manifest, kind, started));
+}
+
+/*
+ * A summary of the run: the containers or volumes and mounts backed up and failed, the bytes
+ * written, the time it took and the failures.
+ */
+pub fn summary(manifest: &Manifest, kind: &str, started: Instant) -> String {
+    let mounts = manifest
+        .containers
+        .iter()
+        .flat_map(|container| container.mounts.iter());
+    let (succeeded, failed): (Vec<&MountManifest>, Vec<&MountManifest>) =
+        mounts.partition(|mount| mount.status == MountStatus::Success);
+    let failed_containers = manifest
+        .containers
+        .iter()
+        .filter(|container| !container.succeeded())
+        .count();
+    let written: u64 = succeeded.iter().map(|mount| mount.size).sum();
+    let estimates: Vec<u64> = succeeded
+        .iter()
+        .filter_map(|mount| mount.estimated_size)
+        .collect();
+    let written = match estimates.is_empty() {
+        true => human_size(written),
+        false => format!(
+            "{} (estimated {})",
+            human_size(written),
+            human_size(estimates.iter().sum())
+        ),
+    };
+    let rows = [
+        (
+            kind,
+            format!(
+                "{} backed up, {} failed",
+                manifest.containers.len() - failed_containers,
+                failed_containers
+            ),
+        ),
+        (
+            "Mounts",
+            format!("{} backed up, {} failed", succeeded.len(), failed.len()),
+        ),
+        ("Written", written),
+        ("Elapsed", human_duration(started.elapsed())),
+    ];
+    let mut summary = "Summary\n".to_string();
+    for (name, value) in rows {
+        summary.push_str(&format!("  {:<12} {}\n", name, value));
+    }
+    if failed_containers > 0 {
+        summary.push_str("Failures\n");
+    }
+    for container in manifest.containers.iter() {
+        if let Some(error) = &container.error {
+            summary.push_str(&format!("  {}: {}\n", container.name, one_line(error)));
+        }
+        for mount in container.mounts.iter() {
+            if let Some(error) = mount
+                .error
+                .as_ref()
+                .filter(|_| mount.status == MountStatus::Failed)
+            {
+                summary.push_str(&format!(
+                    "  {} {}: {}\n",
+                    container.name,
+                    mount.destination,
+                    one_line(error)
+                ));
+            }
+        }
+    }
+    summary
+}
+
+fn one_line(error: &str) -> String {
+    error.lines().collect::<Vec<_>>().join(" ")
+}
+
+/*
+ * A size in bytes with a binary unit, like 1.5 GiB.
+ */
+pub fn human_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in units {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    match unit {
+        "B" => format!("{} B", bytes),
+        unit => format!("{:.1} {}", size, unit),
+    }
+}
+
+/*
+ * A duration like 4.2s, 3m 05s or 1h 02m 03s.
+ */
+fn human_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}h {:02}m {:02}s",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        ),
+    }
+}
@@ -0,0 +1,778 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use clap::Args;
+use log::{debug, info, warn};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    archive::ArchiveName,
+    catalog::Catalog,
+    diff::restore_diff,
+    exec::{
+        create_container, create_volume, execute_pipeline, inspect_container, inspect_volume,
+        invisible_dir, load_image, run_helper_from, visible_to_daemon, Helper, SuspendedContainer,
+    },
+    incremental::Incremental,
+    manifest::{Manifest, MountStatus},
+    storage::Destination,
+    types::DockerError,
+    CliArguments,
+};
+
+/// Restore the contents of a volume from a backup archive.
+#[derive(Args, Clone)]
+pub struct RestoreArguments {
+    /// The archive to restore, defaults to the (latest incremental) archive of the given container
+    /// and mount in the output directory
+    archive: Option<String>,
+
+    /// The container whose mount is restored
+    #[arg(short, long)]
+    container: Option<String>,
+
+    /// Restore the last archive of the container and mount made at or before this time, like
+    /// 2026-10-14T031500, "2026-10-14 03:15" or 2026-10-14 for the last one of that day
+    #[arg(
+        long,
+        visible_alias = "timestamp",
+        value_name = "TIME",
+        value_parser = parse_time,
+        requires = "container",
+        conflicts_with_all = ["archive", "recreate_container"]
+    )]
+    at: Option<DateTime<Local>>,
+
+    /// The mount destination inside the container, e.g. /data
+    #[arg(
+        short,
+        long,
+        required_unless_present = "recreate_container",
+        default_value = ""
+    )]
+    mount: String,
+
+    /// Restore into this named volume instead of the mount of the container, created if missing
+    #[arg(long)]
+    volume: Option<String>,
+
+    /// Restore into a new named volume, leaving the container and its volumes untouched. Fails when
+    /// the volume already exists
+    #[arg(long = "as", value_name = "NEW_VOLUME", conflicts_with_all = ["volume", "stop_start"])]
+    as_volume: Option<String>,
+
+    /// Only restore these files or directories of the mount, like etc/config.yml for
+    /// /data/etc/config.yml
+    #[arg(long = "path", value_name = "PATH")]
+    paths: Vec<String>,
+
+    /// Extract the archive into this directory on the host instead of into a volume, like
+    /// <DIR>/etc/config.yml for /data/etc/config.yml
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["volume", "as_volume", "stop_start"])]
+    to_dir: Option<PathBuf>,
+
+    /// Stop the container before the restore and restart it afterwards
+    #[arg(short, long, default_value = "false")]
+    stop_start: bool,
+
+    /// Recreate the removed container from the configuration its last backup recorded in the
+    /// catalog: restore all its mounts from the archives of that backup into their volumes and
+    /// bind mount directories and run it with the same image, entrypoint, command, environment,
+    /// ports, labels, mounts and restart policy
+    #[arg(
+        long,
+        requires = "container",
+        conflicts_with_all = ["archive", "mount", "volume", "as_volume", "paths", "to_dir", "stop_start"]
+    )]
+    recreate_container: bool,
+
+    /// Only show the files the restore would overwrite, add and delete in the volume, compared
+    /// with its current files in a helper container, and restore nothing
+    #[arg(long, default_value = "false", conflicts_with = "to_dir")]
+    dry_run: bool,
+
+    /// The age identity file to decrypt age encrypted archives with, gpg uses the gpg agent
+    #[arg(long)]
+    age_identity: Option<String>,
+
+    /// Do not ask for confirmation before overwriting
+    #[arg(short, long, default_value = "false")]
+    yes: bool,
+}
+
+impl RestoreArguments {
+    /*
+     * What is restored: the mount or the paths of the mount.
+     */
+    fn restored(&self) -> String {
+        match self.paths.is_empty() {
+            true => self.mount.clone(),
+            false => format!("{} of {}", self.paths.join(", "), self.mount),
+        }
+    }
+
+    /*
+     * The arguments restoring the latest archive of a mount of a container without asking.
+     */
+    pub fn latest(
+        container: &str,
+        mount: &str,
+        stop_start: bool,
+        age_identity: Option<String>,
+    ) -> RestoreArguments {
+        RestoreArguments {
+            archive: None,
+            container: Some(container.to_string()),
+            at: None,
+            mount: mount.to_string(),
+            volume: None,
+            as_volume: None,
+            paths: vec![],
+            to_dir: None,
+            stop_start,
+            recreate_container: false,
+            dry_run: false,
+            age_identity,
+            yes: true,
+        }
+    }
+}
+
+/*
+ * Restore a single archive into a mount of a container or into a named volume.
+ */
+pub fn restore(
+    restore_args: &RestoreArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    if restore_args.recreate_container {
+        return recreate_container(restore_args, cli_args);
+    }
+    let destination = Destination::parse(cli_args.dest.as_deref(), &cli_args.output_dir)?;
+    let archive = match (&restore_args.archive, &restore_args.container) {
+        (Some(archive), _) => PathBuf::from(archive),
+        (None, Some(container)) => find_archive(
+            container,
+            &restore_args.mount,
+            restore_args.at.as_ref(),
+            &destination,
+            cli_args,
+        )?,
+        (None, None) => {
+            return Err(DockerError::from(
+                "Either an archive or a container is required",
+            ))
+        }
+    };
+    let archives = replay_order(&archive, cli_args)?;
+    let archive_name = cli_args.name_template().split_path(&archive).1;
+    let archive = archive_location(&archive, &destination, cli_args);
+
+    if let Some(volume) = &restore_args.as_volume {
+        if inspect_volume(cli_args, volume)?.is_some() {
+            return Err(DockerError::from(
+                format!(
+                    "Volume {} already exists, --as only restores into a new volume",
+                    volume
+                )
+                .as_str(),
+            ));
+        }
+    }
+    let volume = restore_args
+        .volume
+        .as_ref()
+        .or(restore_args.as_volume.as_ref());
+    // The directory of a bind mount is bind mounted into the helper container.
+    let dir = volume
+        .map(Path::new)
+        .filter(|dir| dir.is_absolute() && dir.is_dir());
+    if let Some(dir) = dir.filter(|_| restore_args.to_dir.is_none()) {
+        if !visible_to_daemon(dir, cli_args)? {
+            return Err(invisible_dir(dir));
+        }
+    }
+    let target = match (volume, &restore_args.container) {
+        // Extracted on the host, there is no helper container.
+        _ if restore_args.to_dir.is_some() => Helper::default(),
+        (Some(volume), _) => Helper {
+            volumes: vec![format!("{}:{}", volume, restore_args.mount)],
+            ..Default::default()
+        },
+        (None, Some(container)) => {
+            verify_mount(container, &restore_args.mount, cli_args)?;
+            Helper {
+                volumes_from: Some(container.clone()),
+                ..Default::default()
+            }
+        }
+        (None, None) => {
+            return Err(DockerError::from(
+                "Either a volume or a container is required",
+            ))
+        }
+    };
+
+    let target_name = restore_args
+        .to_dir
+        .as_ref()
+        .map(|dir| format!("directory {}", dir.display()))
+        .or(volume.map(|v| format!("volume {}", v)))
+        .or(restore_args
+            .container
+            .as_ref()
+            .map(|c| format!("container {}", c)))
+        .unwrap_or_default();
+    if restore_args.dry_run {
+        // A new volume is empty, and a missing one would be created by the helper container.
+        let live = match volume {
+            _ if restore_args.as_volume.is_some() => None,
+            Some(volume) if inspect_volume(cli_args, volume)?.is_none() => None,
+            _ => Some(&target),
+        };
+        let diff = restore_diff(
+            &archives,
+            live,
+            &restore_args.mount,
+            &restore_args.paths,
+            &destination,
+            restore_args.age_identity.as_deref(),
+            cli_args,
+        )?;
+        diff.print();
+        info!(
+            "Restoring {} would overwrite {} files, add {} and delete {} in {}, {} files are \
+             unchanged. Nothing was restored, run without --dry-run and with --yes to restore",
+            archive,
+            diff.overwritten.len(),
+            diff.added.len(),
+            diff.deleted.len(),
+            target_name,
+            diff.unchanged
+        );
+        return Ok(());
+    }
+
+    // A new volume has nothing to overwrite.
+    if !restore_args.yes
+        && restore_args.as_volume.is_none()
+        && !confirm(
+            format!(
+                "Restoring {} will overwrite {} in {}. Continue? [y/N] ",
+                archive,
+                restore_args.restored(),
+                target_name
+            )
+            .as_str(),
+        )?
+    {
+        info!("Restore cancelled");
+        return Ok(());
+    }
+
+    let stopped_container = restore_args
+        .container
+        .as_ref()
+        .filter(|_| restore_args.stop_start && volume.is_none());
+    let stopped = stopped_container
+        .map(|container| SuspendedContainer::stop(cli_args, container, container))
+        .transpose()?;
+
+    // The helper container would create a missing volume with the local driver and no options. A
+    // path is the directory of a bind mount.
+    if let Some(volume) = restore_args.volume.as_ref().filter(|v| !v.starts_with('/')) {
+        create_recorded_volume(volume, &archive_name, cli_args)?;
+    }
+    if let Some(dir) = &restore_args.to_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let mut found = 0;
+    let result = archives.iter().try_for_each(|archive| {
+        info!(
+            "Restoring {} into {} of {}",
+            archive_location(archive, &destination, cli_args),
+            restore_args.restored(),
+            target_name
+        );
+        match restore_archive(archive, &target, &destination, restore_args, cli_args) {
+            // An incremental archive only has the files changed since the previous one.
+            Err(e) if !restore_args.paths.is_empty() && not_in_archive(&e) => {
+                debug!("The paths are not in {}: {}", archive.display(), e);
+                Ok(())
+            }
+            result => {
+                found += 1;
+                result
+            }
+        }
+    });
+    let result = result.and_then(|()| match found {
+        0 => Err(DockerError::from(
+            format!("{} is not in {}", restore_args.restored(), archive).as_str(),
+        )),
+        _ => Ok(()),
+    });
+
+    if let Some(stopped) = stopped {
+        stopped.resume()?;
+    }
+    result?;
+    info!("Restore of {} done. 🫡", archive);
+    Ok(())
+}
+
+/*
+ * Create a missing volume with the driver, options and labels the backup of the archive recorded
+ * for it, like a volume of the local driver on an nfs share or a block device. A volume that
+ * exists is kept.
+ */
+fn create_recorded_volume(
+    volume: &str,
+    archive: &str,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let existing = inspect_volume(cli_args, volume)?;
+    // manifest.json is overwritten by every run, the catalog keeps the manifest of each run.
+    let recorded = match Catalog::exists(cli_args) {
+        true => Catalog::open(cli_args)?.archive_manifest(archive)?,
+        false => None,
+    };
+    let manifest = match recorded
+        .map(Ok)
+        .unwrap_or_else(|| Manifest::read(Path::new(&cli_args.output_dir)))
+    {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            debug!("No driver recorded for volume {}: {}", volume, e);
+            return Ok(());
+        }
+    };
+    let Some(spec) = manifest.volume(volume) else {
+        return Ok(());
+    };
+    match existing {
+        Some(existing) if existing.driver != spec.driver => warn!(
+            "Volume {} exists with driver {}, it was backed up with driver {}",
+            volume, existing.driver, spec.driver
+        ),
+        Some(_) => {}
+        None => {
+            info!(
+                "Creating volume {} with driver {} and options {:?}",
+                volume, spec.driver, spec.options
+            );
+            create_volume(cli_args, volume, spec)?;
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Recreate a container from the configuration in the manifest of its last backup: restore its
+ * mounts from the archives of that backup and run it again.
+ */
+fn recreate_container(
+    restore_args: &RestoreArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let name = restore_args
+        .container
+        .as_ref()
+        .ok_or(DockerError::from("A container is required"))?;
+    let output_dir = Path::new(&cli_args.output_dir);
+    // manifest.json is overwritten by every run, the catalog keeps the manifest of each run.
+    let recorded = match Catalog::exists(cli_args) {
+        true => Catalog::open(cli_args)?.last_manifest(name)?,
+        false => None,
+    };
+    let manifest = match recorded {
+        Some(manifest) => manifest,
+        None => Manifest::read(output_dir)?,
+    };
+    let container = manifest
+        .containers
+        .iter()
+        .find(|container| container.name == *name)
+        .ok_or(DockerError::from(
+            format!("No backup of container {} is recorded", name).as_str(),
+        ))?;
+    let spec = container.container.as_ref().ok_or(DockerError::from(
+        format!(
+            "The backup of {} has no configuration of container {}",
+            manifest.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            name
+        )
+        .as_str(),
+    ))?;
+    if inspect_container(cli_args, name)?.is_some() {
+        return Err(DockerError::from(
+            format!("Container {} exists, remove it before recreating it", name).as_str(),
+        ));
+    }
+    if !restore_args.yes
+        && !confirm(
+            format!(
+                "Recreating container {} will overwrite the files in its volumes and bind mount \
+                 directories. Continue? [y/N] ",
+                name
+            )
+            .as_str(),
+        )?
+    {
+        info!("Restore cancelled");
+        return Ok(());
+    }
+
+    for mount in spec.mounts.iter() {
+        let backed_up = container.mounts.iter().find(|backed_up| {
+            backed_up.destination == mount.destination && backed_up.status == MountStatus::Success
+        });
+        let Some(backed_up) = backed_up else {
+            warn!(
+                "{} of container {} was not backed up, {} {} is left as it is",
+                mount.destination, name, mount.kind, mount.source
+            );
+            continue;
+        };
+        // A bind mount is restored into its directory on the host like into a volume.
+        let mount_args = RestoreArguments {
+            archive: backed_up
+                .archive
+                .as_ref()
+                .map(|archive| output_dir.join(archive).to_string_lossy().to_string()),
+            volume: Some(mount.source.clone()),
+            ..RestoreArguments::latest(
+                name,
+                &mount.destination,
+                false,
+                restore_args.age_identity.clone(),
+            )
+        };
+        restore(&mount_args, cli_args)?;
+    }
+    // Without a registry the image comes from the backup. It was saved by its id, so it is loaded
+    // without its tag and the container is run from the id.
+    let mut spec = spec.clone();
+    if let Some(image_archive) = &container.image_archive {
+        let path = output_dir.join(image_archive);
+        match File::open(&path) {
+            Ok(file) => {
+                info!("Loading image {} from {}", spec.image, path.display());
+                load_image(cli_args, file)?;
+                spec.image = spec.image_id.clone();
+            }
+            Err(e) => warn!(
+                "Image archive {} not found, the image is pulled when missing: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    let id = create_container(cli_args, name, &spec)?;
+    info!(
+        "Recreated container {} ({}) from image {}",
+        name, id, spec.image
+    );
+    Ok(())
+}
+
+/*
+ * Stream a single archive into a helper container extracting it into the target, remote archives
+ * are downloaded while streaming.
+ */
+fn restore_archive(
+    archive: &Path,
+    target: &Helper,
+    destination: &Destination,
+    restore_args: &RestoreArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let name = archive_file_name(archive, cli_args)?;
+    let (input, download) = match destination.download_command(&name.file_name())? {
+        Some((download, credentials)) => (credentials, Some(download)),
+        None => {
+            let file = File::open(archive).map_err(|e| {
+                DockerError::from(
+                    format!("Archive {} not found: {}", archive.display(), e).as_str(),
+                )
+            })?;
+            (Some(file), None)
+        }
+    };
+    let decryption = name
+        .encryption
+        .map(|e| e.decrypt_command(restore_args.age_identity.as_deref()))
+        .transpose()?;
+
+    let mut extract = vec![];
+    if name.level.is_some() {
+        // Replaying an incremental archive also removes the files deleted since the previous one.
+        extract.push("--listed-incremental=/dev/null".to_string());
+    }
+    // The archive holds the files below the mount, like data/etc/config.yml.
+    let mount = restore_args.mount.trim_matches('/');
+    extract.extend(
+        restore_args
+            .paths
+            .iter()
+            .map(|path| format!("{}/{}", mount, path.trim_matches('/'))),
+    );
+    let mut commands: Vec<Command> = download.into_iter().collect();
+    commands.extend(decryption);
+    commands.extend(name.compression.decompress_command());
+
+    let Some(dir) = &restore_args.to_dir else {
+        let mut helper = target.clone();
+        helper.command = ["tar", "xf", "-", "-C", "/"].map(String::from).to_vec();
+        helper.command.extend(extract);
+        return run_helper_from(&helper, commands, input, cli_args);
+    };
+    let mut tar = Command::new("tar");
+    tar.args(["xf", "-", "-C"]).arg(dir);
+    tar.arg(format!("--strip-components={}", mount.split('/').count()));
+    tar.args(extract);
+    commands.push(tar);
+    let input = input.map(Stdio::from).unwrap_or(Stdio::null());
+    execute_pipeline(commands, input, Stdio::null())
+}
+
+/*
+ * Whether tar failed as the paths to restore are not in the archive.
+ */
+fn not_in_archive(error: &DockerError) -> bool {
+    match error {
+        DockerError::CommandFailed { stderr, .. }
+        | DockerError::HelperContainerFailed { stderr, .. } => {
+            stderr.contains("Not found in archive")
+        }
+        _ => false,
+    }
+}
+
+/*
+ * The archives to extract in order to restore an archive: for an incremental archive this is its
+ * chain up to and including the archive itself.
+ */
+fn replay_order(archive: &Path, cli_args: &CliArguments) -> Result<Vec<PathBuf>, DockerError> {
+    let name = archive_file_name(archive, cli_args)?;
+    if name.level.is_none() {
+        return Ok(vec![archive.to_path_buf()]);
+    }
+    let (dir, file_name) = cli_args.name_template().split_path(archive);
+    let identity = cli_args.name_template().identity(&name.base);
+    let chain = Incremental::new(&dir, identity.as_deref().unwrap_or(&name.base)).load_chain()?;
+    match chain.archives.iter().position(|a| *a == file_name) {
+        Some(position) => Ok(chain.archives[..=position]
+            .iter()
+            .map(|a| dir.join(a))
+            .collect()),
+        None => {
+            earlier_chain(&dir, &name, identity.as_deref(), cli_args)?.ok_or(DockerError::from(
+                format!(
+                    "Archive {} is not part of the incremental chain of {}",
+                    archive.display(),
+                    name.base
+                )
+                .as_str(),
+            ))
+        }
+    }
+}
+
+/*
+ * The archives of an earlier incremental chain up to an archive of it, from the archives of the
+ * same mount in its directory: each level before it is the last archive of that level made before
+ * the one after it.
+ */
+fn earlier_chain(
+    dir: &Path,
+    name: &ArchiveName,
+    identity: Option<&str>,
+    cli_args: &CliArguments,
+) -> Result<Option<Vec<PathBuf>>, DockerError> {
+    let template = cli_args.name_template();
+    if identity.is_none() || !dir.is_dir() {
+        return Ok(None);
+    }
+    let mut archives = template
+        .find_archives(dir)?
+        .into_iter()
+        .filter(|(_, archive, _, _)| {
+            archive.level.is_some() && template.identity(&archive.base).as_deref() == identity
+        })
+        .map(|(_, archive, path, metadata)| {
+            let taken = template.timestamp(&archive.base);
+            (taken, metadata.modified().ok(), archive, path)
+        })
+        .collect::<Vec<_>>();
+    archives.sort_by_key(|(taken, modified, archive, _)| (*taken, *modified, archive.level));
+    let Some(position) = archives
+        .iter()
+        .position(|(_, _, archive, _)| archive == name)
+    else {
+        return Ok(None);
+    };
+    let mut chain = vec![archives[position].3.clone()];
+    let mut level = name.level.unwrap_or_default();
+    for (_, _, archive, path) in archives[..position].iter().rev() {
+        if level == 0 {
+            break;
+        }
+        if archive.level == Some(level - 1) {
+            chain.push(path.clone());
+            level -= 1;
+        }
+    }
+    if level > 0 {
+        return Ok(None);
+    }
+    chain.reverse();
+    Ok(Some(chain))
+}
+
+/*
+ * The location of an archive for logging, remote archives are referred to by their name.
+ */
+fn archive_location(archive: &Path, destination: &Destination, cli_args: &CliArguments) -> String {
+    if destination.is_remote() {
+        destination.location(&cli_args.name_template().split_path(archive).1)
+    } else {
+        archive.display().to_string()
+    }
+}
+
+/*
+ * Parse the name of an archive, including the subdirectories of the name template.
+ */
+pub fn archive_file_name(
+    archive: &Path,
+    cli_args: &CliArguments,
+) -> Result<ArchiveName, DockerError> {
+    ArchiveName::parse(&cli_args.name_template().split_path(archive).1).ok_or(DockerError::from(
+        format!(
+            "Archive {} is not a (compressed) tar file",
+            archive.display()
+        )
+        .as_str(),
+    ))
+}
+
+/*
+ * Find the archive of a mount of a container in the output directory: the latest successful one
+ * in the catalog, or else the newest archive named after the container and mount. With a time the
+ * latest one made at or before it, by the time of its run in the catalog or in its name.
+ */
+pub fn find_archive(
+    container: &str,
+    mount: &str,
+    at: Option<&DateTime<Local>>,
+    destination: &Destination,
+    cli_args: &CliArguments,
+) -> Result<PathBuf, DockerError> {
+    let output_dir = Path::new(&cli_args.output_dir);
+    if Catalog::exists(cli_args) {
+        let catalog = Catalog::open(cli_args)?;
+        let archive = match at {
+            Some(at) => catalog.archive_at(container, mount, at)?,
+            None => catalog.latest_archive(container, mount)?,
+        };
+        if let Some(archive) = archive
+            .map(|archive| output_dir.join(archive))
+            .filter(|archive| destination.is_remote() || archive.exists())
+        {
+            return Ok(archive);
+        }
+    }
+    let template = &cli_args.name_template();
+    let at_or_before = |base: &str| match at {
+        Some(at) => template
+            .timestamp(base)
+            .is_some_and(|timestamp| timestamp <= *at),
+        None => true,
+    };
+    template
+        .find_archives(output_dir)?
+        .into_iter()
+        .filter(|(_, archive, _, _)| template.matches(&archive.base, Some(container), Some(mount)))
+        .filter(|(_, archive, _, _)| at_or_before(&archive.base))
+        .max_by_key(|(_, archive, _, metadata)| {
+            (
+                template.timestamp(&archive.base),
+                metadata.modified().ok(),
+                archive.level,
+            )
+        })
+        .map(|(_, _, path, _)| path)
+        .ok_or(DockerError::from(
+            format!(
+                "No archive of {} in container {}{} found in {}",
+                mount,
+                container,
+                at.map(|at| format!(" made at or before {}", at.format("%Y-%m-%d %H:%M:%S")))
+                    .unwrap_or_default(),
+                cli_args.output_dir
+            )
+            .as_str(),
+        ))
+}
+
+/*
+ * Parse the time of --at: the date and time of an archive name, a date and time with or without
+ * seconds, RFC 3339, or a date for the end of that day.
+ */
+fn parse_time(time: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(time.with_timezone(&Local));
+    }
+    let formats = [
+        "%Y-%m-%dT%H%M%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ];
+    let local = formats
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default()))
+        })
+        .ok_or("use a time like 2026-10-14T031500, \"2026-10-14 03:15\" or 2026-10-14")?;
+    Local
+        .from_local_datetime(&local)
+        .latest()
+        .ok_or("is not a valid local time".to_string())
+}
+
+/*
+ * Make sure the container actually has the mount we are about to restore.
+ */
+fn verify_mount(container: &str, mount: &str, cli_args: &CliArguments) -> Result<(), DockerError> {
+    match inspect_container(cli_args, container)? {
+        Some(container_info) if container_info.mounts.iter().any(|m| m.destination == mount) => {
+            Ok(())
+        }
+        Some(_) => Err(DockerError::from(
+            format!("Container {} has no mount {}", container, mount).as_str(),
+        )),
+        None => Err(DockerError::from(
+            format!("Container {} not found", container).as_str(),
+        )),
+    }
+}
+
+/*
+ * Ask the user a yes/no question on the terminal.
+ */
+fn confirm(question: &str) -> Result<bool, DockerError> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,166 @@
+use serde::Serialize;
+
+use crate::{
+    backup::{self, BackupArguments, Outcome},
+    exec::inspect_container,
+    lock, logging,
+    restore::{self, RestoreArguments},
+    types::DockerError,
+    CliArguments,
+};
+
+/*
+ * A container a backup would back up, with the destinations of its mounts.
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedContainer {
+    pub name: String,
+    pub running: bool,
+    pub mounts: Vec<String>,
+}
+
+/*
+ * Find the containers a backup would back up, without stopping or archiving anything.
+ */
+pub struct BackupPlanner<'a> {
+    cli_args: &'a CliArguments,
+    backup_args: BackupArguments,
+}
+
+impl<'a> BackupPlanner<'a> {
+    /*
+     * Plan the backup the command line would run without a command.
+     */
+    pub fn new(cli_args: &'a CliArguments) -> Self {
+        BackupPlanner::with_arguments(cli_args, &cli_args.backup)
+    }
+
+    pub fn with_arguments(cli_args: &'a CliArguments, backup_args: &BackupArguments) -> Self {
+        BackupPlanner {
+            cli_args,
+            backup_args: backup_args.clone(),
+        }
+    }
+
+    /*
+     * Only plan the backup of these containers, or of the containers matching these regular
+     * expressions.
+     */
+    pub fn containers<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.backup_args = self
+            .backup_args
+            .including(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn plan(&self) -> Result<Vec<PlannedContainer>, DockerError> {
+        let ps_info = logging::quietly(|| self.backup_args.containers(self.cli_args))?;
+        let mut planned = vec![];
+        for ps_info in ps_info {
+            // A container removed in the meantime is not backed up either.
+            let Some(info) = inspect_container(self.cli_args, &ps_info.names)? else {
+                continue;
+            };
+            planned.push(PlannedContainer {
+                running: ps_info.is_running(),
+                name: ps_info.names,
+                mounts: info.mounts.into_iter().map(|m| m.destination).collect(),
+            });
+        }
+        Ok(planned)
+    }
+}
+
+/*
+ * Run a backup of the containers, locking out other runs like the command line does.
+ */
+pub struct BackupRunner<'a> {
+    cli_args: &'a CliArguments,
+    backup_args: BackupArguments,
+}
+
+impl<'a> BackupRunner<'a> {
+    /*
+     * Run the backup the command line would run without a command.
+     */
+    pub fn new(cli_args: &'a CliArguments) -> Self {
+        BackupRunner::with_arguments(cli_args, &cli_args.backup)
+    }
+
+    pub fn with_arguments(cli_args: &'a CliArguments, backup_args: &BackupArguments) -> Self {
+        BackupRunner {
+            cli_args,
+            backup_args: backup_args.clone(),
+        }
+    }
+
+    /*
+     * Only back up these containers, or the containers matching these regular expressions.
+     */
+    pub fn containers<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.backup_args = self
+            .backup_args
+            .including(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /*
+     * Run the backup, an error when it could not run at all, like when another run holds the
+     * lock.
+     */
+    pub fn run(&self) -> Result<Outcome, DockerError> {
+        let _lock = lock::acquire(self.cli_args)?;
+        backup::backup(&self.backup_args, self.cli_args)
+    }
+}
+
+/*
+ * Restore the latest archive of a mount of a container, locking out other runs like the command
+ * line does.
+ */
+pub struct RestoreRunner<'a> {
+    cli_args: &'a CliArguments,
+    container: String,
+    mount: String,
+    stop_start: bool,
+    age_identity: Option<String>,
+}
+
+impl<'a> RestoreRunner<'a> {
+    pub fn new(cli_args: &'a CliArguments, container: &str, mount: &str) -> Self {
+        RestoreRunner {
+            cli_args,
+            container: container.to_string(),
+            mount: mount.to_string(),
+            stop_start: false,
+            age_identity: None,
+        }
+    }
+
+    /*
+     * Stop the container before the restore and restart it afterwards.
+     */
+    pub fn stop_start(mut self, stop_start: bool) -> Self {
+        self.stop_start = stop_start;
+        self
+    }
+
+    /*
+     * The age identity file to decrypt age encrypted archives with.
+     */
+    pub fn age_identity(mut self, path: &str) -> Self {
+        self.age_identity = Some(path.to_string());
+        self
+    }
+
+    pub fn run(&self) -> Result<(), DockerError> {
+        let _lock = lock::acquire(self.cli_args)?;
+        let restore_args = RestoreArguments::latest(
+            &self.container,
+            &self.mount,
+            self.stop_start,
+            self.age_identity.clone(),
+        );
+        restore::restore(&restore_args, self.cli_args)
+    }
+}
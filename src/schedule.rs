@@ -0,0 +1,772 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+use clap::Args;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+};
+
+use crate::{
+    backup::{self, BackupArguments, Outcome},
+    exec::{inspect_container, watch_events},
+    interrupt,
+    labels::{ContainerLabels, SCHEDULE_LABEL},
+    lock, logging,
+    types::{ContainerEvent, DockerError},
+    CliArguments, HELPER_PID_LABEL,
+};
+
+/// Run as a daemon backing up the containers on a schedule.
+#[derive(Args, Clone)]
+pub struct ScheduleArguments {
+    /// When to back up the containers, a cron expression in the local time like "0 3 * * *" for
+    /// every night at 3:00, or @hourly, @daily, @weekly or @monthly. A container with a schedule
+    /// of its own from the volbackup.schedule label or the configuration file is only backed up
+    /// on that one
+    #[arg(long, value_name = "EXPRESSION", value_parser = Cron::parse)]
+    cron: Option<Cron>,
+
+    /// The file keeping the last runs between restarts of the daemon [default:
+    /// .volbackup-schedule.json in the output directory]
+    #[arg(long, value_name = "FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Run the backups that were missed while the daemon was not running right away when it starts
+    #[arg(long, default_value = "false")]
+    run_missed: bool,
+
+    /// Follow the events of the daemon, so a container that is started is picked up right away
+    /// instead of within a minute
+    #[arg(long, default_value = "false")]
+    watch_events: bool,
+
+    /// Back up a container right after it is stopped other than by volbackup, while its volumes
+    /// are still there to be backed up before it is removed. Follows the events of the daemon
+    #[arg(long, default_value = "false")]
+    backup_on_stop: bool,
+
+    #[command(flatten)]
+    backup: BackupArguments,
+}
+
+const STATE_FILE: &str = ".volbackup-schedule.json";
+
+/*
+ * The key of the runs of --cron in the state, the runs of a container are kept under its name.
+ */
+const ALL: &str = "*";
+
+/*
+ * A cron expression: the minutes, hours, days of the month, months and days of the week it
+ * matches, as bits.
+ */
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Like cron, a time matches either the day of the month or the day of the week when both are
+    // restricted.
+    either_day: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        let weekdays_bits = field(weekdays, 0, 7, &WEEKDAYS, 0)?;
+        let cron = Cron {
+            expression: expression.trim().to_string(),
+            minutes: field(minutes, 0, 59, &[], 0)?,
+            hours: field(hours, 0, 23, &[], 0)?,
+            days: field(days, 1, 31, &[], 0)?,
+            months: field(months, 1, 12, &MONTHS, 1)?,
+            // Sunday is 0 and 7.
+            weekdays: (weekdays_bits | weekdays_bits >> 7) & 0x7f,
+            either_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+        };
+        if cron.next_after(Local::now()).is_none() {
+            return Err(format!("{} never matches", expression));
+        }
+        Ok(cron)
+    }
+
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days & 1 << time.day() != 0;
+        let weekday = self.weekdays & 1 << time.weekday().num_days_from_sunday() != 0;
+        match self.either_day {
+            true => day || weekday,
+            false => day && weekday,
+        }
+    }
+
+    /*
+     * Whether the cron matches the minute of the time.
+     */
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let time = time.naive_local();
+        self.minutes & 1 << time.minute() != 0
+            && self.hours & 1 << time.hour() != 0
+            && self.months & 1 << time.month() != 0
+            && self.matches_day(&time)
+    }
+
+    /*
+     * The first minute after the time the cron matches, skipping the local times that do not
+     * exist when the clock is set forward. None when it does not match within 5 years.
+     */
+    fn next_after<Tz: TimeZone>(&self, time: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let time_zone = time.timezone();
+        let start = time.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let end = start + Duration::days(5 * 366);
+        let mut time = start;
+        while time < end {
+            let midnight = time.date().and_hms_opt(0, 0, 0)?;
+            if self.months & 1 << time.month() == 0 {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(&time) {
+                time = midnight + Duration::days(1);
+            } else if self.hours & 1 << time.hour() == 0 {
+                time = midnight + Duration::hours(i64::from(time.hour()) + 1);
+            } else if self.minutes & 1 << time.minute() == 0 {
+                time += Duration::minutes(1);
+            } else if let Some(local) = time_zone.from_local_datetime(&time).earliest() {
+                return Some(local);
+            } else {
+                time += Duration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/*
+ * A field of a cron expression as bits: *, values, ranges like 1-5 and steps like 0-45/15,
+ * separated by commas. The names are counted from the offset, like jan for 1.
+ */
+fn field(text: &str, min: u32, max: u32, names: &[&str], offset: u32) -> Result<u64, String> {
+    let value = |value: &str| -> Result<u32, String> {
+        let name = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+            .map(|index| index as u32 + offset);
+        let value = match name {
+            Some(value) => value,
+            None => value
+                .parse()
+                .map_err(|_| format!("invalid value {} in {}", value, text))?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(format!("{} is not between {} and {}", value, min, max)),
+        }
+    };
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("invalid step {} in {}", step, text)),
+            },
+            None => (part, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            return Err(format!("invalid range {} in {}", range, text));
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/*
+ * The last runs of the schedules, kept in a file between restarts.
+ */
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    runs: BTreeMap<String, LastRun>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LastRun {
+    started: DateTime<Local>,
+    outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl State {
+    fn load(path: &Path) -> Result<State, DockerError> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| {
+                DockerError::Config(format!("Invalid state file {}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /*
+     * Write the state next to the file first and rename it, so a crash never leaves half a file.
+     */
+    fn save(&self, path: &Path) -> Result<(), DockerError> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /*
+     * Whether the schedule should have run since its last run. A schedule that never ran did not
+     * miss anything.
+     */
+    fn missed(&self, key: &str, cron: &Cron, now: DateTime<Local>) -> bool {
+        self.runs
+            .get(key)
+            .and_then(|run| cron.next_after(run.started))
+            .is_some_and(|next| next <= now)
+    }
+}
+
+/*
+ * The containers that can be backed up and those with a schedule of their own.
+ */
+struct Containers {
+    names: Vec<String>,
+    schedules: Vec<(String, Cron)>,
+}
+
+impl Containers {
+    /*
+     * List the containers, with the schedule of their label or of the configuration file. Only
+     * the containers with the label are inspected.
+     */
+    fn list(backup_args: &BackupArguments, cli_args: &CliArguments) -> Result<Self, DockerError> {
+        let names: Vec<String> = logging::quietly(|| backup_args.containers(cli_args))?
+            .into_iter()
+            .map(|ps_info| ps_info.names)
+            .collect();
+        let labeled: HashSet<String> = backup_args
+            .containers_with_label(Some(SCHEDULE_LABEL), cli_args)?
+            .into_iter()
+            .map(|ps_info| ps_info.names)
+            .collect();
+        let mut schedules = vec![];
+        for name in &names {
+            let mut labels = ContainerLabels::default();
+            if labeled.contains(name) {
+                if let Some(info) = inspect_container(cli_args, name)? {
+                    match ContainerLabels::parse(&info.config.labels) {
+                        Ok(parsed) => labels = parsed,
+                        Err(e) => warn!("[{}] Invalid labels: {}", name, e),
+                    }
+                }
+            }
+            if let Some(settings) = cli_args.containers.get(name) {
+                labels = labels.or(settings.labels()?);
+            }
+            let Some(expression) = labels.schedule else {
+                continue;
+            };
+            match Cron::parse(&expression) {
+                Ok(cron) => schedules.push((name.clone(), cron)),
+                Err(e) => warn!("[{}] Invalid schedule {}: {}", name, expression, e),
+            }
+        }
+        Ok(Containers { names, schedules })
+    }
+
+    /*
+     * The keys of the schedules that are due, with the containers to back up for them.
+     */
+    fn due(
+        &self,
+        cron: Option<&Cron>,
+        due: impl Fn(&str, &Cron) -> bool,
+    ) -> (Vec<String>, Vec<String>) {
+        let mut keys = vec![];
+        let mut containers = vec![];
+        if cron.is_some_and(|cron| due(ALL, cron)) {
+            keys.push(ALL.to_string());
+            containers.extend(
+                self.names
+                    .iter()
+                    .filter(|name| !self.schedules.iter().any(|(own, _)| own == *name))
+                    .cloned(),
+            );
+        }
+        for (name, cron) in &self.schedules {
+            if due(name, cron) {
+                keys.push(name.clone());
+                containers.push(name.clone());
+            }
+        }
+        (keys, containers)
+    }
+
+    /*
+     * When the next backup is.
+     */
+    fn next(&self, cron: Option<&Cron>, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let own = self.schedules.iter().map(|(_, cron)| cron);
+        cron.into_iter()
+            .chain(own)
+            .filter_map(|cron| cron.next_after(now))
+            .min()
+    }
+}
+
+/*
+ * The daemon with the containers it last listed and the last runs of the schedules.
+ */
+struct Daemon<'a> {
+    args: &'a ScheduleArguments,
+    cli_args: &'a CliArguments,
+    containers: Containers,
+    state: State,
+    state_file: PathBuf,
+}
+
+/*
+ * Run as a daemon, backing up the containers every minute their schedule matches like cron does.
+ * The containers and their schedules are listed again every minute, so new containers and
+ * changed labels are picked up. With the events of the daemon a started container is picked up
+ * right away, and a container stopped other than by volbackup can be backed up before it is
+ * removed. A failing backup is logged and the daemon goes on, Ctrl-C or SIGTERM stop it.
+ */
+pub fn schedule(args: &ScheduleArguments, cli_args: &CliArguments) -> Result<(), DockerError> {
+    let state_file = args
+        .state_file
+        .clone()
+        .unwrap_or_else(|| Path::new(&cli_args.output_dir).join(STATE_FILE));
+    let state = State::load(&state_file)?;
+    match &args.cron {
+        Some(cron) => info!("Backing up the containers at {}", cron),
+        None => info!("Only backing up the containers with a schedule of their own"),
+    }
+    for (key, run) in &state.runs {
+        let name = match key.as_str() {
+            ALL => "the containers",
+            name => name,
+        };
+        info!(
+            "The last backup of {} started at {}: {}",
+            name,
+            run.started.format("%Y-%m-%d %H:%M"),
+            run.outcome
+        );
+    }
+    let events = (args.watch_events || args.backup_on_stop).then(|| {
+        let (sender, receiver) = mpsc::channel();
+        watch_events(cli_args, sender);
+        receiver
+    });
+    let mut daemon = Daemon {
+        args,
+        cli_args,
+        containers: Containers::list(&args.backup, cli_args)?,
+        state,
+        state_file,
+    };
+    let cron = args.cron.as_ref();
+    if args.run_missed {
+        let now = Local::now();
+        let state = &daemon.state;
+        let (keys, due) = daemon
+            .containers
+            .due(cron, |key, cron| state.missed(key, cron, now));
+        if !keys.is_empty() {
+            info!("Running the backups missed since the last run");
+            daemon.run(&keys, &due);
+        }
+    }
+    let mut minute = next_minute(Local::now());
+    let mut announce = true;
+    loop {
+        if announce {
+            if let Some(next) = daemon.containers.next(cron, Local::now()) {
+                info!("The next backup is at {}", next.format("%Y-%m-%d %H:%M"));
+            }
+            announce = false;
+        }
+        while let Ok(timeout) = (minute - Local::now()).to_std() {
+            let Some(events) = &events else {
+                thread::sleep(timeout);
+                break;
+            };
+            match events.recv_timeout(timeout) {
+                Ok(event) => announce |= daemon.handle(&event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            }
+        }
+        daemon.list();
+        let (keys, due) = daemon.containers.due(cron, |_, cron| cron.matches(&minute));
+        if !keys.is_empty() {
+            daemon.run(&keys, &due);
+            announce = true;
+        }
+        // The minutes a backup took are skipped, like cron does when it is busy.
+        minute = next_minute(Local::now().max(minute));
+    }
+}
+
+/*
+ * The start of the minute after the time.
+ */
+fn next_minute(time: DateTime<Local>) -> DateTime<Local> {
+    let minute = time.with_second(0).and_then(|time| time.with_nanosecond(0));
+    minute.unwrap_or(time) + Duration::minutes(1)
+}
+
+impl Daemon<'_> {
+    fn list(&mut self) {
+        match Containers::list(&self.args.backup, self.cli_args) {
+            Ok(containers) => self.containers = containers,
+            Err(e) => error!("Could not list the containers: {}", e),
+        }
+    }
+
+    /*
+     * React to an event of a container, returning whether a backup ran. The helper containers and
+     * the containers stopped for a backup are left alone.
+     */
+    fn handle(&mut self, event: &ContainerEvent) -> bool {
+        let name = event.name();
+        if event.actor.attributes.contains_key(HELPER_PID_LABEL) {
+            return false;
+        }
+        match event.action.as_str() {
+            "start" => {
+                self.list();
+                let own = self
+                    .containers
+                    .schedules
+                    .iter()
+                    .find(|(own, _)| own == name);
+                match (own, &self.args.cron) {
+                    (Some((_, cron)), _) => info!("[{}] Started, backed up at {}", name, cron),
+                    (None, Some(cron)) if self.containers.names.iter().any(|n| n == name) => {
+                        info!("[{}] Started, backed up at {}", name, cron)
+                    }
+                    _ => {}
+                }
+                false
+            }
+            "die" if self.args.backup_on_stop && !interrupt::stopped_here(&event.actor.id) => {
+                let backup_args = match self.args.backup.only(name, self.cli_args) {
+                    Ok(Some(backup_args)) => backup_args,
+                    Ok(None) => return false,
+                    Err(e) => {
+                        error!("[{}] Could not back up the stopped container: {}", name, e);
+                        return false;
+                    }
+                };
+                info!("[{}] Stopped, backing up its volumes", name);
+                self.backup(&backup_args, &[]);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /*
+     * Back up the due containers, leaving out the others.
+     */
+    fn run(&mut self, keys: &[String], due: &[String]) {
+        if due.is_empty() {
+            info!("No containers to back up");
+            return;
+        }
+        info!("Backing up {}", due.join(", "));
+        let skipped = self
+            .containers
+            .names
+            .iter()
+            .filter(|name| !due.contains(name));
+        let backup_args = self.args.backup.excluding(skipped.cloned());
+        self.backup(&backup_args, keys);
+    }
+
+    /*
+     * Run a backup and record it as the last run of the schedules.
+     */
+    fn backup(&mut self, backup_args: &BackupArguments, keys: &[String]) {
+        let _lock = match lock::acquire(self.cli_args) {
+            Ok(lock) => lock,
+            Err(e) => {
+                error!("Skipping the backup: {}", e);
+                return;
+            }
+        };
+        let started = Local::now();
+        let result = backup::backup(backup_args, self.cli_args);
+        let (outcome, error) = match &result {
+            Ok(Outcome::Success) => ("success", None),
+            Ok(Outcome::Partial) => ("partial", None),
+            Ok(Outcome::Failed) => ("failed", None),
+            Err(e) => ("failed", Some(e.to_string())),
+        };
+        match &error {
+            Some(e) => error!("Backup failed: {}", e),
+            None => info!("Backup finished: {}", outcome),
+        }
+        if keys.is_empty() {
+            return;
+        }
+        for key in keys {
+            let run = LastRun {
+                started,
+                outcome: outcome.to_string(),
+                error: error.clone(),
+            };
+            self.state.runs.insert(key.clone(), run);
+        }
+        if let Err(e) = self.state.save(&self.state_file) {
+            let path = self.state_file.display();
+            error!("Could not save the state to {}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, NaiveDate, Utc};
+
+    fn at(time: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn next(expression: &str, time: &str) -> String {
+        Cron::parse(expression)
+            .unwrap()
+            .next_after(at(time))
+            .unwrap()
+            .format("%Y-%m-%d %H:%M %a")
+            .to_string()
+    }
+
+    fn bits(values: &[u32]) -> u64 {
+        values.iter().fold(0, |bits, value| bits | 1 << value)
+    }
+
+    /*
+     * Central European time of 2026 around the clock set forward: 2:00 on March 29 is 3:00.
+     */
+    #[derive(Debug, Clone, Copy)]
+    struct SpringForward;
+
+    impl SpringForward {
+        fn offset(summer: bool) -> FixedOffset {
+            FixedOffset::east_opt(if summer { 7200 } else { 3600 }).unwrap()
+        }
+
+        fn change() -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2026, 3, 29)
+                .unwrap()
+                .and_hms_opt(1, 0, 0)
+                .unwrap()
+        }
+    }
+
+    impl TimeZone for SpringForward {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            SpringForward
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            LocalResult::Single(SpringForward::offset(
+                *local >= SpringForward::change().date(),
+            ))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let change = SpringForward::change() + Duration::hours(1);
+            match *local {
+                local if local < change => LocalResult::Single(SpringForward::offset(false)),
+                local if local < change + Duration::hours(1) => LocalResult::None,
+                _ => LocalResult::Single(SpringForward::offset(true)),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            SpringForward::offset(*utc >= SpringForward::change().date())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            SpringForward::offset(*utc >= SpringForward::change())
+        }
+    }
+
+    #[test]
+    fn expands_the_macros() {
+        let time = "2026-10-15 10:30";
+        assert_eq!(next("@hourly", time), "2026-10-15 11:00 Thu");
+        assert_eq!(next("@daily", time), "2026-10-16 00:00 Fri");
+        assert_eq!(next("@midnight", time), "2026-10-16 00:00 Fri");
+        assert_eq!(next("@weekly", time), "2026-10-18 00:00 Sun");
+        assert_eq!(next("@monthly", time), "2026-11-01 00:00 Sun");
+        assert_eq!(next("@yearly", time), "2027-01-01 00:00 Fri");
+        assert_eq!(Cron::parse(" @daily ").unwrap().to_string(), "@daily");
+    }
+
+    #[test]
+    fn parses_names_ranges_and_steps() {
+        assert_eq!(field("*", 0, 5, &[], 0), Ok(bits(&[0, 1, 2, 3, 4, 5])));
+        assert_eq!(field("1,3-4", 0, 59, &[], 0), Ok(bits(&[1, 3, 4])));
+        assert_eq!(field("0-45/15", 0, 59, &[], 0), Ok(bits(&[0, 15, 30, 45])));
+        assert_eq!(field("*/20", 0, 59, &[], 0), Ok(bits(&[0, 20, 40])));
+        assert_eq!(field("5/20", 0, 59, &[], 0), Ok(bits(&[5, 25, 45])));
+        assert_eq!(
+            field("jan,JUL-sep", 1, 12, &MONTHS, 1),
+            Ok(bits(&[1, 7, 8, 9]))
+        );
+        assert_eq!(
+            field("mon-fri", 0, 7, &WEEKDAYS, 0),
+            Ok(bits(&[1, 2, 3, 4, 5]))
+        );
+        assert_eq!(
+            next("*/20 9-17/4 * * *", "2026-10-15 13:50"),
+            "2026-10-15 17:00 Thu"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert!(field("60", 0, 59, &[], 0).is_err());
+        assert!(field("5-1", 0, 59, &[], 0).is_err());
+        assert!(field("*/0", 0, 59, &[], 0).is_err());
+        assert!(field("1-", 0, 59, &[], 0).is_err());
+        assert!(field("monday", 0, 7, &WEEKDAYS, 0).is_err());
+        assert!(Cron::parse("0 3 * *").is_err());
+        assert!(Cron::parse("@reboot").is_err());
+    }
+
+    #[test]
+    fn folds_sunday_seven_into_zero() {
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, bits(&[0]));
+        assert_eq!(
+            Cron::parse("0 0 * * 5-7").unwrap().weekdays,
+            bits(&[0, 5, 6])
+        );
+        assert_eq!(
+            next("0 0 * * 7", "2026-10-15 10:30"),
+            "2026-10-18 00:00 Sun"
+        );
+    }
+
+    #[test]
+    fn matches_either_day_when_both_are_restricted() {
+        // Like cron, the 1st of the month or any Monday.
+        assert_eq!(
+            next("0 0 1 * mon", "2026-10-15 10:30"),
+            "2026-10-19 00:00 Mon"
+        );
+        assert_eq!(
+            next("0 0 1 * mon", "2026-10-26 10:30"),
+            "2026-11-01 00:00 Sun"
+        );
+        // With a * only the other one counts.
+        assert_eq!(
+            next("0 0 1 * *", "2026-10-15 10:30"),
+            "2026-11-01 00:00 Sun"
+        );
+        assert_eq!(
+            next("0 0 * * mon", "2026-10-15 10:30"),
+            "2026-10-19 00:00 Mon"
+        );
+        // A step of * counts as a * like in cron, so both must match: an odd day that is a Monday.
+        assert_eq!(
+            next("0 0 */2 * mon", "2026-10-15 10:30"),
+            "2026-10-19 00:00 Mon"
+        );
+        assert_eq!(
+            next("0 0 */2 * mon", "2026-10-19 10:30"),
+            "2026-11-09 00:00 Mon"
+        );
+    }
+
+    #[test]
+    fn skips_the_times_the_clock_is_set_forward_over() {
+        let before = SpringForward
+            .with_ymd_and_hms(2026, 3, 29, 1, 59, 0)
+            .unwrap();
+        let cron = Cron::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            cron.next_after(before).unwrap().to_rfc3339(),
+            "2026-03-30T02:30:00+02:00"
+        );
+        let cron = Cron::parse("* * * * *").unwrap();
+        assert_eq!(
+            cron.next_after(before).unwrap().to_rfc3339(),
+            "2026-03-29T03:00:00+02:00"
+        );
+    }
+
+    #[test]
+    fn refuses_what_never_matches() {
+        let error = Cron::parse("0 0 30 feb *").unwrap_err();
+        assert!(error.contains("never matches"), "{}", error);
+        assert!(Cron::parse("0 0 31 apr,jun,sep,nov *").is_err());
+        // February 29 is within 5 years.
+        assert_eq!(
+            next("0 0 29 2 *", "2026-10-15 10:30"),
+            "2028-02-29 00:00 Tue"
+        );
+    }
+}
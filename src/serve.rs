@@ -0,0 +1,728 @@
+use chrono::{DateTime, Local};
+use clap::Args;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::{self, Scope},
+    time::Duration,
+};
+
+use crate::{
+    backup::{self, BackupArguments, Outcome},
+    catalog::{Catalog, CatalogEntry},
+    lock::{self, RunLock},
+    logging,
+    restore::{self, RestoreArguments},
+    types::DockerError,
+    BackupPlanner, CliArguments,
+};
+
+/// Serve an HTTP API to trigger the backups and follow them.
+#[derive(Args, Clone)]
+pub struct ServeArguments {
+    /// The address and port to listen on
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// The token the requests have to send as "Authorization: Bearer TOKEN". Without it anyone
+    /// reaching the address can trigger a backup, and restores are refused
+    #[arg(long, value_name = "TOKEN")]
+    api_token: Option<String>,
+
+    /// The age identity file to decrypt age encrypted archives with when restoring, gpg uses the
+    /// gpg agent
+    #[arg(long)]
+    age_identity: Option<String>,
+
+    #[command(flatten)]
+    backup: BackupArguments,
+}
+
+/*
+ * The number of runs GET /runs returns without a limit.
+ */
+const RUNS_LIMIT: usize = 20;
+
+/*
+ * The connections served at the same time, each has a thread of its own. Following the log keeps
+ * a connection open.
+ */
+const MAX_CONNECTIONS: usize = 32;
+
+/*
+ * How long a client may take to send its request or to read a part of the response, so a client
+ * that stops does not keep its connection forever.
+ */
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*
+ * The most bytes of the request line and headers read, there is no body to read.
+ */
+const MAX_REQUEST_SIZE: u64 = 64 << 10;
+
+/*
+ * The dashboard, a single page using the API.
+ */
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/*
+ * The backup or restore the server is running and the last one it ran.
+ */
+#[derive(Default, Serialize)]
+struct Status {
+    running: Option<Run>,
+    last: Option<Run>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Backup,
+    Restore,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Backup => "backup",
+            Action::Restore => "restore",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Action::Backup => "Backup",
+            Action::Restore => "Restore",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Run {
+    action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mount: Option<String>,
+    started: DateTime<Local>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished: Option<DateTime<Local>>,
+    containers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<Outcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Run {
+    fn new(action: Action, containers: Vec<String>, mount: Option<String>) -> Run {
+        Run {
+            action,
+            mount,
+            started: Local::now(),
+            finished: None,
+            containers,
+            outcome: None,
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.mount, &self.containers[..]) {
+            (Some(mount), [container]) => {
+                write!(f, "a {} of {} of {}", self.action.name(), mount, container)
+            }
+            (_, []) => write!(f, "a {} of the containers", self.action.name()),
+            (_, containers) => write!(f, "a {} of {}", self.action.name(), containers.join(", ")),
+        }
+    }
+}
+
+/*
+ * A selected container for the dashboard, with the last backup of each of its mounts.
+ */
+#[derive(Serialize)]
+struct ContainerStatus {
+    name: String,
+    running: bool,
+    mounts: Vec<MountBackup>,
+}
+
+#[derive(Serialize)]
+struct MountBackup {
+    destination: String,
+    last: Option<CatalogEntry>,
+}
+
+/*
+ * A request, the method, the path and the query parameters, and the headers the server looks at.
+ */
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    host: Option<String>,
+    origin: Option<String>,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> io::Result<Request> {
+        let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid request line",
+            ));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = Request {
+            method: method.to_string(),
+            path: decode(path),
+            query: query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode(key), decode(value))
+                })
+                .collect(),
+            authorization: None,
+            host: None,
+            origin: None,
+        };
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let value = Some(value.trim().to_string());
+                if name.eq_ignore_ascii_case("authorization") {
+                    request.authorization = value;
+                } else if name.eq_ignore_ascii_case("host") {
+                    request.host = value;
+                } else if name.eq_ignore_ascii_case("origin") {
+                    request.origin = value;
+                }
+            }
+        }
+        Ok(request)
+    }
+
+    fn param<'a>(&'a self, key: &'a str) -> Option<&'a str> {
+        self.params(key).next()
+    }
+
+    fn params<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.query
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/*
+ * Decode the %XX escapes of a path or a query parameter.
+ */
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/*
+ * Write a complete response with a JSON body.
+ */
+fn respond(mut stream: &TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    let body = format!("{}\n", body);
+    let authenticate = match status {
+        "401 Unauthorized" => "WWW-Authenticate: Bearer\r\n",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        authenticate,
+        body
+    )
+}
+
+fn respond_page(mut stream: &TcpStream) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        DASHBOARD.len(),
+        DASHBOARD
+    )
+}
+
+fn respond_error(stream: &TcpStream, status: &str, message: &str) -> io::Result<()> {
+    respond(stream, status, &json!({ "error": message }))
+}
+
+/*
+ * The server: the arguments of the backups and their status, shared by the threads of the
+ * connections.
+ */
+struct Server<'a> {
+    args: &'a ServeArguments,
+    cli_args: &'a CliArguments,
+    status: Mutex<Status>,
+}
+
+impl<'a> Server<'a> {
+    fn handle<'scope>(&'a self, scope: &'scope Scope<'scope, '_>, stream: TcpStream)
+    where
+        'a: 'scope,
+    {
+        let timeouts = stream
+            .set_read_timeout(Some(CONNECTION_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(CONNECTION_TIMEOUT)));
+        if let Err(e) = timeouts {
+            debug!("Could not set the timeouts of a connection: {}", e);
+            return;
+        }
+        let request = match Request::read(&stream) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("Invalid request: {}", e);
+                let _ = respond_error(&stream, "400 Bad Request", &e.to_string());
+                return;
+            }
+        };
+        debug!("{} {}", request.method, request.path);
+        // The page itself holds no data, it asks for the token to call the API with.
+        let page = request.method == "GET" && request.path == "/";
+        let result = match (page, self.authorized(&request)) {
+            _ if !self.same_origin(&request) => {
+                respond_error(&stream, "403 Forbidden", "Cross-origin request refused")
+            }
+            (true, _) => respond_page(&stream),
+            (false, false) => {
+                respond_error(&stream, "401 Unauthorized", "Missing or invalid API token")
+            }
+            (false, true) => self.route(scope, &request, &stream),
+        };
+        if let Err(e) = result {
+            debug!("Could not respond to {}: {}", request.path, e);
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        match &self.args.api_token {
+            Some(token) => same_token(
+                request.authorization.as_deref().unwrap_or_default(),
+                &format!("Bearer {}", token),
+            ),
+            None => true,
+        }
+    }
+
+    /*
+     * Whether the request comes from the dashboard or a client of the API and not from a page of
+     * another site the browser of the operator has open: the Host has to be the listen address
+     * (any when listening on all addresses), which a site resolving its name to this address does
+     * not send, and a browser sending an Origin has to send the origin of the server.
+     */
+    fn same_origin(&self, request: &Request) -> bool {
+        let listen = self.args.listen;
+        let local_host = |host: &str| {
+            listen.ip().is_unspecified()
+                || host == listen.to_string()
+                || (listen.ip().is_loopback() && host == format!("localhost:{}", listen.port()))
+        };
+        let host = request.host.as_deref();
+        let origin_host = request.origin.as_deref().map(|origin| {
+            origin
+                .strip_prefix("http://")
+                .or(origin.strip_prefix("https://"))
+                .unwrap_or(origin)
+        });
+        host.is_none_or(local_host) && origin_host.is_none_or(|origin| Some(origin) == host)
+    }
+
+    fn route<'scope>(
+        &'a self,
+        scope: &'scope Scope<'scope, '_>,
+        request: &Request,
+        stream: &TcpStream,
+    ) -> io::Result<()>
+    where
+        'a: 'scope,
+    {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), &segments[..]) {
+            ("GET", ["status"]) => {
+                let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+                respond(stream, "200 OK", &json!(*status))
+            }
+            ("GET", ["containers"]) => match self.containers() {
+                Ok(containers) => respond(stream, "200 OK", &json!(containers)),
+                Err(e) => respond_error(stream, "500 Internal Server Error", &e.to_string()),
+            },
+            ("POST", ["backups"]) => self.trigger_backup(scope, request, stream),
+            ("POST", ["restores"]) => self.trigger_restore(scope, request, stream),
+            ("GET", ["runs"]) => {
+                let limit = match request.param("limit").map(str::parse::<usize>) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => {
+                        return respond_error(stream, "400 Bad Request", "Invalid limit")
+                    }
+                    None => RUNS_LIMIT,
+                };
+                match self.catalog(|catalog| catalog.runs(limit)) {
+                    Ok(runs) => respond(stream, "200 OK", &json!(runs)),
+                    Err(e) => respond_error(stream, "500 Internal Server Error", &e.to_string()),
+                }
+            }
+            ("GET", ["runs", timestamp]) => match self.catalog(|catalog| catalog.entries(None)) {
+                Ok(entries) => {
+                    let entries: Vec<_> = entries
+                        .into_iter()
+                        .filter(|entry| entry.timestamp == *timestamp)
+                        .collect();
+                    match entries.is_empty() {
+                        true => respond_error(stream, "404 Not Found", "No such run"),
+                        false => respond(stream, "200 OK", &json!(entries)),
+                    }
+                }
+                Err(e) => respond_error(stream, "500 Internal Server Error", &e.to_string()),
+            },
+            ("GET", ["logs"]) => logs(stream, request.param("follow") != Some("false")),
+            (
+                _,
+                [""]
+                | ["status"]
+                | ["containers"]
+                | ["backups"]
+                | ["restores"]
+                | ["runs"]
+                | ["runs", _]
+                | ["logs"],
+            ) => respond_error(stream, "405 Method Not Allowed", "Method not allowed"),
+            _ => respond_error(stream, "404 Not Found", "No such endpoint"),
+        }
+    }
+
+    /*
+     * The runs recorded in the catalog, none before the first backup created it.
+     */
+    fn catalog<T: Default>(
+        &self,
+        query: impl FnOnce(&Catalog) -> Result<T, DockerError>,
+    ) -> Result<T, DockerError> {
+        match Catalog::exists(self.cli_args) {
+            true => query(&Catalog::open(self.cli_args)?),
+            false => Ok(T::default()),
+        }
+    }
+
+    /*
+     * Start a backup of the containers, or only of the ?container= ones.
+     */
+    fn trigger_backup<'scope>(
+        &'a self,
+        scope: &'scope Scope<'scope, '_>,
+        request: &Request,
+        stream: &TcpStream,
+    ) -> io::Result<()>
+    where
+        'a: 'scope,
+    {
+        let containers: Vec<String> = request.params("container").map(str::to_string).collect();
+        let backup_args = self.args.backup.including(containers.clone());
+        let run = Run::new(Action::Backup, containers, None);
+        self.start(scope, stream, run, move |server| {
+            backup::backup(&backup_args, server.cli_args)
+        })
+    }
+
+    /*
+     * Start a restore of the latest archive of the ?mount= of the ?container=, stopping the
+     * container around it with ?stop_start=true.
+     */
+    fn trigger_restore<'scope>(
+        &'a self,
+        scope: &'scope Scope<'scope, '_>,
+        request: &Request,
+        stream: &TcpStream,
+    ) -> io::Result<()>
+    where
+        'a: 'scope,
+    {
+        // The token keeps a page the operator has open from rolling back a volume.
+        if self.args.api_token.is_none() {
+            return respond_error(
+                stream,
+                "403 Forbidden",
+                "Restores are only served with an --api-token",
+            );
+        }
+        let (Some(container), Some(mount)) = (request.param("container"), request.param("mount"))
+        else {
+            return respond_error(
+                stream,
+                "400 Bad Request",
+                "A container and a mount are required",
+            );
+        };
+        let restore_args = RestoreArguments::latest(
+            container,
+            mount,
+            request.param("stop_start") == Some("true"),
+            self.args.age_identity.clone(),
+        );
+        let run = Run::new(
+            Action::Restore,
+            vec![container.to_string()],
+            Some(mount.to_string()),
+        );
+        self.start(scope, stream, run, move |server| {
+            restore::restore(&restore_args, server.cli_args).map(|()| Outcome::Success)
+        })
+    }
+
+    /*
+     * Run a backup or a restore in the background and respond right away. It is refused while
+     * another one runs, here or in another process.
+     */
+    fn start<'scope>(
+        &'a self,
+        scope: &'scope Scope<'scope, '_>,
+        stream: &TcpStream,
+        mut run: Run,
+        job: impl FnOnce(&Server) -> Result<Outcome, DockerError> + Send + 'scope,
+    ) -> io::Result<()>
+    where
+        'a: 'scope,
+    {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(running) = &status.running {
+            let message = format!("A {} is running already", running.action.name());
+            return respond_error(stream, "409 Conflict", &message);
+        }
+        let lock = match lock::acquire(self.cli_args) {
+            Ok(lock) => lock,
+            Err(e @ DockerError::Locked(_)) => {
+                return respond_error(stream, "409 Conflict", &e.to_string())
+            }
+            Err(e) => return respond_error(stream, "500 Internal Server Error", &e.to_string()),
+        };
+        status.running = Some(run.clone());
+        drop(status);
+        let body = json!(run);
+        info!("Starting {}", run);
+        thread::Builder::new()
+            .name(run.action.name().to_string())
+            .spawn_scoped(scope, move || {
+                let _lock: RunLock = lock;
+                match job(self) {
+                    Ok(outcome) => {
+                        let outcome_name = json!(outcome);
+                        let outcome_name = outcome_name.as_str().unwrap_or_default();
+                        info!("{} finished: {}", run.action.title(), outcome_name);
+                        run.outcome = Some(outcome);
+                    }
+                    Err(e) => {
+                        error!("{} failed: {}", run.action.title(), e);
+                        run.outcome = Some(Outcome::Failed);
+                        run.error = Some(e.to_string());
+                    }
+                }
+                run.finished = Some(Local::now());
+                let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+                status.running = None;
+                status.last = Some(run);
+            })?;
+        respond(stream, "202 Accepted", &body)
+    }
+
+    /*
+     * The containers selected for the backup with the last backup of each of their mounts.
+     */
+    fn containers(&self) -> Result<Vec<ContainerStatus>, DockerError> {
+        let mut latest = self.catalog(|catalog| catalog.latest())?;
+        let planned = BackupPlanner::with_arguments(self.cli_args, &self.args.backup).plan()?;
+        Ok(planned
+            .into_iter()
+            .map(|container| {
+                let mounts = container
+                    .mounts
+                    .into_iter()
+                    .map(|destination| {
+                        let last = latest
+                            .iter()
+                            .position(|entry| {
+                                entry.container == container.name
+                                    && entry.destination == destination
+                            })
+                            .map(|position| latest.swap_remove(position));
+                        MountBackup { destination, last }
+                    })
+                    .collect();
+                ContainerStatus {
+                    name: container.name,
+                    running: container.running,
+                    mounts,
+                }
+            })
+            .collect())
+    }
+}
+
+/*
+ * Send the last lines of the log and, when following, every line logged after them until the
+ * client disconnects.
+ */
+fn logs(mut stream: &TcpStream, follow: bool) -> io::Result<()> {
+    let lines = follow.then(logging::follow);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n"
+    )?;
+    write!(stream, "{}", logging::tail())?;
+    for line in lines.iter().flatten() {
+        writeln!(stream, "{}", line)?;
+    }
+    Ok(())
+}
+
+/*
+ * Whether the authorization sent is the expected one, in a time that does not tell how much of it
+ * is right. The digests are compared so their length does not tell either.
+ */
+fn same_token(sent: &str, expected: &str) -> bool {
+    let sent = Sha256::digest(sent.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    sent.iter()
+        .zip(expected.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/*
+ * A connection counted as open until its thread is done with it.
+ */
+struct OpenConnection<'a>(&'a AtomicUsize);
+
+impl<'a> OpenConnection<'a> {
+    fn open(connections: &'a AtomicUsize) -> Option<Self> {
+        match connections.fetch_add(1, Ordering::SeqCst) < MAX_CONNECTIONS {
+            true => Some(OpenConnection(connections)),
+            false => {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/*
+ * Serve the API until the process is stopped.
+ */
+pub fn serve(args: &ServeArguments, cli_args: &CliArguments) -> Result<(), DockerError> {
+    let listener = TcpListener::bind(args.listen)?;
+    info!("Serving the API on http://{}", listener.local_addr()?);
+    if args.api_token.is_none() && !args.listen.ip().is_loopback() {
+        warn!("The API is served without an --api-token to every host reaching it");
+    }
+    let server = Server {
+        args,
+        cli_args,
+        status: Mutex::new(Status::default()),
+    };
+    let connections = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let Some(open) = OpenConnection::open(&connections) else {
+                        warn!("Refusing a connection, {} are open", MAX_CONNECTIONS);
+                        let _ = stream.set_write_timeout(Some(CONNECTION_TIMEOUT));
+                        let _ = respond_error(
+                            &stream,
+                            "503 Service Unavailable",
+                            "Too many open connections",
+                        );
+                        continue;
+                    };
+                    let server = &server;
+                    let spawned = thread::Builder::new()
+                        .name("connection".to_string())
+                        .spawn_scoped(scope, move || {
+                            let _open = open;
+                            server.handle(scope, stream)
+                        });
+                    if let Err(e) = spawned {
+                        error!("Could not handle a connection: {}", e);
+                    }
+                }
+                Err(e) => warn!("Could not accept a connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_the_whole_token() {
+        assert!(same_token("Bearer secret", "Bearer secret"));
+        assert!(!same_token("Bearer secre", "Bearer secret"));
+        assert!(!same_token("Bearer secretx", "Bearer secret"));
+        assert!(!same_token("", "Bearer secret"));
+    }
+
+    #[test]
+    fn counts_the_open_connections() {
+        let connections = AtomicUsize::new(0);
+        let open: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| OpenConnection::open(&connections).unwrap())
+            .collect();
+        assert!(OpenConnection::open(&connections).is_none());
+        drop(open);
+        assert_eq!(connections.load(Ordering::SeqCst), 0);
+        assert!(OpenConnection::open(&connections).is_some());
+    }
+}
@@ -0,0 +1,777 @@
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use crate::{
+    exec::{command_line, curl_config, pipe, run_command, run_command_with_input, CurlConfig},
+    interrupt,
+    types::DockerError,
+};
+
+/*
+ * Where the archives are stored: in the output directory or uploaded to a remote storage.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Destination {
+    Local(PathBuf),
+    Stdout,
+    S3(String),
+    Rclone(String),
+    WebDav(String),
+    Gcs(String),
+    AzureBlob {
+        container: String,
+        prefix: String,
+    },
+    Ssh {
+        host: String,
+        port: Option<u16>,
+        path: String,
+    },
+}
+
+/*
+ * Writer for a single archive, finish it to complete the upload or abort it to discard the archive.
+ * Local archives are written to a .partial file that is synced to the disk and renamed when
+ * finished. The sha256 of the archive is computed while writing.
+ */
+pub struct ArchiveWriter {
+    writer: Box<dyn Write>,
+    upload: Option<(String, Child)>,
+    partial: Option<(File, PathBuf, PathBuf)>,
+    hasher: Sha256,
+    credentials: Option<CurlConfig>,
+}
+
+impl Destination {
+    /*
+     * Parse the destination option, the output directory is used when there is none.
+     */
+    pub fn parse(dest: Option<&str>, output_dir: &str) -> Result<Destination, DockerError> {
+        let Some(dest) = dest else {
+            return Ok(Destination::Local(PathBuf::from(output_dir)));
+        };
+        let invalid =
+            |reason: &str| DockerError::from(format!("Destination {} {}", dest, reason).as_str());
+        if let Some(bucket) = dest.strip_prefix("s3://") {
+            if bucket.is_empty() {
+                return Err(invalid("has no bucket"));
+            }
+            Ok(Destination::S3(dest.trim_end_matches('/').to_string()))
+        } else if let Some(remote) = dest.strip_prefix("rclone:") {
+            if !remote.contains(':') {
+                return Err(invalid("has no remote, use rclone:remote:path"));
+            }
+            Ok(Destination::Rclone(
+                remote.trim_end_matches('/').to_string(),
+            ))
+        } else if let Some((scheme, location)) = dest
+            .strip_prefix("webdav://")
+            .map(|l| ("http", l))
+            .or(dest.strip_prefix("webdavs://").map(|l| ("https", l)))
+        {
+            if location.is_empty() {
+                return Err(invalid("has no host"));
+            }
+            Ok(Destination::WebDav(format!(
+                "{}://{}",
+                scheme,
+                location.trim_end_matches('/')
+            )))
+        } else if let Some(bucket) = dest.strip_prefix("gs://") {
+            if bucket.is_empty() {
+                return Err(invalid("has no bucket"));
+            }
+            Ok(Destination::Gcs(dest.trim_end_matches('/').to_string()))
+        } else if let Some(location) = dest.strip_prefix("azblob://") {
+            let (container, prefix) = location.split_once('/').unwrap_or((location, ""));
+            if container.is_empty() {
+                return Err(invalid("has no container"));
+            }
+            Ok(Destination::AzureBlob {
+                container: container.to_string(),
+                prefix: prefix.trim_end_matches('/').to_string(),
+            })
+        } else if dest.starts_with("sftp://") {
+            // Streaming needs cat in a shell on the host, sftp only puts regular files.
+            Err(invalid(
+                "is not supported, use ssh://user@host/path with an account that has a shell",
+            ))
+        } else if let Some(location) = dest.strip_prefix("ssh://") {
+            let (authority, path) = location.split_once('/').ok_or(invalid("has no path"))?;
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    Some(
+                        port.parse::<u16>()
+                            .map_err(|_| invalid("has an invalid port"))?,
+                    ),
+                ),
+                None => (authority, None),
+            };
+            if host.is_empty() {
+                return Err(invalid("has no host"));
+            }
+            Ok(Destination::Ssh {
+                host: host.to_string(),
+                port,
+                path: format!("/{}", path.trim_end_matches('/')),
+            })
+        } else {
+            Err(invalid("is not supported"))
+        }
+    }
+
+    /*
+     * Whether the archives are stored in a remote storage instead of the output directory.
+     */
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, Destination::Local(_))
+    }
+
+    /*
+     * The location of an archive, for logging.
+     */
+    pub fn location(&self, file_name: &str) -> String {
+        match self {
+            Destination::Local(dir) => dir.join(file_name).display().to_string(),
+            Destination::Stdout => "stdout".to_string(),
+            Destination::S3(url) | Destination::Gcs(url) => format!("{}/{}", url, file_name),
+            Destination::Rclone(remote) if remote.ends_with(':') => {
+                format!("{}{}", remote, file_name)
+            }
+            Destination::Rclone(remote) => format!("{}/{}", remote, file_name),
+            Destination::WebDav(url) => format!("{}/{}", url, file_name),
+            Destination::AzureBlob { container, prefix } => {
+                format!("azblob://{}/{}", container, blob_name(prefix, file_name))
+            }
+            Destination::Ssh { host, port, path } => format!(
+                "ssh://{}{}{}/{}",
+                host,
+                port.map(|p| format!(":{}", p)).unwrap_or_default(),
+                path,
+                file_name
+            ),
+        }
+    }
+
+    /*
+     * Prepare the destination before the first archive is created.
+     */
+    pub fn prepare(&self) -> Result<(), DockerError> {
+        if let Destination::WebDav(url) = self {
+            self.make_collections(url)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Create the WebDAV collections of a url one by one, the existing ones fail with 405 Method
+     * Not Allowed. The collections above the last one may not be ours to create, like those of
+     * the dav endpoint of a server, so only the last one has to be created or exist.
+     */
+    fn make_collections(&self, url: &str) -> Result<(), DockerError> {
+        let host_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+        let path_start = url[host_start..]
+            .find('/')
+            .map(|i| host_start + i)
+            .unwrap_or(url.len());
+        let (server, path) = url.split_at(path_start);
+        let mut collection = server.to_string();
+        let mut status = None;
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            collection = format!("{}/{}", collection, segment);
+            let mut command = self.curl_command();
+            let null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+            command.args(["--request", "MKCOL", "--output", null]);
+            command.args(["--write-out", "%{http_code}", collection.as_str()]);
+            let credentials = curl_credentials();
+            if credentials.is_some() {
+                command.args(["--config", "-"]);
+            }
+            let output =
+                run_command_with_input(command, credentials.as_deref().map(str::as_bytes))?;
+            let code = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            debug!("MKCOL {}: {}", collection, code);
+            status = Some(code);
+        }
+        match status.as_deref() {
+            None | Some("405") => Ok(()),
+            Some(code) if code.starts_with('2') => Ok(()),
+            Some(code) => Err(DockerError::from(
+                format!(
+                    "Could not create the collection {}: HTTP {}",
+                    collection, code
+                )
+                .as_str(),
+            )),
+        }
+    }
+
+    /*
+     * Create an archive, remote archives are streamed to the upload command. The metadata is stored
+     * with the object when the storage supports it, the expected size when the upload needs it.
+     */
+    pub fn create(
+        &self,
+        file_name: &str,
+        metadata: &[(&str, &str)],
+        size: Option<u64>,
+    ) -> Result<ArchiveWriter, DockerError> {
+        let Some(mut command) = self.upload_command(file_name, metadata, size)? else {
+            let (writer, partial): (Box<dyn Write>, _) = match self {
+                Destination::Local(dir) => {
+                    let path = dir.join(file_name);
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let mut partial = path.clone().into_os_string();
+                    partial.push(".partial");
+                    let partial = PathBuf::from(partial);
+                    let file = File::create(&partial)?;
+                    interrupt::writing(&partial);
+                    let synced = file.try_clone()?;
+                    (Box::new(file), Some((synced, partial, path)))
+                }
+                Destination::Stdout => (Box::new(io::stdout()), None),
+                _ => return Err(DockerError::from("Destination has no upload command")),
+            };
+            return Ok(ArchiveWriter {
+                writer,
+                upload: None,
+                partial,
+                hasher: Sha256::new(),
+                credentials: None,
+            });
+        };
+        // The upload reads the archive from its input, so curl reads the credentials from a file.
+        let credentials = match self {
+            Destination::WebDav(_) => curl_credentials()
+                .map(|config| CurlConfig::create(&mut command, &config))
+                .transpose()?,
+            _ => None,
+        };
+        debug!("Execute {}", command_line(&command));
+        let program = command.get_program().to_string_lossy().to_string();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                DockerError::from(format!("Could not start {}: {}", program, e).as_str())
+            })?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or(DockerError::from("Upload command has no input"))?;
+        Ok(ArchiveWriter {
+            writer: Box::new(stdin),
+            upload: Some((program, child)),
+            partial: None,
+            hasher: Sha256::new(),
+            credentials,
+        })
+    }
+
+    /*
+     * The command storing its input as a remote archive, None for local archives.
+     */
+    fn upload_command(
+        &self,
+        file_name: &str,
+        metadata: &[(&str, &str)],
+        size: Option<u64>,
+    ) -> Result<Option<Command>, DockerError> {
+        Ok(match self {
+            Destination::Local(_) | Destination::Stdout => None,
+            Destination::S3(_) => {
+                // The aws cli does a multipart upload when streaming from stdin, with parts sized
+                // for at most 50GB unless it is told the size of the stream.
+                let mut command = Command::new("aws");
+                command.args(["s3", "cp", "--only-show-errors"]);
+                if let Some(size) = size {
+                    command.args(["--expected-size", &size.to_string()]);
+                }
+                command.args(["-", self.location(file_name).as_str()]);
+                Some(command)
+            }
+            Destination::Gcs(_) => {
+                // Streaming from stdin is done with a resumable upload.
+                let mut command = Command::new("gcloud");
+                command.args(["storage", "cp", "--quiet"]);
+                if !metadata.is_empty() {
+                    let metadata = metadata
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    command.arg(format!("--custom-metadata={}", metadata));
+                }
+                command.args(["-", self.location(file_name).as_str()]);
+                gcloud_credentials(&mut command);
+                Some(command)
+            }
+            Destination::Rclone(_) => {
+                let mut command = Command::new("rclone");
+                command.args(["rcat", self.location(file_name).as_str()]);
+                Some(command)
+            }
+            Destination::WebDav(url) => {
+                if let Some((dir, _)) = file_name.rsplit_once('/') {
+                    self.make_collections(&format!("{}/{}", url, dir))?;
+                }
+                // Without a length curl sends the stream with chunked transfer encoding.
+                let mut command = self.curl_command();
+                command.args(["--fail", "--upload-file", "-"]);
+                command.arg(self.location(file_name));
+                Some(command)
+            }
+            Destination::AzureBlob { .. } => {
+                // Block blobs have at most 50000 blocks, 100MB blocks allow archives up to 4.75TB.
+                let mut command = self.azcopy_command(file_name)?;
+                command.args(["--from-to", "PipeBlob", "--block-size-mb", "100"]);
+                Some(command)
+            }
+            Destination::Ssh { path, .. } => {
+                let remote_path = format!("{}/{}", path, file_name);
+                let dir = remote_path
+                    .rsplit_once('/')
+                    .map_or(path.as_str(), |(dir, _)| dir);
+                Some(
+                    self.ssh_command(
+                        format!(
+                            "mkdir -p {} && cat > {}",
+                            shell_quote(dir),
+                            shell_quote(&remote_path)
+                        )
+                        .as_str(),
+                    ),
+                )
+            }
+        })
+    }
+
+    /*
+     * The command writing a remote archive to its output with the input it reads its credentials
+     * from, None for local archives.
+     */
+    pub fn download_command(
+        &self,
+        file_name: &str,
+    ) -> Result<Option<(Command, Option<File>)>, DockerError> {
+        let command = match self {
+            Destination::Local(_) | Destination::Stdout => None,
+            Destination::S3(_) => {
+                let mut command = Command::new("aws");
+                command.args([
+                    "s3",
+                    "cp",
+                    "--only-show-errors",
+                    self.location(file_name).as_str(),
+                    "-",
+                ]);
+                Some(command)
+            }
+            Destination::Gcs(_) => {
+                let mut command = Command::new("gcloud");
+                command.args([
+                    "storage",
+                    "cp",
+                    "--quiet",
+                    self.location(file_name).as_str(),
+                    "-",
+                ]);
+                gcloud_credentials(&mut command);
+                Some(command)
+            }
+            Destination::Rclone(_) => {
+                let mut command = Command::new("rclone");
+                command.args(["cat", self.location(file_name).as_str()]);
+                Some(command)
+            }
+            Destination::WebDav(_) => {
+                let mut command = self.curl_command();
+                command.arg("--fail").arg(self.location(file_name));
+                Some(command)
+            }
+            Destination::AzureBlob { .. } => {
+                let mut command = self.azcopy_command(file_name)?;
+                command.args(["--from-to", "BlobPipe"]);
+                Some(command)
+            }
+            Destination::Ssh { path, .. } => Some(self.ssh_command(
+                format!("cat {}", shell_quote(&format!("{}/{}", path, file_name))).as_str(),
+            )),
+        };
+        let Some(mut command) = command else {
+            return Ok(None);
+        };
+        let input = match self {
+            Destination::WebDav(_) => credentials_input(&mut command)?,
+            _ => None,
+        };
+        Ok(Some((command, input)))
+    }
+
+    /*
+     * An azcopy command copying a blob from or to a pipe. The account and a shared access
+     * signature are taken from AZURE_STORAGE_CONNECTION_STRING, without it the managed identity
+     * is used for the account in AZURE_STORAGE_ACCOUNT. The signature can only be given in the
+     * url, so with AZCOPY_AUTO_LOGIN_TYPE set azcopy logs in instead.
+     */
+    fn azcopy_command(&self, file_name: &str) -> Result<Command, DockerError> {
+        let Destination::AzureBlob { container, prefix } = self else {
+            return Err(DockerError::from(
+                "Destination is not an Azure blob container",
+            ));
+        };
+        let mut command = Command::new("azcopy");
+        command.args(["copy", "--log-level", "ERROR"]);
+        let (endpoint, signature) = match env::var("AZURE_STORAGE_CONNECTION_STRING") {
+            Ok(connection_string) => {
+                let settings: HashMap<&str, &str> = connection_string
+                    .split(';')
+                    .filter_map(|setting| setting.split_once('='))
+                    .collect();
+                let endpoint =
+                    match (settings.get("BlobEndpoint"), settings.get("AccountName")) {
+                        (Some(endpoint), _) => endpoint.trim_end_matches('/').to_string(),
+                        (None, Some(account)) => format!(
+                            "https://{}.blob.{}",
+                            account,
+                            settings
+                                .get("EndpointSuffix")
+                                .unwrap_or(&"core.windows.net")
+                        ),
+                        (None, None) => return Err(DockerError::from(
+                            "AZURE_STORAGE_CONNECTION_STRING has no BlobEndpoint or AccountName",
+                        )),
+                    };
+                // A login of azcopy keeps the signature off its command line.
+                let signature = settings.get("SharedAccessSignature");
+                if signature.is_none() && env::var_os("AZCOPY_AUTO_LOGIN_TYPE").is_none() {
+                    return Err(DockerError::from(
+                        "AZURE_STORAGE_CONNECTION_STRING has no SharedAccessSignature, azcopy does not support account keys",
+                    ));
+                }
+                (endpoint, signature.map(|signature| signature.to_string()))
+            }
+            Err(_) => {
+                let account = env::var("AZURE_STORAGE_ACCOUNT").map_err(|_| {
+                    DockerError::from(
+                        "Set AZURE_STORAGE_CONNECTION_STRING or AZURE_STORAGE_ACCOUNT for an Azure blob destination",
+                    )
+                })?;
+                if env::var_os("AZCOPY_AUTO_LOGIN_TYPE").is_none() {
+                    command.env("AZCOPY_AUTO_LOGIN_TYPE", "MSI");
+                }
+                (format!("https://{}.blob.core.windows.net", account), None)
+            }
+        };
+        let url = format!(
+            "{}/{}/{}",
+            endpoint,
+            container,
+            blob_name(prefix, file_name)
+        );
+        match signature {
+            Some(signature) => command.arg(format!("{}?{}", url, signature)),
+            None => command.arg(url),
+        };
+        Ok(command)
+    }
+
+    /*
+     * A curl command authenticated with the credentials in ~/.netrc. Those in the environment are
+     * given to curl in a config, see curl_credentials, so they are not on its command line.
+     */
+    fn curl_command(&self) -> Command {
+        let mut command = Command::new("curl");
+        command.args(["--silent", "--show-error", "--netrc-optional"]);
+        command
+    }
+
+    /*
+     * An ssh command running a remote command in the shell of the account on an ssh destination,
+     * with key based authentication only so it never hangs on a password prompt.
+     */
+    fn ssh_command(&self, remote_command: &str) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        if let Destination::Ssh { host, port, .. } = self {
+            if let Some(port) = port {
+                command.args(["-p", port.to_string().as_str()]);
+            }
+            command.arg(host);
+        }
+        command.arg(remote_command);
+        command
+    }
+
+    /*
+     * Copy a local file, like the manifest, to a remote destination.
+     */
+    pub fn upload_file(&self, path: &Path) -> Result<(), DockerError> {
+        let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+            return Err(DockerError::from(
+                format!("{} is not a valid file", path.display()).as_str(),
+            ));
+        };
+        if !self.is_remote() {
+            return Ok(());
+        }
+        let mut file = File::open(path)?;
+        let mut writer = self.create(file_name, &[], Some(file.metadata()?.len()))?;
+        io::copy(&mut file, &mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /*
+     * Store the sha256 of an archive next to it in a .sha256 file, in the format of sha256sum so
+     * it can be checked with sha256sum -c.
+     */
+    pub fn write_checksum(&self, file_name: &str, digest: &str) -> Result<(), DockerError> {
+        if *self == Destination::Stdout {
+            return Ok(());
+        }
+        let name = file_name.rsplit('/').next().unwrap_or(file_name);
+        let mut writer = self.create(&checksum_file_name(file_name), &[], None)?;
+        writeln!(writer, "{}  {}", digest, name)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/*
+ * The name of the file holding the sha256 of an archive.
+ */
+pub fn checksum_file_name(file_name: &str) -> String {
+    format!("{}.sha256", file_name)
+}
+
+/*
+ * The bytes available to an unprivileged user on the filesystem of a directory, from the POSIX
+ * output of df.
+ */
+pub fn free_space(dir: &Path) -> Result<u64, DockerError> {
+    let mut command = Command::new("df");
+    command.args(["-P", "-k"]).arg(dir);
+    let output = run_command(command)?;
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|available| available.parse::<u64>().ok())
+        .map(|available| available * 1024)
+        .ok_or_else(|| {
+            DockerError::from(format!("Unexpected output of {}", output.command).as_str())
+        })
+}
+
+/*
+ * The curl config with a token from WEBDAV_TOKEN, or basic authentication from WEBDAV_USER and
+ * WEBDAV_PASSWORD. None when curl only takes the credentials from ~/.netrc.
+ */
+fn curl_credentials() -> Option<String> {
+    if let Ok(token) = env::var("WEBDAV_TOKEN") {
+        Some(curl_config(&[("oauth2-bearer", &token)]))
+    } else if let Ok(user) = env::var("WEBDAV_USER") {
+        let password = env::var("WEBDAV_PASSWORD").unwrap_or_default();
+        Some(curl_config(&[("user", &format!("{}:{}", user, password))]))
+    } else {
+        None
+    }
+}
+
+/*
+ * Let curl read the credentials from its input, returning the reading end of a pipe holding them.
+ * The config is small enough to fit in the pipe before curl reads it.
+ */
+fn credentials_input(command: &mut Command) -> Result<Option<File>, DockerError> {
+    let Some(config) = curl_credentials() else {
+        return Ok(None);
+    };
+    let (input, mut writer) = pipe()?;
+    writer.write_all(config.as_bytes())?;
+    command.args(["--config", "-"]);
+    Ok(Some(input))
+}
+
+/*
+ * Let gcloud use the application default credentials from GOOGLE_APPLICATION_CREDENTIALS, without
+ * it gcloud uses its own login or the service account of the host.
+ */
+fn gcloud_credentials(command: &mut Command) {
+    if let Some(credentials) = env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        if env::var_os("CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE").is_none() {
+            command.env("CLOUDSDK_AUTH_CREDENTIAL_FILE_OVERRIDE", credentials);
+        }
+    }
+}
+
+/*
+ * The name of a blob in a container, below the prefix.
+ */
+fn blob_name(prefix: &str, file_name: &str) -> String {
+    if prefix.is_empty() {
+        file_name.to_string()
+    } else {
+        format!("{}/{}", prefix, file_name)
+    }
+}
+
+/*
+ * Quote an argument for a remote shell.
+ */
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/*
+ * Sync the directory of a file to the disk, so the rename of the file into it survives a crash.
+ * Directories can only be synced on unix.
+ */
+fn sync_dir(path: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+impl ArchiveWriter {
+    /*
+     * Close the archive and wait for the upload to complete, a local archive is moved into place.
+     * Returns the hex sha256 of the archive.
+     */
+    pub fn finish(self) -> Result<String, DockerError> {
+        let ArchiveWriter {
+            mut writer,
+            upload,
+            partial,
+            hasher,
+            credentials: _credentials,
+        } = self;
+        let digest = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let flushed = writer.flush();
+        drop(writer);
+        if let Some((file, partial, path)) = partial {
+            // The contents are on the disk before the name, so after a crash an archive under its
+            // name is complete.
+            let renamed = flushed
+                .and_then(|_| file.sync_all())
+                .and_then(|_| fs::rename(&partial, &path));
+            interrupt::written(&partial);
+            if let Err(e) = renamed {
+                let _ = fs::remove_file(partial);
+                return Err(DockerError::from(e));
+            }
+            if let Err(e) = sync_dir(&path) {
+                warn!(
+                    "Could not sync the directory of {} to the disk: {}",
+                    path.display(),
+                    e
+                );
+            }
+            return Ok(digest);
+        }
+        flushed?;
+        if let Some((program, mut child)) = upload {
+            let exit_status = child.wait()?;
+            if !exit_status.success() {
+                return Err(DockerError::from(
+                    format!("{} failed with {}", program, exit_status).as_str(),
+                ));
+            }
+        }
+        Ok(digest)
+    }
+
+    /*
+     * Discard an archive that failed: delete the partial file, or kill the upload before it
+     * completes so no truncated archive is stored.
+     */
+    pub fn abort(self) {
+        let ArchiveWriter {
+            writer,
+            upload,
+            partial,
+            ..
+        } = self;
+        drop(writer);
+        if let Some((_, partial, _)) = partial {
+            let _ = fs::remove_file(&partial);
+            interrupt::written(&partial);
+        }
+        if let Some((_, mut child)) = upload {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Write for ArchiveWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(command: Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn passes_the_expected_size_to_s3() {
+        let destination = Destination::parse(Some("s3://backups/web"), "/backups").unwrap();
+        let command = destination
+            .upload_command("web.tar", &[], Some(60_000_000_000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            arguments(command),
+            [
+                "s3",
+                "cp",
+                "--only-show-errors",
+                "--expected-size",
+                "60000000000",
+                "-",
+                "s3://backups/web/web.tar"
+            ]
+        );
+        let command = destination
+            .upload_command("web.tar", &[], None)
+            .unwrap()
+            .unwrap();
+        assert!(!arguments(command).contains(&"--expected-size".to_string()));
+    }
+}
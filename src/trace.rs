@@ -0,0 +1,204 @@
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::BuildHasher,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{archive::hostname, exec::run_command_with_input, types::DockerError};
+
+/*
+ * The spans of a backup run, from the run to its containers and mounts down to the commands,
+ * exported to an OpenTelemetry collector with OTLP over HTTP when the run is done.
+ */
+struct Span {
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: String,
+    start: u64,
+    end: u64,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static FINISHED: Mutex<Vec<Span>> = Mutex::new(vec![]);
+
+static TRACE_ID: OnceLock<u128> = OnceLock::new();
+
+thread_local! {
+    /// The open spans of this thread, the last one is the parent of a new span.
+    static OPEN: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+}
+
+/*
+ * Record the spans of the run from now on.
+ */
+pub fn enable() {
+    TRACE_ID.get_or_init(|| (u128::from(random_id()) << 64) | u128::from(random_id()));
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/*
+ * A random id, none of the ids in a process are the same.
+ */
+fn random_id() -> u64 {
+    static STATE: OnceLock<RandomState> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let state = STATE.get_or_init(RandomState::new);
+    state.hash_one(COUNTER.fetch_add(1, Ordering::SeqCst)) | 1
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/*
+ * An open span, finished when it is dropped. Does nothing when the spans are not recorded.
+ */
+pub struct SpanGuard(Option<Span>);
+
+/*
+ * Open a span below the open span of this thread.
+ */
+pub fn span(name: impl Into<String>) -> SpanGuard {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return SpanGuard(None);
+    }
+    let span_id = random_id();
+    let parent_id = OPEN.with(|open| {
+        let mut open = open.borrow_mut();
+        let parent = open.last().copied();
+        open.push(span_id);
+        parent
+    });
+    SpanGuard(Some(Span {
+        span_id,
+        parent_id,
+        name: name.into(),
+        start: now(),
+        end: 0,
+        attributes: vec![],
+        error: None,
+    }))
+}
+
+impl SpanGuard {
+    pub fn attribute(&mut self, key: &str, value: impl Display) {
+        if let Some(span) = &mut self.0 {
+            span.attributes.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    /*
+     * Mark the span as failed with the error.
+     */
+    pub fn fail(&mut self, error: impl Display) {
+        if let Some(span) = &mut self.0 {
+            span.error = Some(error.to_string());
+        }
+    }
+
+    /*
+     * Mark the span as failed when the result is an error.
+     */
+    pub fn result<T, E: Display>(&mut self, result: &Result<T, E>) {
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let Some(mut span) = self.0.take() else {
+            return;
+        };
+        span.end = now();
+        OPEN.with(|open| open.borrow_mut().retain(|&id| id != span.span_id));
+        FINISHED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(span);
+    }
+}
+
+/*
+ * The open span of this thread, to continue it on another thread.
+ */
+pub fn current() -> Option<u64> {
+    OPEN.with(|open| open.borrow().last().copied())
+}
+
+/*
+ * Continue the span of another thread on this thread, the spans opened now are below it.
+ */
+pub fn continue_in_thread(parent: Option<u64>) {
+    if let Some(parent) = parent {
+        OPEN.with(|open| open.borrow_mut().push(parent));
+    }
+}
+
+/*
+ * Send the finished spans to the OTLP/HTTP endpoint of a collector, like http://collector:4318.
+ */
+pub fn export(endpoint: &str, service: &str) -> Result<(), DockerError> {
+    // The export itself is not traced.
+    ENABLED.store(false, Ordering::SeqCst);
+    let spans = std::mem::take(&mut *FINISHED.lock().unwrap_or_else(|e| e.into_inner()));
+    let trace_id = format!("{:032x}", TRACE_ID.get().copied().unwrap_or_default());
+    let attribute = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut value = json!({
+                "traceId": trace_id,
+                "spanId": format!("{:016x}", span.span_id),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": span.start.to_string(),
+                "endTimeUnixNano": span.end.to_string(),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+                "status": match &span.error {
+                    Some(error) => json!({"code": 2, "message": error}),
+                    None => json!({"code": 1}),
+                },
+            });
+            if let Some(parent_id) = span.parent_id {
+                value["parentSpanId"] = json!(format!("{:016x}", parent_id));
+            }
+            value
+        })
+        .collect();
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {"attributes": [
+                attribute("service.name", service),
+                attribute("host.name", &hostname()),
+            ]},
+            "scopeSpans": [{"scope": {"name": "volbackup"}, "spans": spans}],
+        }]
+    });
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--max-time", "30"]);
+    command.args(["--header", "Content-Type: application/json"]);
+    command.args(["--data-binary", "@-", url.as_str()]);
+    run_command_with_input(command, Some(body.to_string().as_bytes()))?;
+    Ok(())
+}
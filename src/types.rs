@@ -1,53 +1,484 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, fmt::Display};
+use thiserror::Error;
 
 /*
  * Docker json types.
  */
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PsInfo {
+    #[serde(deserialize_with = "names")]
     pub names: String,
+    #[serde(default)]
+    pub state: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl PsInfo {
+    /*
+     * Whether the container is running, docker versions that do not report the state only list
+     * running containers.
+     */
+    pub fn is_running(&self) -> bool {
+        self.state.as_ref().is_none_or(|state| state == "running")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ContainerInfo {
     pub id: String,
+    /// The id of the image, like sha256:<hex>.
+    #[serde(default)]
+    pub image: String,
     pub mounts: Vec<Mounts>,
     pub config: ContainerConfig,
+    #[serde(default)]
+    pub host_config: ContainerHostConfig,
+    /// The full output of docker inspect, to archive it with the backup.
+    #[serde(skip)]
+    pub inspect: Option<serde_json::Value>,
+}
+
+/*
+ * An event of a container, like start or die, from the events of the daemon.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerEvent {
+    pub action: String,
+    pub actor: EventActor,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventActor {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(default, rename = "Attributes")]
+    pub attributes: HashMap<String, String>,
+}
+
+impl ContainerEvent {
+    pub fn name(&self) -> &str {
+        self.actor
+            .attributes
+            .get("name")
+            .map(String::as_str)
+            .unwrap_or(&self.actor.id)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VolumeInfo {
+    pub name: String,
+    #[serde(default)]
+    pub mountpoint: String,
+    #[serde(default)]
+    pub driver: String,
+    /// The options of the driver, like type, device and o of the local driver.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub options: HashMap<String, String>,
+    #[serde(default, deserialize_with = "labels")]
+    pub labels: HashMap<String, String>,
+}
+
+/*
+ * A swarm service as docker service inspect shows it.
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServiceInfo {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub spec: ServiceSpec,
+}
+
+/*
+ * The daemon as docker version shows it, podman calls the api version APIVersion.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DaemonVersion {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default, alias = "APIVersion")]
+    pub api_version: String,
+    #[serde(default)]
+    pub os: String,
+}
+
+/*
+ * The output of docker version, without a server when podman runs without a service.
+ */
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
+pub struct VersionOutput {
+    #[serde(default)]
+    pub client: Option<DaemonVersion>,
+    #[serde(default)]
+    pub server: Option<DaemonVersion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServiceSpec {
+    pub name: String,
+    #[serde(default)]
+    pub mode: ServiceMode,
+}
+
+/*
+ * A replicated service has a number of replicas, a global service a task on every node.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServiceMode {
+    #[serde(default)]
+    pub replicated: Option<ReplicatedMode>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReplicatedMode {
+    #[serde(default)]
+    pub replicas: u64,
+}
+
+impl ServiceInfo {
+    /*
+     * The replicas of a replicated service, None for a global service.
+     */
+    pub fn replicas(&self) -> Option<u64> {
+        self.spec
+            .mode
+            .replicated
+            .as_ref()
+            .map(|replicated| replicated.replicas)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct Mounts {
+    #[serde(default, rename = "Type")]
+    pub kind: Option<MountType>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub source: String,
     pub destination: String,
+    #[serde(default = "read_write", rename = "RW")]
+    pub rw: bool,
 }
 
-#[derive(Debug, Deserialize)]
+impl Mounts {
+    /*
+     * Whether this is an anonymous volume, a volume docker named with 64 random hex characters.
+     */
+    pub fn is_anonymous(&self) -> bool {
+        self.kind == Some(MountType::Volume)
+            && self.name.as_deref().is_some_and(is_anonymous_volume)
+    }
+
+    /*
+     * Why tar can not archive this mount: tmpfs and named pipe mounts and bind mounted devices and
+     * sockets either fail or produce garbage. None for mounts that can be archived.
+     */
+    pub fn unarchivable(&self) -> Option<&'static str> {
+        let in_dev = |path: &str| path == "/dev" || path.starts_with("/dev/");
+        match self.kind {
+            Some(MountType::Tmpfs) => Some("a tmpfs mount"),
+            Some(MountType::Npipe) => Some("a named pipe"),
+            Some(MountType::Bind) if in_dev(&self.source) || in_dev(&self.destination) => {
+                Some("a device")
+            }
+            Some(MountType::Bind) if self.source.ends_with(".sock") => Some("a socket"),
+            _ => None,
+        }
+    }
+}
+
+/*
+ * Whether a volume name is a random name docker gives anonymous volumes.
+ */
+pub fn is_anonymous_volume(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/*
+ * The kind of a mount, docker adds new kinds now and then.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MountType {
+    Volume,
+    Bind,
+    Tmpfs,
+    Npipe,
+    Cluster,
+    Image,
+    #[serde(other)]
+    #[value(skip)]
+    Other,
+}
+
+impl Display for MountType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => f.write_str("other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ContainerConfig {
+    #[serde(default, deserialize_with = "null_as_default")]
     pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub image: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub env: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cmd: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub entrypoint: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerHostConfig {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub links: Vec<String>,
+    /// The published ports by the port of the container, like 80/tcp.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub port_bindings: HashMap<String, Option<Vec<PortBinding>>>,
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
 }
 
-#[derive(Debug)]
-pub struct DockerError {
-    pub message: String,
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PortBinding {
+    #[serde(default)]
+    pub host_ip: String,
+    #[serde(default)]
+    pub host_port: String,
 }
 
-impl From<std::io::Error> for DockerError {
-    fn from(value: std::io::Error) -> Self {
-        DockerError {
-            message: value.to_string(),
-        }
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestartPolicy {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub maximum_retry_count: i64,
+}
+
+impl ContainerInfo {
+    /*
+     * Parse the output of docker inspect for a container, keeping the full output.
+     */
+    pub fn from_inspect(inspect: serde_json::Value) -> Result<ContainerInfo, serde_json::Error> {
+        let mut info = ContainerInfo::deserialize(&inspect)?;
+        info.inspect = Some(inspect);
+        Ok(info)
+    }
+
+    /*
+     * The names of the containers this container links to, docker lists a link as
+     * /<container>:/<this container>/<alias>.
+     */
+    pub fn linked_containers(&self) -> Vec<&str> {
+        self.host_config
+            .links
+            .iter()
+            .filter_map(|link| link.split(':').next())
+            .map(|name| name.trim_start_matches('/'))
+            .collect()
+    }
+}
+
+/*
+ * The names of a container, docker prints them comma separated and podman as an array.
+ */
+fn names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Names {
+        Joined(String),
+        List(Vec<String>),
+    }
+    Ok(match Names::deserialize(deserializer)? {
+        Names::Joined(names) => names,
+        Names::List(names) => names.join(","),
+    })
+}
+
+/*
+ * The labels of a volume, docker volume ls prints them comma separated like a=b,c=d and docker
+ * volume inspect as an object.
+ */
+fn labels<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Labels {
+        Joined(String),
+        Map(HashMap<String, String>),
+    }
+    Ok(match Option::<Labels>::deserialize(deserializer)? {
+        Some(Labels::Joined(labels)) => labels
+            .split(',')
+            .filter_map(|label| label.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        Some(Labels::Map(labels)) => labels,
+        None => HashMap::new(),
+    })
+}
+
+/*
+ * Mounts are writable unless docker says otherwise.
+ */
+fn read_write() -> bool {
+    true
+}
+
+/*
+ * Read null as the default value, like labels of a container without labels.
+ */
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/*
+ * Docker cli configuration types.
+ */
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerConfig {
+    pub current_context: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContextMeta {
+    pub endpoints: ContextEndpoints,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContextEndpoints {
+    pub docker: ContextEndpoint,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContextEndpoint {
+    pub host: String,
+    #[serde(default, rename = "SkipTLSVerify")]
+    pub skip_tls_verify: bool,
+}
+
+/*
+ * The errors of volbackup, by the kind of failure so the exit code can tell them apart.
+ */
+#[derive(Debug, Error)]
+pub enum DockerError {
+    /// The docker daemon is not running, not listening where it is looked for or not accessible.
+    #[error("Can not connect to the docker daemon: {0}")]
+    DaemonUnreachable(String),
+
+    /// Docker printed something that is not the json of a container, volume or their list.
+    #[error("Could not parse the output of {command}: {message}")]
+    InspectParse { command: String, message: String },
+
+    /// A docker command, a command of a pipeline or a command executed in a container failed.
+    #[error("{command} failed with {}{}", exit_status(.exit), stderr_lines(.stderr))]
+    CommandFailed {
+        command: String,
+        exit: Option<i64>,
+        stderr: String,
+    },
+
+    /// The command of a helper container failed.
+    #[error("helper container {command} failed with {}{}", exit_status(.exit), stderr_lines(.stderr))]
+    HelperContainerFailed {
+        command: String,
+        exit: Option<i64>,
+        stderr: String,
+    },
+
+    /// The configuration file, the environment variables or the settings of a container are
+    /// invalid.
+    #[error("{0}")]
+    Config(String),
+
+    /// Another run holds the lock of the run.
+    #[error("{0}")]
+    Locked(String),
+
+    #[error("{0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Catalog(#[from] rusqlite::Error),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Api(bollard::errors::Error),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+fn exit_status(exit: &Option<i64>) -> String {
+    match exit {
+        Some(code) => format!("exit code {}", code),
+        None => "a signal".to_string(),
     }
 }
 
-impl From<serde_json::Error> for DockerError {
-    fn from(value: serde_json::Error) -> Self {
-        DockerError {
-            message: value.to_string(),
+fn stderr_lines(stderr: &str) -> String {
+    match stderr.is_empty() {
+        true => String::new(),
+        false => format!(":\n{}", stderr),
+    }
+}
+
+impl From<&str> for DockerError {
+    fn from(value: &str) -> Self {
+        DockerError::Other(value.to_string())
+    }
+}
+
+impl From<bollard::errors::Error> for DockerError {
+    fn from(value: bollard::errors::Error) -> Self {
+        use bollard::errors::Error;
+        match &value {
+            Error::SocketNotFoundError(_) | Error::RequestTimeoutError => {
+                DockerError::DaemonUnreachable(value.to_string())
+            }
+            Error::HyperLegacyError { err } if err.is_connect() => {
+                DockerError::DaemonUnreachable(value.to_string())
+            }
+            _ => DockerError::Api(value),
         }
     }
 }
+
+impl<T> From<std::sync::PoisonError<T>> for DockerError {
+    fn from(value: std::sync::PoisonError<T>) -> Self {
+        DockerError::Other(value.to_string())
+    }
+}
@@ -0,0 +1,172 @@
+use clap::Args;
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    archive::ArchiveName,
+    catalog::Catalog,
+    exec::{execute_pipeline, run_helper_from, Helper},
+    storage::{checksum_file_name, Destination},
+    types::DockerError,
+    CliArguments,
+};
+
+/// Verify the checksums and the tar structure of the backup archives.
+#[derive(Args, Clone)]
+pub struct VerifyArguments {
+    /// The archives to verify, defaults to all archives in the output directory
+    archives: Vec<String>,
+
+    /// Only verify the archives of this container
+    #[arg(short, long, conflicts_with = "archives")]
+    container: Option<String>,
+
+    /// List the archives with the tar of the helper image instead of the tar of the host
+    #[arg(long, default_value = "false")]
+    in_helper: bool,
+
+    /// The age identity file to decrypt age encrypted archives with, gpg uses the gpg agent
+    #[arg(long)]
+    age_identity: Option<String>,
+}
+
+/*
+ * Verify the archives, printing the result per archive. Returns whether all archives are intact.
+ */
+pub fn verify(verify_args: &VerifyArguments, cli_args: &CliArguments) -> Result<bool, DockerError> {
+    if Destination::parse(cli_args.dest.as_deref(), &cli_args.output_dir)?.is_remote() {
+        return Err(DockerError::from(
+            "Verifying is only supported for archives in the output directory",
+        ));
+    }
+    let template = &cli_args.name_template();
+    let mut archives: Vec<PathBuf> = if verify_args.archives.is_empty() {
+        let mut archives = template
+            .find_archives(Path::new(&cli_args.output_dir))?
+            .into_iter()
+            .filter(|(_, archive, _, _)| {
+                verify_args
+                    .container
+                    .as_ref()
+                    .is_none_or(|container| template.matches(&archive.base, Some(container), None))
+            })
+            .collect::<Vec<_>>();
+        archives.sort_by(|a, b| a.0.cmp(&b.0));
+        archives.into_iter().map(|(_, _, path, _)| path).collect()
+    } else {
+        verify_args.archives.iter().map(PathBuf::from).collect()
+    };
+    archives.dedup();
+    let catalog = Catalog::exists(cli_args)
+        .then(|| Catalog::open(cli_args))
+        .transpose()?;
+
+    let mut intact = true;
+    for archive in archives {
+        let name = template.split_path(&archive).1;
+        match verify_archive(&archive, &name, catalog.as_ref(), verify_args, cli_args) {
+            Ok(()) => println!("ok      {}", name),
+            Err(e) => {
+                println!("FAILED  {}: {}", name, e);
+                intact = false;
+            }
+        }
+    }
+    if intact {
+        info!("All archives are intact");
+    } else {
+        error!("Some archives are broken");
+    }
+    Ok(intact)
+}
+
+/*
+ * Check the sha256 of an archive against its .sha256 file or the catalog, and list its contents
+ * with tar to check the structure.
+ */
+fn verify_archive(
+    archive: &Path,
+    name: &str,
+    catalog: Option<&Catalog>,
+    verify_args: &VerifyArguments,
+    cli_args: &CliArguments,
+) -> Result<(), DockerError> {
+    let archive_name =
+        ArchiveName::parse(name).ok_or(DockerError::from("not a (compressed) tar file"))?;
+    let expected = expected_checksum(archive, name, catalog)?;
+    let mut hasher = Sha256::new();
+    let mut file = File::open(archive)?;
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    match expected {
+        Some(expected) if expected != digest => {
+            return Err(DockerError::from(
+                format!("sha256 is {} instead of {}", digest, expected).as_str(),
+            ))
+        }
+        Some(_) => {}
+        None => info!(
+            "No checksum recorded for {}, only checking its structure",
+            name
+        ),
+    }
+
+    let mut commands: Vec<Command> = archive_name
+        .encryption
+        .map(|e| e.decrypt_command(verify_args.age_identity.as_deref()))
+        .transpose()?
+        .into_iter()
+        .collect();
+    commands.extend(archive_name.compression.decompress_command());
+    let input = File::open(archive)?;
+    if verify_args.in_helper {
+        let helper = Helper {
+            command: ["tar", "tf", "-"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        return run_helper_from(&helper, commands, Some(input), cli_args);
+    }
+    let mut tar = Command::new("tar");
+    tar.args(["tf", "-"]);
+    commands.push(tar);
+    execute_pipeline(commands, Stdio::from(input), Stdio::null())
+}
+
+/*
+ * The recorded sha256 of an archive: from its .sha256 file, or else from the catalog.
+ */
+fn expected_checksum(
+    archive: &Path,
+    name: &str,
+    catalog: Option<&Catalog>,
+) -> Result<Option<String>, DockerError> {
+    let file_name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let sidecar = archive.with_file_name(checksum_file_name(&file_name));
+    if sidecar.exists() {
+        return Ok(fs::read_to_string(sidecar)?
+            .split_whitespace()
+            .next()
+            .map(String::from));
+    }
+    match catalog {
+        Some(catalog) => catalog.checksum(name),
+        None => Ok(None),
+    }
+}
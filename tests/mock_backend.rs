@@ -0,0 +1,393 @@
+use clap::Parser;
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use volbackup::{
+    backup, prune, restore, CliArguments, Commands, DockerError, MockExecutor, Outcome,
+};
+
+const WEB_ID: &str = "4f1c2a9e0b7d";
+
+/*
+ * The daemon of the fixtures: web running with the /data volume and the /etc/nginx/conf.d bind
+ * mount, db exited with the /var/lib/postgresql/data volume.
+ */
+fn fixtures() -> MockExecutor {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    MockExecutor::from_fixtures(&dir).unwrap()
+}
+
+/*
+ * An empty output directory of a test, removed with the archives when the test is done.
+ */
+struct OutputDir(PathBuf);
+
+impl OutputDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!(
+            "volbackup-it-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        OutputDir(dir)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /*
+     * Run the command line against the mock daemon in the directory, like the volbackup binary
+     * does for the backup, restore and prune commands.
+     */
+    fn volbackup(&self, mock: &Arc<MockExecutor>, args: &[&str]) -> Result<Outcome, DockerError> {
+        let output = self.0.to_string_lossy();
+        let command_line = ["volbackup", "--output-dir", &output, "--no-progress"];
+        let mut cli_args = CliArguments::parse_from(command_line.iter().chain(args));
+        cli_args.executor = Some(mock.clone());
+        match &cli_args.command {
+            None => backup::backup(&cli_args.backup, &cli_args),
+            Some(Commands::Backup(backup_args)) => backup::backup(backup_args, &cli_args),
+            Some(Commands::Restore(restore_args)) => {
+                restore::restore(restore_args, &cli_args).map(|_| Outcome::Success)
+            }
+            Some(Commands::Prune(prune_args)) => {
+                prune::prune(prune_args, &cli_args).map(|_| Outcome::Success)
+            }
+            Some(_) => panic!("Command not run by the tests"),
+        }
+    }
+
+    /*
+     * The names of the archives in the directory, sorted.
+     */
+    fn archives(&self) -> Vec<String> {
+        let mut archives: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".tar"))
+            .collect();
+        archives.sort();
+        archives
+    }
+
+    fn read_json(&self, name: &str) -> Value {
+        serde_json::from_slice(&fs::read(self.join(name)).unwrap()).unwrap()
+    }
+}
+
+impl Drop for OutputDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/*
+ * The files of a tar archive with their contents.
+ */
+fn tar_files(archive: &[u8]) -> Vec<(String, String)> {
+    tar::Archive::new(archive)
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            (path, content)
+        })
+        .collect()
+}
+
+/*
+ * The destination and status of the mounts of a container in the manifest or the report.
+ */
+fn mount_statuses(container: &Value) -> Vec<(&str, &str)> {
+    container["mounts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| {
+            (
+                m["destination"].as_str().unwrap(),
+                m["status"].as_str().unwrap(),
+            )
+        })
+        .collect()
+}
+
+/*
+ * The level of an incremental archive from its name, like 1 for web_data-<run-id>.1.tar.
+ */
+fn level(archive: &str) -> u32 {
+    let level = Regex::new(r"\.(\d+)\.tar$").unwrap();
+    level.captures(archive).unwrap()[1].parse().unwrap()
+}
+
+#[test]
+fn names_the_archives_after_container_mount_and_time() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    assert_eq!(output_dir.volbackup(&mock, &[]).unwrap(), Outcome::Success);
+    let archives = output_dir.archives();
+    let named = |name: &str| {
+        Regex::new(&format!(r"^{}-\d{{4}}-\d{{2}}-\d{{2}}T\d{{6}}\.tar$", name)).unwrap()
+    };
+    assert_eq!(archives.len(), 2, "{:?}", archives);
+    assert!(named("web_data").is_match(&archives[0]), "{}", archives[0]);
+    assert!(
+        named(r"web_etc_nginx_conf\.d").is_match(&archives[1]),
+        "{}",
+        archives[1]
+    );
+    for archive in &archives {
+        assert!(output_dir.join(&format!("{}.sha256", archive)).exists());
+    }
+    let files = tar_files(&fs::read(output_dir.join(&archives[0])).unwrap());
+    let mock_file = (
+        "data/volbackup-mock.txt".to_string(),
+        format!("{} /data\n", WEB_ID),
+    );
+    assert_eq!(files, [mock_file]);
+}
+
+#[test]
+fn writes_the_manifest_and_the_report() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    let report = output_dir.join("report.json");
+    let args = ["--all", "--report-json", report.to_str().unwrap()];
+    assert_eq!(
+        output_dir.volbackup(&mock, &args).unwrap(),
+        Outcome::Success
+    );
+    let archives = output_dir.archives();
+
+    let manifest = output_dir.read_json("manifest.json");
+    let containers = manifest["containers"].as_array().unwrap();
+    let mut names: Vec<_> = containers
+        .iter()
+        .map(|c| c["name"].as_str().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["db", "web"]);
+    let web = containers.iter().find(|c| c["name"] == "web").unwrap();
+    assert_eq!(
+        mount_statuses(web),
+        [("/data", "success"), ("/etc/nginx/conf.d", "success")]
+    );
+    for mount in web["mounts"].as_array().unwrap() {
+        assert!(archives.contains(&mount["archive"].as_str().unwrap().to_string()));
+        assert_eq!(mount["size"], 2048);
+    }
+
+    let report = output_dir.read_json("report.json");
+    assert_eq!(report["status"], "success");
+    assert_eq!(report["error"], Value::Null);
+    let containers = report["containers"].as_array().unwrap();
+    assert_eq!(containers.len(), 2);
+    for container in containers {
+        assert_eq!(container["status"], "success");
+    }
+    let db = containers.iter().find(|c| c["name"] == "db").unwrap();
+    assert_eq!(
+        mount_statuses(db),
+        [("/var/lib/postgresql/data", "success")]
+    );
+    let db_archive = db["mounts"][0]["archive"].as_str().unwrap();
+    assert!(
+        db_archive.starts_with("db_var_lib_postgresql_data-"),
+        "{}",
+        db_archive
+    );
+    assert!(archives.contains(&db_archive.to_string()));
+}
+
+#[test]
+fn reports_a_failed_mount() {
+    let failing = format!("run {} tar cf - /data", WEB_ID);
+    let mock = Arc::new(fixtures().fail(&failing, "tar: /data: Cannot open"));
+    let output_dir = OutputDir::new();
+    let report = output_dir.join("report.json");
+    let args = ["--report-json", report.to_str().unwrap()];
+    assert_eq!(output_dir.volbackup(&mock, &args).unwrap(), Outcome::Failed);
+    let report = output_dir.read_json("report.json");
+    assert_eq!(report["status"], "failed");
+    let web = &report["containers"][0];
+    assert_eq!(web["status"], "failed");
+    assert_eq!(
+        mount_statuses(web),
+        [("/data", "failed"), ("/etc/nginx/conf.d", "success")]
+    );
+    let error = web["mounts"][0]["error"].as_str().unwrap();
+    assert!(error.contains("Cannot open"), "{}", error);
+}
+
+#[test]
+fn starts_a_new_chain_of_incremental_archives() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    let report = output_dir.join("report.json");
+    let args = [
+        "--incremental",
+        "--full-every",
+        "1",
+        "--name-template",
+        "{container}{mount}-{run-id}",
+        "--report-json",
+        report.to_str().unwrap(),
+    ];
+    let mut levels = vec![];
+    for _ in 0..3 {
+        assert_eq!(
+            output_dir.volbackup(&mock, &args).unwrap(),
+            Outcome::Success
+        );
+        let report = output_dir.read_json("report.json");
+        levels.push(level(
+            report["containers"][0]["mounts"][0]["archive"]
+                .as_str()
+                .unwrap(),
+        ));
+    }
+    assert_eq!(levels, [0, 1, 0]);
+    assert_eq!(output_dir.archives().len(), 6);
+    let chain = output_dir.read_json(".snapshots/web_data-{run-id}.chain.json");
+    let chain = chain["archives"].as_array().unwrap();
+    assert_eq!(chain.len(), 1);
+    assert_eq!(level(chain[0].as_str().unwrap()), 0);
+}
+
+/*
+ * Write empty archives into the directory, the first one modified last so only the times in their
+ * names order them, and prune them keeping the last ones.
+ */
+fn prune_last(archives: &[&str], keep: &str, dry_run: bool) -> Vec<String> {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    let now = SystemTime::now();
+    for (age, archive) in archives.iter().enumerate() {
+        let file = File::create(output_dir.join(archive)).unwrap();
+        file.set_modified(now - Duration::from_secs(3600 * age as u64))
+            .unwrap();
+    }
+    let mut args = vec!["prune", "--keep-last", keep];
+    args.extend(dry_run.then_some("--dry-run"));
+    output_dir.volbackup(&mock, &args).unwrap();
+    output_dir.archives()
+}
+
+#[test]
+fn prunes_all_but_the_last_archives() {
+    let archives = [
+        "web_data-2026-10-01T030000.tar",
+        "web_data-2026-10-02T030000.tar",
+        "web_data-2026-10-03T030000.tar",
+    ];
+    assert_eq!(prune_last(&archives, "3", false), archives);
+    assert_eq!(prune_last(&archives, "1", true), archives);
+    assert_eq!(prune_last(&archives, "2", false), archives[1..]);
+}
+
+#[test]
+fn prunes_incremental_chains_as_a_whole() {
+    let archives = [
+        "web_data-2026-10-01T030000.0.tar",
+        "web_data-2026-10-02T030000.1.tar",
+        "web_data-2026-10-03T030000.0.tar",
+        "web_data-2026-10-04T030000.1.tar",
+        "web_data-2026-10-05T030000.2.tar",
+    ];
+    // The increment of the first chain keeps its full archive too.
+    assert_eq!(prune_last(&archives, "4", false), archives);
+    assert_eq!(prune_last(&archives, "1", false), archives[2..]);
+}
+
+#[test]
+fn refuses_to_prune_archives_without_a_time_in_their_name() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    File::create(output_dir.join("web_data.tar")).unwrap();
+    let args = [
+        "--name-template",
+        "{container}{mount}",
+        "prune",
+        "--keep-last",
+        "1",
+    ];
+    let error = output_dir.volbackup(&mock, &args).unwrap_err();
+    assert!(matches!(error, DockerError::Config(_)), "{}", error);
+    assert_eq!(output_dir.archives(), ["web_data.tar"]);
+}
+
+#[test]
+fn restores_the_latest_archive_into_the_container() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    assert_eq!(output_dir.volbackup(&mock, &[]).unwrap(), Outcome::Success);
+    let args = ["restore", "--container", "web", "--mount", "/data", "--yes"];
+    output_dir.volbackup(&mock, &args).unwrap();
+    // The restore helper gets the volumes of the container by its name.
+    let restored = tar_files(&mock.received("web").unwrap());
+    let mock_file = (
+        "data/volbackup-mock.txt".to_string(),
+        format!("{} /data\n", WEB_ID),
+    );
+    assert_eq!(restored, [mock_file]);
+}
+
+#[test]
+fn recreates_a_container_from_its_last_backup() {
+    let mock = Arc::new(fixtures());
+    let output_dir = OutputDir::new();
+    assert_eq!(output_dir.volbackup(&mock, &[]).unwrap(), Outcome::Success);
+    // The manifest.json of the next run no longer has web.
+    let args = ["--all", "--exclude", "web"];
+    assert_eq!(
+        output_dir.volbackup(&mock, &args).unwrap(),
+        Outcome::Success
+    );
+    let manifest = output_dir.read_json("manifest.json");
+    assert_eq!(manifest["containers"][0]["name"], "db");
+
+    let new_host = Arc::new(MockExecutor::new());
+    let args = [
+        "restore",
+        "--container",
+        "web",
+        "--recreate-container",
+        "--yes",
+    ];
+    output_dir.volbackup(&new_host, &args).unwrap();
+    let calls = new_host.calls();
+    assert!(
+        calls.contains(&"volume create web_data local".to_string()),
+        "{:?}",
+        calls
+    );
+    assert!(
+        calls.contains(&"create web nginx".to_string()),
+        "{:?}",
+        calls
+    );
+    let restored = tar_files(&new_host.received("web_data").unwrap());
+    let mock_file = (
+        "data/volbackup-mock.txt".to_string(),
+        format!("{} /data\n", WEB_ID),
+    );
+    assert_eq!(restored, [mock_file]);
+}